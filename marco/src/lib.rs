@@ -49,54 +49,6 @@ pub fn to_lower_case_str(streal: TokenStream) -> TokenStream {
     TokenStream::from(TokenTree::Literal(Literal::string(&result)))
 }
 
-#[proc_macro_derive(CommandParser)]
-pub fn add_command_parser(input: TokenStream) -> TokenStream {
-    // Parse the string representation
-    let ast = syn::parse(input).unwrap();
-
-    // Build the impl
-    let gen = impl_command_parse(&ast);
-
-    // Return the generated impl
-    gen
-}
-
-fn impl_command_parse(ast: &syn::DeriveInput) -> TokenStream {
-    let name = &ast.ident;
-    let gen = quote! {
-        impl CommandParser for #name {
-            fn parse(frames: Vec<Frame>) -> Result<Box<dyn CommandApplyer>> {
-                Ok(Box::new(#name::from_frames(frames)?))
-            }
-        }
-    };
-    gen.into()
-}
-
-#[proc_macro_derive(Applyer)]
-pub fn add_command_applyer(input: TokenStream) -> TokenStream {
-    // Parse the string representation
-    let ast = syn::parse(input).unwrap();
-
-    // Build the impl
-    let gen = impl_command_apply(&ast);
-
-    // Return the generated impl
-    gen
-}
-
-fn impl_command_apply(ast: &syn::DeriveInput) -> TokenStream {
-    let name = &ast.ident;
-    let gen = quote! {
-        impl CommandApplyer for #name {
-            fn apply(self: Box<Self>, db: DB) -> Frame {
-                self.apply(db)
-            }
-        }
-    };
-    gen.into()
-}
-
 #[proc_macro_derive(ValueDecorator)]
 pub fn add_value_decorator(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).unwrap();
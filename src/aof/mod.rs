@@ -0,0 +1,375 @@
+//! https://redis.io/docs/management/persistence/#append-only-file
+//! every write command is appended to the file as RESP once it's applied,
+//! the file is replayed through the command parser on startup
+
+mod rewrite;
+pub use rewrite::write_base;
+
+use crate::cmd::{Command, Parser};
+use crate::config::AppendFsync;
+use crate::db::DB;
+use crate::frame::Frame;
+use crate::helper::{bulk, unix_timestamp_ms};
+use crate::{RedisErr, Result};
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use log::{error, warn};
+
+#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct AOF {
+    file: File,
+    path: PathBuf,
+    fsync: AppendFsync,
+
+    // current size and the size after the last rewrite, for the automatic rewrite
+    size: u64,
+    base_size: u64,
+
+    // commands arriving while the file is rewritten, appended to the new file at the end
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+impl AOF {
+    pub fn open(path: &Path, fsync: AppendFsync) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            fsync,
+            size,
+            base_size: size,
+            rewrite_buffer: None,
+        })
+    }
+
+    // append a serialized command
+    pub fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data)?;
+        if self.fsync == AppendFsync::Always {
+            self.file.sync_data()?;
+        }
+        self.size += data.len() as u64;
+        if let Some(buffer) = self.rewrite_buffer.as_mut() {
+            buffer.extend_from_slice(data);
+        }
+        Ok(())
+    }
+
+    pub fn fsync(&self) -> AppendFsync {
+        self.fsync
+    }
+
+    // another handle of the file, so it can be synced without holding the lock
+    pub fn file(&self) -> Result<File> {
+        Ok(self.file.try_clone()?)
+    }
+
+    // the temporary file the rewrite writes the key space to
+    pub fn rewrite_path(&self) -> PathBuf {
+        let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+        dir.join(format!("temp-rewriteaof-bg-{}.aof", std::process::id()))
+    }
+
+    pub fn is_rewriting(&self) -> bool {
+        self.rewrite_buffer.is_some()
+    }
+
+    pub fn start_rewrite(&mut self) {
+        self.rewrite_buffer = Some(Vec::new());
+    }
+
+    // append the buffered commands to the rewritten file and swap it in
+    pub fn finish_rewrite(&mut self) -> Result<()> {
+        let temp = self.rewrite_path();
+        let mut file = OpenOptions::new().append(true).open(&temp)?;
+        file.write_all(&self.rewrite_buffer.take().unwrap_or_default())?;
+        file.sync_all()?;
+        std::fs::rename(&temp, &self.path)?;
+
+        self.size = file.metadata()?.len();
+        self.base_size = self.size;
+        self.file = file;
+        Ok(())
+    }
+
+    pub fn abort_rewrite(&mut self) {
+        self.rewrite_buffer = None;
+        let _ = std::fs::remove_file(self.rewrite_path());
+    }
+
+    // the file has grown by percentage since the last rewrite and is larger than min_size
+    pub fn need_rewrite(&self, percentage: u64, min_size: u64) -> bool {
+        if percentage == 0 || self.is_rewriting() || self.size < min_size {
+            return false;
+        }
+        let base = self.base_size.max(1);
+        self.size.saturating_sub(base) * 100 / base >= percentage
+    }
+}
+
+// replay the commands in the file, returns the number of commands applied
+// a command cut off at the end is dropped, as a crash may happen in the middle of a write,
+// so is a transaction without its EXEC, it's applied all at once or not at all
+pub fn load(path: &Path, db: &mut DB) -> Result<usize> {
+    let data = std::fs::read(path)?;
+    let parser = Parser::new();
+    let mut pos = 0;
+    let mut applied = 0;
+    // the offset of MULTI and the commands queued after it
+    let mut transaction: Option<(usize, Vec<(usize, Command)>)> = None;
+    while pos < data.len() {
+        let (frame, len) = match Frame::parse(&data[pos..]) {
+            Ok(parsed) => parsed,
+            Err(RedisErr::FrameIncomplete) => break,
+            Err(e) => {
+                error!("bad frame in aof at offset {}: {}", pos, e);
+                return Err(RedisErr::AOFMalformed);
+            }
+        };
+        let cmd = parser.parse(frame).map_err(|e| {
+            error!("bad command in aof at offset {}: {}", pos, e);
+            RedisErr::AOFMalformed
+        })?;
+        match (cmd, transaction.as_mut()) {
+            (Command::Multi(_), None) => transaction = Some((pos, Vec::new())),
+            (Command::Exec(_), Some(_)) => {
+                let (_, queued) = transaction.take().unwrap();
+                for (pos, cmd) in queued {
+                    replay(db, pos, cmd)?;
+                    applied += 1;
+                }
+            }
+            (Command::Multi(_) | Command::Exec(_), _) => {
+                error!("unbalanced MULTI or EXEC in aof at offset {}", pos);
+                return Err(RedisErr::AOFMalformed);
+            }
+            (cmd, Some((_, queued))) => queued.push((pos, cmd)),
+            (cmd, None) => {
+                replay(db, pos, cmd)?;
+                applied += 1;
+            }
+        }
+        pos += len;
+    }
+    if let Some((multi, _)) = transaction {
+        pos = multi;
+    }
+    if pos < data.len() {
+        warn!(
+            "aof is truncated at offset {}, dropping the last {} bytes",
+            pos,
+            data.len() - pos
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(pos as u64)?;
+    }
+    Ok(applied)
+}
+
+fn replay(db: &mut DB, pos: usize, cmd: Command) -> Result<()> {
+    if let Frame::Error(e) = cmd.apply_to_db(db)? {
+        warn!("replaying command at offset {} failed: {}", pos, e);
+    }
+    Ok(())
+}
+
+// relative expire times are turned into absolute ones,
+// so replaying the command later keeps the deadline
+pub fn normalize(frame: Frame) -> Frame {
+    let args = match frame {
+        Frame::Array(args) => args.into_iter().map(to_bulk).collect::<Vec<_>>(),
+        frame => return frame,
+    };
+    let name = match args.first() {
+        Some(Frame::BulkString(name)) => String::from_utf8_lossy(name).to_ascii_uppercase(),
+        _ => return Frame::Array(args),
+    };
+    match name.as_str() {
+        // EXPIRE key seconds => PEXPIREAT key unix-time-milliseconds
+        "EXPIRE" if args.len() == 3 => match bulk_to_u64(&args[2]) {
+            Some(secs) => Frame::Array(vec![
+                bulk("PEXPIREAT"),
+                args[1].clone(),
+                bulk(&(unix_timestamp_ms() + secs * 1000).to_string()),
+            ]),
+            None => Frame::Array(args),
+        },
+        // SET key value EX seconds => SET key value PXAT unix-time-milliseconds,
+        // only the options after the value are looked at, the key or the value may be EX
+        "SET" => {
+            let mut res = Vec::with_capacity(args.len());
+            let mut iter = args.into_iter();
+            res.extend(iter.by_ref().take(3));
+            while let Some(arg) = iter.next() {
+                let unit = match &arg {
+                    Frame::BulkString(b) if b.eq_ignore_ascii_case(b"EX") => 1000,
+                    Frame::BulkString(b) if b.eq_ignore_ascii_case(b"PX") => 1,
+                    _ => {
+                        res.push(arg);
+                        continue;
+                    }
+                };
+                match iter.next() {
+                    Some(ttl) => match bulk_to_u64(&ttl) {
+                        Some(ttl) => {
+                            res.push(bulk("PXAT"));
+                            res.push(bulk(&(unix_timestamp_ms() + ttl * unit).to_string()));
+                        }
+                        None => res.extend([arg, ttl]),
+                    },
+                    None => res.push(arg),
+                }
+            }
+            Frame::Array(res)
+        }
+        _ => Frame::Array(args),
+    }
+}
+
+// inline commands are logged as bulk strings like the others
+fn to_bulk(frame: Frame) -> Frame {
+    match frame {
+        Frame::SimpleString(s) => Frame::BulkString(Bytes::from(s)),
+        Frame::Integer(i) => Frame::BulkString(Bytes::from(i.to_string())),
+        frame => frame,
+    }
+}
+
+#[inline]
+fn bulk_to_u64(frame: &Frame) -> Option<u64> {
+    match frame {
+        Frame::BulkString(b) => std::str::from_utf8(b).ok()?.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::command;
+
+    #[test]
+    fn test_normalize() {
+        let frame = normalize(Frame::Array(command(&[
+            "set", "key", "value", "ex", "10", "nx",
+        ])));
+        let now = unix_timestamp_ms();
+        match frame {
+            Frame::Array(args) => {
+                assert_eq!(args[..4], command(&["set", "key", "value", "PXAT"]));
+                let at = bulk_to_u64(&args[4]).unwrap();
+                assert!(at > now + 9000 && at <= now + 10000);
+                assert_eq!(args[5], bulk("nx"));
+            }
+            _ => panic!("expect array"),
+        }
+
+        let frame = normalize(Frame::Array(vec![
+            Frame::SimpleString("expire".to_string()),
+            Frame::SimpleString("key".to_string()),
+            Frame::Integer(10),
+        ]));
+        match frame {
+            Frame::Array(args) => {
+                assert_eq!(args[..2], command(&["PEXPIREAT", "key"]));
+                assert!(bulk_to_u64(&args[2]).unwrap() > now);
+            }
+            _ => panic!("expect array"),
+        }
+
+        let frame = Frame::Array(command(&["lpush", "list", "a"]));
+        assert_eq!(normalize(frame.clone()), frame);
+
+        // the key and the value are not options
+        let frame = Frame::Array(command(&["set", "ex", "5"]));
+        assert_eq!(normalize(frame.clone()), frame);
+        let frame = normalize(Frame::Array(command(&["set", "px", "px", "EX", "100"])));
+        match frame {
+            Frame::Array(args) => {
+                assert_eq!(args[..4], command(&["set", "px", "px", "PXAT"]));
+                assert!(bulk_to_u64(&args[4]).unwrap() > now + 99000);
+            }
+            _ => panic!("expect array"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_append_and_load() {
+        let dir = std::env::temp_dir().join(format!("redis-rs-aof-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("appendonly.aof");
+        let _ = std::fs::remove_file(&path);
+
+        let mut aof = AOF::open(&path, AppendFsync::Always).unwrap();
+        for args in [
+            &["set", "a", "1"][..],
+            &["lpush", "list", "x", "y"],
+            &["set", "b", "2", "ex", "100"],
+        ] {
+            aof.append(&normalize(Frame::Array(command(args))).serialize())
+                .unwrap();
+        }
+        drop(aof);
+        // a half written command
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"*3\r\n$3\r\nset\r\n$1\r\nc").unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        let mut db = DB::new();
+        assert_eq!(load(&path, &mut db).unwrap(), 3);
+        assert_eq!(db.get("a").unwrap(), Bytes::from("1"));
+        assert_eq!(db.get_type("list"), Some("list"));
+        assert_eq!(db.get("b").unwrap(), Bytes::from("2"));
+        assert_eq!(db.get_type("c"), None);
+        // the broken tail is cut off
+        assert!(std::fs::metadata(&path).unwrap().len() < len);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_transaction() {
+        let dir = std::env::temp_dir().join(format!("redis-rs-aof-multi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("appendonly.aof");
+        let _ = std::fs::remove_file(&path);
+
+        let mut aof = AOF::open(&path, AppendFsync::Always).unwrap();
+        for args in [
+            &["multi"][..],
+            &["set", "a", "1"],
+            &["set", "b", "2"],
+            &["exec"],
+            &["set", "c", "3"],
+        ] {
+            aof.append(&Frame::Array(command(args)).serialize())
+                .unwrap();
+        }
+        let len = std::fs::metadata(&path).unwrap().len();
+        // a transaction cut off before its EXEC
+        for args in [&["multi"][..], &["set", "d", "4"]] {
+            aof.append(&Frame::Array(command(args)).serialize())
+                .unwrap();
+        }
+        drop(aof);
+
+        let mut db = DB::new();
+        assert_eq!(load(&path, &mut db).unwrap(), 3);
+        assert_eq!(db.get("b").unwrap(), Bytes::from("2"));
+        assert_eq!(db.get("c").unwrap(), Bytes::from("3"));
+        assert_eq!(db.get_type("d"), None);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! rewrite the aof from the key space
+//! each key becomes a single command that rebuilds it, followed by PEXPIREAT if it has a ttl,
+//! the function libraries are loaded first
+
+use crate::frame::Frame;
+use crate::helper::bulk;
+use crate::rdb::Record;
+use crate::value::{ConsumerGroup, Stream, StreamId, Value};
+use crate::Result;
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use bytes::Bytes;
+
+// write the commands of the records to the file, replacing its content
+pub fn write_base(path: &Path, records: &[Record], functions: &[Bytes]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for code in functions {
+        let frame = Frame::Array(vec![
+            bulk("FUNCTION"),
+            bulk("LOAD"),
+            Frame::BulkString(code.clone()),
+        ]);
+        writer.write_all(&frame.serialize())?;
+    }
+    for record in records {
+        for frame in rewrite_record(record) {
+            writer.write_all(&frame.serialize())?;
+        }
+    }
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+pub fn rewrite_record((key, value, expire_at): &Record) -> Vec<Frame> {
+    let key = Frame::BulkString(Bytes::from(key.clone()));
+    let args = match value {
+        Value::KV(v) => vec![bulk("SET"), key.clone(), Frame::BulkString(v.clone())],
+        Value::List(list) => {
+            let mut args = vec![bulk("RPUSH"), key.clone()];
+            args.extend(list.iter().cloned().map(Frame::BulkString));
+            args
+        }
+        Value::Hash(map) => {
+            let mut args = vec![bulk("HSET"), key.clone()];
+            for (field, v) in map {
+                args.push(bulk(field));
+                args.push(Frame::BulkString(v.clone()));
+            }
+            args
+        }
+        Value::ZSet(zset) => {
+            let mut args = vec![bulk("ZADD"), key.clone()];
+            for (member, score) in zset.iter() {
+                args.push(bulk(&score.to_string()));
+                args.push(Frame::BulkString(member.clone()));
+            }
+            args
+        }
+        // an XADD with the id of each entry, XSETID to the last id which may have been deleted,
+        // then the consumer groups
+        Value::Stream(stream) => {
+            let mut frames = stream
+                .iter()
+                .map(|(id, fields)| {
+                    let mut args = vec![bulk("XADD"), key.clone(), bulk(&id.to_string())];
+                    for (field, v) in fields {
+                        args.push(Frame::BulkString(field.clone()));
+                        args.push(Frame::BulkString(v.clone()));
+                    }
+                    Frame::Array(args)
+                })
+                .collect::<Vec<_>>();
+            // an entry trimmed right away leaves an empty stream, like redis does
+            if frames.is_empty() {
+                frames.push(Frame::Array(vec![
+                    bulk("XADD"),
+                    key.clone(),
+                    bulk("MAXLEN"),
+                    bulk("0"),
+                    bulk("0-1"),
+                    bulk("x"),
+                    bulk("y"),
+                ]));
+            }
+            frames.push(Frame::Array(vec![
+                bulk("XSETID"),
+                key.clone(),
+                bulk(&stream.last_id().to_string()),
+            ]));
+            for (name, group) in stream.groups() {
+                frames.extend(rewrite_group(&key, name, group, stream));
+            }
+            return with_expire(key, frames, expire_at);
+        }
+        Value::Set(set) => {
+            let mut args = vec![bulk("SADD"), key.clone()];
+            args.extend(set.iter().cloned().map(Frame::BulkString));
+            args
+        }
+        // the serialized filter in a single chunk
+        Value::BloomFilter(bloom) => vec![
+            bulk("BF.LOADCHUNK"),
+            key.clone(),
+            bulk("1"),
+            Frame::BulkString(Bytes::from(bloom.to_bytes())),
+        ],
+    };
+    // empty collections are not kept in the key space
+    if args.len() == 2 {
+        return vec![];
+    }
+    with_expire(key, vec![Frame::Array(args)], expire_at)
+}
+
+// the commands rebuilding the key followed by PEXPIREAT if it has a ttl
+// the group with its consumers, and an XCLAIM for each pending entry still in the stream
+fn rewrite_group(key: &Frame, name: &str, group: &ConsumerGroup, stream: &Stream) -> Vec<Frame> {
+    let mut frames = vec![Frame::Array(vec![
+        bulk("XGROUP"),
+        bulk("CREATE"),
+        key.clone(),
+        bulk(name),
+        bulk(&group.last_id().to_string()),
+        bulk("MKSTREAM"),
+    ])];
+    for (consumer, _) in group.consumers() {
+        frames.push(Frame::Array(vec![
+            bulk("XGROUP"),
+            bulk("CREATECONSUMER"),
+            key.clone(),
+            bulk(name),
+            bulk(consumer),
+        ]));
+    }
+    // FORCE only claims the entries in the stream
+    let exists = |id: &StreamId| stream.range(*id, *id).next().is_some();
+    for (id, entry) in group.pending().filter(|(id, _)| exists(id)) {
+        frames.push(Frame::Array(vec![
+            bulk("XCLAIM"),
+            key.clone(),
+            bulk(name),
+            bulk(&entry.consumer),
+            bulk("0"),
+            bulk(&id.to_string()),
+            bulk("TIME"),
+            bulk(&entry.delivery_time.to_string()),
+            bulk("RETRYCOUNT"),
+            bulk(&entry.delivery_count.to_string()),
+            bulk("FORCE"),
+            bulk("JUSTID"),
+        ]));
+    }
+    frames
+}
+
+fn with_expire(key: Frame, mut frames: Vec<Frame>, expire_at: &Option<u64>) -> Vec<Frame> {
+    if frames.is_empty() {
+        return frames;
+    }
+    if let Some(expire_at) = expire_at {
+        frames.push(Frame::Array(vec![
+            bulk("PEXPIREAT"),
+            key,
+            bulk(&expire_at.to_string()),
+        ]));
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::BloomFilter;
+
+    use std::collections::{HashMap, VecDeque};
+
+    #[test]
+    fn test_rewrite_record() {
+        let frames = rewrite_record(&(
+            "key".to_string(),
+            Value::KV(Bytes::from("value")),
+            Some(1700000000000),
+        ));
+        assert_eq!(
+            frames,
+            vec![
+                Frame::Array(vec![bulk("SET"), bulk("key"), bulk("value")]),
+                Frame::Array(vec![bulk("PEXPIREAT"), bulk("key"), bulk("1700000000000")]),
+            ]
+        );
+
+        let list = VecDeque::from(vec![Bytes::from("a"), Bytes::from("b")]);
+        let frames = rewrite_record(&("list".to_string(), Value::List(list), None));
+        assert_eq!(
+            frames,
+            vec![Frame::Array(vec![
+                bulk("RPUSH"),
+                bulk("list"),
+                bulk("a"),
+                bulk("b")
+            ])]
+        );
+
+        let hash = HashMap::from([("f".to_string(), Bytes::from("v"))]);
+        let frames = rewrite_record(&("hash".to_string(), Value::Hash(hash), None));
+        assert_eq!(
+            frames,
+            vec![Frame::Array(vec![
+                bulk("HSET"),
+                bulk("hash"),
+                bulk("f"),
+                bulk("v")
+            ])]
+        );
+
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            stream.add(
+                StreamId::new(ms, 0),
+                vec![(Bytes::from("f"), Bytes::from("v"))],
+            );
+        }
+        stream.remove(&StreamId::new(3, 0));
+        let frames = rewrite_record(&("stream".to_string(), Value::Stream(stream), None));
+        assert_eq!(
+            frames,
+            vec![
+                Frame::Array(vec![
+                    bulk("XADD"),
+                    bulk("stream"),
+                    bulk("1-0"),
+                    bulk("f"),
+                    bulk("v")
+                ]),
+                Frame::Array(vec![
+                    bulk("XADD"),
+                    bulk("stream"),
+                    bulk("2-0"),
+                    bulk("f"),
+                    bulk("v")
+                ]),
+                Frame::Array(vec![bulk("XSETID"), bulk("stream"), bulk("3-0")]),
+            ]
+        );
+
+        // nothing left, the stream is kept with its last id
+        let mut stream = Stream::new();
+        stream.set_last_id(StreamId::new(3, 0));
+        let frames = rewrite_record(&("stream".to_string(), Value::Stream(stream), None));
+        assert_eq!(
+            frames,
+            vec![
+                Frame::Array(vec![
+                    bulk("XADD"),
+                    bulk("stream"),
+                    bulk("MAXLEN"),
+                    bulk("0"),
+                    bulk("0-1"),
+                    bulk("x"),
+                    bulk("y")
+                ]),
+                Frame::Array(vec![bulk("XSETID"), bulk("stream"), bulk("3-0")]),
+            ]
+        );
+
+        let mut bloom = BloomFilter::new();
+        bloom.add("a");
+        let data = Bytes::from(bloom.to_bytes());
+        let frames = rewrite_record(&("bf".to_string(), Value::BloomFilter(bloom), None));
+        assert_eq!(
+            frames,
+            vec![Frame::Array(vec![
+                bulk("BF.LOADCHUNK"),
+                bulk("bf"),
+                bulk("1"),
+                Frame::BulkString(data.clone()),
+            ])]
+        );
+        assert!(BloomFilter::from_bytes(&data).unwrap().contains("a"));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::config::{AppendFsync, KeyspaceEvents, PubSubOutputLimit};
+
 use clap::Parser;
 use marco::Getter;
 
@@ -11,6 +13,53 @@ pub struct Arg {
 
     #[clap(long, default_value = "1024")]
     max_clients: usize,
+
+    #[clap(long)]
+    requirepass: Option<String>,
+
+    #[clap(long, default_value = ".")]
+    dir: String,
+    #[clap(long, default_value = "dump.rdb")]
+    dbfilename: String,
+
+    #[clap(long)]
+    appendonly: bool,
+    #[clap(long, default_value = "appendonly.aof")]
+    appendfilename: String,
+    // always, everysec or no
+    #[clap(long, default_value = "everysec")]
+    appendfsync: AppendFsync,
+    #[clap(long, default_value = "100")]
+    auto_aof_rewrite_percentage: u64,
+    #[clap(long, default_value = "67108864")]
+    auto_aof_rewrite_min_size: u64,
+
+    // "host port" of the primary
+    #[clap(long)]
+    replicaof: Option<String>,
+    #[clap(long, default_value = "1048576")]
+    repl_backlog_size: usize,
+
+    #[clap(long)]
+    cluster_enabled: bool,
+    #[clap(long, default_value = "15000")]
+    cluster_node_timeout: u64,
+
+    #[clap(long, default_value = "64")]
+    keyspace_shards: usize,
+
+    #[clap(long, default_value = "5000")]
+    busy_reply_threshold: u64,
+
+    // classes of keyspace events to publish, such as "KEA" or "Egx", empty disables them
+    #[clap(long, default_value = "")]
+    notify_keyspace_events: KeyspaceEvents,
+
+    #[clap(long, default_value = "1024")]
+    pubsub_buffer_size: usize,
+    // "hard soft seconds" in messages pending for a subscription, "0 0 0" disables it
+    #[clap(long, default_value = "1024 256 60")]
+    pubsub_output_limit: PubSubOutputLimit,
 }
 
 impl Arg {
@@ -2,11 +2,9 @@
 
 use super::*;
 
-use crate::{db::DB, frame::Frame};
+use crate::{db::DB, frame::Frame, value::BloomFilter};
 
-use marco::Applyer;
-
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct BFAdd {
     key: String,
     value: String,
@@ -33,7 +31,7 @@ impl BFAdd {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct BFExists {
     key: String,
     value: String,
@@ -60,3 +58,37 @@ impl BFExists {
         }
     }
 }
+
+// BF.LOADCHUNK key iterator data
+// restore a filter, the whole of it is in the chunk of iterator 1, as written by the aof rewrite
+#[derive(Debug)]
+pub struct BFLoadChunk {
+    key: String,
+    bloom: BloomFilter,
+}
+
+impl BFLoadChunk {
+    pub fn new(key: String, bloom: BloomFilter) -> Self {
+        Self { key, bloom }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"BF.LOADCHUNK")?;
+        let key = next_string(&mut iter)?;
+        if next_integer(&mut iter)? != 1 {
+            return Err(RedisErr::InvalidArgument);
+        }
+        let bloom =
+            BloomFilter::from_bytes(&next_bytes(&mut iter)?).ok_or(RedisErr::InvalidArgument)?;
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(key, bloom))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        db.bf_load_chunk(self.key, self.bloom);
+        Frame::SimpleString("OK".to_string())
+    }
+}
@@ -0,0 +1,208 @@
+//! Cluster commands
+
+use super::*;
+use crate::connection::AsyncConnection;
+use crate::db::DB;
+use crate::frame::Frame;
+use crate::server::cluster::{key_slot, SetSlot, CLUSTER_SLOTS};
+use crate::Result;
+
+use std::net::IpAddr;
+
+#[derive(Debug)]
+enum ClusterOption {
+    Info,
+    MyId,
+    Nodes,
+    Slots,
+    Shards,
+    KeySlot(Bytes),
+    AddSlots(Vec<u16>),
+    SetSlot(u16, SetSlot),
+    Meet(IpAddr, u16, Option<u16>),
+}
+
+// CLUSTER INFO | MYID | NODES | SLOTS | SHARDS | KEYSLOT key | ADDSLOTS slot [slot ...]
+// | SETSLOT slot IMPORTING|MIGRATING|NODE node-id | SETSLOT slot STABLE | MEET ip port [cport]
+#[derive(Debug)]
+pub struct Cluster {
+    option: ClusterOption,
+}
+
+impl Cluster {
+    fn new(option: ClusterOption) -> Self {
+        Self { option }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"CLUSTER")?;
+        let option = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "INFO" => ClusterOption::Info,
+            "MYID" => ClusterOption::MyId,
+            "NODES" => ClusterOption::Nodes,
+            "SLOTS" => ClusterOption::Slots,
+            "SHARDS" => ClusterOption::Shards,
+            "KEYSLOT" => ClusterOption::KeySlot(next_bytes(&mut iter)?),
+            "ADDSLOTS" => {
+                let mut slots = Vec::new();
+                while iter.len() > 0 {
+                    slots.push(next_slot(&mut iter)?);
+                }
+                if slots.is_empty() {
+                    return Err(RedisErr::WrongNumberOfArguments);
+                }
+                ClusterOption::AddSlots(slots)
+            }
+            "SETSLOT" => {
+                let slot = next_slot(&mut iter)?;
+                let option = match next_string(&mut iter)?.to_uppercase().as_str() {
+                    "MIGRATING" => SetSlot::Migrating(next_string(&mut iter)?),
+                    "IMPORTING" => SetSlot::Importing(next_string(&mut iter)?),
+                    "NODE" => SetSlot::Node(next_string(&mut iter)?),
+                    "STABLE" => SetSlot::Stable,
+                    _ => return Err(RedisErr::SyntaxError),
+                };
+                ClusterOption::SetSlot(slot, option)
+            }
+            "MEET" => {
+                let ip = next_string(&mut iter)?
+                    .parse()
+                    .map_err(|_| RedisErr::InvalidArgument)?;
+                let port = next_port(&mut iter)?;
+                let cport = if iter.len() > 0 {
+                    Some(next_port(&mut iter)?)
+                } else {
+                    None
+                };
+                ClusterOption::Meet(ip, port, cport)
+            }
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(option))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        // the hash slot is the same whether cluster mode is enabled or not
+        if let ClusterOption::KeySlot(key) = &self.option {
+            return Frame::Integer(key_slot(key) as i64);
+        }
+        let Some(cluster) = db.cluster() else {
+            return Frame::Error("ERR This instance has cluster support disabled".to_string());
+        };
+        let ok = |res: std::result::Result<(), String>| match res {
+            Ok(()) => Frame::SimpleString("OK".to_string()),
+            Err(e) => Frame::Error(e),
+        };
+        match self.option {
+            ClusterOption::Info => Frame::BulkString(Bytes::from(cluster.info())),
+            ClusterOption::MyId => Frame::BulkString(Bytes::from(cluster.myself().to_string())),
+            ClusterOption::Nodes => Frame::BulkString(Bytes::from(cluster.nodes())),
+            ClusterOption::Slots => cluster.slots(),
+            ClusterOption::Shards => cluster.shards(),
+            ClusterOption::KeySlot(_) => unreachable!(),
+            ClusterOption::AddSlots(slots) => ok(cluster.add_slots(&slots)),
+            ClusterOption::SetSlot(slot, option) => ok(cluster.set_slot(slot, option)),
+            ClusterOption::Meet(ip, port, cport) => {
+                cluster.meet(ip, port, cport);
+                Frame::SimpleString("OK".to_string())
+            }
+        }
+    }
+}
+
+#[inline]
+fn next_slot(iter: &mut std::vec::IntoIter<Frame>) -> Result<u16> {
+    match next_integer(iter)? {
+        slot if (0..CLUSTER_SLOTS as i64).contains(&slot) => Ok(slot as u16),
+        _ => Err(RedisErr::InvalidArgument),
+    }
+}
+
+#[inline]
+fn next_port(iter: &mut std::vec::IntoIter<Frame>) -> Result<u16> {
+    u16::try_from(next_integer(iter)?).map_err(|_| RedisErr::InvalidArgument)
+}
+
+// ASKING, the next command may access a slot this node is importing
+#[derive(Debug)]
+pub struct Asking {}
+
+impl Asking {
+    fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 1 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        check_cmd(&mut frames.into_iter(), b"ASKING")?;
+        Ok(Self::new())
+    }
+
+    pub fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        if db.cluster().is_none() {
+            return Frame::Error("ERR This instance has cluster support disabled".to_string());
+        }
+        dst.set_asking();
+        Frame::SimpleString("OK".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::helper::command;
+
+    #[tokio::test]
+    async fn test_cluster() {
+        let mut db = DB::new();
+        let cmd = Cluster::from_frames(command(&["cluster", "keyslot", "foo"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(12182));
+        let cmd = Cluster::from_frames(command(&["cluster", "slots"])).unwrap();
+        assert!(matches!(cmd.apply(&mut db), Frame::Error(_)));
+
+        let mut db = DB::new_with_config(Config {
+            port: 7000,
+            cluster_enabled: true,
+            ..Default::default()
+        });
+        let cmd = Cluster::from_frames(command(&["cluster", "addslots", "0", "1", "2"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::SimpleString("OK".to_string()));
+        let cmd = Cluster::from_frames(command(&["cluster", "addslots", "2"])).unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::Error("ERR Slot 2 is already busy".to_string())
+        );
+        assert!(Cluster::from_frames(command(&["cluster", "addslots", "16384"])).is_err());
+
+        let myid = db.cluster().unwrap().myself().to_string();
+        let cmd = Cluster::from_frames(command(&["cluster", "slots"])).unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::Array(vec![Frame::Array(vec![
+                Frame::Integer(0),
+                Frame::Integer(2),
+                Frame::Array(vec![
+                    Frame::BulkString(Bytes::from("127.0.0.1")),
+                    Frame::Integer(7000),
+                    Frame::BulkString(Bytes::from(myid.clone())),
+                ]),
+            ])])
+        );
+        let cmd = Cluster::from_frames(command(&["cluster", "nodes"])).unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::BulkString(Bytes::from(format!(
+                "{} 127.0.0.1:7000@17000 myself,master - 0 0 0 connected 0-2\n",
+                myid
+            )))
+        );
+    }
+}
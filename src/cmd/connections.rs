@@ -1,13 +1,12 @@
 //! Connection related commands
 
 use super::*;
+use crate::connection::AsyncConnection;
 use crate::db::DB;
-use crate::frame::Frame;
+use crate::frame::{Frame, Protocol};
 use crate::Result;
 
-use marco::Applyer;
-
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Quit {}
 
 impl Quit {
@@ -26,3 +25,322 @@ impl Quit {
         Frame::SimpleString("OK".to_string())
     }
 }
+
+// HELLO [protover [AUTH username password] [SETNAME clientname]]
+// switch the protocol of the connection and reply the server information
+#[derive(Debug)]
+pub struct Hello {
+    protover: Option<i64>,
+    auth: Option<(String, String)>,
+    setname: Option<String>,
+}
+
+impl Hello {
+    fn new(protover: Option<i64>, auth: Option<(String, String)>, setname: Option<String>) -> Self {
+        Self {
+            protover,
+            auth,
+            setname,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HELLO")?;
+
+        if iter.len() == 0 {
+            return Ok(Self::new(None, None, None));
+        }
+        let protover = next_string(&mut iter)?
+            .parse::<i64>()
+            .map_err(|_| RedisErr::InvalidArgument)?;
+
+        let (mut auth, mut setname) = (None, None);
+        while iter.len() > 0 {
+            match next_string(&mut iter)?.to_uppercase().as_str() {
+                "AUTH" => {
+                    let username = next_string(&mut iter)?;
+                    let password = next_string(&mut iter)?;
+                    auth = Some((username, password));
+                }
+                "SETNAME" => setname = Some(next_string(&mut iter)?),
+                _ => return Err(RedisErr::SyntaxError),
+            }
+        }
+        Ok(Self::new(Some(protover), auth, setname))
+    }
+
+    pub fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        let protocol = match self.protover {
+            Some(version) => match Protocol::from_version(version) {
+                Some(protocol) => protocol,
+                None => return Frame::Error("NOPROTO unsupported protocol version".to_string()),
+            },
+            None => dst.protocol(),
+        };
+
+        if let Some((username, password)) = self.auth {
+            if let Err(e) = authenticate(db, dst, Some(&username), &password) {
+                return e;
+            }
+        }
+        if !dst.is_authenticated() {
+            return Frame::Error(
+                "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time".to_string(),
+            );
+        }
+        if let Some(name) = self.setname {
+            dst.set_name(Some(name));
+        }
+        dst.set_protocol(protocol);
+
+        Frame::Map(vec![
+            (
+                Frame::BulkString(Bytes::from_static(b"server")),
+                Frame::BulkString(Bytes::from_static(b"redis")),
+            ),
+            (
+                Frame::BulkString(Bytes::from_static(b"version")),
+                Frame::BulkString(Bytes::from_static(env!("CARGO_PKG_VERSION").as_bytes())),
+            ),
+            (
+                Frame::BulkString(Bytes::from_static(b"proto")),
+                Frame::Integer(protocol.version()),
+            ),
+            (
+                Frame::BulkString(Bytes::from_static(b"id")),
+                Frame::Integer(dst.id() as i64),
+            ),
+            (
+                Frame::BulkString(Bytes::from_static(b"mode")),
+                Frame::BulkString(Bytes::from_static(if db.cluster().is_some() {
+                    b"cluster"
+                } else {
+                    b"standalone"
+                })),
+            ),
+            (
+                Frame::BulkString(Bytes::from_static(b"role")),
+                Frame::BulkString(Bytes::from_static(if db.is_replica() {
+                    b"replica"
+                } else {
+                    b"master"
+                })),
+            ),
+            (
+                Frame::BulkString(Bytes::from_static(b"modules")),
+                Frame::Array(vec![]),
+            ),
+        ])
+    }
+} // impl Hello
+
+// AUTH [username] password
+#[derive(Debug)]
+pub struct Auth {
+    username: Option<String>,
+    password: String,
+}
+
+impl Auth {
+    fn new(username: Option<String>, password: String) -> Self {
+        Self { username, password }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"AUTH")?;
+        match iter.len() {
+            1 => Ok(Self::new(None, next_string(&mut iter)?)),
+            2 => {
+                let username = next_string(&mut iter)?;
+                let password = next_string(&mut iter)?;
+                Ok(Self::new(Some(username), password))
+            }
+            _ => Err(RedisErr::WrongNumberOfArguments),
+        }
+    }
+
+    pub fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        match authenticate(db, dst, self.username.as_deref(), &self.password) {
+            Ok(()) => Frame::SimpleString("OK".to_string()),
+            Err(e) => e,
+        }
+    }
+} // impl Auth
+
+// only the default user is supported, its password is the `requirepass`
+fn authenticate(
+    db: &DB,
+    dst: &mut AsyncConnection,
+    username: Option<&str>,
+    password: &str,
+) -> std::result::Result<(), Frame> {
+    let requirepass = match &db.config().requirepass {
+        Some(requirepass) => requirepass,
+        None if username.is_none() => {
+            return Err(Frame::Error(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".to_string(),
+            ))
+        }
+        // any password is accepted by the default user without requirepass
+        None => "",
+    };
+    let username = username.unwrap_or("default");
+    if username == "default" && (requirepass.is_empty() || requirepass == password) {
+        dst.set_authenticated(true);
+        Ok(())
+    } else {
+        Err(Frame::Error(
+            "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+        ))
+    }
+}
+
+#[derive(Debug)]
+enum ClientOption {
+    Id,
+    GetName,
+    SetName(String),
+}
+
+// CLIENT ID | GETNAME | SETNAME connection-name
+#[derive(Debug)]
+pub struct Client {
+    option: ClientOption,
+}
+
+impl Client {
+    fn new(option: ClientOption) -> Self {
+        Self { option }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"CLIENT")?;
+        let option = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "ID" => ClientOption::Id,
+            "GETNAME" => ClientOption::GetName,
+            "SETNAME" => {
+                let name = next_string(&mut iter)?;
+                // the name is shown in the client list, so no spaces
+                if name.contains(' ') {
+                    return Err(RedisErr::InvalidArgument);
+                }
+                ClientOption::SetName(name)
+            }
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(option))
+    }
+
+    pub fn apply(self, _db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        match self.option {
+            ClientOption::Id => Frame::Integer(dst.id() as i64),
+            ClientOption::GetName => match dst.name() {
+                Some(name) => Frame::BulkString(Bytes::from(name.to_string())),
+                None => Frame::Nil,
+            },
+            ClientOption::SetName(name) => {
+                // an empty name removes the name
+                dst.set_name(if name.is_empty() { None } else { Some(name) });
+                Frame::SimpleString("OK".to_string())
+            }
+        }
+    }
+} // impl Client
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::config::Config;
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn connection() -> AsyncConnection {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        AsyncConnection::new(stream)
+    }
+
+    #[tokio::test]
+    async fn test_hello() {
+        let mut db = DB::new();
+        let mut conn = connection().await;
+
+        let cmd = Hello::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"hello")),
+            Frame::BulkString(Bytes::from_static(b"3")),
+            Frame::BulkString(Bytes::from_static(b"setname")),
+            Frame::BulkString(Bytes::from_static(b"worker")),
+        ])
+        .unwrap();
+        let result = cmd.apply(&mut db, &mut conn);
+        match result {
+            Frame::Map(m) => assert!(m.contains(&(
+                Frame::BulkString(Bytes::from_static(b"proto")),
+                Frame::Integer(3)
+            ))),
+            _ => panic!("unexpected hello reply {:?}", result),
+        }
+        assert_eq!(conn.protocol(), Protocol::Resp3);
+        assert_eq!(conn.name(), Some("worker"));
+
+        let cmd = Hello::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"hello")),
+            Frame::BulkString(Bytes::from_static(b"4")),
+        ])
+        .unwrap();
+        let result = cmd.apply(&mut db, &mut conn);
+        assert_eq!(
+            result,
+            Frame::Error("NOPROTO unsupported protocol version".to_string())
+        );
+        assert_eq!(conn.protocol(), Protocol::Resp3);
+    }
+
+    #[tokio::test]
+    async fn test_auth() {
+        let mut db = DB::new_with_config(Config {
+            requirepass: Some("secret".to_string()),
+            ..Default::default()
+        });
+        let mut conn = connection().await;
+        conn.set_authenticated(false);
+
+        let cmd = Hello::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"hello")),
+            Frame::BulkString(Bytes::from_static(b"3")),
+        ])
+        .unwrap();
+        assert!(matches!(cmd.apply(&mut db, &mut conn), Frame::Error(_)));
+        assert_eq!(conn.protocol(), Protocol::Resp2);
+
+        let cmd = Auth::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"auth")),
+            Frame::BulkString(Bytes::from_static(b"wrong")),
+        ])
+        .unwrap();
+        assert!(matches!(cmd.apply(&mut db, &mut conn), Frame::Error(_)));
+        assert!(!conn.is_authenticated());
+
+        let cmd = Hello::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"hello")),
+            Frame::BulkString(Bytes::from_static(b"3")),
+            Frame::BulkString(Bytes::from_static(b"auth")),
+            Frame::BulkString(Bytes::from_static(b"default")),
+            Frame::BulkString(Bytes::from_static(b"secret")),
+        ])
+        .unwrap();
+        assert!(matches!(cmd.apply(&mut db, &mut conn), Frame::Map(_)));
+        assert!(conn.is_authenticated());
+        assert_eq!(conn.protocol(), Protocol::Resp3);
+    }
+}
@@ -6,11 +6,9 @@ use crate::db::DB;
 use crate::frame::Frame;
 use crate::{RedisErr, Result};
 
-use marco::Applyer;
-
 use bytes::Bytes;
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Ping {
     message: Option<Bytes>,
 }
@@ -43,7 +41,7 @@ impl Ping {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Flush {}
 
 impl Flush {
@@ -65,18 +63,320 @@ impl Flush {
     }
 }
 
+// SAVE, write the rdb snapshot synchronously
+#[derive(Debug)]
+pub struct Save {}
+
+impl Save {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 1 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        check_cmd(&mut frames.into_iter(), b"SAVE")?;
+        Ok(Self::new())
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.save() {
+            Ok(()) => Frame::SimpleString("OK".to_string()),
+            Err(RedisErr::SaveInProgress) => {
+                Frame::Error("ERR Background save already in progress".to_string())
+            }
+            Err(e) => Frame::Error(format!("ERR {}", e)),
+        }
+    }
+}
+
+// BGSAVE, snapshot the key space and write it in background
+#[derive(Debug)]
+pub struct BgSave {}
+
+impl BgSave {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"BGSAVE")?;
+        // SCHEDULE is accepted for compatibility, the save always starts immediately
+        if iter.len() > 0 && !next_string(&mut iter)?.eq_ignore_ascii_case("SCHEDULE") {
+            return Err(RedisErr::SyntaxError);
+        }
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new())
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.bgsave() {
+            Ok(()) => Frame::SimpleString("Background saving started".to_string()),
+            Err(RedisErr::SaveInProgress) => {
+                Frame::Error("ERR Background save already in progress".to_string())
+            }
+            Err(e) => Frame::Error(format!("ERR {}", e)),
+        }
+    }
+}
+
+// BGREWRITEAOF, compact the append only file in the background
+#[derive(Debug)]
+pub struct BgRewriteAof {}
+
+impl BgRewriteAof {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 1 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        check_cmd(&mut frames.into_iter(), b"BGREWRITEAOF")?;
+        Ok(Self::new())
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.bgrewriteaof() {
+            Ok(()) => {
+                Frame::SimpleString("Background append only file rewriting started".to_string())
+            }
+            Err(RedisErr::RewriteInProgress) => Frame::Error(
+                "ERR Background append only file rewriting already in progress".to_string(),
+            ),
+            Err(RedisErr::AOFDisabled) => {
+                Frame::Error("ERR Append only file is disabled".to_string())
+            }
+            Err(e) => Frame::Error(format!("ERR {}", e)),
+        }
+    }
+}
+
+// LASTSAVE, unix time of the last successful save
+#[derive(Debug)]
+pub struct LastSave {}
+
+impl LastSave {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 1 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        check_cmd(&mut frames.into_iter(), b"LASTSAVE")?;
+        Ok(Self::new())
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        Frame::Integer(db.lastsave() as i64)
+    }
+}
+
+// INFO [section], only the server, stats, replication and cluster sections are kept
+#[derive(Debug)]
+pub struct Info {
+    section: Option<String>,
+}
+
+impl Info {
+    pub fn new(section: Option<String>) -> Self {
+        Self { section }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() > 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"INFO")?;
+        let section = if iter.len() == 1 {
+            Some(next_string(&mut iter)?.to_lowercase())
+        } else {
+            None
+        };
+        Ok(Self::new(section))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let all = matches!(self.section.as_deref(), None | Some("all" | "default"));
+        let mut sections = Vec::new();
+        if all || self.section.as_deref() == Some("server") {
+            sections.push(format!(
+                "# Server\r\nredis_version:{}\r\nprocess_id:{}\r\ntcp_port:{}\r\n",
+                env!("CARGO_PKG_VERSION"),
+                std::process::id(),
+                db.config().port
+            ));
+        }
+        if all || self.section.as_deref() == Some("stats") {
+            sections.push(format!(
+                "# Stats\r\npubsub_lagged_messages:{}\r\nclient_output_buffer_limit_disconnections:{}\r\n",
+                db.pubsub_lagged_messages(),
+                db.pubsub_disconnections()
+            ));
+        }
+        if all || self.section.as_deref() == Some("replication") {
+            sections.push(db.replication().info());
+        }
+        if all || self.section.as_deref() == Some("cluster") {
+            sections.push(format!(
+                "# Cluster\r\ncluster_enabled:{}\r\n",
+                db.cluster().is_some() as u8
+            ));
+        }
+        Frame::BulkString(Bytes::from(sections.join("\r\n")))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
-    #[test]
-    fn test_flush() {
+    #[tokio::test]
+    async fn test_flush() {
         let mut db = DB::new();
         let cmd = Flush::from_frames(vec![Frame::BulkString(Bytes::from_static(b"flush"))]);
-        assert_eq!(cmd.is_ok(), true);
+        assert!(cmd.is_ok());
         let cmd: Flush = cmd.unwrap();
 
         let result = cmd.apply(&mut db);
         assert_eq!(result, Frame::SimpleString("OK".to_string()));
     }
+
+    fn temp_config(name: &str) -> crate::config::Config {
+        let dir = std::env::temp_dir().join(format!("redis-rs-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        crate::config::Config {
+            dir: dir.to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_info() {
+        let mut db = DB::new();
+        let cmd = Info::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"info")),
+            Frame::BulkString(Bytes::from_static(b"replication")),
+        ])
+        .unwrap();
+        match cmd.apply(&mut db) {
+            Frame::BulkString(info) => {
+                let info = String::from_utf8(info.to_vec()).unwrap();
+                assert!(info.starts_with("# Replication\r\n"));
+                assert!(info.contains("role:master\r\n"));
+                assert!(!info.contains("# Server"));
+            }
+            frame => panic!("unexpected info reply {:?}", frame),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save() {
+        let config = temp_config("save");
+        let path = config.rdb_path();
+        let mut db = DB::new_with_config(config);
+        db.set(
+            "key".to_string(),
+            Bytes::from_static(b"value"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+
+        let cmd = Save::from_frames(vec![Frame::BulkString(Bytes::from_static(b"save"))]).unwrap();
+        let result = cmd.apply(&mut db);
+        assert_eq!(result, Frame::SimpleString("OK".to_string()));
+
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(b"REDIS0009"));
+        assert!(data.windows(5).any(|w| w == b"value"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bgsave() {
+        let config = temp_config("bgsave");
+        let path = config.rdb_path();
+        let mut db = DB::new_with_config(config);
+
+        let cmd =
+            BgSave::from_frames(vec![Frame::BulkString(Bytes::from_static(b"bgsave"))]).unwrap();
+        let result = cmd.apply(&mut db);
+        assert_eq!(
+            result,
+            Frame::SimpleString("Background saving started".to_string())
+        );
+
+        // wait for the background save
+        for _ in 0..100 {
+            if path.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(b"REDIS0009"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bgrewriteaof() {
+        let mut config = temp_config("bgrewriteaof");
+        config.appendonly = true;
+        let path = config.aof_path();
+        let _ = std::fs::remove_file(&path);
+        let mut db = DB::new_with_config(config);
+        db.open_aof().unwrap();
+
+        // the same key written many times
+        for i in 0..10 {
+            let value = Bytes::from(i.to_string());
+            db.set("key".to_string(), value, false, false, false, false, None)
+                .unwrap();
+            db.propagate(Frame::Array(vec![
+                Frame::BulkString(Bytes::from_static(b"set")),
+                Frame::BulkString(Bytes::from_static(b"key")),
+                Frame::BulkString(Bytes::from(i.to_string())),
+            ]));
+        }
+        let before = std::fs::metadata(&path).unwrap().len();
+
+        let cmd =
+            BgRewriteAof::from_frames(vec![Frame::BulkString(Bytes::from_static(b"bgrewriteaof"))])
+                .unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::SimpleString("Background append only file rewriting started".to_string())
+        );
+
+        // wait for the background rewrite
+        for _ in 0..100 {
+            if std::fs::metadata(&path).unwrap().len() < before {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data, b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$1\r\n9\r\n");
+
+        // the new file is appended to
+        db.propagate(Frame::Array(vec![
+            Frame::BulkString(Bytes::from_static(b"del")),
+            Frame::BulkString(Bytes::from_static(b"key")),
+        ]));
+        assert!(std::fs::read(&path).unwrap().ends_with(b"$3\r\nkey\r\n"));
+        assert!(std::fs::read(&path).unwrap().len() > data.len());
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -6,11 +6,9 @@ use crate::db::DB;
 use crate::frame::Frame;
 use crate::{RedisErr, Result};
 
-use marco::Applyer;
-
 use bytes::Bytes;
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct HSet {
     key: String,
     field_values: Vec<(String, Bytes)>,
@@ -51,7 +49,7 @@ impl HSet {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct HGet {
     key: String,
     field: String,
@@ -88,12 +86,466 @@ impl HGet {
     }
 }
 
+// HDEL key field [field ...]
+#[derive(Debug)]
+pub struct HDel {
+    key: String,
+    fields: Vec<String>,
+}
+
+impl HDel {
+    fn new(key: String, fields: Vec<String>) -> Self {
+        Self { key, fields }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HDEL")?;
+        let key = next_string(&mut iter)?;
+        let fields = next_fields(&mut iter)?;
+        Ok(Self::new(key, fields))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.hdel(&self.key, &self.fields) {
+            Ok(removed) => Frame::Integer(removed as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HGETALL key
+#[derive(Debug)]
+pub struct HGetAll {
+    key: String,
+}
+
+impl HGetAll {
+    fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HGETALL")?;
+        let key = next_string(&mut iter)?;
+        Ok(Self::new(key))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let result = db.hash(&self.key, |map| {
+            map.iter()
+                .map(|(field, value)| {
+                    (
+                        Frame::BulkString(Bytes::from(field.clone())),
+                        Frame::BulkString(value.clone()),
+                    )
+                })
+                .collect()
+        });
+        match result {
+            Ok(pairs) => Frame::Map(pairs.unwrap_or_default()),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HKEYS key
+#[derive(Debug)]
+pub struct HKeys {
+    key: String,
+}
+
+impl HKeys {
+    fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HKEYS")?;
+        let key = next_string(&mut iter)?;
+        Ok(Self::new(key))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let result = db.hash(&self.key, |map| {
+            map.keys()
+                .map(|field| Frame::BulkString(Bytes::from(field.clone())))
+                .collect()
+        });
+        match result {
+            Ok(fields) => Frame::Array(fields.unwrap_or_default()),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HVALS key
+#[derive(Debug)]
+pub struct HVals {
+    key: String,
+}
+
+impl HVals {
+    fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HVALS")?;
+        let key = next_string(&mut iter)?;
+        Ok(Self::new(key))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let result = db.hash(&self.key, |map| {
+            map.values().cloned().map(Frame::BulkString).collect()
+        });
+        match result {
+            Ok(values) => Frame::Array(values.unwrap_or_default()),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HLEN key
+#[derive(Debug)]
+pub struct HLen {
+    key: String,
+}
+
+impl HLen {
+    fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HLEN")?;
+        let key = next_string(&mut iter)?;
+        Ok(Self::new(key))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.hash(&self.key, |map| map.len()) {
+            Ok(len) => Frame::Integer(len.unwrap_or(0) as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HEXISTS key field
+#[derive(Debug)]
+pub struct HExists {
+    key: String,
+    field: String,
+}
+
+impl HExists {
+    fn new(key: String, field: String) -> Self {
+        Self { key, field }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HEXISTS")?;
+        let key = next_string(&mut iter)?;
+        let field = next_string(&mut iter)?;
+        Ok(Self::new(key, field))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.hash(&self.key, |map| map.contains_key(&self.field)) {
+            Ok(exists) => Frame::Integer(exists.unwrap_or(false) as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HMGET key field [field ...]
+#[derive(Debug)]
+pub struct HMGet {
+    key: String,
+    fields: Vec<String>,
+}
+
+impl HMGet {
+    fn new(key: String, fields: Vec<String>) -> Self {
+        Self { key, fields }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HMGET")?;
+        let key = next_string(&mut iter)?;
+        let fields = next_fields(&mut iter)?;
+        Ok(Self::new(key, fields))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let result = db.hash(&self.key, |map| {
+            self.fields
+                .iter()
+                .map(|field| {
+                    map.get(field)
+                        .cloned()
+                        .map_or(Frame::Nil, Frame::BulkString)
+                })
+                .collect()
+        });
+        match result {
+            Ok(values) => {
+                Frame::Array(values.unwrap_or_else(|| vec![Frame::Nil; self.fields.len()]))
+            }
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HSETNX key field value
+#[derive(Debug)]
+pub struct HSetNX {
+    key: String,
+    field: String,
+    value: Bytes,
+}
+
+impl HSetNX {
+    fn new(key: String, field: String, value: Bytes) -> Self {
+        Self { key, field, value }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 4 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HSETNX")?;
+        let key = next_string(&mut iter)?;
+        let field = next_string(&mut iter)?;
+        let value = next_bytes(&mut iter)?;
+        Ok(Self::new(key, field, value))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.hsetnx(&self.key, self.field, self.value) {
+            Ok(set) => Frame::Integer(set as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HINCRBY key field increment
+#[derive(Debug)]
+pub struct HIncrBy {
+    key: String,
+    field: String,
+    increment: i64,
+}
+
+impl HIncrBy {
+    fn new(key: String, field: String, increment: i64) -> Self {
+        Self {
+            key,
+            field,
+            increment,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 4 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HINCRBY")?;
+        let key = next_string(&mut iter)?;
+        let field = next_string(&mut iter)?;
+        let increment = next_integer(&mut iter)?;
+        Ok(Self::new(key, field, increment))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.hincrby(&self.key, &self.field, self.increment) {
+            Ok(value) => Frame::Integer(value),
+            Err(RedisErr::InvalidArgument) => {
+                Frame::Error("ERR hash value is not an integer".to_string())
+            }
+            Err(RedisErr::Overflow) => {
+                Frame::Error("ERR increment or decrement would overflow".to_string())
+            }
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HINCRBYFLOAT key field increment
+#[derive(Debug)]
+pub struct HIncrByFloat {
+    key: String,
+    field: String,
+    increment: f64,
+}
+
+impl HIncrByFloat {
+    fn new(key: String, field: String, increment: f64) -> Self {
+        Self {
+            key,
+            field,
+            increment,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 4 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HINCRBYFLOAT")?;
+        let key = next_string(&mut iter)?;
+        let field = next_string(&mut iter)?;
+        let increment = next_float(&mut iter)?;
+        if !increment.is_finite() {
+            return Err(RedisErr::InvalidArgument);
+        }
+        Ok(Self::new(key, field, increment))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.hincrbyfloat(&self.key, &self.field, self.increment) {
+            Ok(value) => Frame::BulkString(Bytes::from(value.to_string())),
+            Err(RedisErr::InvalidArgument) => {
+                Frame::Error("ERR hash value is not a float".to_string())
+            }
+            Err(RedisErr::Overflow) => {
+                Frame::Error("ERR increment would produce NaN or Infinity".to_string())
+            }
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HSTRLEN key field
+#[derive(Debug)]
+pub struct HStrLen {
+    key: String,
+    field: String,
+}
+
+impl HStrLen {
+    fn new(key: String, field: String) -> Self {
+        Self { key, field }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HSTRLEN")?;
+        let key = next_string(&mut iter)?;
+        let field = next_string(&mut iter)?;
+        Ok(Self::new(key, field))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.hash(&self.key, |map| map.get(&self.field).map_or(0, |v| v.len())) {
+            Ok(len) => Frame::Integer(len.unwrap_or(0) as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// HRANDFIELD key [count [WITHVALUES]]
+// without count a single field is replied, nil if the key doesn't exist
+#[derive(Debug)]
+pub struct HRandField {
+    key: String,
+    count: Option<i64>,
+    with_values: bool,
+}
+
+impl HRandField {
+    fn new(key: String, count: Option<i64>, with_values: bool) -> Self {
+        Self {
+            key,
+            count,
+            with_values,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"HRANDFIELD")?;
+        let key = next_string(&mut iter)?;
+        let count = match iter.len() {
+            0 => None,
+            _ => Some(next_integer(&mut iter)?),
+        };
+        let with_values = match iter.len() {
+            0 => false,
+            1 if next_string(&mut iter)?.eq_ignore_ascii_case("WITHVALUES") => true,
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        Ok(Self::new(key, count, with_values))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let fields = match db.hrandfield(&self.key, self.count.unwrap_or(1)) {
+            Ok(fields) => fields,
+            Err(e) => return error_frame(e),
+        };
+        if self.count.is_none() {
+            return fields.into_iter().next().map_or(Frame::Nil, |(field, _)| {
+                Frame::BulkString(Bytes::from(field))
+            });
+        }
+        let mut frames = Vec::new();
+        for (field, value) in fields {
+            frames.push(Frame::BulkString(Bytes::from(field)));
+            if self.with_values {
+                frames.push(Frame::BulkString(value));
+            }
+        }
+        Frame::Array(frames)
+    }
+}
+
+// field [field ...]
+fn next_fields(iter: &mut std::vec::IntoIter<Frame>) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    while iter.len() > 0 {
+        fields.push(next_string(iter)?);
+    }
+    if fields.is_empty() {
+        return Err(RedisErr::WrongNumberOfArguments);
+    }
+    Ok(fields)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::helper::command;
 
-    #[test]
-    fn test_hset() {
+    #[tokio::test]
+    async fn test_hset() {
         let mut db = DB::new();
         let cmd = HSet::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"hset")),
@@ -106,8 +558,8 @@ mod test {
         assert_eq!(result, Frame::Integer(1));
     }
 
-    #[test]
-    fn test_hget() {
+    #[tokio::test]
+    async fn test_hget() {
         let mut db = DB::new();
         let cmd = HGet::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"hget")),
@@ -118,4 +570,81 @@ mod test {
         let result = cmd.apply(&mut db);
         assert_eq!(result, Frame::Nil);
     }
+
+    #[tokio::test]
+    async fn test_hset_hdel() {
+        let mut db = DB::new();
+        let cmd = HSet::from_frames(command(&["hset", "key", "a", "1", "b", "2"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(2));
+        // only the new fields are counted
+        let cmd = HSet::from_frames(command(&["hset", "key", "a", "3", "c", "4"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(1));
+        let cmd = HSetNX::from_frames(command(&["hsetnx", "key", "a", "5"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(0));
+        let cmd = HMGet::from_frames(command(&["hmget", "key", "a", "x"])).unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::Array(vec![Frame::BulkString(Bytes::from("3")), Frame::Nil])
+        );
+        let cmd = HLen::from_frames(command(&["hlen", "key"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(3));
+
+        let cmd = HDel::from_frames(command(&["hdel", "key", "a", "b", "x"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(2));
+        let cmd = HGetAll::from_frames(command(&["hgetall", "key"])).unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::Map(vec![(
+                Frame::BulkString(Bytes::from("c")),
+                Frame::BulkString(Bytes::from("4"))
+            )])
+        );
+        // the emptied hash is deleted
+        let cmd = HDel::from_frames(command(&["hdel", "key", "c"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(1));
+        assert!(db.snapshot().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_hincrby() {
+        let mut db = DB::new();
+        let cmd = HIncrBy::from_frames(command(&["hincrby", "key", "n", "5"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(5));
+        let cmd = HIncrBy::from_frames(command(&["hincrby", "key", "n", "-7"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(-2));
+        let cmd =
+            HIncrBy::from_frames(command(&["hincrby", "key", "n", &i64::MIN.to_string()])).unwrap();
+        assert!(matches!(cmd.apply(&mut db), Frame::Error(_)));
+
+        let cmd = HIncrByFloat::from_frames(command(&["hincrbyfloat", "key", "n", "0.5"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::BulkString(Bytes::from("-1.5")));
+        let cmd = HIncrBy::from_frames(command(&["hincrby", "key", "n", "1"])).unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::Error("ERR hash value is not an integer".to_string())
+        );
+        let cmd = HStrLen::from_frames(command(&["hstrlen", "key", "n"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(4));
+    }
+
+    #[tokio::test]
+    async fn test_hrandfield() {
+        let mut db = DB::new();
+        let cmd = HRandField::from_frames(command(&["hrandfield", "key"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Nil);
+        HSet::from_frames(command(&["hset", "key", "a", "1", "b", "2"]))
+            .unwrap()
+            .apply(&mut db);
+        let cmd =
+            HRandField::from_frames(command(&["hrandfield", "key", "5", "withvalues"])).unwrap();
+        let Frame::Array(frames) = cmd.apply(&mut db) else {
+            panic!("hrandfield doesn't reply an array");
+        };
+        assert_eq!(frames.len(), 4);
+        let cmd = HRandField::from_frames(command(&["hrandfield", "key", "-5"])).unwrap();
+        let Frame::Array(frames) = cmd.apply(&mut db) else {
+            panic!("hrandfield doesn't reply an array");
+        };
+        assert_eq!(frames.len(), 5);
+    }
 }
@@ -6,11 +6,9 @@ use crate::frame::Frame;
 use crate::Result;
 use crate::{db::DB, RedisErr};
 
-use marco::Applyer;
-
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Get {
     key: String,
 }
@@ -41,7 +39,7 @@ impl Get {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct MGet {
     key: Vec<String>,
 }
@@ -66,8 +64,8 @@ impl MGet {
 
     pub fn apply(self, db: &mut DB) -> Frame {
         let mut result = Vec::new();
-        for k in self.key {
-            match db.get(&k) {
+        for value in db.mget(&self.key) {
+            match value {
                 Ok(value) => result.push(Frame::BulkString(value)),
                 Err(e) => match e {
                     RedisErr::KeyNotFound => result.push(Frame::Nil),
@@ -83,7 +81,7 @@ impl MGet {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Set {
     key: String,
     value: Bytes,
@@ -96,6 +94,7 @@ pub struct Set {
 }
 
 impl Set {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         key: String,
         value: Bytes,
@@ -142,19 +141,14 @@ impl Set {
                     ex = Some(Duration::from_millis(next_integer(&mut iter)? as u64));
                 }
                 "EXAT" => {
+                    // a time in the past expires the key at once
                     let exat_ts = UNIX_EPOCH + Duration::from_secs(next_integer(&mut iter)? as u64);
-                    if exat_ts < now {
-                        return Err(RedisErr::SyntaxError);
-                    }
-                    exat = Some(Instant::now() + exat_ts.duration_since(now).unwrap());
+                    exat = Some(Instant::now() + exat_ts.duration_since(now).unwrap_or_default());
                 }
                 "PXAT" => {
                     let exat_ts =
                         UNIX_EPOCH + Duration::from_millis(next_integer(&mut iter)? as u64);
-                    if exat_ts < now {
-                        return Err(RedisErr::SyntaxError);
-                    }
-                    exat = Some(Instant::now() + exat_ts.duration_since(now).unwrap());
+                    exat = Some(Instant::now() + exat_ts.duration_since(now).unwrap_or_default());
                 }
                 _ => {
                     return Err(RedisErr::SyntaxError);
@@ -204,7 +198,7 @@ impl Set {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct MSet {
     pairs: Vec<(String, Bytes)>,
 }
@@ -231,15 +225,7 @@ impl MSet {
     }
 
     pub fn apply(self, db: &mut DB) -> Frame {
-        for (key, value) in self.pairs {
-            match db.set(key, value, false, false, false, false, None) {
-                Ok(_) => {}
-                Err(e) => match e {
-                    RedisErr::OutOfMemory => return Frame::Error("Out of memory".to_string()),
-                    _ => unreachable!("unexpect set error: {:?}", e),
-                },
-            }
-        }
+        db.mset(self.pairs);
         Frame::SimpleString("Ok".to_string())
     }
 }
@@ -248,8 +234,8 @@ impl MSet {
 mod test {
     use super::*;
 
-    #[test]
-    fn test_get() {
+    #[tokio::test]
+    async fn test_get() {
         let mut db = DB::new();
         let cmd = Get::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"get")),
@@ -260,8 +246,8 @@ mod test {
         assert_eq!(result, Frame::Nil);
     }
 
-    #[test]
-    fn test_mget() {
+    #[tokio::test]
+    async fn test_mget() {
         let mut db = DB::new();
         let cmd = MGet::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"mget")),
@@ -274,8 +260,8 @@ mod test {
         assert_eq!(result, Frame::Array(vec![Frame::Nil, Frame::Nil]));
     }
 
-    #[test]
-    fn test_mset() {
+    #[tokio::test]
+    async fn test_mset() {
         let mut db = DB::new();
         let cmd = MSet::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"mset")),
@@ -290,8 +276,8 @@ mod test {
         assert_eq!(result, Frame::SimpleString("Ok".to_string()));
     }
 
-    #[test]
-    fn test_set() {
+    #[tokio::test]
+    async fn test_set() {
         let mut db = DB::new();
         let cmd = Set::from_frames(vec![
             Frame::SimpleString("set".to_string()),
@@ -2,13 +2,13 @@
 
 use super::*;
 
-use crate::db::DB;
+use crate::db::{ListSide, DB};
 use crate::frame::Frame;
 use crate::{RedisErr, Result};
 
-use marco::Applyer;
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct LPush {
     key: String,
     values: Vec<Bytes>,
@@ -28,6 +28,9 @@ impl LPush {
         while iter.len() > 0 {
             value.push(next_bytes(&mut iter)?);
         }
+        if value.is_empty() {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
         Ok(Self::new(key, value))
     }
 
@@ -45,7 +48,46 @@ impl LPush {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
+pub struct RPush {
+    key: String,
+    values: Vec<Bytes>,
+}
+
+impl RPush {
+    fn new(key: String, values: Vec<Bytes>) -> Self {
+        Self { key, values }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"RPUSH")?;
+
+        let key = next_string(&mut iter)?; // key
+        let mut value = Vec::new();
+        while iter.len() > 0 {
+            value.push(next_bytes(&mut iter)?);
+        }
+        if value.is_empty() {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(key, value))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.rpush(&self.key, self.values) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => match e {
+                RedisErr::WrongType => Frame::Error(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                ),
+                _ => unreachable!("unexpect rpush error: {:?}", e),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct LRange {
     key: String,
     start: i64,
@@ -85,12 +127,751 @@ impl LRange {
     }
 }
 
+// LPUSHX key value [value ...]
+// RPUSHX key value [value ...]
+// push only if the list exists
+#[derive(Debug)]
+pub struct LPushX {
+    key: String,
+    values: Vec<Bytes>,
+    side: ListSide,
+}
+
+impl LPushX {
+    fn new(key: String, values: Vec<Bytes>, side: ListSide) -> Self {
+        Self { key, values, side }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        let side = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "LPUSHX" => ListSide::Left,
+            "RPUSHX" => ListSide::Right,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let key = next_string(&mut iter)?;
+        let mut values = Vec::new();
+        while iter.len() > 0 {
+            values.push(next_bytes(&mut iter)?);
+        }
+        if values.is_empty() {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(key, values, side))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.push(&self.key, self.values, self.side, true) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// LPOP key [count]
+// RPOP key [count]
+// without count a single value is replied
+#[derive(Debug)]
+pub struct LPop {
+    key: String,
+    side: ListSide,
+    count: Option<usize>,
+}
+
+impl LPop {
+    fn new(key: String, side: ListSide, count: Option<usize>) -> Self {
+        Self { key, side, count }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        let side = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "LPOP" => ListSide::Left,
+            "RPOP" => ListSide::Right,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let key = next_string(&mut iter)?;
+        let count = match iter.len() {
+            0 => None,
+            1 => Some(next_count(&mut iter)?),
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        Ok(Self::new(key, side, count))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match (
+            db.pop(&self.key, self.side, self.count.unwrap_or(1)),
+            self.count,
+        ) {
+            (Ok(None), _) => Frame::Nil,
+            (Ok(Some(values)), Some(_)) => make_values_frame(values),
+            (Ok(Some(values)), None) => values
+                .into_iter()
+                .next()
+                .map_or(Frame::Nil, Frame::BulkString),
+            (Err(e), _) => error_frame(e),
+        }
+    }
+}
+
+// LLEN key
+#[derive(Debug)]
+pub struct LLen {
+    key: String,
+}
+
+impl LLen {
+    fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LLEN")?;
+        let key = next_string(&mut iter)?;
+        Ok(Self::new(key))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.llen(&self.key) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// LINDEX key index
+#[derive(Debug)]
+pub struct LIndex {
+    key: String,
+    index: i64,
+}
+
+impl LIndex {
+    fn new(key: String, index: i64) -> Self {
+        Self { key, index }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LINDEX")?;
+        let key = next_string(&mut iter)?;
+        let index = next_integer(&mut iter)?;
+        Ok(Self::new(key, index))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.lindex(&self.key, self.index) {
+            Ok(value) => value.map_or(Frame::Nil, Frame::BulkString),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// LSET key index value
+#[derive(Debug)]
+pub struct LSet {
+    key: String,
+    index: i64,
+    value: Bytes,
+}
+
+impl LSet {
+    fn new(key: String, index: i64, value: Bytes) -> Self {
+        Self { key, index, value }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 4 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LSET")?;
+        let key = next_string(&mut iter)?;
+        let index = next_integer(&mut iter)?;
+        let value = next_bytes(&mut iter)?;
+        Ok(Self::new(key, index, value))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.lset(&self.key, self.index, self.value) {
+            Ok(()) => Frame::SimpleString("OK".to_string()),
+            Err(RedisErr::KeyNotFound) => Frame::Error("ERR no such key".to_string()),
+            Err(RedisErr::InvalidArgument) => Frame::Error("ERR index out of range".to_string()),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// LINSERT key BEFORE|AFTER pivot element
+// -1 if the pivot is not found, 0 if the key doesn't exist
+#[derive(Debug)]
+pub struct LInsert {
+    key: String,
+    before: bool,
+    pivot: Bytes,
+    value: Bytes,
+}
+
+impl LInsert {
+    fn new(key: String, before: bool, pivot: Bytes, value: Bytes) -> Self {
+        Self {
+            key,
+            before,
+            pivot,
+            value,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 5 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LINSERT")?;
+        let key = next_string(&mut iter)?;
+        let before = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "BEFORE" => true,
+            "AFTER" => false,
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        let pivot = next_bytes(&mut iter)?;
+        let value = next_bytes(&mut iter)?;
+        Ok(Self::new(key, before, pivot, value))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.linsert(&self.key, self.before, &self.pivot, self.value) {
+            Ok(Some(len)) => Frame::Integer(len as i64),
+            Ok(None) => Frame::Integer(-1),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// LREM key count element
+#[derive(Debug)]
+pub struct LRem {
+    key: String,
+    count: i64,
+    value: Bytes,
+}
+
+impl LRem {
+    fn new(key: String, count: i64, value: Bytes) -> Self {
+        Self { key, count, value }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 4 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LREM")?;
+        let key = next_string(&mut iter)?;
+        let count = next_integer(&mut iter)?;
+        let value = next_bytes(&mut iter)?;
+        Ok(Self::new(key, count, value))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.lrem(&self.key, self.count, &self.value) {
+            Ok(removed) => Frame::Integer(removed as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// LTRIM key start stop
+#[derive(Debug)]
+pub struct LTrim {
+    key: String,
+    start: i64,
+    stop: i64,
+}
+
+impl LTrim {
+    fn new(key: String, start: i64, stop: i64) -> Self {
+        Self { key, start, stop }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 4 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LTRIM")?;
+        let key = next_string(&mut iter)?;
+        let start = next_integer(&mut iter)?;
+        let stop = next_integer(&mut iter)?;
+        Ok(Self::new(key, start, stop))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.ltrim(&self.key, self.start, self.stop) {
+            Ok(()) => Frame::SimpleString("OK".to_string()),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+// without COUNT the index of the first match is replied, nil if there is none
+#[derive(Debug)]
+pub struct LPos {
+    key: String,
+    value: Bytes,
+    rank: i64,
+    count: Option<usize>,
+    maxlen: usize,
+}
+
+impl LPos {
+    fn new(key: String, value: Bytes, rank: i64, count: Option<usize>, maxlen: usize) -> Self {
+        Self {
+            key,
+            value,
+            rank,
+            count,
+            maxlen,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LPOS")?;
+        let key = next_string(&mut iter)?;
+        let value = next_bytes(&mut iter)?;
+        let mut rank = 1;
+        let mut count = None;
+        let mut maxlen = 0;
+        while iter.len() > 0 {
+            match next_string(&mut iter)?.to_uppercase().as_str() {
+                "RANK" => rank = next_integer(&mut iter)?,
+                "COUNT" => count = Some(next_count(&mut iter)?),
+                "MAXLEN" => maxlen = next_count(&mut iter)?,
+                _ => return Err(RedisErr::SyntaxError),
+            }
+        }
+        Ok(Self::new(key, value, rank, count, maxlen))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        if self.rank == 0 || self.rank == i64::MIN {
+            return Frame::Error(
+                "ERR RANK can't be zero: use 1 to start from the first match, \
+                 2 from the second ... or use negative to start from the end of the list"
+                    .to_string(),
+            );
+        }
+        let count = self.count.unwrap_or(1);
+        match (
+            db.lpos(&self.key, &self.value, self.rank, count, self.maxlen),
+            self.count,
+        ) {
+            (Ok(indexes), Some(_)) => Frame::Array(
+                indexes
+                    .into_iter()
+                    .map(|i| Frame::Integer(i as i64))
+                    .collect(),
+            ),
+            (Ok(indexes), None) => indexes
+                .first()
+                .map_or(Frame::Nil, |i| Frame::Integer(*i as i64)),
+            (Err(e), _) => error_frame(e),
+        }
+    }
+}
+
+// LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+#[derive(Debug)]
+pub struct LMove {
+    source: String,
+    destination: String,
+    from: ListSide,
+    to: ListSide,
+}
+
+impl LMove {
+    fn new(source: String, destination: String, from: ListSide, to: ListSide) -> Self {
+        Self {
+            source,
+            destination,
+            from,
+            to,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 5 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LMOVE")?;
+        Self::from_args(&mut iter)
+    }
+
+    // source destination LEFT|RIGHT LEFT|RIGHT
+    fn from_args(iter: &mut std::vec::IntoIter<Frame>) -> Result<Self> {
+        let source = next_string(iter)?;
+        let destination = next_string(iter)?;
+        let from = next_side(iter)?;
+        let to = next_side(iter)?;
+        Ok(Self::new(source, destination, from, to))
+    }
+
+    // LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+    fn request(&self) -> Frame {
+        Frame::Array(vec![
+            Frame::BulkString(Bytes::from_static(b"LMOVE")),
+            Frame::BulkString(Bytes::from(self.source.clone())),
+            Frame::BulkString(Bytes::from(self.destination.clone())),
+            Frame::BulkString(Bytes::from_static(side_name(self.from))),
+            Frame::BulkString(Bytes::from_static(side_name(self.to))),
+        ])
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.lmove(&self.source, &self.destination, self.from, self.to) {
+            Ok(value) => value.map_or(Frame::Nil, Frame::BulkString),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+// the key of the first non-empty list and the values popped from it
+#[derive(Debug)]
+pub struct LMPop {
+    keys: Vec<String>,
+    side: ListSide,
+    count: usize,
+}
+
+impl LMPop {
+    fn new(keys: Vec<String>, side: ListSide, count: usize) -> Self {
+        Self { keys, side, count }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"LMPOP")?;
+        Self::from_args(&mut iter)
+    }
+
+    // numkeys key [key ...] LEFT|RIGHT [COUNT count]
+    fn from_args(iter: &mut std::vec::IntoIter<Frame>) -> Result<Self> {
+        let numkeys = next_integer(iter)?;
+        if numkeys <= 0 || numkeys as usize >= iter.len() {
+            return Err(RedisErr::InvalidArgument);
+        }
+        let mut keys = Vec::with_capacity(numkeys as usize);
+        for _ in 0..numkeys {
+            keys.push(next_string(iter)?);
+        }
+        let side = next_side(iter)?;
+        let count = match iter.len() {
+            0 => 1,
+            2 if next_string(iter)?.eq_ignore_ascii_case("COUNT") => {
+                let count = next_integer(iter)?;
+                if count <= 0 {
+                    return Err(RedisErr::InvalidArgument);
+                }
+                count as usize
+            }
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        Ok(Self::new(keys, side, count))
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.keys.clone()
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.lmpop(&self.keys, self.side, self.count) {
+            Ok(Some((key, values))) => Frame::Array(vec![
+                Frame::BulkString(Bytes::from(key)),
+                make_values_frame(values),
+            ]),
+            Ok(None) => Frame::Nil,
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// BLPOP key [key ...] timeout
+// BRPOP key [key ...] timeout
+// pop from the first non-empty list, the client waits for a push if they're all empty,
+// a timeout of 0 waits forever
+#[derive(Debug)]
+pub struct BLPop {
+    keys: Vec<String>,
+    side: ListSide,
+    timeout: Option<Duration>,
+}
+
+impl BLPop {
+    fn new(keys: Vec<String>, side: ListSide, timeout: Option<Duration>) -> Self {
+        Self {
+            keys,
+            side,
+            timeout,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() < 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        let side = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "BLPOP" => ListSide::Left,
+            "BRPOP" => ListSide::Right,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let mut keys = Vec::new();
+        while iter.len() > 1 {
+            keys.push(next_string(&mut iter)?);
+        }
+        let timeout = next_timeout(&mut iter)?;
+        Ok(Self::new(keys, side, timeout))
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.keys.clone()
+    }
+
+    // the pop is propagated as LPOP or RPOP of the key it's served from
+    pub async fn apply_blocking(self, db: &mut DB, shutdown: Arc<Notify>) -> Frame {
+        pop_blocking(db, &self.keys, self.timeout, shutdown, |db| self.pop(db)).await
+    }
+
+    // popped once without blocking where the client can't wait
+    pub fn apply(self, db: &mut DB) -> Frame {
+        pop_once(self.pop(db))
+    }
+
+    fn pop(&self, db: &mut DB) -> Result<Option<(Frame, Frame)>> {
+        let Some((key, values)) = db.lmpop(&self.keys, self.side, 1)? else {
+            return Ok(None);
+        };
+        let request = pop_request(&key, self.side, None);
+        let mut reply = vec![Frame::BulkString(Bytes::from(key))];
+        reply.extend(values.into_iter().map(Frame::BulkString));
+        Ok(Some((Frame::Array(reply), request)))
+    }
+}
+
+// BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout
+#[derive(Debug)]
+pub struct BLMove {
+    lmove: LMove,
+    timeout: Option<Duration>,
+}
+
+impl BLMove {
+    fn new(lmove: LMove, timeout: Option<Duration>) -> Self {
+        Self { lmove, timeout }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 6 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"BLMOVE")?;
+        let lmove = LMove::from_args(&mut iter)?;
+        let timeout = next_timeout(&mut iter)?;
+        Ok(Self::new(lmove, timeout))
+    }
+
+    // the move is propagated as LMOVE
+    pub async fn apply_blocking(self, db: &mut DB, shutdown: Arc<Notify>) -> Frame {
+        let keys = [self.lmove.source.clone()];
+        pop_blocking(db, &keys, self.timeout, shutdown, |db| self.pop(db)).await
+    }
+
+    // moved once without blocking where the client can't wait
+    pub fn apply(self, db: &mut DB) -> Frame {
+        pop_once(self.pop(db))
+    }
+
+    fn pop(&self, db: &mut DB) -> Result<Option<(Frame, Frame)>> {
+        let lmove = &self.lmove;
+        let value = db.lmove(&lmove.source, &lmove.destination, lmove.from, lmove.to)?;
+        Ok(value.map(|value| (Frame::BulkString(value), lmove.request())))
+    }
+}
+
+// BLMPOP timeout numkeys key [key ...] LEFT|RIGHT [COUNT count]
+#[derive(Debug)]
+pub struct BLMPop {
+    lmpop: LMPop,
+    timeout: Option<Duration>,
+}
+
+impl BLMPop {
+    fn new(lmpop: LMPop, timeout: Option<Duration>) -> Self {
+        Self { lmpop, timeout }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"BLMPOP")?;
+        let timeout = next_timeout(&mut iter)?;
+        let lmpop = LMPop::from_args(&mut iter)?;
+        Ok(Self::new(lmpop, timeout))
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.lmpop.keys()
+    }
+
+    // the pop is propagated as LPOP or RPOP with the count
+    pub async fn apply_blocking(self, db: &mut DB, shutdown: Arc<Notify>) -> Frame {
+        pop_blocking(db, &self.lmpop.keys, self.timeout, shutdown, |db| {
+            self.pop(db)
+        })
+        .await
+    }
+
+    // popped once without blocking where the client can't wait
+    pub fn apply(self, db: &mut DB) -> Frame {
+        pop_once(self.pop(db))
+    }
+
+    fn pop(&self, db: &mut DB) -> Result<Option<(Frame, Frame)>> {
+        let lmpop = &self.lmpop;
+        let Some((key, values)) = db.lmpop(&lmpop.keys, lmpop.side, lmpop.count)? else {
+            return Ok(None);
+        };
+        let request = pop_request(&key, lmpop.side, Some(lmpop.count));
+        let reply = Frame::Array(vec![
+            Frame::BulkString(Bytes::from(key)),
+            make_values_frame(values),
+        ]);
+        Ok(Some((reply, request)))
+    }
+}
+
+// pop again every time a value is pushed to one of the lists until there is one,
+// pop replies the client and gives the request propagated in place of the blocking one
+async fn pop_blocking<F>(
+    db: &mut DB,
+    keys: &[String],
+    timeout: Option<Duration>,
+    shutdown: Arc<Notify>,
+    mut pop: F,
+) -> Frame
+where
+    F: FnMut(&mut DB) -> Result<Option<(Frame, Frame)>>,
+{
+    let guard = db.clone();
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    // registered once before popping, the client keeps its place in line until it's served
+    let waiter = db.block_on_lists(keys);
+    let mut timed_out = false;
+    loop {
+        {
+            let _command = guard.command_guard().await;
+            let _propagate = guard.propagate_guard();
+            match pop(db) {
+                Ok(Some((reply, request))) => {
+                    db.propagate(request);
+                    return reply;
+                }
+                Ok(None) if timed_out => return Frame::Nil,
+                Ok(None) => {}
+                Err(e) => return error_frame(e),
+            }
+        }
+        tokio::select! {
+            // pop once more after the deadline, a value may be pushed just before it
+            ready = waiter.wait(deadline) => timed_out = !ready,
+            _ = shutdown.notified() => return Frame::Nil,
+        }
+    }
+}
+
+// the reply of a pop, nil if all the lists are empty
+fn pop_once(popped: Result<Option<(Frame, Frame)>>) -> Frame {
+    match popped {
+        Ok(Some((reply, _))) => reply,
+        Ok(None) => Frame::Nil,
+        Err(e) => error_frame(e),
+    }
+}
+
+// LPOP|RPOP key [count]
+fn pop_request(key: &str, side: ListSide, count: Option<usize>) -> Frame {
+    let cmd: &'static [u8] = match side {
+        ListSide::Left => b"LPOP",
+        ListSide::Right => b"RPOP",
+    };
+    let mut args = vec![
+        Frame::BulkString(Bytes::from_static(cmd)),
+        Frame::BulkString(Bytes::from(key.to_string())),
+    ];
+    if let Some(count) = count {
+        args.push(Frame::BulkString(Bytes::from(count.to_string())));
+    }
+    Frame::Array(args)
+}
+
+// the timeout in seconds, None for 0
+fn next_timeout(iter: &mut std::vec::IntoIter<Frame>) -> Result<Option<Duration>> {
+    let timeout = next_float(iter)?;
+    if !timeout.is_finite() || timeout < 0.0 {
+        return Err(RedisErr::InvalidArgument);
+    }
+    Ok((timeout > 0.0).then(|| Duration::from_secs_f64(timeout)))
+}
+
+fn side_name(side: ListSide) -> &'static [u8] {
+    match side {
+        ListSide::Left => b"LEFT",
+        ListSide::Right => b"RIGHT",
+    }
+}
+
+// a count not less than 0
+fn next_count(iter: &mut std::vec::IntoIter<Frame>) -> Result<usize> {
+    let count = next_integer(iter)?;
+    if count < 0 {
+        return Err(RedisErr::InvalidArgument);
+    }
+    Ok(count as usize)
+}
+
+fn next_side(iter: &mut std::vec::IntoIter<Frame>) -> Result<ListSide> {
+    match next_string(iter)?.to_uppercase().as_str() {
+        "LEFT" => Ok(ListSide::Left),
+        "RIGHT" => Ok(ListSide::Right),
+        _ => Err(RedisErr::SyntaxError),
+    }
+}
+
+fn make_values_frame(values: Vec<Bytes>) -> Frame {
+    Frame::Array(values.into_iter().map(Frame::BulkString).collect())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::helper::command;
+
+    use std::collections::VecDeque;
 
-    #[test]
-    fn test_lpush() {
+    #[tokio::test]
+    async fn test_lpush() {
         let mut db = DB::new();
         let cmd = LPush::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"lpush")),
@@ -104,8 +885,37 @@ mod test {
         assert_eq!(result, Frame::Integer(3));
     }
 
-    #[test]
-    fn test_lrange() {
+    #[tokio::test]
+    async fn test_rpush() {
+        let mut db = DB::new();
+        let cmd = RPush::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"rpush")),
+            Frame::BulkString(Bytes::from_static(b"key")),
+            Frame::BulkString(Bytes::from_static(b"1")),
+            Frame::BulkString(Bytes::from_static(b"2")),
+        ])
+        .unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(2));
+        let cmd = RPush::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"rpush")),
+            Frame::BulkString(Bytes::from_static(b"key")),
+            Frame::BulkString(Bytes::from_static(b"3")),
+        ])
+        .unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(3));
+        let snapshot = db.snapshot();
+        assert_eq!(
+            snapshot[0].1.as_list_ref().unwrap(),
+            &VecDeque::from(vec![
+                Bytes::from_static(b"1"),
+                Bytes::from_static(b"2"),
+                Bytes::from_static(b"3")
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lrange() {
         let mut db = DB::new();
         let cmd = LRange::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"lrange")),
@@ -117,4 +927,180 @@ mod test {
         let result = cmd.apply(&mut db);
         assert_eq!(result, Frame::Array(vec![]));
     }
+
+    fn values(args: &[&str]) -> Frame {
+        Frame::Array(
+            args.iter()
+                .map(|arg| Frame::BulkString(Bytes::from(arg.to_string())))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_lpush_lrange() {
+        let mut db = DB::new();
+        LPush::from_frames(command(&["lpush", "key", "1", "2", "3"]))
+            .unwrap()
+            .apply(&mut db);
+        let cmd = LRange::from_frames(command(&["lrange", "key", "0", "1"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), values(&["3", "2"]));
+        let cmd = LRange::from_frames(command(&["lrange", "key", "-100", "100"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), values(&["3", "2", "1"]));
+    }
+
+    #[tokio::test]
+    async fn test_lpop() {
+        let mut db = DB::new();
+        RPush::from_frames(command(&["rpush", "key", "a", "b", "c"]))
+            .unwrap()
+            .apply(&mut db);
+        let cmd = LPop::from_frames(command(&["lpop", "key"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::BulkString(Bytes::from("a")));
+        let cmd = LPop::from_frames(command(&["rpop", "key", "5"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), values(&["c", "b"]));
+        // the drained list is deleted
+        assert!(db.snapshot().is_empty());
+        let cmd = LPop::from_frames(command(&["rpop", "key", "5"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Nil);
+        let cmd = LPushX::from_frames(command(&["rpushx", "key", "a"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn test_lpos() {
+        let mut db = DB::new();
+        RPush::from_frames(command(&[
+            "rpush", "key", "a", "b", "c", "1", "2", "3", "c", "c",
+        ]))
+        .unwrap()
+        .apply(&mut db);
+        let cmd = LPos::from_frames(command(&["lpos", "key", "c"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(2));
+        let cmd = LPos::from_frames(command(&["lpos", "key", "c", "rank", "2"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(6));
+        let cmd =
+            LPos::from_frames(command(&["lpos", "key", "c", "rank", "-1", "count", "2"])).unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::Array(vec![Frame::Integer(7), Frame::Integer(6)])
+        );
+        let cmd =
+            LPos::from_frames(command(&["lpos", "key", "c", "count", "0", "maxlen", "3"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Array(vec![Frame::Integer(2)]));
+        let cmd = LPos::from_frames(command(&["lpos", "key", "x"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Nil);
+    }
+
+    #[tokio::test]
+    async fn test_lmove_lmpop() {
+        let mut db = DB::new();
+        RPush::from_frames(command(&["rpush", "src", "a", "b"]))
+            .unwrap()
+            .apply(&mut db);
+        let cmd = LMove::from_frames(command(&["lmove", "src", "dst", "left", "right"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::BulkString(Bytes::from("a")));
+        let cmd = LMove::from_frames(command(&["lmove", "src", "dst", "right", "left"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::BulkString(Bytes::from("b")));
+        let cmd = LMove::from_frames(command(&["lmove", "src", "dst", "left", "left"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Nil);
+        let cmd = LMPop::from_frames(command(&[
+            "lmpop", "2", "src", "dst", "right", "count", "5",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cmd.apply(&mut db),
+            Frame::Array(vec![
+                Frame::BulkString(Bytes::from("dst")),
+                values(&["a", "b"])
+            ])
+        );
+        assert!(db.snapshot().is_empty());
+        let cmd = LMPop::from_frames(command(&["lmpop", "1", "src", "left"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Nil);
+    }
+
+    #[tokio::test]
+    async fn test_blpop() {
+        let mut db = DB::new();
+        let blpop = |db: &DB, args: &[&str]| {
+            let cmd = BLPop::from_frames(command(args)).unwrap();
+            let mut db = db.clone();
+            tokio::spawn(async move { cmd.apply_blocking(&mut db, Arc::new(Notify::new())).await })
+        };
+        let pair = |key: &str, value: &str| values(&[key, value]);
+        // let the spawned clients run until they are blocked
+        async fn blocked(db: &DB, key: &str, clients: usize) {
+            while db.blocked_clients(key) < clients {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        // served at once
+        RPush::from_frames(command(&["rpush", "b", "1"]))
+            .unwrap()
+            .apply(&mut db);
+        assert_eq!(
+            blpop(&db, &["blpop", "a", "b", "0"]).await.unwrap(),
+            pair("b", "1")
+        );
+        assert!(db.snapshot().is_empty());
+
+        // the clients are served in the order they are blocked
+        let first = blpop(&db, &["blpop", "a", "0"]);
+        blocked(&db, "a", 1).await;
+        let second = blpop(&db, &["brpop", "b", "a", "0"]);
+        blocked(&db, "a", 2).await;
+        let third = blpop(&db, &["blpop", "a", "0"]);
+        blocked(&db, "a", 3).await;
+        RPush::from_frames(command(&["rpush", "a", "1"]))
+            .unwrap()
+            .apply(&mut db);
+        assert_eq!(first.await.unwrap(), pair("a", "1"));
+        tokio::task::yield_now().await;
+        assert!(!second.is_finished() && !third.is_finished());
+        RPush::from_frames(command(&["rpush", "a", "2", "3"]))
+            .unwrap()
+            .apply(&mut db);
+        assert_eq!(second.await.unwrap(), pair("a", "3"));
+        assert_eq!(third.await.unwrap(), pair("a", "2"));
+
+        // timed out
+        assert_eq!(
+            blpop(&db, &["blpop", "a", "0.01"]).await.unwrap(),
+            Frame::Nil
+        );
+        assert!(BLPop::from_frames(command(&["blpop", "a", "-1"])).is_err());
+
+        // moved once the source is pushed to
+        let cmd = BLMove::from_frames(command(&["blmove", "a", "b", "left", "left", "0"])).unwrap();
+        let mut reader = db.clone();
+        let mover = tokio::spawn(async move {
+            cmd.apply_blocking(&mut reader, Arc::new(Notify::new()))
+                .await
+        });
+        blocked(&db, "a", 1).await;
+        LPush::from_frames(command(&["lpush", "a", "x"]))
+            .unwrap()
+            .apply(&mut db);
+        assert_eq!(mover.await.unwrap(), Frame::BulkString(Bytes::from("x")));
+        let cmd = LRange::from_frames(command(&["lrange", "b", "0", "-1"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), values(&["x"]));
+
+        // popped without blocking inside MULTI and scripts
+        let apply = |db: &mut DB, args: &[&str]| {
+            let cmd = Parser::new().parse(Frame::Array(command(args))).unwrap();
+            assert!(cmd.is_blocking());
+            cmd.apply_to_db(db).unwrap()
+        };
+        assert_eq!(apply(&mut db, &["blpop", "a", "b", "0"]), pair("b", "x"));
+        assert_eq!(apply(&mut db, &["blpop", "a", "b", "0"]), Frame::Nil);
+        assert_eq!(
+            apply(&mut db, &["blmove", "a", "b", "left", "left", "0"]),
+            Frame::Nil
+        );
+        assert_eq!(
+            apply(&mut db, &["blmpop", "0", "1", "a", "left"]),
+            Frame::Nil
+        );
+    }
 }
@@ -3,13 +3,12 @@
 use super::*;
 use crate::db::DB;
 use crate::frame::Frame;
+use crate::helper::unix_ms_to_instant;
 use crate::Result;
 
-use marco::Applyer;
-
 use std::time::{Duration, Instant};
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Type {
     key: String,
 }
@@ -37,7 +36,7 @@ impl Type {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Del {
     key: String,
 }
@@ -62,7 +61,7 @@ impl Del {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Expire {
     key: String,
     expire: Duration,
@@ -93,6 +92,39 @@ impl Expire {
     }
 }
 
+// PEXPIREAT key unix-time-milliseconds
+#[derive(Debug)]
+pub struct PExpireAt {
+    key: String,
+    expire_at: u64,
+}
+
+impl PExpireAt {
+    fn new(key: String, expire_at: u64) -> Self {
+        Self { key, expire_at }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"PEXPIREAT")?;
+        let key = next_string(&mut iter)?; // key
+        let expire_at = next_integer(&mut iter)?.max(0) as u64; // unix time in milliseconds
+        Ok(Self::new(key, expire_at))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        // a time in the past deletes the key
+        let res = match unix_ms_to_instant(self.expire_at) {
+            Some(expire_at) => db.expire(&self.key, expire_at).is_ok(),
+            None => db.del(&self.key).is_some(),
+        };
+        Frame::Integer(res as i64)
+    }
+}
+
 #[derive(Debug)]
 enum ObjectOption {
     Encoding,
@@ -101,7 +133,7 @@ enum ObjectOption {
     Frequency,
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct Object {
     key: String,
     option: ObjectOption,
@@ -151,8 +183,8 @@ impl Object {
 mod test {
     use super::*;
 
-    #[test]
-    fn test_del() {
+    #[tokio::test]
+    async fn test_del() {
         let mut db = DB::new();
         let cmd = Del::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"del")),
@@ -163,8 +195,8 @@ mod test {
         assert_eq!(result, Frame::Integer(0));
     }
 
-    #[test]
-    fn test_expire() {
+    #[tokio::test]
+    async fn test_expire() {
         let mut db = DB::new();
         let cmd = Expire::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"expire")),
@@ -176,8 +208,41 @@ mod test {
         assert_eq!(result, Frame::Integer(0));
     }
 
-    #[test]
-    fn test_type() {
+    #[tokio::test]
+    async fn test_pexpireat() {
+        let mut db = DB::new();
+        db.set(
+            "key".to_string(),
+            Bytes::from("v"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        let at = crate::helper::unix_timestamp_ms() + 10000;
+        let cmd = PExpireAt::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"pexpireat")),
+            Frame::BulkString(Bytes::from_static(b"key")),
+            Frame::BulkString(Bytes::from(at.to_string())),
+        ])
+        .unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(1));
+        assert!(db.get("key").is_ok());
+
+        let cmd = PExpireAt::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"pexpireat")),
+            Frame::BulkString(Bytes::from_static(b"key")),
+            Frame::Integer(1000),
+        ])
+        .unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::Integer(1));
+        assert!(db.get("key").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_type() {
         let mut db = DB::new();
         let cmd = Type::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"type")),
@@ -11,8 +11,12 @@ mod list;
 pub use list::*;
 mod hash;
 pub use hash::*;
+mod set;
+pub use set::*;
 mod sort_set;
 pub use sort_set::*;
+mod stream;
+pub use stream::*;
 mod bf;
 pub use bf::*;
 mod meta;
@@ -26,6 +30,18 @@ pub use connections::*;
 mod db;
 pub use db::*;
 
+mod replication;
+pub use replication::*;
+
+mod cluster;
+pub use cluster::*;
+
+mod transaction;
+pub use transaction::*;
+
+mod scripting;
+pub use scripting::*;
+
 use crate::connection::AsyncConnection;
 use crate::db::DB;
 use crate::frame::Frame;
@@ -53,10 +69,6 @@ impl core::fmt::Debug for Parser {
     }
 }
 
-pub trait CommandApplyer {
-    fn apply(self: Box<Self>, db: DB) -> Frame;
-}
-
 macro_rules! add_tire {
     ($tire:ident, $($cmd:ident),*) => {
         $(
@@ -64,9 +76,127 @@ macro_rules! add_tire {
                 Ok(Command::$cmd($cmd::from_frames(frames)?))
             }));
         )*
-        $tire.insert("SUBSCRIBE", Box::new(|frames: Vec<Frame>| -> Result<Command> {
-            Ok(Command::Subscribe(Subscribe::from_frames(frames)?))
+        for name in ["SUBSCRIBE", "PSUBSCRIBE", "SSUBSCRIBE"] {
+            $tire.insert(name, Box::new(|frames: Vec<Frame>| -> Result<Command> {
+                Ok(Command::Subscribe(Subscribe::from_frames(frames)?))
+            }));
+        }
+        for name in ["PUNSUBSCRIBE", "SUNSUBSCRIBE"] {
+            $tire.insert(name, Box::new(|frames: Vec<Frame>| -> Result<Command> {
+                Ok(Command::Unsubscribe(Unsubscribe::from_frames(frames)?))
+            }));
+        }
+        $tire.insert("HELLO", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Hello(Hello::from_frames(frames)?))
+        }));
+        $tire.insert("AUTH", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Auth(Auth::from_frames(frames)?))
+        }));
+        $tire.insert("CLIENT", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Client(Client::from_frames(frames)?))
+        }));
+        $tire.insert("REPLCONF", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::ReplConf(ReplConf::from_frames(frames)?))
+        }));
+        $tire.insert("PSYNC", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Psync(Psync::from_frames(frames)?))
+        }));
+        $tire.insert("WAIT", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Wait(Wait::from_frames(frames)?))
+        }));
+        $tire.insert("ASKING", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Asking(Asking::from_frames(frames)?))
+        }));
+        $tire.insert("MULTI", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Multi(Multi::from_frames(frames)?))
+        }));
+        $tire.insert("EXEC", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Exec(Exec::from_frames(frames)?))
+        }));
+        $tire.insert("DISCARD", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Discard(Discard::from_frames(frames)?))
+        }));
+        $tire.insert("WATCH", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Watch(Watch::from_frames(frames)?))
+        }));
+        $tire.insert("UNWATCH", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Unwatch(Unwatch::from_frames(frames)?))
+        }));
+        for name in ["EVAL", "EVAL_RO"] {
+            $tire.insert(name, Box::new(|frames: Vec<Frame>| -> Result<Command> {
+                Ok(Command::Eval(Eval::from_frames(frames)?))
+            }));
+        }
+        for name in ["EVALSHA", "EVALSHA_RO"] {
+            $tire.insert(name, Box::new(|frames: Vec<Frame>| -> Result<Command> {
+                Ok(Command::EvalSha(EvalSha::from_frames(frames)?))
+            }));
+        }
+        $tire.insert("SCRIPT", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::Script(Script::from_frames(frames)?))
+        }));
+        $tire.insert("RPUSHX", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::LPushX(LPushX::from_frames(frames)?))
+        }));
+        $tire.insert("RPOP", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::LPop(LPop::from_frames(frames)?))
+        }));
+        for name in ["BLPOP", "BRPOP"] {
+            $tire.insert(name, Box::new(|frames: Vec<Frame>| -> Result<Command> {
+                Ok(Command::BLPop(BLPop::from_frames(frames)?))
+            }));
+        }
+        $tire.insert("BLMOVE", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::BLMove(BLMove::from_frames(frames)?))
+        }));
+        $tire.insert("BLMPOP", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::BLMPop(BLMPop::from_frames(frames)?))
         }));
+        for name in ["SUNION", "SDIFF"] {
+            $tire.insert(name, Box::new(|frames: Vec<Frame>| -> Result<Command> {
+                Ok(Command::SInter(SInter::from_frames(frames)?))
+            }));
+        }
+        for name in ["SUNIONSTORE", "SDIFFSTORE"] {
+            $tire.insert(name, Box::new(|frames: Vec<Frame>| -> Result<Command> {
+                Ok(Command::SInterStore(SInterStore::from_frames(frames)?))
+            }));
+        }
+        $tire.insert("XREVRANGE", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::XRange(XRange::from_frames(frames)?))
+        }));
+        // XREAD without BLOCK is an ordinary command
+        $tire.insert("XREAD", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            let cmd = XRead::from_frames(frames)?;
+            if cmd.is_blocking() {
+                Ok(Command::XReadBlock(cmd))
+            } else {
+                Ok(Command::XRead(cmd))
+            }
+        }));
+        $tire.insert("XREADGROUP", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            let cmd = XReadGroup::from_frames(frames)?;
+            if cmd.is_blocking() {
+                Ok(Command::XReadGroupBlock(cmd))
+            } else {
+                Ok(Command::XReadGroup(cmd))
+            }
+        }));
+        // the names of the bloom filter commands have a dot
+        $tire.insert("BF.ADD", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::BFAdd(BFAdd::from_frames(frames)?))
+        }));
+        $tire.insert("BF.EXISTS", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::BFExists(BFExists::from_frames(frames)?))
+        }));
+        $tire.insert("BF.LOADCHUNK", Box::new(|frames: Vec<Frame>| -> Result<Command> {
+            Ok(Command::BFLoadChunk(BFLoadChunk::from_frames(frames)?))
+        }));
+        for name in ["FCALL", "FCALL_RO"] {
+            $tire.insert(name, Box::new(|frames: Vec<Frame>| -> Result<Command> {
+                Ok(Command::FCall(FCall::from_frames(frames)?))
+            }));
+        }
     };
 }
 
@@ -78,6 +208,39 @@ macro_rules! def_command_impl_parse {
 
                 Subscribe(Subscribe),
                 // Unsubscribe(Unsubscribe),
+
+                // connection states
+                Hello(Hello),
+                Auth(Auth),
+                Client(Client),
+
+                // replication link
+                ReplConf(ReplConf),
+                Psync(Psync),
+                Wait(Wait),
+
+                // cluster redirection
+                Asking(Asking),
+
+                // transactions
+                Multi(Multi),
+                Exec(Exec),
+                Discard(Discard),
+                Watch(Watch),
+                Unwatch(Unwatch),
+
+                // scripts run with no other command in between
+                Eval(Eval),
+                EvalSha(EvalSha),
+                Script(Script),
+                FCall(FCall),
+
+                // blocked until the keys are written
+                XReadBlock(XRead),
+                XReadGroupBlock(XReadGroup),
+                BLPop(BLPop),
+                BLMove(BLMove),
+                BLMPop(BLMPop),
             }
 
         impl Command {
@@ -86,8 +249,234 @@ macro_rules! def_command_impl_parse {
                 match self {
                     $(Command::$cmd(cmd) => cmd.apply(db),)*
                     Command::Subscribe(cmd) =>  cmd.apply(db, dst, shutdown).await,//cmd.apply(db.db()),
+                    Command::Hello(cmd) => cmd.apply(db, dst),
+                    Command::Auth(cmd) => cmd.apply(db, dst),
+                    Command::Client(cmd) => cmd.apply(db, dst),
+                    Command::ReplConf(cmd) => cmd.apply(db, dst),
+                    Command::Psync(cmd) => match cmd.apply(db, dst, shutdown).await {
+                        Ok(()) => Frame::Nil,
+                        Err(e) => Frame::Error(e.to_string()),
+                    },
+                    Command::Wait(cmd) => cmd.apply(db, dst).await,
+                    Command::Asking(cmd) => cmd.apply(db, dst),
+                    Command::Multi(cmd) => cmd.apply(db, dst),
+                    Command::Exec(cmd) => cmd.apply(db, dst).await,
+                    Command::Discard(cmd) => cmd.apply(db, dst),
+                    Command::Watch(cmd) => cmd.apply(db, dst),
+                    Command::Unwatch(cmd) => cmd.apply(db, dst),
+                    Command::Eval(cmd) => cmd.apply(db, dst).await,
+                    Command::EvalSha(cmd) => cmd.apply(db, dst).await,
+                    Command::Script(cmd) => cmd.apply(db, dst),
+                    Command::FCall(cmd) => cmd.apply(db, dst).await,
+                    Command::XReadBlock(cmd) => dst.unless_closed(cmd.apply_blocking(db, shutdown)).await,
+                    Command::XReadGroupBlock(cmd) => dst.unless_closed(cmd.apply_blocking(db, shutdown)).await,
+                    Command::BLPop(cmd) => dst.unless_closed(cmd.apply_blocking(db, shutdown)).await,
+                    Command::BLMove(cmd) => dst.unless_closed(cmd.apply_blocking(db, shutdown)).await,
+                    Command::BLMPop(cmd) => dst.unless_closed(cmd.apply_blocking(db, shutdown)).await,
                 }
             }
+
+            // apply without a client connection, such as replaying the aof
+            pub fn apply_to_db(self, db: &mut DB) -> Result<Frame> {
+                match self {
+                    $(Command::$cmd(cmd) => Ok(cmd.apply(db)),)*
+                    // applied once without blocking, such as in a transaction or a script
+                    Command::XReadBlock(cmd) => Ok(cmd.apply(db)),
+                    Command::XReadGroupBlock(cmd) => Ok(cmd.apply(db)),
+                    Command::BLPop(cmd) => Ok(cmd.apply(db)),
+                    Command::BLMove(cmd) => Ok(cmd.apply(db)),
+                    Command::BLMPop(cmd) => Ok(cmd.apply(db)),
+                    _ => Err(RedisErr::InvalidProtocol),
+                }
+            }
+
+            // commands need the client connection, they can't be queued in a transaction
+            // unless they only block, see is_blocking
+            pub fn is_connection_bound(&self) -> bool {
+                !matches!(self, $(Command::$cmd(_))|*)
+            }
+
+            // commands modify the key space, they are logged to the aof once applied
+            pub fn is_write(&self) -> bool {
+                if let Command::Function(cmd) = self {
+                    return cmd.is_write();
+                }
+                matches!(
+                    self,
+                    Command::Set(_)
+                        | Command::MSet(_)
+                        | Command::LPush(_)
+                        | Command::RPush(_)
+                        | Command::LPushX(_)
+                        | Command::LPop(_)
+                        | Command::LSet(_)
+                        | Command::LInsert(_)
+                        | Command::LRem(_)
+                        | Command::LTrim(_)
+                        | Command::LMove(_)
+                        | Command::LMPop(_)
+                        | Command::BLPop(_)
+                        | Command::BLMove(_)
+                        | Command::BLMPop(_)
+                        | Command::HSet(_)
+                        | Command::HDel(_)
+                        | Command::HSetNX(_)
+                        | Command::HIncrBy(_)
+                        | Command::HIncrByFloat(_)
+                        | Command::SAdd(_)
+                        | Command::SRem(_)
+                        | Command::SPop(_)
+                        | Command::SMove(_)
+                        | Command::SInterStore(_)
+                        | Command::ZAdd(_)
+                        | Command::ZRem(_)
+                        | Command::BFAdd(_)
+                        | Command::BFLoadChunk(_)
+                        | Command::XAdd(_)
+                        | Command::XDel(_)
+                        | Command::XTrim(_)
+                        | Command::XSetId(_)
+                        | Command::XGroup(_)
+                        | Command::XReadGroup(_)
+                        | Command::XReadGroupBlock(_)
+                        | Command::XAck(_)
+                        | Command::XClaim(_)
+                        | Command::XAutoClaim(_)
+                        | Command::Del(_)
+                        | Command::Expire(_)
+                        | Command::PExpireAt(_)
+                        | Command::Flush(_)
+                )
+            }
+
+            // positions of the keys in the arguments: first, last and step,
+            // a negative last counts from the end, like the key specs of the redis command table
+            fn key_spec(&self) -> Option<(usize, isize, usize)> {
+                match self {
+                    Command::MGet(_)
+                    | Command::Del(_)
+                    | Command::Watch(_)
+                    | Command::SInter(_)
+                    | Command::SInterStore(_) => Some((1, -1, 1)),
+                    Command::Subscribe(cmd) if cmd.is_shard() => Some((1, -1, 1)),
+                    Command::MSet(_) => Some((1, -1, 2)),
+                    Command::SMove(_) | Command::LMove(_) | Command::BLMove(_) => Some((1, 2, 1)),
+                    Command::Object(_) | Command::XGroup(_) | Command::XInfo(_) => Some((2, 2, 1)),
+                    Command::Get(_)
+                    | Command::Set(_)
+                    | Command::LPush(_)
+                    | Command::RPush(_)
+                    | Command::LRange(_)
+                    | Command::LPushX(_)
+                    | Command::LPop(_)
+                    | Command::LLen(_)
+                    | Command::LIndex(_)
+                    | Command::LSet(_)
+                    | Command::LInsert(_)
+                    | Command::LRem(_)
+                    | Command::LTrim(_)
+                    | Command::LPos(_)
+                    | Command::HSet(_)
+                    | Command::HGet(_)
+                    | Command::HDel(_)
+                    | Command::HGetAll(_)
+                    | Command::HKeys(_)
+                    | Command::HVals(_)
+                    | Command::HLen(_)
+                    | Command::HExists(_)
+                    | Command::HMGet(_)
+                    | Command::HSetNX(_)
+                    | Command::HIncrBy(_)
+                    | Command::HIncrByFloat(_)
+                    | Command::HStrLen(_)
+                    | Command::HRandField(_)
+                    | Command::SAdd(_)
+                    | Command::SRem(_)
+                    | Command::SMembers(_)
+                    | Command::SIsMember(_)
+                    | Command::SMIsMember(_)
+                    | Command::SCard(_)
+                    | Command::SPop(_)
+                    | Command::SRandMember(_)
+                    | Command::ZAdd(_)
+                    | Command::ZCard(_)
+                    | Command::ZRem(_)
+                    | Command::BFAdd(_)
+                    | Command::BFExists(_)
+                    | Command::BFLoadChunk(_)
+                    | Command::Expire(_)
+                    | Command::PExpireAt(_)
+                    | Command::Type(_)
+                    | Command::SPublish(_)
+                    | Command::XAdd(_)
+                    | Command::XRange(_)
+                    | Command::XLen(_)
+                    | Command::XDel(_)
+                    | Command::XTrim(_)
+                    | Command::XSetId(_)
+                    | Command::XAck(_)
+                    | Command::XPending(_)
+                    | Command::XClaim(_)
+                    | Command::XAutoClaim(_) => Some((1, 1, 1)),
+                    _ => None,
+                }
+            }
+
+            // the keys in the request of the command, to find their hash slot
+            pub fn keys(&self, request: &Frame) -> Vec<String> {
+                let Frame::Array(args) = request else {
+                    return vec![];
+                };
+                // the keys follow numkeys, such as EVAL script numkeys key [key ...] arg [arg ...]
+                if matches!(self, Command::Eval(_) | Command::EvalSha(_) | Command::FCall(_)) {
+                    let numkeys = args
+                        .get(2)
+                        .and_then(|arg| frame_to_string(arg).ok())
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    return args
+                        .iter()
+                        .skip(3)
+                        .take(numkeys)
+                        .filter_map(|arg| frame_to_string(arg).ok())
+                        .collect();
+                }
+                if let Command::XRead(cmd) | Command::XReadBlock(cmd) = self {
+                    return cmd.keys();
+                }
+                if let Command::XReadGroup(cmd) | Command::XReadGroupBlock(cmd) = self {
+                    return cmd.keys();
+                }
+                if let Command::SInterCard(cmd) = self {
+                    return cmd.keys();
+                }
+                if let Command::LMPop(cmd) = self {
+                    return cmd.keys();
+                }
+                if let Command::BLPop(cmd) = self {
+                    return cmd.keys();
+                }
+                if let Command::BLMPop(cmd) = self {
+                    return cmd.keys();
+                }
+                let Some((first, last, step)) = self.key_spec() else {
+                    return vec![];
+                };
+                let last = if last < 0 {
+                    args.len() as isize + last
+                } else {
+                    last
+                };
+                (first..=last.max(0) as usize)
+                    .step_by(step)
+                    .filter_map(|i| args.get(i).and_then(|arg| frame_to_string(arg).ok()))
+                    .collect()
+            }
+
+            // commands can be executed before the connection is authenticated
+            pub fn need_auth(&self) -> bool {
+                !matches!(self, Command::Hello(_) | Command::Auth(_) | Command::Quit(_))
+            }
         }
         impl Parser {
             pub fn new() -> Self {
@@ -110,16 +499,142 @@ macro_rules! def_command_impl_parse {
     }
 }
 
+impl Command {
+    // SCRIPT and FUNCTION KILL reach a script running for too long,
+    // they are neither replied BUSY nor wait for the script to finish
+    pub fn is_script_kill(&self) -> bool {
+        match self {
+            Command::Script(cmd) => cmd.is_kill(),
+            Command::Function(cmd) => cmd.is_kill(),
+            _ => false,
+        }
+    }
+
+    // commands a script can't call, FUNCTION would wait for the interpreter running the script
+    pub fn is_noscript(&self) -> bool {
+        (self.is_connection_bound() && !self.is_blocking()) || matches!(self, Command::Function(_))
+    }
+
+    // commands waiting for the keys to be written, where the client can't wait,
+    // such as in a transaction or a script, they're applied once without blocking
+    pub fn is_blocking(&self) -> bool {
+        matches!(
+            self,
+            Command::XReadBlock(_)
+                | Command::XReadGroupBlock(_)
+                | Command::BLPop(_)
+                | Command::BLMove(_)
+                | Command::BLMPop(_)
+        )
+    }
+
+    // apply the command and propagate it to the aof and the replicas if it's a write,
+    // the caller holds the propagate guard
+    pub fn apply_and_propagate(self, db: &mut DB, request: Frame) -> Frame {
+        let (resp, requests) = self.apply_and_collect(db, request);
+        db.propagate_transaction(requests);
+        resp
+    }
+
+    // apply the commands with nothing propagated in between,
+    // their writes are propagated at once wrapped in MULTI and EXEC,
+    // the caller holds the transaction guard and the propagate guard
+    pub fn apply_transaction(db: &mut DB, commands: Vec<(Command, Frame)>) -> Vec<Frame> {
+        let mut requests = Vec::new();
+        let replies = commands
+            .into_iter()
+            .map(|(cmd, request)| {
+                let (resp, collected) = cmd.apply_and_collect(db, request);
+                requests.extend(collected);
+                resp
+            })
+            .collect();
+        db.propagate_transaction(requests);
+        replies
+    }
+
+    // apply the command, the request to propagate is returned if it's a write
+    pub fn apply_and_collect(self, db: &mut DB, request: Frame) -> (Frame, Vec<Frame>) {
+        // the claims depend on the time, they're propagated as their outcome
+        let this = match self {
+            Command::XClaim(cmd) => return cmd.apply_and_collect(db),
+            Command::XAutoClaim(cmd) => return cmd.apply_and_collect(db),
+            this => this,
+        };
+        let is_write = this.is_write();
+        // an id generated by XADD is propagated in place of * so the replay adds the same entry
+        let generated_id = match &this {
+            Command::XAdd(cmd) => cmd.generated_id_index(),
+            _ => None,
+        };
+        // the random members removed by SPOP are propagated as SREM
+        let popped_key = match &this {
+            Command::SPop(cmd) => Some(cmd.key().to_string()),
+            _ => None,
+        };
+        let resp = this
+            .apply_to_db(db)
+            .unwrap_or_else(|e| Frame::Error(e.to_string()));
+        if !is_write || matches!(resp, Frame::Error(_)) {
+            return (resp, Vec::new());
+        }
+        let request = match (generated_id, popped_key, &resp, request) {
+            (Some(index), _, Frame::BulkString(id), Frame::Array(mut args)) => {
+                args[index] = Frame::BulkString(id.clone());
+                Some(Frame::Array(args))
+            }
+            (_, Some(key), resp, _) => popped_request(key, resp),
+            (_, _, _, request) => Some(request),
+        };
+        (resp, request.into_iter().collect())
+    }
+}
+
 def_command_impl_parse! {
     Get, MGet, Set, MSet,
-    LPush, LRange,
-    HSet, HGet,
+    LPush, RPush, LRange, LPushX, LPop, LLen, LIndex, LSet, LInsert, LRem, LTrim, LPos,
+    LMove, LMPop,
+    HSet, HGet, HDel, HGetAll, HKeys, HVals, HLen, HExists, HMGet, HSetNX, HIncrBy,
+    HIncrByFloat, HStrLen, HRandField,
+    SAdd, SRem, SMembers, SIsMember, SMIsMember, SCard, SPop, SRandMember, SMove,
+    SInter, SInterStore, SInterCard,
     ZAdd, ZCard, ZRem,
-    BFAdd, BFExists,
-    Publish, Unsubscribe,
-    Del, Expire, Type, Object,
+    BFAdd, BFExists, BFLoadChunk,
+    XAdd, XRange, XLen, XDel, XTrim, XSetId, XRead,
+    XGroup, XReadGroup, XAck, XPending, XClaim, XAutoClaim, XInfo,
+    Publish, SPublish, Unsubscribe, PubSub,
+    Del, Expire, PExpireAt, Type, Object,
     Quit,
-    Ping, Flush
+    Ping, Flush,
+    Save, BgSave, LastSave, BgRewriteAof,
+    Info, ReplicaOf,
+    Cluster,
+    Function
+}
+
+// SREM of the members in the reply of SPOP, None if nothing is popped
+fn popped_request(key: String, resp: &Frame) -> Option<Frame> {
+    let members = match resp {
+        Frame::BulkString(member) => vec![Frame::BulkString(member.clone())],
+        Frame::Set(members) if !members.is_empty() => members.clone(),
+        _ => return None,
+    };
+    let mut args = vec![
+        Frame::BulkString(Bytes::from_static(b"SREM")),
+        Frame::BulkString(Bytes::from(key)),
+    ];
+    args.extend(members);
+    Some(Frame::Array(args))
+}
+
+// the reply of an error from the key space
+fn error_frame(e: RedisErr) -> Frame {
+    match e {
+        RedisErr::WrongType => Frame::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+        ),
+        e => Frame::Error(e.to_string()),
+    }
 }
 
 #[inline]
@@ -155,7 +670,8 @@ fn next_bytes(frame: &mut std::vec::IntoIter<Frame>) -> Result<Bytes> {
 fn next_integer(frame: &mut std::vec::IntoIter<Frame>) -> Result<i64> {
     match frame.next() {
         Some(Frame::Integer(i)) => Ok(i),
-        Some(Frame::SimpleString(s)) => Ok(s.parse::<i64>().unwrap()),
+        Some(Frame::SimpleString(s)) => Ok(s.parse::<i64>()?),
+        Some(Frame::BulkString(bytes)) => Ok(String::from_utf8(bytes.to_vec())?.parse::<i64>()?),
         None => Err(RedisErr::WrongNumberOfArguments),
         _ => Err(RedisErr::InvalidProtocol),
     }
@@ -164,10 +680,10 @@ fn next_integer(frame: &mut std::vec::IntoIter<Frame>) -> Result<i64> {
 #[inline]
 fn next_float(frame: &mut std::vec::IntoIter<Frame>) -> Result<f64> {
     match frame.next() {
-        Some(Frame::SimpleString(s)) => Ok(s.parse::<f64>().unwrap()),
-        Some(Frame::BulkString(bytes)) => {
-            Ok(String::from_utf8(bytes.to_vec())?.parse::<f64>().unwrap())
-        }
+        Some(Frame::SimpleString(s)) => s.parse::<f64>().map_err(|_| RedisErr::InvalidArgument),
+        Some(Frame::BulkString(bytes)) => String::from_utf8(bytes.to_vec())?
+            .parse::<f64>()
+            .map_err(|_| RedisErr::InvalidArgument),
         None => Err(RedisErr::WrongNumberOfArguments),
         _ => Err(RedisErr::InvalidProtocol),
     }
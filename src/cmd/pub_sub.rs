@@ -4,13 +4,13 @@ use std::pin::Pin;
 
 use super::*;
 use crate::connection::AsyncConnection;
-use crate::db::DB;
+use crate::db::{Subscriber, DB};
 use crate::frame::Frame;
 use crate::Result;
 
 use bytes::Bytes;
 use log::{error, warn};
-use tokio::{select, sync::broadcast};
+use tokio::select;
 use tokio_stream::{Stream, StreamExt, StreamMap};
 
 #[derive(Debug)]
@@ -37,32 +37,150 @@ impl Publish {
     }
 } // impl Publish
 
+// SPUBLISH shardchannel message
+#[derive(Debug)]
+pub struct SPublish {
+    channel: String,
+    message: Bytes,
+}
+
+impl SPublish {
+    fn new(channel: String, message: Bytes) -> Self {
+        Self { channel, message }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SPUBLISH")?;
+        let channel = next_string(&mut iter)?;
+        let message = next_bytes(&mut iter)?;
+        Ok(Self::new(channel, message))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        Frame::Integer(db.spublish(self.channel, self.message) as i64)
+    }
+} // impl SPublish
+
 // stream of messages
 // the stream receive messages from the boardcast receiver
+// and yields the frames pushed to the client
 // stream! macro is used to create a stream
 // Pin is used to pin the stream to the memory, the address of the stream will not change
 // Box is used to box the stream
 // send is used to send the stream to other threads
 // sync is used to share the stream between threads
-type Messages = Pin<Box<dyn Stream<Item = Bytes> + Send + Sync>>;
+// an error ends the stream once the subscriber is over the output buffer limit
+type Messages = Pin<Box<dyn Stream<Item = Result<Frame>> + Send + Sync>>;
+
+// what a subscribe or unsubscribe command is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionKind {
+    Channel,
+    Pattern,
+    ShardChannel,
+}
+
+impl SubscriptionKind {
+    fn subscribe_reply(self) -> &'static str {
+        match self {
+            SubscriptionKind::Channel => "subscribe",
+            SubscriptionKind::Pattern => "psubscribe",
+            SubscriptionKind::ShardChannel => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(self) -> &'static str {
+        match self {
+            SubscriptionKind::Channel => "unsubscribe",
+            SubscriptionKind::Pattern => "punsubscribe",
+            SubscriptionKind::ShardChannel => "sunsubscribe",
+        }
+    }
+}
+
+// the channels and the patterns a connection in subscribe mode is subscribed to
+#[derive(Default)]
+struct Subscriptions {
+    channels: StreamMap<String, Messages>,
+    patterns: StreamMap<String, Messages>,
+    shard_channels: StreamMap<String, Messages>,
+}
+
+impl Subscriptions {
+    // the count replied with each subscribe and unsubscribe,
+    // the shard channels are counted apart from the channels and the patterns
+    fn len(&self, kind: SubscriptionKind) -> usize {
+        match kind {
+            SubscriptionKind::ShardChannel => self.shard_channels.len(),
+            _ => self.channels.len() + self.patterns.len(),
+        }
+    }
+
+    fn subscribed(&self, kind: SubscriptionKind) -> &StreamMap<String, Messages> {
+        match kind {
+            SubscriptionKind::Channel => &self.channels,
+            SubscriptionKind::Pattern => &self.patterns,
+            SubscriptionKind::ShardChannel => &self.shard_channels,
+        }
+    }
+
+    fn subscribed_mut(&mut self, kind: SubscriptionKind) -> &mut StreamMap<String, Messages> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    fn names(&self, kind: SubscriptionKind) -> Vec<String> {
+        self.subscribed(kind).keys().cloned().collect()
+    }
 
+    fn insert(&mut self, kind: SubscriptionKind, name: String, messages: Messages) {
+        self.subscribed_mut(kind).insert(name, messages);
+    }
+
+    fn remove(&mut self, kind: SubscriptionKind, name: &str) {
+        self.subscribed_mut(kind).remove(name);
+    }
+}
+
+// SUBSCRIBE channel [channel ...]
+// PSUBSCRIBE pattern [pattern ...], the pattern is matched against the channels with glob style
+// SSUBSCRIBE shardchannel [shardchannel ...]
 #[derive(Debug)]
 pub struct Subscribe {
     channels: Vec<String>,
+    patterns: Vec<String>,
+    shard_channels: Vec<String>,
     cmd_parser: Parser,
 }
 
 impl Subscribe {
-    fn new(channels: Vec<String>) -> Self {
-        Self {
-            channels,
+    fn new(kind: SubscriptionKind, names: Vec<String>) -> Self {
+        let mut cmd = Self {
+            channels: vec![],
+            patterns: vec![],
+            shard_channels: vec![],
             cmd_parser: Parser::new(),
+        };
+        match kind {
+            SubscriptionKind::Channel => cmd.channels = names,
+            SubscriptionKind::Pattern => cmd.patterns = names,
+            SubscriptionKind::ShardChannel => cmd.shard_channels = names,
         }
+        cmd
     }
 
     pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
         let mut iter = frames.into_iter();
-        check_cmd(&mut iter, b"SUBSCRIBE")?;
+        let kind = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "SUBSCRIBE" => SubscriptionKind::Channel,
+            "PSUBSCRIBE" => SubscriptionKind::Pattern,
+            "SSUBSCRIBE" => SubscriptionKind::ShardChannel,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
         let mut channels = Vec::new();
         for next in iter {
             match next {
@@ -75,7 +193,15 @@ impl Subscribe {
                 _ => return Err(RedisErr::FrameMalformed),
             }
         }
-        Ok(Self::new(channels))
+        if channels.is_empty() {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(kind, channels))
+    }
+
+    // the shard channels are the keys of SSUBSCRIBE, they are redirected in cluster mode
+    pub fn is_shard(&self) -> bool {
+        !self.shard_channels.is_empty()
     }
 
     // after subscribe, the connection will be blocked
@@ -87,7 +213,7 @@ impl Subscribe {
         dst: &mut AsyncConnection,
         shutdown: Arc<Notify>,
     ) -> Frame {
-        let mut subscriptions: StreamMap<String, Messages> = StreamMap::new();
+        let mut subscriptions = Subscriptions::default();
 
         // Subscribe to the channel.
         // infinate loop until
@@ -102,11 +228,42 @@ impl Subscribe {
                     return Frame::Error(e.to_string());
                 }
             }
+            for pattern in self.patterns.drain(..) {
+                if let Err(e) =
+                    subscribe_pattern(pattern, &mut subscriptions, db.clone(), dst).await
+                {
+                    return Frame::Error(e.to_string());
+                }
+            }
+            for channel_name in self.shard_channels.drain(..) {
+                if let Err(e) =
+                    subscribe_shard_channel(channel_name, &mut subscriptions, db.clone(), dst).await
+                {
+                    return Frame::Error(e.to_string());
+                }
+            }
 
             select! {
-                Some((channel_name, msg)) = subscriptions.next() => {
+                Some((channel_name, msg)) = subscriptions.channels.next() => {
                     trace!("received message from channel: {}", channel_name);
-                    dst.write_frame(make_message_frame(channel_name, msg)).await.unwrap();
+                    match msg {
+                        Ok(msg) => dst.write_frame(msg).await.unwrap(),
+                        Err(_) => return disconnect(dst),
+                    }
+                }
+                Some((pattern, msg)) = subscriptions.patterns.next() => {
+                    trace!("received message matching pattern: {}", pattern);
+                    match msg {
+                        Ok(msg) => dst.write_frame(msg).await.unwrap(),
+                        Err(_) => return disconnect(dst),
+                    }
+                }
+                Some((channel_name, msg)) = subscriptions.shard_channels.next() => {
+                    trace!("received message from shard channel: {}", channel_name);
+                    match msg {
+                        Ok(msg) => dst.write_frame(msg).await.unwrap(),
+                        Err(_) => return disconnect(dst),
+                    }
                 }
                 res = dst.read_frame() => {
                     match res {
@@ -129,22 +286,40 @@ impl Subscribe {
     async fn handle_command(
         &mut self,
         frames: Frame,
-        subscriptions: &mut StreamMap<String, Messages>,
+        subscriptions: &mut Subscriptions,
         dst: &mut AsyncConnection,
     ) -> Result<()> {
         match self.cmd_parser.parse(frames)? {
             Command::Unsubscribe(mut cmd) => {
+                let kind = cmd.kind;
                 if cmd.channels().is_empty() {
-                    cmd.channels = subscriptions.keys().cloned().collect();
+                    cmd.channels = subscriptions.names(kind);
+                }
+                // nothing to unsubscribe from is still replied
+                if cmd.channels().is_empty() {
+                    let response = make_unsubscribe_frame(
+                        kind.unsubscribe_reply(),
+                        None,
+                        subscriptions.len(kind),
+                    );
+                    dst.write_frame(response).await?;
                 }
                 for channel in cmd.channels() {
-                    subscriptions.remove(channel);
-                    let response = make_unsubscribe_frame(channel.clone(), subscriptions.len());
+                    subscriptions.remove(kind, channel);
+                    let response = make_unsubscribe_frame(
+                        kind.unsubscribe_reply(),
+                        Some(channel.clone()),
+                        subscriptions.len(kind),
+                    );
                     dst.write_frame(response).await?;
                 }
             }
 
-            Command::Subscribe(cmd) => self.channels.extend(cmd.channels),
+            Command::Subscribe(cmd) => {
+                self.channels.extend(cmd.channels);
+                self.patterns.extend(cmd.patterns);
+                self.shard_channels.extend(cmd.shard_channels);
+            }
             cmd => {
                 warn!(
                     "could not handle command in subscribe, dropped, received cmd: {:?}",
@@ -159,70 +334,171 @@ impl Subscribe {
 
 async fn subscribe_channel(
     channel_name: String,
-    subscriptions: &mut StreamMap<String, Messages>,
+    subscriptions: &mut Subscriptions,
     db: DB,
     dst: &mut AsyncConnection,
 ) -> Result<()> {
-    let mut rx = db.subscribe(channel_name.clone());
+    let rx = db.subscribe(channel_name.clone());
+    let name = channel_name.clone();
+    let rx = messages(rx, move |msg| {
+        make_message_frame("message", name.clone(), msg)
+    });
 
-    let rx = Box::pin(async_stream::stream! {
-        loop {
-            match rx.recv().await {
-                // yield message
-                Ok(msg) => yield msg,
-                Err(broadcast::error::RecvError::Lagged(_)) => {}
-                Err(_) => break,
-            }
-        }
+    let kind = SubscriptionKind::Channel;
+    subscriptions.insert(kind, channel_name.clone(), rx);
+
+    let response = make_subscribe_frame(
+        kind.subscribe_reply(),
+        channel_name,
+        subscriptions.len(kind),
+    );
+    dst.write_frame(response).await?;
+
+    Ok(())
+}
+
+async fn subscribe_pattern(
+    pattern: String,
+    subscriptions: &mut Subscriptions,
+    db: DB,
+    dst: &mut AsyncConnection,
+) -> Result<()> {
+    let rx = db.psubscribe(pattern.clone());
+    let name = pattern.clone();
+    let rx = messages(rx, move |(channel, msg)| {
+        make_pmessage_frame(name.clone(), channel, msg)
     });
 
-    subscriptions.insert(channel_name.clone(), rx);
+    let kind = SubscriptionKind::Pattern;
+    subscriptions.insert(kind, pattern.clone(), rx);
 
-    let response = make_subscribe_frame(channel_name, subscriptions.len());
+    let response = make_subscribe_frame(kind.subscribe_reply(), pattern, subscriptions.len(kind));
     dst.write_frame(response).await?;
 
     Ok(())
 }
 
-fn make_message_frame(channel_name: String, message: Bytes) -> Frame {
-    Frame::Array(vec![
-        Frame::BulkString(Bytes::from_static(b"message")),
+async fn subscribe_shard_channel(
+    channel_name: String,
+    subscriptions: &mut Subscriptions,
+    db: DB,
+    dst: &mut AsyncConnection,
+) -> Result<()> {
+    let rx = db.ssubscribe(channel_name.clone());
+    let name = channel_name.clone();
+    let rx = messages(rx, move |msg| {
+        make_message_frame("smessage", name.clone(), msg)
+    });
+
+    let kind = SubscriptionKind::ShardChannel;
+    subscriptions.insert(kind, channel_name.clone(), rx);
+
+    let response = make_subscribe_frame(
+        kind.subscribe_reply(),
+        channel_name,
+        subscriptions.len(kind),
+    );
+    dst.write_frame(response).await?;
+
+    Ok(())
+}
+
+// the frames of the messages received by the subscription
+fn messages<T, F>(mut rx: Subscriber<T>, make_frame: F) -> Messages
+where
+    T: Clone + Send + Sync + 'static,
+    F: Fn(T) -> Frame + Send + Sync + 'static,
+{
+    Box::pin(async_stream::stream! {
+        loop {
+            match rx.next_message().await {
+                // yield message
+                Ok(Some(msg)) => yield Ok(make_frame(msg)),
+                Ok(None) => break,
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+// the client is over pubsub-output-limit, it's closed without a reply
+fn disconnect(dst: &mut AsyncConnection) -> Frame {
+    warn!(
+        "disconnect client {} over the pub/sub output buffer limit",
+        dst.id()
+    );
+    dst.close();
+    Frame::Nil
+}
+
+// pub/sub messages are pushed to the client out of the request/response order,
+// they are RESP3 push frames and downgrade to arrays for RESP2 clients
+// shard channel messages are smessage
+fn make_message_frame(kind: &'static str, channel_name: String, message: Bytes) -> Frame {
+    Frame::Push(vec![
+        Frame::BulkString(Bytes::from_static(kind.as_bytes())),
         Frame::BulkString(Bytes::from(channel_name)),
         Frame::BulkString(message),
     ])
 }
 
-fn make_subscribe_frame(channel_name: String, num_subscriptions: usize) -> Frame {
-    Frame::Array(vec![
-        Frame::BulkString(Bytes::from_static(b"message")),
+fn make_pmessage_frame(pattern: String, channel_name: String, message: Bytes) -> Frame {
+    Frame::Push(vec![
+        Frame::BulkString(Bytes::from_static(b"pmessage")),
+        Frame::BulkString(Bytes::from(pattern)),
         Frame::BulkString(Bytes::from(channel_name)),
-        Frame::Integer(num_subscriptions as i64),
+        Frame::BulkString(message),
     ])
 }
 
-fn make_unsubscribe_frame(channel_name: String, num_subscriptions: usize) -> Frame {
-    Frame::Array(vec![
-        Frame::BulkString(Bytes::from_static(b"unsubscribe")),
+fn make_subscribe_frame(
+    kind: &'static str,
+    channel_name: String,
+    num_subscriptions: usize,
+) -> Frame {
+    Frame::Push(vec![
+        Frame::BulkString(Bytes::from_static(kind.as_bytes())),
         Frame::BulkString(Bytes::from(channel_name)),
         Frame::Integer(num_subscriptions as i64),
     ])
 }
 
-// Unsubscribe from a channel
+fn make_unsubscribe_frame(
+    kind: &'static str,
+    channel_name: Option<String>,
+    num_subscriptions: usize,
+) -> Frame {
+    Frame::Push(vec![
+        Frame::BulkString(Bytes::from_static(kind.as_bytes())),
+        channel_name.map_or(Frame::Nil, |name| Frame::BulkString(Bytes::from(name))),
+        Frame::Integer(num_subscriptions as i64),
+    ])
+}
+
+// Unsubscribe from a channel, or a pattern with PUNSUBSCRIBE
 // can only be used after subscribe
 #[derive(Debug)]
 pub struct Unsubscribe {
     channels: Vec<String>,
+    kind: SubscriptionKind,
 }
 
 impl Unsubscribe {
-    fn new(channels: Vec<String>) -> Self {
-        Self { channels }
+    fn new(channels: Vec<String>, kind: SubscriptionKind) -> Self {
+        Self { channels, kind }
     }
 
     pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
         let mut iter = frames.into_iter();
-        check_cmd(&mut iter, b"UNSUBSCRIBE")?;
+        let kind = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "UNSUBSCRIBE" => SubscriptionKind::Channel,
+            "PUNSUBSCRIBE" => SubscriptionKind::Pattern,
+            "SUNSUBSCRIBE" => SubscriptionKind::ShardChannel,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
         let mut channels = Vec::new();
         for next in iter {
             match next {
@@ -235,7 +511,7 @@ impl Unsubscribe {
                 _ => return Err(RedisErr::FrameMalformed),
             }
         }
-        Ok(Self::new(channels))
+        Ok(Self::new(channels, kind))
     }
 
     pub fn apply(self, _db: &mut DB) -> Frame {
@@ -247,6 +523,101 @@ impl Unsubscribe {
     }
 } // impl Unsubscribe
 
+#[derive(Debug)]
+enum PubSubOption {
+    Channels(Option<String>),
+    NumSub(Vec<String>),
+    NumPat,
+    ShardChannels(Option<String>),
+    ShardNumSub(Vec<String>),
+}
+
+// PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT
+// PUBSUB SHARDCHANNELS [pattern] | SHARDNUMSUB [shardchannel ...]
+#[derive(Debug)]
+pub struct PubSub {
+    option: PubSubOption,
+}
+
+impl PubSub {
+    fn new(option: PubSubOption) -> Self {
+        Self { option }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"PUBSUB")?;
+        let option = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "CHANNELS" => PubSubOption::Channels(next_pattern(&mut iter)?),
+            "NUMSUB" => PubSubOption::NumSub(next_channels(&mut iter)?),
+            "NUMPAT" => PubSubOption::NumPat,
+            "SHARDCHANNELS" => PubSubOption::ShardChannels(next_pattern(&mut iter)?),
+            "SHARDNUMSUB" => PubSubOption::ShardNumSub(next_channels(&mut iter)?),
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(option))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match self.option {
+            PubSubOption::Channels(pattern) => {
+                make_channels_frame(db.pubsub_channels(pattern.as_deref()))
+            }
+            PubSubOption::NumSub(channels) => {
+                make_numsub_frame(channels, |channel| db.pubsub_numsub(channel))
+            }
+            PubSubOption::NumPat => Frame::Integer(db.pubsub_numpat() as i64),
+            PubSubOption::ShardChannels(pattern) => {
+                make_channels_frame(db.pubsub_shardchannels(pattern.as_deref()))
+            }
+            PubSubOption::ShardNumSub(channels) => {
+                make_numsub_frame(channels, |channel| db.pubsub_shardnumsub(channel))
+            }
+        }
+    }
+} // impl PubSub
+
+fn next_pattern(iter: &mut std::vec::IntoIter<Frame>) -> Result<Option<String>> {
+    if iter.len() > 0 {
+        Ok(Some(next_string(iter)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn next_channels(iter: &mut std::vec::IntoIter<Frame>) -> Result<Vec<String>> {
+    let mut channels = Vec::new();
+    while iter.len() > 0 {
+        channels.push(next_string(iter)?);
+    }
+    Ok(channels)
+}
+
+fn make_channels_frame(channels: Vec<String>) -> Frame {
+    Frame::Array(
+        channels
+            .into_iter()
+            .map(|channel| Frame::BulkString(Bytes::from(channel)))
+            .collect(),
+    )
+}
+
+// channel and count one after another
+fn make_numsub_frame(channels: Vec<String>, count: impl Fn(&str) -> usize) -> Frame {
+    Frame::Array(
+        channels
+            .into_iter()
+            .flat_map(|channel| {
+                let n = count(&channel) as i64;
+                [Frame::BulkString(Bytes::from(channel)), Frame::Integer(n)]
+            })
+            .collect(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -269,7 +640,80 @@ mod tests {
         ]);
         assert_eq!(
             cmd.unwrap().channels,
-            Subscribe::new(vec!["channel".to_string()]).channels
+            Subscribe::new(SubscriptionKind::Channel, vec!["channel".to_string()]).channels
+        );
+
+        let cmd = Subscribe::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"PSUBSCRIBE")),
+            Frame::BulkString(Bytes::from_static(b"news.*")),
+        ])
+        .unwrap();
+        assert!(cmd.channels.is_empty());
+        assert_eq!(cmd.patterns, vec!["news.*".to_string()]);
+
+        let cmd =
+            Unsubscribe::from_frames(vec![Frame::BulkString(Bytes::from_static(b"PUNSUBSCRIBE"))])
+                .unwrap();
+        assert!(cmd.kind == SubscriptionKind::Pattern && cmd.channels.is_empty());
+
+        let cmd = Subscribe::from_frames(vec![
+            Frame::BulkString(Bytes::from_static(b"SSUBSCRIBE")),
+            Frame::BulkString(Bytes::from_static(b"{user}.news")),
+        ])
+        .unwrap();
+        assert!(cmd.is_shard() && cmd.channels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pubsub() {
+        let mut db = DB::new();
+        let pubsub = |args: &[&str]| {
+            let mut frames = vec![Frame::BulkString(Bytes::from_static(b"PUBSUB"))];
+            frames.extend(
+                args.iter()
+                    .map(|arg| Frame::BulkString(Bytes::from(arg.to_string()))),
+            );
+            PubSub::from_frames(frames).unwrap()
+        };
+
+        let first = db.subscribe("news.tech".to_string());
+        let second = db.subscribe("news.tech".to_string());
+        let _other = db.subscribe("weather".to_string());
+        let pattern = db.psubscribe("news.*".to_string());
+        assert_eq!(
+            pubsub(&["CHANNELS", "news.*"]).apply(&mut db),
+            Frame::Array(vec![Frame::BulkString(Bytes::from("news.tech"))])
         );
+        assert_eq!(
+            pubsub(&["NUMSUB", "news.tech", "nochannel"]).apply(&mut db),
+            Frame::Array(vec![
+                Frame::BulkString(Bytes::from("news.tech")),
+                Frame::Integer(2),
+                Frame::BulkString(Bytes::from("nochannel")),
+                Frame::Integer(0),
+            ])
+        );
+        assert_eq!(pubsub(&["NUMPAT"]).apply(&mut db), Frame::Integer(1));
+        let _shard = db.ssubscribe("news.tech".to_string());
+        assert_eq!(
+            pubsub(&["SHARDNUMSUB", "news.tech"]).apply(&mut db),
+            Frame::Array(vec![
+                Frame::BulkString(Bytes::from("news.tech")),
+                Frame::Integer(1),
+            ])
+        );
+        assert_eq!(
+            pubsub(&["SHARDCHANNELS", "weather"]).apply(&mut db),
+            Frame::Array(vec![])
+        );
+
+        // the channel is reclaimed with its last subscriber
+        drop(first);
+        assert_eq!(db.pubsub_numsub("news.tech"), 1);
+        drop(second);
+        drop(pattern);
+        assert_eq!(db.pubsub_channels(None), vec!["weather".to_string()]);
+        assert_eq!(db.pubsub_numpat(), 0);
+        assert_eq!(db.publish("news.tech".to_string(), Bytes::from("x")), 0);
     }
 }
@@ -0,0 +1,198 @@
+//! Replication commands
+
+use super::*;
+use crate::connection::AsyncConnection;
+use crate::db::DB;
+use crate::frame::Frame;
+use crate::server::replication;
+use crate::Result;
+
+use std::time::Duration;
+
+// REPLICAOF host port | NO ONE
+#[derive(Debug)]
+pub struct ReplicaOf {
+    primary: Option<(String, u16)>,
+}
+
+impl ReplicaOf {
+    fn new(primary: Option<(String, u16)>) -> Self {
+        Self { primary }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"REPLICAOF")?;
+        let host = next_string(&mut iter)?;
+        let port = next_string(&mut iter)?;
+        if host.eq_ignore_ascii_case("NO") && port.eq_ignore_ascii_case("ONE") {
+            return Ok(Self::new(None));
+        }
+        let port = port.parse::<u16>().map_err(|_| RedisErr::InvalidArgument)?;
+        Ok(Self::new(Some((host, port))))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        replication::replicaof(db, self.primary);
+        Frame::SimpleString("OK".to_string())
+    }
+}
+
+#[derive(Debug)]
+enum ReplConfOption {
+    ListeningPort(u16),
+    Capa,
+    // ACK is only expected on the replication link
+    Ack,
+}
+
+// REPLCONF listening-port port | capa capability | ACK offset
+// sent by a replica before PSYNC
+#[derive(Debug)]
+pub struct ReplConf {
+    option: ReplConfOption,
+}
+
+impl ReplConf {
+    fn new(option: ReplConfOption) -> Self {
+        Self { option }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"REPLCONF")?;
+        let option = match next_string(&mut iter)?.to_lowercase().as_str() {
+            "listening-port" => ReplConfOption::ListeningPort(
+                next_string(&mut iter)?
+                    .parse()
+                    .map_err(|_| RedisErr::InvalidArgument)?,
+            ),
+            "capa" => ReplConfOption::Capa,
+            "ack" => ReplConfOption::Ack,
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        Ok(Self::new(option))
+    }
+
+    pub fn apply(self, _db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        match self.option {
+            ReplConfOption::ListeningPort(port) => dst.set_listening_port(port),
+            ReplConfOption::Capa => {}
+            ReplConfOption::Ack => {
+                return Frame::Error("ERR REPLCONF ACK out of a replication link".to_string())
+            }
+        }
+        Frame::SimpleString("OK".to_string())
+    }
+}
+
+// PSYNC replicationid offset
+// the connection becomes the replication link of the replica
+#[derive(Debug)]
+pub struct Psync {
+    replid: String,
+    offset: i64,
+}
+
+impl Psync {
+    fn new(replid: String, offset: i64) -> Self {
+        Self { replid, offset }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"PSYNC")?;
+        let replid = next_string(&mut iter)?;
+        let offset = next_integer(&mut iter)?;
+        Ok(Self::new(replid, offset))
+    }
+
+    // returns once the link is closed
+    pub async fn apply(
+        self,
+        db: &mut DB,
+        dst: &mut AsyncConnection,
+        shutdown: Arc<Notify>,
+    ) -> Result<()> {
+        replication::serve_replica(db, dst, self.replid, self.offset, shutdown).await
+    }
+}
+
+// WAIT numreplicas timeout
+// block until the writes of the connection are acknowledged by numreplicas replicas,
+// a timeout of 0 blocks forever
+#[derive(Debug)]
+pub struct Wait {
+    numreplicas: usize,
+    timeout: Option<Duration>,
+}
+
+impl Wait {
+    fn new(numreplicas: usize, timeout: Option<Duration>) -> Self {
+        Self {
+            numreplicas,
+            timeout,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"WAIT")?;
+        let numreplicas = next_integer(&mut iter)?;
+        let timeout = next_integer(&mut iter)?;
+        if numreplicas < 0 || timeout < 0 {
+            return Err(RedisErr::InvalidArgument);
+        }
+        let timeout = match timeout {
+            0 => None,
+            ms => Some(Duration::from_millis(ms as u64)),
+        };
+        Ok(Self::new(numreplicas as usize, timeout))
+    }
+
+    pub async fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        if db.is_replica() {
+            return Frame::Error("ERR WAIT cannot be used with replica instances.".to_string());
+        }
+        let acked = db
+            .replication()
+            .wait(self.numreplicas, dst.write_offset(), self.timeout)
+            .await;
+        Frame::Integer(acked as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::command;
+
+    #[tokio::test]
+    async fn test_replicaof() {
+        let mut db = DB::new();
+        assert!(!db.is_replica());
+
+        let cmd = ReplicaOf::from_frames(command(&["replicaof", "127.0.0.1", "0"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::SimpleString("OK".to_string()));
+        assert!(db.is_replica());
+        assert!(db.replication().info().contains("master_link_status:down"));
+
+        let cmd = ReplicaOf::from_frames(command(&["replicaof", "no", "one"])).unwrap();
+        assert_eq!(cmd.apply(&mut db), Frame::SimpleString("OK".to_string()));
+        assert!(!db.is_replica());
+
+        assert!(ReplicaOf::from_frames(command(&["replicaof", "127.0.0.1", "port"])).is_err());
+    }
+}
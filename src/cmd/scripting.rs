@@ -0,0 +1,380 @@
+//! Scripting commands
+
+use super::*;
+use crate::connection::AsyncConnection;
+use crate::db::DB;
+use crate::frame::Frame;
+use crate::rdb;
+use crate::server::scripting::RestorePolicy;
+use crate::Result;
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+// a script may run for long, the other tasks of the worker are moved to another thread
+fn block_in_place<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(f),
+        _ => f(),
+    }
+}
+
+// numkeys key [key ...] arg [arg ...]
+fn next_keys_args(iter: &mut std::vec::IntoIter<Frame>) -> Result<(Vec<Bytes>, Vec<Bytes>)> {
+    let numkeys = next_integer(iter)?;
+    if numkeys < 0 || numkeys as usize > iter.len() {
+        return Err(RedisErr::InvalidArgument);
+    }
+    let mut keys = Vec::with_capacity(numkeys as usize);
+    for _ in 0..numkeys {
+        keys.push(next_bytes(iter)?);
+    }
+    let mut args = Vec::with_capacity(iter.len());
+    while iter.len() > 0 {
+        args.push(next_bytes(iter)?);
+    }
+    Ok((keys, args))
+}
+
+// EVAL script numkeys key [key ...] arg [arg ...]
+// EVAL_RO is the same but the script can't write
+#[derive(Debug)]
+pub struct Eval {
+    script: Bytes,
+    keys: Vec<Bytes>,
+    args: Vec<Bytes>,
+    read_only: bool,
+}
+
+impl Eval {
+    fn new(script: Bytes, keys: Vec<Bytes>, args: Vec<Bytes>, read_only: bool) -> Self {
+        Self {
+            script,
+            keys,
+            args,
+            read_only,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        let read_only = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "EVAL" => false,
+            "EVAL_RO" => true,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let script = next_bytes(&mut iter)?;
+        let (keys, args) = next_keys_args(&mut iter)?;
+        Ok(Self::new(script, keys, args, read_only))
+    }
+
+    pub async fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        let _transaction = db.transaction_guard().await;
+        let resp = block_in_place(|| {
+            db.scripting()
+                .eval(db, self.script, self.keys, self.args, self.read_only)
+        });
+        dst.set_write_offset(db.replication().offset());
+        resp
+    }
+}
+
+// EVALSHA sha1 numkeys key [key ...] arg [arg ...]
+// EVALSHA_RO is the same but the script can't write
+#[derive(Debug)]
+pub struct EvalSha {
+    sha: String,
+    keys: Vec<Bytes>,
+    args: Vec<Bytes>,
+    read_only: bool,
+}
+
+impl EvalSha {
+    fn new(sha: String, keys: Vec<Bytes>, args: Vec<Bytes>, read_only: bool) -> Self {
+        Self {
+            sha,
+            keys,
+            args,
+            read_only,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        let read_only = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "EVALSHA" => false,
+            "EVALSHA_RO" => true,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let sha = next_string(&mut iter)?;
+        let (keys, args) = next_keys_args(&mut iter)?;
+        Ok(Self::new(sha, keys, args, read_only))
+    }
+
+    pub async fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        let _transaction = db.transaction_guard().await;
+        let resp = block_in_place(|| {
+            db.scripting()
+                .eval_sha(db, &self.sha, self.keys, self.args, self.read_only)
+        });
+        dst.set_write_offset(db.replication().offset());
+        resp
+    }
+}
+
+#[derive(Debug)]
+enum ScriptOption {
+    Load(Bytes),
+    Exists(Vec<String>),
+    Flush,
+    Kill,
+}
+
+// SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC] | KILL
+// it's not applied under the command guard, so SCRIPT KILL can reach a running script
+#[derive(Debug)]
+pub struct Script {
+    option: ScriptOption,
+}
+
+impl Script {
+    fn new(option: ScriptOption) -> Self {
+        Self { option }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SCRIPT")?;
+        let option = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "LOAD" => ScriptOption::Load(next_bytes(&mut iter)?),
+            "EXISTS" => {
+                let mut shas = Vec::new();
+                while iter.len() > 0 {
+                    shas.push(next_string(&mut iter)?);
+                }
+                if shas.is_empty() {
+                    return Err(RedisErr::WrongNumberOfArguments);
+                }
+                ScriptOption::Exists(shas)
+            }
+            "FLUSH" => {
+                if iter.len() > 0 {
+                    match next_string(&mut iter)?.to_uppercase().as_str() {
+                        "ASYNC" | "SYNC" => {}
+                        _ => return Err(RedisErr::SyntaxError),
+                    }
+                }
+                ScriptOption::Flush
+            }
+            "KILL" => ScriptOption::Kill,
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(option))
+    }
+
+    pub fn is_kill(&self) -> bool {
+        matches!(self.option, ScriptOption::Kill)
+    }
+
+    pub fn apply(self, db: &mut DB, _dst: &mut AsyncConnection) -> Frame {
+        let scripting = db.scripting();
+        match self.option {
+            ScriptOption::Load(script) => Frame::BulkString(Bytes::from(scripting.load(script))),
+            ScriptOption::Exists(shas) => Frame::Array(
+                shas.iter()
+                    .map(|sha| Frame::Integer(scripting.exists(sha) as i64))
+                    .collect(),
+            ),
+            ScriptOption::Flush => {
+                scripting.flush();
+                Frame::SimpleString("OK".to_string())
+            }
+            ScriptOption::Kill => match scripting.kill() {
+                Ok(()) => Frame::SimpleString("OK".to_string()),
+                Err(e) => Frame::Error(e),
+            },
+        }
+    }
+}
+
+// FCALL function numkeys key [key ...] arg [arg ...]
+// FCALL_RO only calls the functions registered with the no-writes flag
+#[derive(Debug)]
+pub struct FCall {
+    function: String,
+    keys: Vec<Bytes>,
+    args: Vec<Bytes>,
+    read_only: bool,
+}
+
+impl FCall {
+    fn new(function: String, keys: Vec<Bytes>, args: Vec<Bytes>, read_only: bool) -> Self {
+        Self {
+            function,
+            keys,
+            args,
+            read_only,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        let read_only = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "FCALL" => false,
+            "FCALL_RO" => true,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let function = next_string(&mut iter)?;
+        let (keys, args) = next_keys_args(&mut iter)?;
+        Ok(Self::new(function, keys, args, read_only))
+    }
+
+    pub async fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        let _transaction = db.transaction_guard().await;
+        let resp = block_in_place(|| {
+            db.scripting()
+                .fcall(db, &self.function, self.keys, self.args, self.read_only)
+        });
+        dst.set_write_offset(db.replication().offset());
+        resp
+    }
+}
+
+#[derive(Debug)]
+enum FunctionOption {
+    Load {
+        code: Bytes,
+        replace: bool,
+    },
+    Delete(String),
+    List {
+        pattern: Option<String>,
+        with_code: bool,
+    },
+    Dump,
+    Restore {
+        payload: Bytes,
+        policy: RestorePolicy,
+    },
+    Flush,
+    Kill,
+}
+
+// FUNCTION LOAD [REPLACE] code | DELETE library | LIST [LIBRARYNAME pattern] [WITHCODE]
+// | DUMP | RESTORE payload [FLUSH|APPEND|REPLACE] | FLUSH [ASYNC|SYNC] | KILL
+// the commands changing the libraries are propagated like writes
+#[derive(Debug)]
+pub struct Function {
+    option: FunctionOption,
+}
+
+impl Function {
+    fn new(option: FunctionOption) -> Self {
+        Self { option }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"FUNCTION")?;
+        let option = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "LOAD" => {
+                let mut code = next_bytes(&mut iter)?;
+                let replace = code.eq_ignore_ascii_case(b"REPLACE") && iter.len() > 0;
+                if replace {
+                    code = next_bytes(&mut iter)?;
+                }
+                FunctionOption::Load { code, replace }
+            }
+            "DELETE" => FunctionOption::Delete(next_string(&mut iter)?),
+            "LIST" => {
+                let (mut pattern, mut with_code) = (None, false);
+                while iter.len() > 0 {
+                    match next_string(&mut iter)?.to_uppercase().as_str() {
+                        "LIBRARYNAME" if pattern.is_none() => {
+                            pattern = Some(next_string(&mut iter)?)
+                        }
+                        "WITHCODE" if !with_code => with_code = true,
+                        _ => return Err(RedisErr::SyntaxError),
+                    }
+                }
+                FunctionOption::List { pattern, with_code }
+            }
+            "DUMP" => FunctionOption::Dump,
+            "RESTORE" => {
+                let payload = next_bytes(&mut iter)?;
+                let policy = if iter.len() > 0 {
+                    match next_string(&mut iter)?.to_uppercase().as_str() {
+                        "FLUSH" => RestorePolicy::Flush,
+                        "APPEND" => RestorePolicy::Append,
+                        "REPLACE" => RestorePolicy::Replace,
+                        _ => return Err(RedisErr::SyntaxError),
+                    }
+                } else {
+                    RestorePolicy::Append
+                };
+                FunctionOption::Restore { payload, policy }
+            }
+            "FLUSH" => {
+                if iter.len() > 0 {
+                    match next_string(&mut iter)?.to_uppercase().as_str() {
+                        "ASYNC" | "SYNC" => {}
+                        _ => return Err(RedisErr::SyntaxError),
+                    }
+                }
+                FunctionOption::Flush
+            }
+            "KILL" => FunctionOption::Kill,
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(option))
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self.option,
+            FunctionOption::Load { .. }
+                | FunctionOption::Delete(_)
+                | FunctionOption::Restore { .. }
+                | FunctionOption::Flush
+        )
+    }
+
+    pub fn is_kill(&self) -> bool {
+        matches!(self.option, FunctionOption::Kill)
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let scripting = db.scripting();
+        let resp = match self.option {
+            FunctionOption::Load { code, replace } => scripting
+                .function_load(code, replace)
+                .map(|name| Frame::BulkString(Bytes::from(name))),
+            FunctionOption::Delete(name) => scripting.function_delete(&name).map(|_| ok()),
+            FunctionOption::List { pattern, with_code } => {
+                Ok(scripting.function_list(pattern.as_deref(), with_code))
+            }
+            FunctionOption::Dump => rdb::dump_functions(&scripting.function_codes())
+                .map(|payload| Frame::BulkString(Bytes::from(payload)))
+                .map_err(|e| e.to_string()),
+            FunctionOption::Restore { payload, policy } => rdb::restore_functions(&payload)
+                .map_err(|_| "ERR payload version or checksum are wrong".to_string())
+                .and_then(|codes| scripting.function_restore(codes, policy))
+                .map(|_| ok()),
+            FunctionOption::Flush => {
+                scripting.function_flush();
+                Ok(ok())
+            }
+            FunctionOption::Kill => scripting.kill().map(|_| ok()),
+        };
+        resp.unwrap_or_else(Frame::Error)
+    }
+}
+
+fn ok() -> Frame {
+    Frame::SimpleString("OK".to_string())
+}
@@ -0,0 +1,692 @@
+//! Set commands
+
+use super::*;
+
+use crate::db::{SetOp, DB};
+use crate::frame::Frame;
+use crate::{RedisErr, Result};
+
+use bytes::Bytes;
+
+// SADD key member [member ...]
+#[derive(Debug)]
+pub struct SAdd {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+impl SAdd {
+    fn new(key: String, members: Vec<Bytes>) -> Self {
+        Self { key, members }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SADD")?;
+        let key = next_string(&mut iter)?;
+        let members = next_members(&mut iter)?;
+        Ok(Self::new(key, members))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.sadd(&self.key, self.members) {
+            Ok(added) => Frame::Integer(added as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SREM key member [member ...]
+#[derive(Debug)]
+pub struct SRem {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+impl SRem {
+    fn new(key: String, members: Vec<Bytes>) -> Self {
+        Self { key, members }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SREM")?;
+        let key = next_string(&mut iter)?;
+        let members = next_members(&mut iter)?;
+        Ok(Self::new(key, members))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.srem(&self.key, &self.members) {
+            Ok(removed) => Frame::Integer(removed as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SMEMBERS key
+#[derive(Debug)]
+pub struct SMembers {
+    key: String,
+}
+
+impl SMembers {
+    fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SMEMBERS")?;
+        let key = next_string(&mut iter)?;
+        Ok(Self::new(key))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.smembers(&self.key) {
+            Ok(members) => make_set_frame(members),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SISMEMBER key member
+#[derive(Debug)]
+pub struct SIsMember {
+    key: String,
+    member: Bytes,
+}
+
+impl SIsMember {
+    fn new(key: String, member: Bytes) -> Self {
+        Self { key, member }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 3 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SISMEMBER")?;
+        let key = next_string(&mut iter)?;
+        let member = next_bytes(&mut iter)?;
+        Ok(Self::new(key, member))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.smismember(&self.key, &[self.member]) {
+            Ok(found) => Frame::Integer(found[0] as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SMISMEMBER key member [member ...]
+#[derive(Debug)]
+pub struct SMIsMember {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+impl SMIsMember {
+    fn new(key: String, members: Vec<Bytes>) -> Self {
+        Self { key, members }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SMISMEMBER")?;
+        let key = next_string(&mut iter)?;
+        let members = next_members(&mut iter)?;
+        Ok(Self::new(key, members))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.smismember(&self.key, &self.members) {
+            Ok(found) => Frame::Array(
+                found
+                    .into_iter()
+                    .map(|found| Frame::Integer(found as i64))
+                    .collect(),
+            ),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SCARD key
+#[derive(Debug)]
+pub struct SCard {
+    key: String,
+}
+
+impl SCard {
+    fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SCARD")?;
+        let key = next_string(&mut iter)?;
+        Ok(Self::new(key))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.scard(&self.key) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SPOP key [count]
+// without count a single member is replied, nil if the set doesn't exist
+#[derive(Debug)]
+pub struct SPop {
+    key: String,
+    count: Option<usize>,
+}
+
+impl SPop {
+    fn new(key: String, count: Option<usize>) -> Self {
+        Self { key, count }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SPOP")?;
+        let key = next_string(&mut iter)?;
+        let count = match iter.len() {
+            0 => None,
+            1 => {
+                let count = next_integer(&mut iter)?;
+                if count < 0 {
+                    return Err(RedisErr::InvalidArgument);
+                }
+                Some(count as usize)
+            }
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        Ok(Self::new(key, count))
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match (db.spop(&self.key, self.count.unwrap_or(1)), self.count) {
+            (Ok(members), Some(_)) => make_set_frame(members),
+            (Ok(members), None) => members
+                .into_iter()
+                .next()
+                .map_or(Frame::Nil, Frame::BulkString),
+            (Err(e), _) => error_frame(e),
+        }
+    }
+}
+
+// SRANDMEMBER key [count]
+// a negative count may return the same member multiple times
+#[derive(Debug)]
+pub struct SRandMember {
+    key: String,
+    count: Option<i64>,
+}
+
+impl SRandMember {
+    fn new(key: String, count: Option<i64>) -> Self {
+        Self { key, count }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SRANDMEMBER")?;
+        let key = next_string(&mut iter)?;
+        let count = match iter.len() {
+            0 => None,
+            1 => Some(next_integer(&mut iter)?),
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        Ok(Self::new(key, count))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match (
+            db.srandmember(&self.key, self.count.unwrap_or(1)),
+            self.count,
+        ) {
+            (Ok(members), Some(_)) => {
+                Frame::Array(members.into_iter().map(Frame::BulkString).collect())
+            }
+            (Ok(members), None) => members
+                .into_iter()
+                .next()
+                .map_or(Frame::Nil, Frame::BulkString),
+            (Err(e), _) => error_frame(e),
+        }
+    }
+}
+
+// SMOVE source destination member
+#[derive(Debug)]
+pub struct SMove {
+    source: String,
+    destination: String,
+    member: Bytes,
+}
+
+impl SMove {
+    fn new(source: String, destination: String, member: Bytes) -> Self {
+        Self {
+            source,
+            destination,
+            member,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 4 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SMOVE")?;
+        let source = next_string(&mut iter)?;
+        let destination = next_string(&mut iter)?;
+        let member = next_bytes(&mut iter)?;
+        Ok(Self::new(source, destination, member))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.smove(&self.source, &self.destination, self.member) {
+            Ok(moved) => Frame::Integer(moved as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SINTER key [key ...]
+// SUNION key [key ...]
+// SDIFF key [key ...]
+#[derive(Debug)]
+pub struct SInter {
+    op: SetOp,
+    keys: Vec<String>,
+}
+
+impl SInter {
+    fn new(op: SetOp, keys: Vec<String>) -> Self {
+        Self { op, keys }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        let op = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "SINTER" => SetOp::Inter,
+            "SUNION" => SetOp::Union,
+            "SDIFF" => SetOp::Diff,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let keys = next_keys(&mut iter)?;
+        Ok(Self::new(op, keys))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.sop(self.op, &self.keys) {
+            Ok(members) => make_set_frame(members.into_iter().collect()),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SINTERSTORE destination key [key ...]
+// SUNIONSTORE destination key [key ...]
+// SDIFFSTORE destination key [key ...]
+#[derive(Debug)]
+pub struct SInterStore {
+    op: SetOp,
+    destination: String,
+    keys: Vec<String>,
+}
+
+impl SInterStore {
+    fn new(op: SetOp, destination: String, keys: Vec<String>) -> Self {
+        Self {
+            op,
+            destination,
+            keys,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        let op = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "SINTERSTORE" => SetOp::Inter,
+            "SUNIONSTORE" => SetOp::Union,
+            "SDIFFSTORE" => SetOp::Diff,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let destination = next_string(&mut iter)?;
+        let keys = next_keys(&mut iter)?;
+        Ok(Self::new(op, destination, keys))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.sopstore(self.op, &self.destination, &self.keys) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// SINTERCARD numkeys key [key ...] [LIMIT limit]
+// the size of the intersection, LIMIT 0 counts all of it
+#[derive(Debug)]
+pub struct SInterCard {
+    keys: Vec<String>,
+    limit: Option<usize>,
+}
+
+impl SInterCard {
+    fn new(keys: Vec<String>, limit: Option<usize>) -> Self {
+        Self { keys, limit }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"SINTERCARD")?;
+        let numkeys = next_integer(&mut iter)?;
+        if numkeys <= 0 || numkeys as usize > iter.len() {
+            return Err(RedisErr::InvalidArgument);
+        }
+        let mut keys = Vec::with_capacity(numkeys as usize);
+        for _ in 0..numkeys {
+            keys.push(next_string(&mut iter)?);
+        }
+        let limit = match iter.len() {
+            0 => None,
+            2 if next_string(&mut iter)?.eq_ignore_ascii_case("LIMIT") => {
+                let limit = next_integer(&mut iter)?;
+                if limit < 0 {
+                    return Err(RedisErr::InvalidArgument);
+                }
+                (limit > 0).then_some(limit as usize)
+            }
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        Ok(Self::new(keys, limit))
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.keys.clone()
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.sintercard(&self.keys, self.limit) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+fn next_keys(iter: &mut std::vec::IntoIter<Frame>) -> Result<Vec<String>> {
+    let mut keys = Vec::with_capacity(iter.len());
+    while iter.len() > 0 {
+        keys.push(next_string(iter)?);
+    }
+    if keys.is_empty() {
+        return Err(RedisErr::WrongNumberOfArguments);
+    }
+    Ok(keys)
+}
+
+// at least one member
+fn next_members(iter: &mut std::vec::IntoIter<Frame>) -> Result<Vec<Bytes>> {
+    let mut members = Vec::with_capacity(iter.len());
+    while iter.len() > 0 {
+        members.push(next_bytes(iter)?);
+    }
+    if members.is_empty() {
+        return Err(RedisErr::WrongNumberOfArguments);
+    }
+    Ok(members)
+}
+
+fn make_set_frame(members: Vec<Bytes>) -> Frame {
+    Frame::Set(members.into_iter().map(Frame::BulkString).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::command;
+
+    fn members(frame: Frame) -> Vec<String> {
+        let (Frame::Set(members) | Frame::Array(members)) = frame else {
+            panic!("unexpected members {:?}", frame);
+        };
+        let mut members = members
+            .into_iter()
+            .map(|member| match member {
+                Frame::BulkString(member) => String::from_utf8(member.to_vec()).unwrap(),
+                frame => panic!("unexpected member {:?}", frame),
+            })
+            .collect::<Vec<_>>();
+        members.sort();
+        members
+    }
+
+    #[tokio::test]
+    async fn test_sadd() {
+        let mut db = DB::new();
+        let sadd = |db: &mut DB, args: &[&str]| SAdd::from_frames(command(args)).unwrap().apply(db);
+        assert_eq!(
+            sadd(&mut db, &["SADD", "s", "a", "b", "a"]),
+            Frame::Integer(2)
+        );
+        assert_eq!(sadd(&mut db, &["SADD", "s", "b", "c"]), Frame::Integer(1));
+        assert_eq!(
+            members(
+                SMembers::from_frames(command(&["SMEMBERS", "s"]))
+                    .unwrap()
+                    .apply(&mut db)
+            ),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            SMIsMember::from_frames(command(&["SMISMEMBER", "s", "a", "x"]))
+                .unwrap()
+                .apply(&mut db),
+            Frame::Array(vec![Frame::Integer(1), Frame::Integer(0)])
+        );
+        assert_eq!(
+            SIsMember::from_frames(command(&["SISMEMBER", "nokey", "a"]))
+                .unwrap()
+                .apply(&mut db),
+            Frame::Integer(0)
+        );
+
+        // the set is deleted with its last member
+        assert_eq!(
+            SRem::from_frames(command(&["SREM", "s", "a", "b", "c", "x"]))
+                .unwrap()
+                .apply(&mut db),
+            Frame::Integer(3)
+        );
+        assert_eq!(db.get_type("s"), None);
+
+        db.set(
+            "string".to_string(),
+            Bytes::from("value"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            sadd(&mut db, &["SADD", "string", "a"]),
+            Frame::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+        assert!(matches!(
+            SCard::from_frames(command(&["SCARD", "string"]))
+                .unwrap()
+                .apply(&mut db),
+            Frame::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_spop() {
+        let mut db = DB::new();
+        SAdd::from_frames(command(&["SADD", "s", "a", "b", "c"]))
+            .unwrap()
+            .apply(&mut db);
+
+        let srandmember =
+            |db: &mut DB, args: &[&str]| SRandMember::from_frames(command(args)).unwrap().apply(db);
+        assert_eq!(
+            members(srandmember(&mut db, &["SRANDMEMBER", "s", "5"])),
+            vec!["a", "b", "c"]
+        );
+        // the same member may be repeated
+        let repeated = members(srandmember(&mut db, &["SRANDMEMBER", "s", "-5"]));
+        assert_eq!(repeated.len(), 5);
+        assert!(repeated
+            .iter()
+            .all(|m| ["a", "b", "c"].contains(&m.as_str())));
+        assert_eq!(srandmember(&mut db, &["SRANDMEMBER", "nokey"]), Frame::Nil);
+
+        let popped = members(
+            SPop::from_frames(command(&["SPOP", "s", "2"]))
+                .unwrap()
+                .apply(&mut db),
+        );
+        assert_eq!(popped.len(), 2);
+        assert_eq!(db.scard("s"), Ok(1));
+        assert!(matches!(
+            SPop::from_frames(command(&["SPOP", "s"]))
+                .unwrap()
+                .apply(&mut db),
+            Frame::BulkString(_)
+        ));
+        assert_eq!(db.get_type("s"), None);
+        assert!(SPop::from_frames(command(&["SPOP", "s", "-1"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_smove() {
+        let mut db = DB::new();
+        SAdd::from_frames(command(&["SADD", "src", "a"]))
+            .unwrap()
+            .apply(&mut db);
+        let smove =
+            |db: &mut DB, args: &[&str]| SMove::from_frames(command(args)).unwrap().apply(db);
+        assert_eq!(
+            smove(&mut db, &["SMOVE", "src", "dst", "x"]),
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            smove(&mut db, &["SMOVE", "src", "src", "a"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            smove(&mut db, &["SMOVE", "src", "dst", "a"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(db.get_type("src"), None);
+        assert_eq!(db.smembers("dst"), Ok(vec![Bytes::from("a")]));
+
+        db.set(
+            "string".to_string(),
+            Bytes::from("value"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            smove(&mut db, &["SMOVE", "dst", "string", "a"]),
+            Frame::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+        assert_eq!(db.scard("dst"), Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_sinter() {
+        let mut db = DB::new();
+        for args in [
+            ["SADD", "a", "1", "2", "3"],
+            ["SADD", "b", "2", "3", "4"],
+            ["SADD", "c", "3", "4", "5"],
+        ] {
+            SAdd::from_frames(command(&args)).unwrap().apply(&mut db);
+        }
+        let sop =
+            |db: &mut DB, args: &[&str]| SInter::from_frames(command(args)).unwrap().apply(db);
+        assert_eq!(members(sop(&mut db, &["SINTER", "a", "b", "c"])), vec!["3"]);
+        assert!(members(sop(&mut db, &["SINTER", "a", "nokey"])).is_empty());
+        assert_eq!(
+            members(sop(&mut db, &["SUNION", "a", "c", "nokey"])),
+            vec!["1", "2", "3", "4", "5"]
+        );
+        assert_eq!(members(sop(&mut db, &["SDIFF", "a", "b"])), vec!["1"]);
+
+        let store =
+            |db: &mut DB, args: &[&str]| SInterStore::from_frames(command(args)).unwrap().apply(db);
+        assert_eq!(
+            store(&mut db, &["SUNIONSTORE", "dst", "a", "b"]),
+            Frame::Integer(4)
+        );
+        assert_eq!(db.scard("dst"), Ok(4));
+        // an empty result deletes the destination
+        assert_eq!(
+            store(&mut db, &["SDIFFSTORE", "dst", "a", "a"]),
+            Frame::Integer(0)
+        );
+        assert_eq!(db.get_type("dst"), None);
+
+        let sintercard =
+            |db: &mut DB, args: &[&str]| SInterCard::from_frames(command(args)).unwrap().apply(db);
+        assert_eq!(
+            sintercard(&mut db, &["SINTERCARD", "2", "a", "b"]),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            sintercard(&mut db, &["SINTERCARD", "2", "a", "b", "LIMIT", "1"]),
+            Frame::Integer(1)
+        );
+        assert!(SInterCard::from_frames(command(&["SINTERCARD", "3", "a", "b"])).is_err());
+
+        db.set(
+            "string".to_string(),
+            Bytes::from("value"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            sop(&mut db, &["SUNION", "a", "string"]),
+            Frame::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+        // the destination is overwritten whatever it holds
+        assert_eq!(
+            store(&mut db, &["SINTERSTORE", "string", "a", "b"]),
+            Frame::Integer(2)
+        );
+        assert_eq!(db.get_type("string"), Some("set"));
+    }
+}
@@ -5,9 +5,7 @@ use crate::db::DB;
 use crate::frame::Frame;
 use crate::Result;
 
-use marco::Applyer;
-
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct ZAdd {
     key: String,
     nx: bool,
@@ -20,6 +18,7 @@ pub struct ZAdd {
 }
 
 impl ZAdd {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         key: String,
         nx: bool,
@@ -124,7 +123,7 @@ impl ZAdd {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct ZCard {
     key: String,
 }
@@ -158,7 +157,7 @@ impl ZCard {
     }
 }
 
-#[derive(Debug, Applyer)]
+#[derive(Debug)]
 pub struct ZRem {
     key: String,
     members: Vec<Bytes>,
@@ -202,8 +201,8 @@ impl ZRem {
 mod test {
     use super::*;
 
-    #[test]
-    fn test_zadd() {
+    #[tokio::test]
+    async fn test_zadd() {
         let mut db = DB::new();
         let cmd = ZAdd::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"zadd")),
@@ -216,8 +215,8 @@ mod test {
         assert_eq!(result, Frame::Integer(1));
     }
 
-    #[test]
-    fn test_zcard() {
+    #[tokio::test]
+    async fn test_zcard() {
         let mut db = DB::new();
         let cmd = ZCard::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"zcard")),
@@ -228,8 +227,8 @@ mod test {
         assert_eq!(result, Frame::Integer(0));
     }
 
-    #[test]
-    fn test_zrem() {
+    #[tokio::test]
+    async fn test_zrem() {
         let mut db = DB::new();
         let cmd = ZRem::from_frames(vec![
             Frame::BulkString(Bytes::from_static(b"zrem")),
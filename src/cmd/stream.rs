@@ -0,0 +1,2002 @@
+//! Stream commands
+
+use super::*;
+use crate::db::DB;
+use crate::frame::Frame;
+use crate::helper::{bulk, unix_timestamp_ms};
+use crate::value::{
+    AutoClaimed, ClaimOptions, ConsumerGroup, NewStreamId, StreamEntry, StreamFields, StreamId,
+    StreamTrim,
+};
+use crate::Result;
+
+use std::time::{Duration, Instant};
+
+// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] *|id field value [field value ...]
+#[derive(Debug)]
+pub struct XAdd {
+    key: String,
+    nomkstream: bool,
+    trim: Option<(StreamTrim, Option<usize>)>,
+    id: NewStreamId,
+    // position of the id in the request, a generated one is propagated in its place
+    id_index: usize,
+    fields: StreamFields,
+}
+
+impl XAdd {
+    fn new(
+        key: String,
+        nomkstream: bool,
+        trim: Option<(StreamTrim, Option<usize>)>,
+        id: NewStreamId,
+        id_index: usize,
+        fields: StreamFields,
+    ) -> Self {
+        Self {
+            key,
+            nomkstream,
+            trim,
+            id,
+            id_index,
+            fields,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let len = frames.len();
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XADD")?;
+        let key = next_string(&mut iter)?;
+
+        let mut nomkstream = false;
+        let mut trim = None;
+        let id = loop {
+            let arg = next_string(&mut iter)?;
+            match arg.to_uppercase().as_str() {
+                "NOMKSTREAM" => nomkstream = true,
+                "MAXLEN" | "MINID" => trim = Some(next_trim(&arg, &mut iter)?),
+                _ => break parse_new_id(&arg)?,
+            }
+        };
+        let id_index = len - iter.len() - 1;
+
+        if iter.len() == 0 || !iter.len().is_multiple_of(2) {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut fields = Vec::with_capacity(iter.len() / 2);
+        while iter.len() > 0 {
+            let field = next_bytes(&mut iter)?;
+            let value = next_bytes(&mut iter)?;
+            fields.push((field, value));
+        }
+        Ok(Self::new(key, nomkstream, trim, id, id_index, fields))
+    }
+
+    // the position of `*` or `ms-*` in the request
+    pub fn generated_id_index(&self) -> Option<usize> {
+        match self.id {
+            NewStreamId::Explicit(_) => None,
+            _ => Some(self.id_index),
+        }
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        if self.id == NewStreamId::Explicit(StreamId::MIN) {
+            return Frame::Error(
+                "ERR The ID specified in XADD must be greater than 0-0".to_string(),
+            );
+        }
+        match db.xadd(&self.key, self.id, self.fields, self.nomkstream, self.trim) {
+            Ok(Some(id)) => Frame::BulkString(Bytes::from(id.to_string())),
+            Ok(None) => Frame::Nil,
+            Err(RedisErr::InvalidArgument) => Frame::Error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .to_string(),
+            ),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XRANGE key start end [COUNT count]
+// XREVRANGE key end start [COUNT count]
+// - and + are the min and the max ids, an id prefixed with ( is excluded
+#[derive(Debug)]
+pub struct XRange {
+    key: String,
+    start: StreamId,
+    end: StreamId,
+    count: Option<usize>,
+    rev: bool,
+}
+
+impl XRange {
+    fn new(key: String, start: StreamId, end: StreamId, count: Option<usize>, rev: bool) -> Self {
+        Self {
+            key,
+            start,
+            end,
+            count,
+            rev,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        let rev = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "XRANGE" => false,
+            "XREVRANGE" => true,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let key = next_string(&mut iter)?;
+        let (first, second) = (next_string(&mut iter)?, next_string(&mut iter)?);
+        let (start, end) = if rev {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        let start = parse_range_id(&start, true)?;
+        let end = parse_range_id(&end, false)?;
+
+        let count = match iter.len() {
+            0 => None,
+            2 => {
+                if next_string(&mut iter)?.to_uppercase() != "COUNT" {
+                    return Err(RedisErr::SyntaxError);
+                }
+                Some(next_integer(&mut iter)?.max(0) as usize)
+            }
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        match (start, end) {
+            (Some(start), Some(end)) => Ok(Self::new(key, start, end, count, rev)),
+            // an excluded bound past the min or the max leaves nothing in the range
+            _ => Ok(Self::new(key, StreamId::MAX, StreamId::MIN, count, rev)),
+        }
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.xrange(&self.key, self.start, self.end, self.count, self.rev) {
+            Ok(entries) => make_entries_frame(entries),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XLEN key
+#[derive(Debug)]
+pub struct XLen {
+    key: String,
+}
+
+impl XLen {
+    fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 2 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XLEN")?;
+        let key = next_string(&mut iter)?;
+        Ok(Self::new(key))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.xlen(&self.key) {
+            Ok(len) => Frame::Integer(len as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XDEL key id [id ...]
+#[derive(Debug)]
+pub struct XDel {
+    key: String,
+    ids: Vec<StreamId>,
+}
+
+impl XDel {
+    fn new(key: String, ids: Vec<StreamId>) -> Self {
+        Self { key, ids }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XDEL")?;
+        let key = next_string(&mut iter)?;
+        let mut ids = Vec::new();
+        while iter.len() > 0 {
+            ids.push(next_id(&mut iter)?);
+        }
+        if ids.is_empty() {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(key, ids))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.xdel(&self.key, &self.ids) {
+            Ok(deleted) => Frame::Integer(deleted as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XSETID key last-id
+#[derive(Debug)]
+pub struct XSetId {
+    key: String,
+    id: StreamId,
+}
+
+impl XSetId {
+    fn new(key: String, id: StreamId) -> Self {
+        Self { key, id }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XSETID")?;
+        let key = next_string(&mut iter)?;
+        let id = next_id(&mut iter)?;
+        if iter.len() > 0 {
+            return Err(RedisErr::SyntaxError);
+        }
+        Ok(Self::new(key, id))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.xsetid(&self.key, self.id) {
+            Ok(()) => Frame::SimpleString("OK".to_string()),
+            Err(RedisErr::KeyNotFound) => Frame::Error("ERR no such key".to_string()),
+            Err(RedisErr::InvalidArgument) => Frame::Error(
+                "ERR The ID specified in XSETID is smaller than the target stream top item"
+                    .to_string(),
+            ),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+#[derive(Debug)]
+pub struct XTrim {
+    key: String,
+    trim: StreamTrim,
+    limit: Option<usize>,
+}
+
+impl XTrim {
+    fn new(key: String, trim: StreamTrim, limit: Option<usize>) -> Self {
+        Self { key, trim, limit }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XTRIM")?;
+        let key = next_string(&mut iter)?;
+        let strategy = next_string(&mut iter)?;
+        if !matches!(strategy.to_uppercase().as_str(), "MAXLEN" | "MINID") {
+            return Err(RedisErr::SyntaxError);
+        }
+        let (trim, limit) = next_trim(&strategy, &mut iter)?;
+        if iter.len() > 0 {
+            return Err(RedisErr::SyntaxError);
+        }
+        Ok(Self::new(key, trim, limit))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.xtrim(&self.key, self.trim, self.limit) {
+            Ok(trimmed) => Frame::Integer(trimmed as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+// the entries after the ids, $ is the last id of the stream when the command is run,
+// with BLOCK the client waits for new entries if there are none, 0 waits forever
+#[derive(Debug)]
+pub struct XRead {
+    count: Option<usize>,
+    block: Option<Duration>,
+    // None for $
+    streams: Vec<(String, Option<StreamId>)>,
+}
+
+impl XRead {
+    fn new(
+        count: Option<usize>,
+        block: Option<Duration>,
+        streams: Vec<(String, Option<StreamId>)>,
+    ) -> Self {
+        Self {
+            count,
+            block,
+            streams,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XREAD")?;
+        let mut count = None;
+        let mut block = None;
+        loop {
+            match next_string(&mut iter)?.to_uppercase().as_str() {
+                "COUNT" => count = Some(next_integer(&mut iter)?.max(0) as usize),
+                "BLOCK" => {
+                    let ms = next_integer(&mut iter)?;
+                    if ms < 0 {
+                        return Err(RedisErr::InvalidArgument);
+                    }
+                    block = Some(Duration::from_millis(ms as u64));
+                }
+                "STREAMS" => break,
+                _ => return Err(RedisErr::SyntaxError),
+            }
+        }
+
+        if iter.len() == 0 || !iter.len().is_multiple_of(2) {
+            return Err(RedisErr::SyntaxError);
+        }
+        let mut keys = Vec::with_capacity(iter.len() / 2);
+        for _ in 0..iter.len() / 2 {
+            keys.push(next_string(&mut iter)?);
+        }
+        let mut streams = Vec::with_capacity(keys.len());
+        for key in keys {
+            let id = match next_string(&mut iter)?.as_str() {
+                "$" => None,
+                id => Some(StreamId::parse(id, 0).ok_or(RedisErr::InvalidArgument)?),
+            };
+            streams.push((key, id));
+        }
+        Ok(Self::new(count, block, streams))
+    }
+
+    pub fn is_blocking(&self) -> bool {
+        self.block.is_some()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.streams.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    // replace $ with the last ids of the streams
+    fn resolve_ids(&self, db: &mut DB) -> Result<Vec<(String, StreamId)>> {
+        self.streams
+            .iter()
+            .map(|(key, id)| {
+                let id = match id {
+                    Some(id) => *id,
+                    None => db.xlast_id(key)?.unwrap_or(StreamId::MIN),
+                };
+                Ok((key.clone(), id))
+            })
+            .collect()
+    }
+
+    fn read(&self, db: &mut DB, streams: &[(String, StreamId)]) -> Result<Option<Frame>> {
+        let result = db.xread(streams, self.count)?;
+        if result.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Frame::Array(
+            result
+                .into_iter()
+                .map(|(key, entries)| {
+                    Frame::Array(vec![
+                        Frame::BulkString(Bytes::from(key)),
+                        make_entries_frame(entries),
+                    ])
+                })
+                .collect(),
+        )))
+    }
+
+    // BLOCK is ignored where the client can't wait, such as in a transaction or a script
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let result = self
+            .resolve_ids(db)
+            .and_then(|streams| self.read(db, &streams));
+        match result {
+            Ok(frame) => frame.unwrap_or(Frame::Nil),
+            Err(e) => error_frame(e),
+        }
+    }
+
+    // read again every time one of the streams is written until there are new entries
+    pub async fn apply_blocking(self, db: &mut DB, shutdown: Arc<Notify>) -> Frame {
+        let guard = db.clone();
+        let streams = {
+            let _guard = guard.command_guard().await;
+            match self.resolve_ids(db) {
+                Ok(streams) => streams,
+                Err(e) => return error_frame(e),
+            }
+        };
+        let deadline = self
+            .block
+            .filter(|block| !block.is_zero())
+            .map(|block| Instant::now() + block);
+
+        loop {
+            // registered before reading, so an entry added in between wakes it up
+            let waiter = db.block_on_keys(&self.keys());
+            let result = {
+                let _guard = guard.command_guard().await;
+                self.read(db, &streams)
+            };
+            match result {
+                Ok(Some(frame)) => return frame,
+                Ok(None) => {}
+                Err(e) => return error_frame(e),
+            }
+            tokio::select! {
+                ready = waiter.wait(deadline) => {
+                    if !ready {
+                        return Frame::Nil;
+                    }
+                }
+                _ = shutdown.notified() => return Frame::Nil,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum XGroupOption {
+    // None for $
+    Create {
+        key: String,
+        group: String,
+        id: Option<StreamId>,
+        mkstream: bool,
+    },
+    Destroy {
+        key: String,
+        group: String,
+    },
+    SetId {
+        key: String,
+        group: String,
+        id: Option<StreamId>,
+    },
+    CreateConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+    DelConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+}
+
+// XGROUP CREATE key group id|$ [MKSTREAM] | DESTROY key group | SETID key group id|$
+// XGROUP CREATECONSUMER key group consumer | DELCONSUMER key group consumer
+#[derive(Debug)]
+pub struct XGroup {
+    option: XGroupOption,
+}
+
+impl XGroup {
+    fn new(option: XGroupOption) -> Self {
+        Self { option }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XGROUP")?;
+        let subcommand = next_string(&mut iter)?.to_uppercase();
+        let key = next_string(&mut iter)?;
+        let group = next_string(&mut iter)?;
+        let option = match subcommand.as_str() {
+            "CREATE" => {
+                let id = next_group_id(&mut iter)?;
+                let mkstream = match iter.len() {
+                    0 => false,
+                    1 if next_string(&mut iter)?.eq_ignore_ascii_case("MKSTREAM") => true,
+                    _ => return Err(RedisErr::SyntaxError),
+                };
+                XGroupOption::Create {
+                    key,
+                    group,
+                    id,
+                    mkstream,
+                }
+            }
+            "DESTROY" => XGroupOption::Destroy { key, group },
+            "SETID" => XGroupOption::SetId {
+                key,
+                group,
+                id: next_group_id(&mut iter)?,
+            },
+            "CREATECONSUMER" => XGroupOption::CreateConsumer {
+                key,
+                group,
+                consumer: next_string(&mut iter)?,
+            },
+            "DELCONSUMER" => XGroupOption::DelConsumer {
+                key,
+                group,
+                consumer: next_string(&mut iter)?,
+            },
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(option))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let (key, group, result) = match self.option {
+            XGroupOption::Create {
+                key,
+                group,
+                id,
+                mkstream,
+            } => {
+                let result = db
+                    .xgroup_create(&key, &group, id, mkstream)
+                    .map(|_| Frame::SimpleString("OK".to_string()));
+                (key, group, result)
+            }
+            XGroupOption::Destroy { key, group } => {
+                let result = db
+                    .xgroup_destroy(&key, &group)
+                    .map(|destroyed| Frame::Integer(destroyed as i64));
+                (key, group, result)
+            }
+            XGroupOption::SetId { key, group, id } => {
+                let result = db
+                    .xgroup_setid(&key, &group, id)
+                    .map(|_| Frame::SimpleString("OK".to_string()));
+                (key, group, result)
+            }
+            XGroupOption::CreateConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let result = db
+                    .xgroup_create_consumer(&key, &group, &consumer)
+                    .map(|created| Frame::Integer(created as i64));
+                (key, group, result)
+            }
+            XGroupOption::DelConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let result = db
+                    .xgroup_del_consumer(&key, &group, &consumer)
+                    .map(|pending| Frame::Integer(pending as i64));
+                (key, group, result)
+            }
+        };
+        match result {
+            Ok(frame) => frame,
+            Err(RedisErr::KeyNotFound) => Frame::Error(
+                "ERR The XGROUP subcommand requires the key to exist. \
+                 Note that for CREATE you may want to use the MKSTREAM option \
+                 to create an empty stream automatically."
+                    .to_string(),
+            ),
+            Err(RedisErr::BusyGroup) => {
+                Frame::Error("BUSYGROUP Consumer Group name already exists".to_string())
+            }
+            Err(RedisErr::NoGroup) => Frame::Error(format!(
+                "NOGROUP No such consumer group '{}' for key name '{}'",
+                group, key
+            )),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK]
+//   STREAMS key [key ...] id [id ...]
+// > reads the entries never delivered to the group, they're pending for the consumer until
+// they're acknowledged unless NOACK is set, an id reads the pending entries of the consumer after it,
+// with BLOCK the client waits for new entries only if all the ids are >
+#[derive(Debug)]
+pub struct XReadGroup {
+    group: String,
+    consumer: String,
+    count: Option<usize>,
+    block: Option<Duration>,
+    noack: bool,
+    // None for >
+    streams: Vec<(String, Option<StreamId>)>,
+}
+
+impl XReadGroup {
+    fn new(
+        group: String,
+        consumer: String,
+        count: Option<usize>,
+        block: Option<Duration>,
+        noack: bool,
+        streams: Vec<(String, Option<StreamId>)>,
+    ) -> Self {
+        Self {
+            group,
+            consumer,
+            count,
+            block,
+            noack,
+            streams,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XREADGROUP")?;
+        if !next_string(&mut iter)?.eq_ignore_ascii_case("GROUP") {
+            return Err(RedisErr::SyntaxError);
+        }
+        let group = next_string(&mut iter)?;
+        let consumer = next_string(&mut iter)?;
+        let mut count = None;
+        let mut block = None;
+        let mut noack = false;
+        loop {
+            match next_string(&mut iter)?.to_uppercase().as_str() {
+                "COUNT" => count = Some(next_integer(&mut iter)?.max(0) as usize),
+                "BLOCK" => {
+                    let ms = next_integer(&mut iter)?;
+                    if ms < 0 {
+                        return Err(RedisErr::InvalidArgument);
+                    }
+                    block = Some(Duration::from_millis(ms as u64));
+                }
+                "NOACK" => noack = true,
+                "STREAMS" => break,
+                _ => return Err(RedisErr::SyntaxError),
+            }
+        }
+
+        if iter.len() == 0 || !iter.len().is_multiple_of(2) {
+            return Err(RedisErr::SyntaxError);
+        }
+        let mut keys = Vec::with_capacity(iter.len() / 2);
+        for _ in 0..iter.len() / 2 {
+            keys.push(next_string(&mut iter)?);
+        }
+        let mut streams = Vec::with_capacity(keys.len());
+        for key in keys {
+            let id = match next_string(&mut iter)?.as_str() {
+                ">" => None,
+                id => Some(StreamId::parse(id, 0).ok_or(RedisErr::InvalidArgument)?),
+            };
+            streams.push((key, id));
+        }
+        Ok(Self::new(group, consumer, count, block, noack, streams))
+    }
+
+    pub fn is_blocking(&self) -> bool {
+        self.block.is_some()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.streams.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    // the request without BLOCK, propagated once the client is served
+    fn request(&self) -> Frame {
+        let bulk = |s: &str| Frame::BulkString(Bytes::from(s.to_string()));
+        let mut args = vec![
+            bulk("XREADGROUP"),
+            bulk("GROUP"),
+            bulk(&self.group),
+            bulk(&self.consumer),
+        ];
+        if let Some(count) = self.count {
+            args.extend([bulk("COUNT"), bulk(&count.to_string())]);
+        }
+        if self.noack {
+            args.push(bulk("NOACK"));
+        }
+        args.push(bulk("STREAMS"));
+        args.extend(self.streams.iter().map(|(key, _)| bulk(key)));
+        args.extend(self.streams.iter().map(|(_, id)| match id {
+            Some(id) => bulk(&id.to_string()),
+            None => bulk(">"),
+        }));
+        Frame::Array(args)
+    }
+
+    fn read(&self, db: &mut DB) -> Result<Option<Frame>> {
+        let result = db.xreadgroup(
+            &self.group,
+            &self.consumer,
+            &self.streams,
+            self.count,
+            self.noack,
+        )?;
+        if result.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Frame::Array(
+            result
+                .into_iter()
+                .map(|(key, entries)| {
+                    Frame::Array(vec![
+                        Frame::BulkString(Bytes::from(key)),
+                        Frame::Array(
+                            entries
+                                .into_iter()
+                                .map(|(id, fields)| make_entry_frame(id, fields))
+                                .collect(),
+                        ),
+                    ])
+                })
+                .collect(),
+        )))
+    }
+
+    fn error_frame(&self, db: &mut DB, e: RedisErr) -> Frame {
+        if e != RedisErr::NoGroup {
+            return error_frame(e);
+        }
+        let key = self
+            .streams
+            .iter()
+            .map(|(key, _)| key)
+            .find(|key| {
+                !matches!(
+                    db.stream(key, |stream| stream.group(&self.group).is_some()),
+                    Ok(Some(true))
+                )
+            })
+            .cloned()
+            .unwrap_or_default();
+        Frame::Error(format!(
+            "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+            key, self.group
+        ))
+    }
+
+    // BLOCK is ignored where the client can't wait, such as in a transaction or a script
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match self.read(db) {
+            Ok(frame) => frame.unwrap_or(Frame::Nil),
+            Err(e) => self.error_frame(db, e),
+        }
+    }
+
+    // read again every time one of the streams is written until there are new entries,
+    // the read is propagated by itself since the request is never applied as it is
+    pub async fn apply_blocking(self, db: &mut DB, shutdown: Arc<Notify>) -> Frame {
+        let guard = db.clone();
+        let deadline = self
+            .block
+            .filter(|block| !block.is_zero())
+            .map(|block| Instant::now() + block);
+        let history = self.streams.iter().any(|(_, id)| id.is_some());
+
+        let mut timed_out = false;
+        loop {
+            // registered before reading, so an entry added in between wakes it up
+            let waiter = db.block_on_keys(&self.keys());
+            {
+                let _command = guard.command_guard().await;
+                let _propagate = guard.propagate_guard();
+                match self.read(db) {
+                    Ok(Some(frame)) => {
+                        db.propagate(self.request());
+                        return frame;
+                    }
+                    Ok(None) if timed_out || history => {
+                        db.propagate(self.request());
+                        return Frame::Nil;
+                    }
+                    Ok(None) => {}
+                    Err(e) => return self.error_frame(db, e),
+                }
+            }
+            tokio::select! {
+                // read once more after the deadline, an entry may be added just before it
+                ready = waiter.wait(deadline) => timed_out = !ready,
+                _ = shutdown.notified() => return Frame::Nil,
+            }
+        }
+    }
+}
+
+// XACK key group id [id ...]
+#[derive(Debug)]
+pub struct XAck {
+    key: String,
+    group: String,
+    ids: Vec<StreamId>,
+}
+
+impl XAck {
+    fn new(key: String, group: String, ids: Vec<StreamId>) -> Self {
+        Self { key, group, ids }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XACK")?;
+        let key = next_string(&mut iter)?;
+        let group = next_string(&mut iter)?;
+        let mut ids = Vec::new();
+        while iter.len() > 0 {
+            ids.push(next_id(&mut iter)?);
+        }
+        if ids.is_empty() {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(key, group, ids))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        match db.xack(&self.key, &self.group, &self.ids) {
+            Ok(acked) => Frame::Integer(acked as i64),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PendingRange {
+    min_idle: u64,
+    start: StreamId,
+    end: StreamId,
+    count: usize,
+    consumer: Option<String>,
+}
+
+// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+// the summary of the pending entries of the group, or the entries in the range
+#[derive(Debug)]
+pub struct XPending {
+    key: String,
+    group: String,
+    range: Option<PendingRange>,
+}
+
+impl XPending {
+    fn new(key: String, group: String, range: Option<PendingRange>) -> Self {
+        Self { key, group, range }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XPENDING")?;
+        let key = next_string(&mut iter)?;
+        let group = next_string(&mut iter)?;
+        if iter.len() == 0 {
+            return Ok(Self::new(key, group, None));
+        }
+
+        let mut start = next_string(&mut iter)?;
+        let mut min_idle = 0;
+        if start.eq_ignore_ascii_case("IDLE") {
+            min_idle = next_integer(&mut iter)?.max(0) as u64;
+            start = next_string(&mut iter)?;
+        }
+        let start = parse_range_id(&start, true)?;
+        let end = parse_range_id(&next_string(&mut iter)?, false)?;
+        let count = next_integer(&mut iter)?.max(0) as usize;
+        let consumer = match iter.len() {
+            0 => None,
+            1 => Some(next_string(&mut iter)?),
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        // an excluded bound past the min or the max leaves nothing in the range
+        let (start, end) = start.zip(end).unwrap_or((StreamId::MAX, StreamId::MIN));
+        Ok(Self::new(
+            key,
+            group,
+            Some(PendingRange {
+                min_idle,
+                start,
+                end,
+                count,
+                consumer,
+            }),
+        ))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let now = unix_timestamp_ms();
+        let result = db.stream(&self.key, |stream| {
+            let group = stream.group(&self.group)?;
+            let Some(range) = &self.range else {
+                return Some(make_pending_summary_frame(group));
+            };
+            let entries = group
+                .pending_range(range.start, range.end, range.consumer.as_deref())
+                .map(|(id, entry)| (id, entry, now.saturating_sub(entry.delivery_time)))
+                .filter(|(_, _, idle)| *idle >= range.min_idle)
+                .take(range.count)
+                .map(|(id, entry, idle)| {
+                    Frame::Array(vec![
+                        Frame::BulkString(Bytes::from(id.to_string())),
+                        Frame::BulkString(Bytes::from(entry.consumer.clone())),
+                        Frame::Integer(idle as i64),
+                        Frame::Integer(entry.delivery_count as i64),
+                    ])
+                })
+                .collect();
+            Some(Frame::Array(entries))
+        });
+        match result {
+            Ok(Some(Some(frame))) => frame,
+            Ok(_) => Frame::Error(format!(
+                "NOGROUP No such key '{}' or consumer group '{}'",
+                self.key, self.group
+            )),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+//   [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+// the pending entries idle for at least min-idle-time are given to the consumer
+#[derive(Debug)]
+pub struct XClaim {
+    key: String,
+    group: String,
+    consumer: String,
+    min_idle: u64,
+    ids: Vec<StreamId>,
+    // IDLE, turned into the delivery time when it's applied
+    idle: Option<u64>,
+    options: ClaimOptions,
+    last_id: Option<StreamId>,
+}
+
+impl XClaim {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+        idle: Option<u64>,
+        options: ClaimOptions,
+        last_id: Option<StreamId>,
+    ) -> Self {
+        Self {
+            key,
+            group,
+            consumer,
+            min_idle,
+            ids,
+            idle,
+            options,
+            last_id,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XCLAIM")?;
+        let key = next_string(&mut iter)?;
+        let group = next_string(&mut iter)?;
+        let consumer = next_string(&mut iter)?;
+        let min_idle = next_integer(&mut iter)?.max(0) as u64;
+
+        let mut ids = Vec::new();
+        let mut idle = None;
+        let mut options = ClaimOptions::default();
+        let mut last_id = None;
+        while iter.len() > 0 {
+            let arg = next_string(&mut iter)?;
+            match arg.to_uppercase().as_str() {
+                "IDLE" => idle = Some(next_integer(&mut iter)?.max(0) as u64),
+                "TIME" => options.delivery_time = Some(next_integer(&mut iter)?.max(0) as u64),
+                "RETRYCOUNT" => options.retry_count = Some(next_integer(&mut iter)?.max(0) as u64),
+                "FORCE" => options.force = true,
+                "JUSTID" => options.just_id = true,
+                "LASTID" => last_id = Some(next_id(&mut iter)?),
+                // the ids come before the options
+                _ if idle.is_none() && options == ClaimOptions::default() && last_id.is_none() => {
+                    ids.push(StreamId::parse(&arg, 0).ok_or(RedisErr::InvalidArgument)?)
+                }
+                _ => return Err(RedisErr::SyntaxError),
+            }
+        }
+        if ids.is_empty() {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(
+            key, group, consumer, min_idle, ids, idle, options, last_id,
+        ))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let result = self.claim(db);
+        self.reply(result)
+    }
+
+    // which entries are claimed depends on when it's applied,
+    // so the outcome is propagated instead of the request
+    pub fn apply_and_collect(self, db: &mut DB) -> (Frame, Vec<Frame>) {
+        let created = !has_consumer(db, &self.key, &self.group, &self.consumer);
+        let result = self.claim(db);
+        let mut requests = Vec::new();
+        if result.is_ok() {
+            requests = claim_requests(db, &self.key, &self.group, &self.consumer, &self.ids);
+            if self.last_id.is_some() {
+                requests.extend(group_id_request(db, &self.key, &self.group));
+            }
+            if requests.is_empty() && created {
+                requests.push(create_consumer_request(
+                    &self.key,
+                    &self.group,
+                    &self.consumer,
+                ));
+            }
+        }
+        (self.reply(result), requests)
+    }
+
+    fn claim(&self, db: &mut DB) -> Result<Vec<StreamEntry>> {
+        let mut options = self.options;
+        if let Some(idle) = self.idle {
+            options.delivery_time = Some(unix_timestamp_ms().saturating_sub(idle));
+        }
+        db.xclaim(
+            &self.key,
+            &self.group,
+            &self.consumer,
+            &self.ids,
+            self.min_idle,
+            options,
+            self.last_id,
+        )
+    }
+
+    fn reply(&self, result: Result<Vec<StreamEntry>>) -> Frame {
+        match result {
+            Ok(entries) if self.options.just_id => {
+                make_ids_frame(entries.into_iter().map(|(id, _)| id))
+            }
+            Ok(entries) => make_entries_frame(entries),
+            Err(RedisErr::NoGroup) => Frame::Error(format!(
+                "NOGROUP No such key '{}' or consumer group '{}'",
+                self.key, self.group
+            )),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+// claim the pending entries idle for at least min-idle-time from start,
+// replied with the id to continue from, the claimed entries and the deleted ids
+#[derive(Debug)]
+pub struct XAutoClaim {
+    key: String,
+    group: String,
+    consumer: String,
+    min_idle: u64,
+    start: StreamId,
+    count: usize,
+    just_id: bool,
+}
+
+impl XAutoClaim {
+    fn new(
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        just_id: bool,
+    ) -> Self {
+        Self {
+            key,
+            group,
+            consumer,
+            min_idle,
+            start,
+            count,
+            just_id,
+        }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XAUTOCLAIM")?;
+        let key = next_string(&mut iter)?;
+        let group = next_string(&mut iter)?;
+        let consumer = next_string(&mut iter)?;
+        let min_idle = next_integer(&mut iter)?.max(0) as u64;
+        let start =
+            parse_range_id(&next_string(&mut iter)?, true)?.ok_or(RedisErr::InvalidArgument)?;
+
+        let mut count = 100;
+        let mut just_id = false;
+        while iter.len() > 0 {
+            match next_string(&mut iter)?.to_uppercase().as_str() {
+                "COUNT" => {
+                    count = next_integer(&mut iter)?;
+                    if count < 1 {
+                        return Err(RedisErr::InvalidArgument);
+                    }
+                }
+                "JUSTID" => just_id = true,
+                _ => return Err(RedisErr::SyntaxError),
+            }
+        }
+        Ok(Self::new(
+            key,
+            group,
+            consumer,
+            min_idle,
+            start,
+            count as usize,
+            just_id,
+        ))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let result = self.claim(db);
+        self.reply(result)
+    }
+
+    // propagated as the outcome like XCLAIM, the scan keeps no state in the group
+    pub fn apply_and_collect(self, db: &mut DB) -> (Frame, Vec<Frame>) {
+        let created = !has_consumer(db, &self.key, &self.group, &self.consumer);
+        let result = self.claim(db);
+        let mut requests = Vec::new();
+        if let Ok((_, claimed, deleted)) = &result {
+            let ids = claimed
+                .iter()
+                .map(|(id, _)| *id)
+                .chain(deleted.iter().copied())
+                .collect::<Vec<_>>();
+            requests = claim_requests(db, &self.key, &self.group, &self.consumer, &ids);
+            if requests.is_empty() && created {
+                requests.push(create_consumer_request(
+                    &self.key,
+                    &self.group,
+                    &self.consumer,
+                ));
+            }
+        }
+        (self.reply(result), requests)
+    }
+
+    fn claim(&self, db: &mut DB) -> Result<AutoClaimed> {
+        db.xautoclaim(
+            &self.key,
+            &self.group,
+            &self.consumer,
+            self.min_idle,
+            self.start,
+            self.count,
+            self.just_id,
+        )
+    }
+
+    fn reply(&self, result: Result<AutoClaimed>) -> Frame {
+        match result {
+            Ok((next, claimed, deleted)) => Frame::Array(vec![
+                Frame::BulkString(Bytes::from(next.to_string())),
+                if self.just_id {
+                    make_ids_frame(claimed.into_iter().map(|(id, _)| id))
+                } else {
+                    make_entries_frame(claimed)
+                },
+                make_ids_frame(deleted.into_iter()),
+            ]),
+            Err(RedisErr::NoGroup) => Frame::Error(format!(
+                "NOGROUP No such key '{}' or consumer group '{}'",
+                self.key, self.group
+            )),
+            Err(e) => error_frame(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum XInfoOption {
+    Stream(String),
+    Groups(String),
+    Consumers(String, String),
+}
+
+// XINFO STREAM key | GROUPS key | CONSUMERS key group
+#[derive(Debug)]
+pub struct XInfo {
+    option: XInfoOption,
+}
+
+impl XInfo {
+    fn new(option: XInfoOption) -> Self {
+        Self { option }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"XINFO")?;
+        let option = match next_string(&mut iter)?.to_uppercase().as_str() {
+            "STREAM" => XInfoOption::Stream(next_string(&mut iter)?),
+            "GROUPS" => XInfoOption::Groups(next_string(&mut iter)?),
+            "CONSUMERS" => XInfoOption::Consumers(next_string(&mut iter)?, next_string(&mut iter)?),
+            _ => return Err(RedisErr::SyntaxError),
+        };
+        if iter.len() > 0 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(option))
+    }
+
+    pub fn apply(self, db: &mut DB) -> Frame {
+        let bulk = |s: &str| Frame::BulkString(Bytes::from(s.to_string()));
+        let now = unix_timestamp_ms();
+        let (key, result) = match &self.option {
+            XInfoOption::Stream(key) => (
+                key,
+                db.stream(key, |stream| {
+                    let entry = |entry: Option<(&StreamId, &StreamFields)>| {
+                        entry.map_or(Frame::Nil, |(id, fields)| {
+                            make_entry_frame(*id, Some(fields.clone()))
+                        })
+                    };
+                    Some(Frame::Map(vec![
+                        (bulk("length"), Frame::Integer(stream.len() as i64)),
+                        (
+                            bulk("last-generated-id"),
+                            bulk(&stream.last_id().to_string()),
+                        ),
+                        (
+                            bulk("groups"),
+                            Frame::Integer(stream.groups().count() as i64),
+                        ),
+                        (bulk("first-entry"), entry(stream.first_entry())),
+                        (bulk("last-entry"), entry(stream.last_entry())),
+                    ]))
+                }),
+            ),
+            XInfoOption::Groups(key) => (
+                key,
+                db.stream(key, |stream| {
+                    Some(Frame::Array(
+                        stream
+                            .groups()
+                            .map(|(name, group)| {
+                                Frame::Map(vec![
+                                    (bulk("name"), bulk(name)),
+                                    (
+                                        bulk("consumers"),
+                                        Frame::Integer(group.consumers().count() as i64),
+                                    ),
+                                    (bulk("pending"), Frame::Integer(group.pending_len() as i64)),
+                                    (
+                                        bulk("last-delivered-id"),
+                                        bulk(&group.last_id().to_string()),
+                                    ),
+                                ])
+                            })
+                            .collect(),
+                    ))
+                }),
+            ),
+            XInfoOption::Consumers(key, group) => (
+                key,
+                db.stream(key, |stream| {
+                    let group = stream.group(group)?;
+                    Some(Frame::Array(
+                        group
+                            .consumers()
+                            .map(|(name, consumer)| {
+                                let inactive = consumer
+                                    .active_time
+                                    .map_or(-1, |at| now.saturating_sub(at) as i64);
+                                Frame::Map(vec![
+                                    (bulk("name"), bulk(name)),
+                                    (
+                                        bulk("pending"),
+                                        Frame::Integer(consumer.pending_len() as i64),
+                                    ),
+                                    (
+                                        bulk("idle"),
+                                        Frame::Integer(
+                                            now.saturating_sub(consumer.seen_time) as i64
+                                        ),
+                                    ),
+                                    (bulk("inactive"), Frame::Integer(inactive)),
+                                ])
+                            })
+                            .collect(),
+                    ))
+                }),
+            ),
+        };
+        match (result, &self.option) {
+            (Ok(Some(Some(frame))), _) => frame,
+            (Ok(Some(None)), XInfoOption::Consumers(_, group)) => Frame::Error(format!(
+                "NOGROUP No such consumer group '{}' for key name '{}'",
+                group, key
+            )),
+            (Ok(_), _) => Frame::Error("ERR no such key".to_string()),
+            (Err(e), _) => error_frame(e),
+        }
+    }
+}
+
+// "ms-*" or "*" for a generated id
+fn parse_new_id(s: &str) -> Result<NewStreamId> {
+    if s == "*" {
+        return Ok(NewStreamId::Auto);
+    }
+    if let Some(ms) = s.strip_suffix("-*") {
+        return Ok(NewStreamId::AutoSeq(ms.parse()?));
+    }
+    StreamId::parse(s, 0)
+        .map(NewStreamId::Explicit)
+        .ok_or(RedisErr::InvalidArgument)
+}
+
+// a bound of XRANGE, the missing sequence is the min for the start and the max for the end,
+// None if an excluded bound leaves nothing
+fn parse_range_id(s: &str, is_start: bool) -> Result<Option<StreamId>> {
+    match s {
+        "-" => return Ok(Some(StreamId::MIN)),
+        "+" => return Ok(Some(StreamId::MAX)),
+        _ => {}
+    }
+    let (s, exclusive) = match s.strip_prefix('(') {
+        Some(s) => (s, true),
+        None => (s, false),
+    };
+    let default_seq = if is_start { 0 } else { u64::MAX };
+    let id = StreamId::parse(s, default_seq).ok_or(RedisErr::InvalidArgument)?;
+    Ok(match (exclusive, is_start) {
+        (false, _) => Some(id),
+        (true, true) => id.next(),
+        (true, false) => id.prev(),
+    })
+}
+
+fn next_id(iter: &mut std::vec::IntoIter<Frame>) -> Result<StreamId> {
+    StreamId::parse(&next_string(iter)?, 0).ok_or(RedisErr::InvalidArgument)
+}
+
+// [=|~] threshold [LIMIT count] after MAXLEN or MINID,
+// the trimming is always exact, LIMIT is only allowed with ~ like redis
+fn next_trim(
+    strategy: &str,
+    iter: &mut std::vec::IntoIter<Frame>,
+) -> Result<(StreamTrim, Option<usize>)> {
+    let mut threshold = next_string(iter)?;
+    let mut approx = false;
+    if threshold == "=" || threshold == "~" {
+        approx = threshold == "~";
+        threshold = next_string(iter)?;
+    }
+    let trim = if strategy.eq_ignore_ascii_case("MAXLEN") {
+        let max_len = threshold.parse::<i64>()?;
+        if max_len < 0 {
+            return Err(RedisErr::InvalidArgument);
+        }
+        StreamTrim::MaxLen(max_len as usize)
+    } else {
+        StreamTrim::MinId(StreamId::parse(&threshold, 0).ok_or(RedisErr::InvalidArgument)?)
+    };
+
+    let has_limit = iter
+        .as_slice()
+        .first()
+        .and_then(|frame| frame_to_string(frame).ok())
+        .is_some_and(|arg| arg.eq_ignore_ascii_case("LIMIT"));
+    if !has_limit {
+        return Ok((trim, None));
+    }
+    if !approx {
+        return Err(RedisErr::SyntaxError);
+    }
+    iter.next();
+    let limit = next_integer(iter)?;
+    if limit < 0 {
+        return Err(RedisErr::InvalidArgument);
+    }
+    // LIMIT 0 removes the limit
+    Ok((trim, (limit > 0).then_some(limit as usize)))
+}
+
+// the id of XGROUP CREATE and SETID, None for $
+fn next_group_id(iter: &mut std::vec::IntoIter<Frame>) -> Result<Option<StreamId>> {
+    match next_string(iter)?.as_str() {
+        "$" => Ok(None),
+        id => Ok(Some(
+            StreamId::parse(id, 0).ok_or(RedisErr::InvalidArgument)?,
+        )),
+    }
+}
+
+// an entry is the id and its fields and values, the fields are nil for a deleted entry
+fn make_entry_frame(id: StreamId, fields: Option<StreamFields>) -> Frame {
+    let fields = match fields {
+        Some(fields) => Frame::Array(
+            fields
+                .into_iter()
+                .flat_map(|(field, value)| [Frame::BulkString(field), Frame::BulkString(value)])
+                .collect(),
+        ),
+        None => Frame::Nil,
+    };
+    Frame::Array(vec![Frame::BulkString(Bytes::from(id.to_string())), fields])
+}
+
+fn make_entries_frame(entries: Vec<StreamEntry>) -> Frame {
+    Frame::Array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| make_entry_frame(id, Some(fields)))
+            .collect(),
+    )
+}
+
+fn has_consumer(db: &mut DB, key: &str, group: &str, consumer: &str) -> bool {
+    db.stream(key, |stream| {
+        stream
+            .group(group)
+            .is_some_and(|group| group.consumer(consumer).is_some())
+    })
+    .ok()
+    .flatten()
+    .unwrap_or(false)
+}
+
+// the claims of the ids as the requests replaying them at any time:
+// an XCLAIM setting the delivery time and count of each entry pending for the consumer,
+// and an XCLAIM of each id of a deleted entry, which removes it from the pending list
+fn claim_requests(
+    db: &mut DB,
+    key: &str,
+    group: &str,
+    consumer: &str,
+    ids: &[StreamId],
+) -> Vec<Frame> {
+    db.stream(key, |stream| {
+        let Some(group_ref) = stream.group(group) else {
+            return Vec::new();
+        };
+        let mut requests = Vec::new();
+        for id in ids {
+            let mut args = vec![
+                bulk("XCLAIM"),
+                bulk(key),
+                bulk(group),
+                bulk(consumer),
+                bulk("0"),
+                bulk(&id.to_string()),
+            ];
+            match group_ref.pending_range(*id, *id, None).next() {
+                Some((_, entry)) if entry.consumer == consumer => args.extend([
+                    bulk("TIME"),
+                    bulk(&entry.delivery_time.to_string()),
+                    bulk("RETRYCOUNT"),
+                    bulk(&entry.delivery_count.to_string()),
+                    bulk("FORCE"),
+                ]),
+                None if stream.range(*id, *id).next().is_none() => {}
+                _ => continue,
+            }
+            args.push(bulk("JUSTID"));
+            requests.push(Frame::Array(args));
+        }
+        requests
+    })
+    .ok()
+    .flatten()
+    .unwrap_or_default()
+}
+
+// XGROUP SETID to the last delivered id of the group
+fn group_id_request(db: &mut DB, key: &str, group: &str) -> Option<Frame> {
+    let last_id = db
+        .stream(key, |stream| {
+            stream.group(group).map(ConsumerGroup::last_id)
+        })
+        .ok()
+        .flatten()
+        .flatten()?;
+    Some(Frame::Array(vec![
+        bulk("XGROUP"),
+        bulk("SETID"),
+        bulk(key),
+        bulk(group),
+        bulk(&last_id.to_string()),
+    ]))
+}
+
+fn create_consumer_request(key: &str, group: &str, consumer: &str) -> Frame {
+    Frame::Array(vec![
+        bulk("XGROUP"),
+        bulk("CREATECONSUMER"),
+        bulk(key),
+        bulk(group),
+        bulk(consumer),
+    ])
+}
+
+fn make_ids_frame(ids: impl Iterator<Item = StreamId>) -> Frame {
+    Frame::Array(
+        ids.map(|id| Frame::BulkString(Bytes::from(id.to_string())))
+            .collect(),
+    )
+}
+
+// the number of the pending entries, the min and the max ids and the number of each consumer
+fn make_pending_summary_frame(group: &ConsumerGroup) -> Frame {
+    let (Some((min, _)), Some((max, _))) = (group.pending().next(), group.pending().next_back())
+    else {
+        return Frame::Array(vec![Frame::Integer(0), Frame::Nil, Frame::Nil, Frame::Nil]);
+    };
+    let consumers = group
+        .consumers()
+        .filter(|(_, consumer)| consumer.pending_len() > 0)
+        .map(|(name, consumer)| {
+            Frame::Array(vec![
+                Frame::BulkString(Bytes::from(name.clone())),
+                Frame::BulkString(Bytes::from(consumer.pending_len().to_string())),
+            ])
+        })
+        .collect();
+    Frame::Array(vec![
+        Frame::Integer(group.pending_len() as i64),
+        Frame::BulkString(Bytes::from(min.to_string())),
+        Frame::BulkString(Bytes::from(max.to_string())),
+        Frame::Array(consumers),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::command;
+
+    fn ids(frame: Frame) -> Vec<String> {
+        let Frame::Array(entries) = frame else {
+            panic!("unexpected entries {:?}", frame);
+        };
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                Frame::Array(mut entry) => match entry.remove(0) {
+                    Frame::BulkString(id) => String::from_utf8(id.to_vec()).unwrap(),
+                    frame => panic!("unexpected id {:?}", frame),
+                },
+                frame => panic!("unexpected entry {:?}", frame),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_xadd() {
+        let mut db = DB::new();
+        let xadd = |args: &[&str]| XAdd::from_frames(command(args)).unwrap();
+        assert_eq!(
+            xadd(&["XADD", "s", "1-1", "f", "v"]).apply(&mut db),
+            Frame::BulkString(Bytes::from("1-1"))
+        );
+        assert_eq!(
+            xadd(&["XADD", "s", "1-*", "f", "v"]).apply(&mut db),
+            Frame::BulkString(Bytes::from("1-2"))
+        );
+        assert!(matches!(
+            xadd(&["XADD", "s", "1-2", "f", "v"]).apply(&mut db),
+            Frame::Error(e) if e.contains("equal or smaller")
+        ));
+        assert!(matches!(
+            xadd(&["XADD", "s", "0-0", "f", "v"]).apply(&mut db),
+            Frame::Error(e) if e.contains("greater than 0-0")
+        ));
+        assert_eq!(
+            xadd(&["XADD", "nokey", "NOMKSTREAM", "*", "f", "v"]).apply(&mut db),
+            Frame::Nil
+        );
+        assert_eq!(db.xlen("nokey"), Ok(0));
+        assert!(XAdd::from_frames(command(&["XADD", "s", "*", "f"])).is_err());
+        assert!(XAdd::from_frames(command(&[
+            "XADD", "s", "MAXLEN", "1", "LIMIT", "1", "*", "f", "v"
+        ]))
+        .is_err());
+
+        for i in 3..=5 {
+            xadd(&["XADD", "s", &format!("1-{}", i), "f", "v"]).apply(&mut db);
+        }
+        let cmd = xadd(&["XADD", "s", "MAXLEN", "~", "3", "LIMIT", "0", "*", "f", "v"]);
+        assert_eq!(cmd.generated_id_index(), Some(7));
+        cmd.apply(&mut db);
+        assert_eq!(db.xlen("s"), Ok(3));
+
+        db.set(
+            "string".to_string(),
+            Bytes::from("value"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            xadd(&["XADD", "string", "*", "f", "v"]).apply(&mut db),
+            Frame::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_xsetid() {
+        let mut db = DB::new();
+        let xadd = |db: &mut DB, args: &[&str]| XAdd::from_frames(command(args)).unwrap().apply(db);
+        let xsetid =
+            |db: &mut DB, args: &[&str]| XSetId::from_frames(command(args)).unwrap().apply(db);
+        assert_eq!(
+            xsetid(&mut db, &["XSETID", "s", "1-0"]),
+            Frame::Error("ERR no such key".to_string())
+        );
+
+        xadd(&mut db, &["XADD", "s", "2-0", "f", "v"]);
+        assert!(matches!(
+            xsetid(&mut db, &["XSETID", "s", "1-0"]),
+            Frame::Error(e) if e.contains("smaller than the target stream top item")
+        ));
+        assert_eq!(
+            xsetid(&mut db, &["XSETID", "s", "5-0"]),
+            Frame::SimpleString("OK".to_string())
+        );
+        assert_eq!(db.xlast_id("s").unwrap(), Some(StreamId::new(5, 0)));
+        assert!(matches!(
+            xadd(&mut db, &["XADD", "s", "4-0", "f", "v"]),
+            Frame::Error(_)
+        ));
+
+        // an empty stream as rewritten in the aof
+        xadd(&mut db, &["XADD", "e", "MAXLEN", "0", "0-1", "x", "y"]);
+        xsetid(&mut db, &["XSETID", "e", "3-0"]);
+        assert_eq!(db.xlen("e").unwrap(), 0);
+        assert_eq!(db.xlast_id("e").unwrap(), Some(StreamId::new(3, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_xrange() {
+        let mut db = DB::new();
+        for id in ["1-0", "1-1", "2-0", "3-0"] {
+            XAdd::from_frames(command(&["XADD", "s", id, "f", "v"]))
+                .unwrap()
+                .apply(&mut db);
+        }
+        let xrange = |args: &[&str]| XRange::from_frames(command(args)).unwrap();
+        assert_eq!(
+            ids(xrange(&["XRANGE", "s", "-", "+"]).apply(&mut db)),
+            vec!["1-0", "1-1", "2-0", "3-0"]
+        );
+        assert_eq!(
+            ids(xrange(&["XRANGE", "s", "1", "2"]).apply(&mut db)),
+            vec!["1-0", "1-1", "2-0"]
+        );
+        assert_eq!(
+            ids(xrange(&["XRANGE", "s", "(1-0", "+", "COUNT", "2"]).apply(&mut db)),
+            vec!["1-1", "2-0"]
+        );
+        assert_eq!(
+            ids(xrange(&["XREVRANGE", "s", "+", "-", "COUNT", "3"]).apply(&mut db)),
+            vec!["3-0", "2-0", "1-1"]
+        );
+        assert_eq!(
+            ids(xrange(&["XREVRANGE", "s", "(3-0", "(1-0"]).apply(&mut db)),
+            vec!["2-0", "1-1"]
+        );
+        assert!(XRange::from_frames(command(&["XRANGE", "s", "(+", "+"])).is_err());
+        assert!(ids(xrange(&["XRANGE", "nokey", "-", "+"]).apply(&mut db)).is_empty());
+
+        assert_eq!(
+            XDel::from_frames(command(&["XDEL", "s", "1-1", "9-9"]))
+                .unwrap()
+                .apply(&mut db),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            XTrim::from_frames(command(&["XTRIM", "s", "MINID", "3"]))
+                .unwrap()
+                .apply(&mut db),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            ids(xrange(&["XRANGE", "s", "-", "+"]).apply(&mut db)),
+            vec!["3-0"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_xread() {
+        let mut db = DB::new();
+        let xread = |args: &[&str]| XRead::from_frames(command(args)).unwrap();
+        for id in ["1-0", "2-0"] {
+            XAdd::from_frames(command(&["XADD", "a", id, "f", "v"]))
+                .unwrap()
+                .apply(&mut db);
+        }
+        assert_eq!(
+            xread(&["XREAD", "COUNT", "1", "STREAMS", "a", "b", "0", "0"]).apply(&mut db),
+            Frame::Array(vec![Frame::Array(vec![
+                Frame::BulkString(Bytes::from("a")),
+                Frame::Array(vec![Frame::Array(vec![
+                    Frame::BulkString(Bytes::from("1-0")),
+                    Frame::Array(vec![
+                        Frame::BulkString(Bytes::from("f")),
+                        Frame::BulkString(Bytes::from("v")),
+                    ]),
+                ])]),
+            ])])
+        );
+        assert_eq!(
+            xread(&["XREAD", "STREAMS", "a", "$"]).apply(&mut db),
+            Frame::Nil
+        );
+        assert!(XRead::from_frames(command(&["XREAD", "STREAMS", "a"])).is_err());
+
+        // applied once without blocking in a transaction or a script
+        let cmd = Parser::new()
+            .parse(Frame::Array(command(&[
+                "XREAD", "BLOCK", "0", "STREAMS", "a", "$",
+            ])))
+            .unwrap();
+        assert!(cmd.is_blocking());
+        assert_eq!(cmd.apply_to_db(&mut db).unwrap(), Frame::Nil);
+
+        // woken up by an entry added after blocking
+        let cmd = xread(&["XREAD", "BLOCK", "0", "STREAMS", "a", "$"]);
+        assert!(cmd.is_blocking());
+        let mut writer = db.clone();
+        let reader = tokio::spawn(async move {
+            let mut db = db;
+            cmd.apply_blocking(&mut db, Arc::new(Notify::new())).await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        XAdd::from_frames(command(&["XADD", "a", "3-0", "f", "v"]))
+            .unwrap()
+            .apply(&mut writer);
+        let Frame::Array(streams) = reader.await.unwrap() else {
+            panic!("xread is not woken up");
+        };
+        assert_eq!(streams.len(), 1);
+
+        // timed out
+        let cmd = xread(&["XREAD", "BLOCK", "10", "STREAMS", "a", "$"]);
+        assert_eq!(
+            cmd.apply_blocking(&mut writer, Arc::new(Notify::new()))
+                .await,
+            Frame::Nil
+        );
+    }
+
+    #[tokio::test]
+    async fn test_xreadgroup() {
+        let mut db = DB::new();
+        let run = |db: &mut DB, args: &[&str]| -> Frame {
+            let frames = command(args);
+            match args[0] {
+                "XADD" => XAdd::from_frames(frames).unwrap().apply(db),
+                "XGROUP" => XGroup::from_frames(frames).unwrap().apply(db),
+                "XREADGROUP" => XReadGroup::from_frames(frames).unwrap().apply(db),
+                "XACK" => XAck::from_frames(frames).unwrap().apply(db),
+                "XPENDING" => XPending::from_frames(frames).unwrap().apply(db),
+                "XCLAIM" => XClaim::from_frames(frames).unwrap().apply(db),
+                "XAUTOCLAIM" => XAutoClaim::from_frames(frames).unwrap().apply(db),
+                _ => unreachable!(),
+            }
+        };
+        let bulk = |s: &str| Frame::BulkString(Bytes::from(s.to_string()));
+
+        assert!(matches!(
+            run(&mut db, &["XGROUP", "CREATE", "s", "g", "$"]),
+            Frame::Error(e) if e.contains("requires the key to exist")
+        ));
+        assert_eq!(
+            run(&mut db, &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]),
+            Frame::SimpleString("OK".to_string())
+        );
+        assert!(matches!(
+            run(&mut db, &["XGROUP", "CREATE", "s", "g", "0"]),
+            Frame::Error(e) if e.starts_with("BUSYGROUP")
+        ));
+        for id in ["1-0", "2-0", "3-0"] {
+            run(&mut db, &["XADD", "s", id, "f", "v"]);
+        }
+
+        let read = run(
+            &mut db,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "alice",
+                "COUNT",
+                "2",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+        );
+        let Frame::Array(mut streams) = read else {
+            panic!("unexpected reply {:?}", read);
+        };
+        let Frame::Array(mut stream) = streams.remove(0) else {
+            panic!("unexpected stream");
+        };
+        assert_eq!(ids(stream.remove(1)), vec!["1-0", "2-0"]);
+        assert!(matches!(
+            run(&mut db, &["XREADGROUP", "GROUP", "nogroup", "c", "STREAMS", "s", ">"]),
+            Frame::Error(e) if e.starts_with("NOGROUP No such key 's'")
+        ));
+
+        assert_eq!(
+            run(&mut db, &["XACK", "s", "g", "1-0", "3-0"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&mut db, &["XPENDING", "s", "g"]),
+            Frame::Array(vec![
+                Frame::Integer(1),
+                bulk("2-0"),
+                bulk("2-0"),
+                Frame::Array(vec![Frame::Array(vec![bulk("alice"), bulk("1")])]),
+            ])
+        );
+
+        // claimed by bob even though it's not idle for long
+        assert_eq!(
+            run(&mut db, &["XCLAIM", "s", "g", "bob", "0", "2-0", "JUSTID"]),
+            Frame::Array(vec![bulk("2-0")])
+        );
+        let Frame::Array(pending) = run(&mut db, &["XPENDING", "s", "g", "-", "+", "10", "bob"])
+        else {
+            panic!("unexpected pending entries");
+        };
+        let Frame::Array(entry) = &pending[0] else {
+            panic!("unexpected pending entry");
+        };
+        assert_eq!(entry[1], bulk("bob"));
+        // JUSTID is not counted as a delivery
+        assert_eq!(entry[3], Frame::Integer(1));
+
+        assert_eq!(
+            run(
+                &mut db,
+                &["XAUTOCLAIM", "s", "g", "carol", "0", "0", "JUSTID"]
+            ),
+            Frame::Array(vec![
+                bulk("0-0"),
+                Frame::Array(vec![bulk("2-0")]),
+                Frame::Array(vec![]),
+            ])
+        );
+
+        run(
+            &mut db,
+            &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        );
+        // woken up by an entry added after blocking
+        let cmd = XReadGroup::from_frames(command(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "dave",
+            "BLOCK",
+            "0",
+            "STREAMS",
+            "s",
+            ">",
+        ]))
+        .unwrap();
+        assert!(cmd.is_blocking());
+        let mut reader = db.clone();
+        let reader = tokio::spawn(async move {
+            cmd.apply_blocking(&mut reader, Arc::new(Notify::new()))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!reader.is_finished());
+        run(&mut db, &["XADD", "s", "4-0", "f", "v"]);
+        let Frame::Array(streams) = reader.await.unwrap() else {
+            panic!("xreadgroup is not woken up");
+        };
+        assert_eq!(streams.len(), 1);
+        let Frame::Array(pending) = run(&mut db, &["XPENDING", "s", "g", "-", "+", "10", "dave"])
+        else {
+            panic!("unexpected pending entries");
+        };
+        assert_eq!(pending.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_claim_propagation() {
+        let mut db = DB::new();
+        let parser = Parser::new();
+        let mut propagated = Vec::new();
+        let mut run = |db: &mut DB, args: &[&str]| -> Frame {
+            let request = Frame::Array(command(args));
+            let cmd = parser.parse(request.clone()).unwrap();
+            let (resp, requests) = cmd.apply_and_collect(db, request);
+            propagated.extend(requests);
+            resp
+        };
+        run(&mut db, &["XADD", "s", "1-0", "f", "v"]);
+        run(&mut db, &["XADD", "s", "2-0", "f", "v"]);
+        run(&mut db, &["XADD", "s", "3-0", "f", "v"]);
+        run(&mut db, &["XGROUP", "CREATE", "s", "g", "0"]);
+        run(
+            &mut db,
+            &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        );
+        run(&mut db, &["XDEL", "s", "3-0"]);
+        std::thread::sleep(Duration::from_millis(20));
+        // idle long enough here, not when they're replayed right away
+        let claimed = run(&mut db, &["XCLAIM", "s", "g", "bob", "10", "1-0", "JUSTID"]);
+        assert_eq!(claimed, Frame::Array(vec![bulk("1-0")]));
+        let claimed = run(&mut db, &["XAUTOCLAIM", "s", "g", "carol", "10", "0"]);
+        let Frame::Array(claimed) = claimed else {
+            panic!("unexpected reply {:?}", claimed);
+        };
+        assert_eq!(ids(claimed[1].clone()), vec!["2-0"]);
+        assert_eq!(claimed[2], Frame::Array(vec![bulk("3-0")]));
+        run(
+            &mut db,
+            &["XCLAIM", "s", "g", "dave", "0", "1-0", "LASTID", "5-0"],
+        );
+        // nothing claimed, the consumer is created
+        run(&mut db, &["XCLAIM", "s", "g", "erin", "100000", "1-0"]);
+
+        let mut replica = DB::new();
+        for request in propagated {
+            let cmd = parser.parse(request.clone()).unwrap();
+            let (resp, _) = cmd.apply_and_collect(&mut replica, request);
+            assert!(!matches!(resp, Frame::Error(_)), "{:?}", resp);
+        }
+        let group = |db: &mut DB| {
+            db.stream("s", |stream| {
+                let group = stream.group("g").unwrap();
+                (
+                    group.last_id(),
+                    group
+                        .pending()
+                        .map(|(id, entry)| (*id, entry.clone()))
+                        .collect::<Vec<_>>(),
+                    group
+                        .consumers()
+                        .map(|(name, _)| name.clone())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .unwrap()
+            .unwrap()
+        };
+        let (last_id, pending, consumers) = group(&mut db);
+        assert_eq!(last_id, StreamId::new(5, 0));
+        assert_eq!(
+            pending
+                .iter()
+                .map(|(id, entry)| (id.to_string(), entry.consumer.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("1-0".to_string(), "dave"), ("2-0".to_string(), "carol")]
+        );
+        assert_eq!(consumers, vec!["alice", "bob", "carol", "dave", "erin"]);
+        assert_eq!(group(&mut replica), (last_id, pending, consumers));
+    }
+}
@@ -0,0 +1,323 @@
+//! Transaction commands
+//! commands between MULTI and EXEC are queued by the connection,
+//! then applied by EXEC with no other command running in between
+
+use super::*;
+use crate::connection::AsyncConnection;
+use crate::db::DB;
+use crate::frame::Frame;
+use crate::Result;
+
+// MULTI
+#[derive(Debug)]
+pub struct Multi {}
+
+impl Multi {
+    fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 1 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        check_cmd(&mut frames.into_iter(), b"MULTI")?;
+        Ok(Self::new())
+    }
+
+    pub fn apply(self, _db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        if dst.in_multi() {
+            return Frame::Error("ERR MULTI calls can not be nested".to_string());
+        }
+        dst.start_multi();
+        Frame::SimpleString("OK".to_string())
+    }
+}
+
+// EXEC
+// replies the results of the queued commands,
+// or nil if any of the watched keys has changed
+#[derive(Debug)]
+pub struct Exec {}
+
+impl Exec {
+    fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 1 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        check_cmd(&mut frames.into_iter(), b"EXEC")?;
+        Ok(Self::new())
+    }
+
+    pub async fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        let Some((queued, failed)) = dst.take_multi() else {
+            return Frame::Error("ERR EXEC without MULTI".to_string());
+        };
+        let watched = dst.take_watched();
+        if failed {
+            return Frame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string(),
+            );
+        }
+
+        let guard = db.clone();
+        let _transaction = guard.transaction_guard().await;
+        if watched
+            .iter()
+            .any(|(key, version)| db.version(key) != *version)
+        {
+            return Frame::Nil;
+        }
+        let _propagate = guard.propagate_guard();
+        let replies = Command::apply_transaction(db, queued);
+        dst.set_write_offset(db.replication().offset());
+        Frame::Array(replies)
+    }
+}
+
+// DISCARD
+#[derive(Debug)]
+pub struct Discard {}
+
+impl Discard {
+    fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 1 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        check_cmd(&mut frames.into_iter(), b"DISCARD")?;
+        Ok(Self::new())
+    }
+
+    pub fn apply(self, _db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        if dst.take_multi().is_none() {
+            return Frame::Error("ERR DISCARD without MULTI".to_string());
+        }
+        dst.take_watched();
+        Frame::SimpleString("OK".to_string())
+    }
+}
+
+// WATCH key [key ...]
+// the next EXEC of the connection aborts if any of the keys is modified
+#[derive(Debug)]
+pub struct Watch {
+    keys: Vec<String>,
+}
+
+impl Watch {
+    fn new(keys: Vec<String>) -> Self {
+        Self { keys }
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        let mut iter = frames.into_iter();
+        check_cmd(&mut iter, b"WATCH")?;
+        let mut keys = Vec::new();
+        while iter.len() > 0 {
+            keys.push(next_string(&mut iter)?);
+        }
+        if keys.is_empty() {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        Ok(Self::new(keys))
+    }
+
+    pub fn apply(self, db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        if dst.in_multi() {
+            return Frame::Error("ERR WATCH inside MULTI is not allowed".to_string());
+        }
+        for key in self.keys {
+            let version = db.version(&key);
+            dst.watch(key, version);
+        }
+        Frame::SimpleString("OK".to_string())
+    }
+}
+
+// UNWATCH
+#[derive(Debug)]
+pub struct Unwatch {}
+
+impl Unwatch {
+    fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Result<Self> {
+        if frames.len() != 1 {
+            return Err(RedisErr::WrongNumberOfArguments);
+        }
+        check_cmd(&mut frames.into_iter(), b"UNWATCH")?;
+        Ok(Self::new())
+    }
+
+    pub fn apply(self, _db: &mut DB, dst: &mut AsyncConnection) -> Frame {
+        dst.take_watched();
+        Frame::SimpleString("OK".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::command;
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn connection() -> AsyncConnection {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        AsyncConnection::new(stream)
+    }
+
+    fn queue(parser: &Parser, conn: &mut AsyncConnection, args: &[&str]) {
+        let request = Frame::Array(command(args));
+        conn.queue(parser.parse(request.clone()).unwrap(), request);
+    }
+
+    #[tokio::test]
+    async fn test_exec() {
+        let parser = Parser::new();
+        let mut db = DB::new();
+        let mut conn = connection().await;
+
+        assert_eq!(
+            Exec::new().apply(&mut db, &mut conn).await,
+            Frame::Error("ERR EXEC without MULTI".to_string())
+        );
+        assert_eq!(
+            Multi::new().apply(&mut db, &mut conn),
+            Frame::SimpleString("OK".to_string())
+        );
+        assert!(matches!(
+            Multi::new().apply(&mut db, &mut conn),
+            Frame::Error(_)
+        ));
+        queue(&parser, &mut conn, &["set", "key", "1"]);
+        queue(&parser, &mut conn, &["lpush", "key", "1"]);
+        queue(&parser, &mut conn, &["get", "key"]);
+        let replies = match Exec::new().apply(&mut db, &mut conn).await {
+            Frame::Array(replies) => replies,
+            frame => panic!("unexpected exec reply {:?}", frame),
+        };
+        assert_eq!(replies.len(), 3);
+        assert!(matches!(replies[1], Frame::Error(_)));
+        assert_eq!(replies[2], Frame::BulkString(Bytes::from("1")));
+        assert!(!conn.in_multi());
+
+        // a failed command discards the whole transaction
+        Multi::new().apply(&mut db, &mut conn);
+        queue(&parser, &mut conn, &["set", "key", "2"]);
+        conn.fail_multi();
+        assert!(matches!(
+            Exec::new().apply(&mut db, &mut conn).await,
+            Frame::Error(e) if e.starts_with("EXECABORT")
+        ));
+        assert_eq!(db.get("key"), Ok(Bytes::from("1")));
+
+        Multi::new().apply(&mut db, &mut conn);
+        queue(&parser, &mut conn, &["set", "key", "2"]);
+        assert_eq!(
+            Discard::new().apply(&mut db, &mut conn),
+            Frame::SimpleString("OK".to_string())
+        );
+        assert_eq!(db.get("key"), Ok(Bytes::from("1")));
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let parser = Parser::new();
+        let mut db = DB::new();
+        let mut conn = connection().await;
+
+        db.set(
+            "key".to_string(),
+            Bytes::from("1"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        Watch::new(vec!["key".to_string(), "nokey".to_string()]).apply(&mut db, &mut conn);
+        Multi::new().apply(&mut db, &mut conn);
+        assert!(matches!(
+            Watch::new(vec!["key".to_string()]).apply(&mut db, &mut conn),
+            Frame::Error(_)
+        ));
+        queue(&parser, &mut conn, &["set", "key", "2"]);
+        assert_eq!(
+            Exec::new().apply(&mut db, &mut conn).await,
+            Frame::Array(vec![Frame::SimpleString("OK".to_string())])
+        );
+
+        // modified by another client after WATCH
+        Watch::new(vec!["key".to_string()]).apply(&mut db, &mut conn);
+        db.set(
+            "key".to_string(),
+            Bytes::from("3"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        Multi::new().apply(&mut db, &mut conn);
+        queue(&parser, &mut conn, &["set", "key", "4"]);
+        assert_eq!(Exec::new().apply(&mut db, &mut conn).await, Frame::Nil);
+        assert_eq!(db.get("key"), Ok(Bytes::from("3")));
+
+        // a watched key created or deleted is a change too
+        Watch::new(vec!["nokey".to_string(), "key".to_string()]).apply(&mut db, &mut conn);
+        db.del("key");
+        Multi::new().apply(&mut db, &mut conn);
+        assert_eq!(Exec::new().apply(&mut db, &mut conn).await, Frame::Nil);
+
+        // and so is one created and deleted again before EXEC
+        Watch::new(vec!["nokey".to_string()]).apply(&mut db, &mut conn);
+        db.set(
+            "nokey".to_string(),
+            Bytes::from("1"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        db.del("nokey");
+        Multi::new().apply(&mut db, &mut conn);
+        assert_eq!(Exec::new().apply(&mut db, &mut conn).await, Frame::Nil);
+
+        Watch::new(vec!["key".to_string()]).apply(&mut db, &mut conn);
+        db.set(
+            "key".to_string(),
+            Bytes::from("5"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        Unwatch::new().apply(&mut db, &mut conn);
+        Multi::new().apply(&mut db, &mut conn);
+        assert_eq!(
+            Exec::new().apply(&mut db, &mut conn).await,
+            Frame::Array(vec![])
+        );
+    }
+}
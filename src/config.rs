@@ -0,0 +1,227 @@
+//! Server configuration
+//! options are collected from the command line by `Arg` or set by `ServerBuilder`,
+//! then shared with every connection through the `DB`
+
+use crate::RedisErr;
+
+use std::{path::PathBuf, str::FromStr};
+
+// when the append only file is synced to the disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AppendFsync {
+    // after every write command
+    Always,
+    // once a second in the background
+    #[default]
+    EverySec,
+    // leave it to the operating system
+    No,
+}
+
+impl FromStr for AppendFsync {
+    type Err = RedisErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "everysec" => Ok(Self::EverySec),
+            "no" => Ok(Self::No),
+            _ => Err(RedisErr::InvalidArgument),
+        }
+    }
+}
+
+// classes of the keyspace events to publish, parsed from a notify-keyspace-events string:
+// K keyspace channel, E keyevent channel, g generic, $ string, l list, s set, h hash,
+// z sorted set, x expired, e evicted, t stream, m key miss, n new key, d module, A all of g$lshzxetd
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents(u16);
+
+impl KeyspaceEvents {
+    pub const KEYSPACE: Self = Self(1 << 0);
+    pub const KEYEVENT: Self = Self(1 << 1);
+    pub const GENERIC: Self = Self(1 << 2);
+    pub const STRING: Self = Self(1 << 3);
+    pub const LIST: Self = Self(1 << 4);
+    pub const SET: Self = Self(1 << 5);
+    pub const HASH: Self = Self(1 << 6);
+    pub const ZSET: Self = Self(1 << 7);
+    pub const EXPIRED: Self = Self(1 << 8);
+    pub const EVICTED: Self = Self(1 << 9);
+    pub const STREAM: Self = Self(1 << 10);
+    pub const KEY_MISS: Self = Self(1 << 11);
+    pub const NEW: Self = Self(1 << 12);
+    pub const MODULE: Self = Self(1 << 13);
+    const ALL: Self = Self(
+        Self::GENERIC.0
+            | Self::STRING.0
+            | Self::LIST.0
+            | Self::SET.0
+            | Self::HASH.0
+            | Self::ZSET.0
+            | Self::EXPIRED.0
+            | Self::EVICTED.0
+            | Self::STREAM.0
+            | Self::MODULE.0,
+    );
+
+    pub fn contains(self, class: Self) -> bool {
+        self.0 & class.0 == class.0
+    }
+
+    // nothing is published unless one of the channels and one of the classes are enabled
+    pub fn is_enabled(self, class: Self) -> bool {
+        self.contains(class) && self.0 & (Self::KEYSPACE.0 | Self::KEYEVENT.0) != 0
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = RedisErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.chars().try_fold(Self::default(), |events, c| {
+            let class = match c {
+                'K' => Self::KEYSPACE,
+                'E' => Self::KEYEVENT,
+                'g' => Self::GENERIC,
+                '$' => Self::STRING,
+                'l' => Self::LIST,
+                's' => Self::SET,
+                'h' => Self::HASH,
+                'z' => Self::ZSET,
+                'x' => Self::EXPIRED,
+                'e' => Self::EVICTED,
+                't' => Self::STREAM,
+                'm' => Self::KEY_MISS,
+                'n' => Self::NEW,
+                'd' => Self::MODULE,
+                'A' => Self::ALL,
+                _ => return Err(RedisErr::InvalidArgument),
+            };
+            Ok(Self(events.0 | class.0))
+        })
+    }
+}
+
+// output limit of the pub/sub clients, counted in messages pending for a subscription rather than
+// in bytes like client-output-buffer-limit: reaching the hard limit, or staying at the soft limit
+// for the seconds, disconnects the client, 0 disables a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PubSubOutputLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+impl PubSubOutputLimit {
+    pub fn is_enabled(&self) -> bool {
+        self.hard > 0 || self.soft > 0
+    }
+}
+
+impl Default for PubSubOutputLimit {
+    fn default() -> Self {
+        Self {
+            hard: 1024,
+            soft: 256,
+            soft_seconds: 60,
+        }
+    }
+}
+
+// "hard soft seconds", the limits are plain message counts without byte units
+impl FromStr for PubSubOutputLimit {
+    type Err = RedisErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split_whitespace().collect::<Vec<_>>();
+        let [hard, soft, soft_seconds] = parts.as_slice() else {
+            return Err(RedisErr::InvalidArgument);
+        };
+        Ok(Self {
+            hard: hard.parse()?,
+            soft: soft.parse()?,
+            soft_seconds: soft_seconds.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // password of the default user, connections must authenticate if it's set
+    pub requirepass: Option<String>,
+
+    // rdb snapshot is saved to dir/dbfilename
+    pub dir: String,
+    pub dbfilename: String,
+
+    // write commands are logged to dir/appendfilename
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: AppendFsync,
+
+    // rewrite the aof when it has grown by the percentage since the last rewrite,
+    // and is larger than the min size in bytes, 0 percentage disables it
+    pub auto_aof_rewrite_percentage: u64,
+    pub auto_aof_rewrite_min_size: u64,
+
+    // port the server listens on, announced to the primary by a replica
+    pub port: u16,
+    // replicate from the primary at startup
+    pub replicaof: Option<(String, u16)>,
+    // size in bytes of the write commands kept for partial resync
+    pub repl_backlog_size: usize,
+
+    // serve a part of the hash slots and talk to the other nodes on the cluster bus
+    pub cluster_enabled: bool,
+    // milliseconds a node can be unreachable before it's considered failing
+    pub cluster_node_timeout: u64,
+
+    // number of shards the key space is split into, each has its own lock
+    pub keyspace_shards: usize,
+
+    // milliseconds a script runs before the other clients are replied BUSY
+    pub busy_reply_threshold: u64,
+
+    // keyspace events published to the __keyspace@0__ and __keyevent@0__ channels
+    pub notify_keyspace_events: KeyspaceEvents,
+
+    // messages a subscription can fall behind its channel before it loses them
+    pub pubsub_buffer_size: usize,
+    pub pubsub_output_limit: PubSubOutputLimit,
+}
+
+impl Config {
+    pub fn rdb_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.dbfilename)
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.appendfilename)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            requirepass: None,
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: AppendFsync::default(),
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            port: 6379,
+            replicaof: None,
+            repl_backlog_size: 1024 * 1024,
+            cluster_enabled: false,
+            cluster_node_timeout: 15000,
+            keyspace_shards: 64,
+            busy_reply_threshold: 5000,
+            notify_keyspace_events: KeyspaceEvents::default(),
+            pubsub_buffer_size: 1024,
+            pubsub_output_limit: PubSubOutputLimit::default(),
+        }
+    }
+}
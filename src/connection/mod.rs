@@ -1,10 +1,13 @@
-use crate::frame::Frame;
+use crate::cmd::Command;
+use crate::frame::{Frame, Protocol};
 use crate::{RedisErr, Result};
 
 use std::fmt::Debug;
+use std::future::Future;
 use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use bytes::BytesMut;
+use bytes::{Buf, BytesMut};
 use log::trace;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
 use tokio::net::TcpStream;
@@ -12,10 +15,36 @@ use tokio::net::TcpStream;
 pub trait SyncConnectionLike: Read + Write + Debug {}
 impl SyncConnectionLike for std::net::TcpStream {}
 
+// client id is unique during the server lifetime
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug)]
 pub struct AsyncConnection {
     stream: BufWriter<TcpStream>,
     read_buffer: BytesMut,
+
+    // client states, changed by HELLO, AUTH and CLIENT commands
+    id: u64,
+    protocol: Protocol,
+    name: Option<String>,
+    authenticated: bool,
+    listening_port: Option<u16>,
+
+    // replication offset after the last write of the client, for WAIT
+    write_offset: u64,
+
+    // set by ASKING, the next command may access a slot being imported
+    asking: bool,
+
+    // requests queued since MULTI, applied by EXEC
+    multi: Option<Vec<(Command, Frame)>>,
+    // a request failed to queue, EXEC discards the transaction
+    multi_failed: bool,
+    // keys and their versions when watched, EXEC aborts if any has changed
+    watched: Vec<(String, u64)>,
+
+    // the server closes the connection, such as a subscriber over its output buffer limit
+    closed: bool,
 }
 
 impl AsyncConnection {
@@ -23,30 +52,156 @@ impl AsyncConnection {
         Self {
             stream: BufWriter::new(stream),
             read_buffer: BytesMut::with_capacity(4096),
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: Protocol::Resp2,
+            name: None,
+            authenticated: true,
+            listening_port: None,
+            write_offset: 0,
+            asking: false,
+            multi: None,
+            multi_failed: false,
+            watched: Vec::new(),
+            closed: false,
         }
     }
 
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn set_name(&mut self, name: Option<String>) {
+        self.name = name;
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    pub fn set_authenticated(&mut self, authenticated: bool) {
+        self.authenticated = authenticated;
+    }
+
+    pub fn peer_addr(&self) -> Option<std::net::SocketAddr> {
+        self.stream.get_ref().peer_addr().ok()
+    }
+
+    // port a replica serves clients on, set by REPLCONF listening-port
+    pub fn listening_port(&self) -> Option<u16> {
+        self.listening_port
+    }
+
+    pub fn set_listening_port(&mut self, port: u16) {
+        self.listening_port = Some(port);
+    }
+
+    pub fn write_offset(&self) -> u64 {
+        self.write_offset
+    }
+
+    pub fn set_write_offset(&mut self, offset: u64) {
+        self.write_offset = offset;
+    }
+
+    pub fn set_asking(&mut self) {
+        self.asking = true;
+    }
+
+    // the flag only lasts for one command
+    pub fn take_asking(&mut self) -> bool {
+        std::mem::take(&mut self.asking)
+    }
+
+    pub fn in_multi(&self) -> bool {
+        self.multi.is_some()
+    }
+
+    pub fn start_multi(&mut self) {
+        self.multi = Some(Vec::new());
+        self.multi_failed = false;
+    }
+
+    pub fn queue(&mut self, cmd: Command, request: Frame) {
+        if let Some(queued) = &mut self.multi {
+            queued.push((cmd, request));
+        }
+    }
+
+    pub fn fail_multi(&mut self) {
+        self.multi_failed = self.in_multi();
+    }
+
+    // the queued requests and whether any failed to queue, None out of MULTI
+    pub fn take_multi(&mut self) -> Option<(Vec<(Command, Frame)>, bool)> {
+        let queued = self.multi.take()?;
+        Some((queued, std::mem::take(&mut self.multi_failed)))
+    }
+
+    pub fn watch(&mut self, key: String, version: u64) {
+        self.watched.push((key, version));
+    }
+
+    // EXEC, DISCARD and UNWATCH forget all the watched keys
+    pub fn take_watched(&mut self) -> Vec<(String, u64)> {
+        std::mem::take(&mut self.watched)
+    }
+
     fn parse_frame(&mut self) -> Result<Option<Frame>> {
-        match Frame::from_bytes(&self.read_buffer) {
-            Ok(frame) => Ok(Some(frame)),
+        match Frame::parse(&self.read_buffer) {
+            Ok((frame, len)) => {
+                // only drop the bytes of this frame, the rest are
+                // pipelined commands which arrived in the same read
+                self.read_buffer.advance(len);
+                Ok(Some(frame))
+            }
             Err(RedisErr::FrameIncomplete) => Ok(None),
             Err(e) => Err(e),
         }
     }
 
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
     pub async fn read_frame(&mut self) -> Result<Frame> {
         loop {
             if let Some(frame) = self.parse_frame()? {
-                self.read_buffer.clear();
                 return Ok(frame);
             }
 
+            // every buffered command has been answered,
+            // send the replies out before waiting for the next batch
+            self.stream.flush().await?;
+
             if self.stream.read_buf(&mut self.read_buffer).await? == 0 {
                 return Err(RedisErr::ConnectionAborted);
             }
         }
     }
     pub async fn write_frame(&mut self, frame: Frame) -> Result<()> {
+        // commands reply in RESP3 natively,
+        // downgrade the reply if the client still speaks RESP2
+        let frame = match self.protocol {
+            Protocol::Resp2 if frame.is_attribute() => return Ok(()),
+            Protocol::Resp2 => frame.into_resp2(),
+            Protocol::Resp3 => frame.into_resp3(),
+        };
         let data = frame.serialize();
         trace!(
             "writing frame {}",
@@ -54,7 +209,47 @@ impl AsyncConnection {
         );
         self.stream.write_all(&data).await?;
 
-        // flush the stream so the client can see the response immediately
+        // flush the stream so the client can see the response immediately,
+        // unless there are pipelined commands left in the read buffer,
+        // their replies are flushed together by the next read_frame
+        if self.read_buffer.is_empty() {
+            self.stream.flush().await?;
+        }
+        Ok(())
+    }
+
+    // send out the replies held back for pipelined commands,
+    // such as before a command blocks the connection
+    pub async fn flush(&mut self) -> Result<()> {
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    // wait for a blocking command unless the client disconnects first,
+    // the connection is closed then and the command dropped
+    pub async fn unless_closed(&mut self, blocking: impl Future<Output = Frame>) -> Frame {
+        tokio::select! {
+            frame = blocking => frame,
+            _ = self.wait_closed() => {
+                self.close();
+                Frame::Nil
+            }
+        }
+    }
+
+    // bytes read meanwhile are kept as pipelined commands
+    async fn wait_closed(&mut self) {
+        loop {
+            match self.stream.read_buf(&mut self.read_buffer).await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+        }
+    }
+
+    // raw bytes out of the RESP framing, such as the rdb payload of a full resync
+    pub async fn write_bytes(&mut self, data: &[u8]) -> Result<()> {
+        self.stream.write_all(data).await?;
         self.stream.flush().await?;
         Ok(())
     }
@@ -130,3 +325,81 @@ fn write_response(stream: &mut impl Write, response: &[u8]) -> Result<usize> {
     }
     Ok(cnt)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_read_pipelined_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = AsyncConnection::new(stream);
+
+        // three commands in a single write
+        client
+            .write_all(b"*1\r\n$4\r\nPING\r\n*2\r\n$4\r\nECHO\r\n$2\r\nhi\r\nPING\r\n")
+            .await
+            .unwrap();
+
+        let mut frames = vec![];
+        for _ in 0..3 {
+            let frame = conn.read_frame().await.unwrap();
+            conn.write_frame(Frame::SimpleString("OK".to_string()))
+                .await
+                .unwrap();
+            frames.push(frame);
+        }
+        assert_eq!(
+            frames,
+            vec![
+                Frame::Array(vec![Frame::BulkString(Bytes::from_static(b"PING"))]),
+                Frame::Array(vec![
+                    Frame::BulkString(Bytes::from_static(b"ECHO")),
+                    Frame::BulkString(Bytes::from_static(b"hi")),
+                ]),
+                Frame::Array(vec![Frame::SimpleString("PING".to_string())]),
+            ]
+        );
+
+        let expected = b"+OK\r\n+OK\r\n+OK\r\n";
+        let mut buf = vec![0; expected.len()];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, expected);
+    }
+
+    #[tokio::test]
+    async fn test_unless_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = AsyncConnection::new(stream);
+
+        // served, the command pipelined meanwhile is kept
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let served = async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            Frame::Integer(1)
+        };
+        assert_eq!(conn.unless_closed(served).await, Frame::Integer(1));
+        assert!(!conn.is_closed());
+        assert_eq!(
+            conn.read_frame().await.unwrap(),
+            Frame::Array(vec![Frame::BulkString(Bytes::from_static(b"PING"))])
+        );
+
+        // the client disconnects while the command is blocked
+        drop(client);
+        let blocked = std::future::pending();
+        assert_eq!(conn.unless_closed(blocked).await, Frame::Nil);
+        assert!(conn.is_closed());
+    }
+}
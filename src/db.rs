@@ -1,15 +1,39 @@
 //! Database module
 
-use crate::{value::Value, RedisErr, Result};
+use crate::{
+    aof::{self, AOF},
+    config::{AppendFsync, Config, KeyspaceEvents},
+    frame::Frame,
+    helper::{
+        glob_match, instant_to_unix_ms, random_u64, unix_ms_to_instant, unix_timestamp,
+        unix_timestamp_ms,
+    },
+    rdb::{Record, RDB},
+    server::{
+        cluster::{key_slot, Cluster},
+        replication::Replication,
+        scripting::{RestorePolicy, Scripting},
+    },
+    value::{
+        AutoClaimed, ClaimOptions, NewStreamId, Stream, StreamEntry, StreamFields, StreamId,
+        StreamTrim, Value,
+    },
+    RedisErr, Result,
+};
 
 use std::{
-    collections::{BTreeSet, HashMap, VecDeque},
-    sync::{Arc, Mutex},
-    time::Instant,
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
-use log::{debug, trace};
+use log::{debug, error, info, trace, warn};
 use tokio::sync::{broadcast, Notify};
 
 pub struct DBDropGuard {
@@ -17,10 +41,17 @@ pub struct DBDropGuard {
 }
 
 impl DBDropGuard {
+    #[allow(dead_code)]
     pub fn new() -> Self {
         Self { db: DB::new() }
     }
 
+    pub fn new_with_config(config: Config) -> Self {
+        Self {
+            db: DB::new_with_config(config),
+        }
+    }
+
     pub fn db(&self) -> DB {
         self.db.clone()
     }
@@ -38,41 +69,124 @@ pub struct DB {
 }
 
 impl DB {
+    #[allow(dead_code)]
     pub fn new() -> Self {
-        let shard = Arc::new(Shared {
-            state: Mutex::new(State::new()),
+        Self::new_with_config(Config::default())
+    }
+
+    pub fn new_with_config(config: Config) -> Self {
+        let cluster = config.cluster_enabled.then(|| {
+            Cluster::new(
+                config.port,
+                Duration::from_millis(config.cluster_node_timeout),
+            )
+        });
+        let shards = (0..config.keyspace_shards.max(1))
+            .map(|_| Mutex::new(Shard::new()))
+            .collect();
+        let shared = Arc::new(Shared {
+            shards,
+            publisher: Mutex::new(HashMap::new()),
+            pattern_publisher: Mutex::new(HashMap::new()),
+            shard_publisher: Mutex::new(BTreeMap::new()),
+            blocked: Mutex::new(HashMap::new()),
+            shutdown: AtomicBool::new(false),
             background_task: Notify::new(),
+            config,
+            bgsave_in_progress: AtomicBool::new(false),
+            lastsave: AtomicU64::new(unix_timestamp()),
+            pubsub_lagged_messages: AtomicU64::new(0),
+            pubsub_disconnections: AtomicU64::new(0),
+            aof: Mutex::new(None),
+            propagate_lock: RwLock::new(()),
+            transaction_lock: tokio::sync::RwLock::new(()),
+            replication: Replication::new(),
+            cluster,
+            scripting: Scripting::new(),
         });
 
         // spawn a background task to purge expired keys
-        tokio::spawn(purge_expired_tasks(shard.clone()));
+        tokio::spawn(purge_expired_tasks(shared.clone()));
+
+        Self { db: shared }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.db.config
+    }
+
+    // the shard the key belongs to
+    fn shard(&self, key: &str) -> ShardGuard<'_> {
+        ShardGuard {
+            shared: &self.db,
+            guard: self.db.shards[self.db.shard_index(key)].lock().unwrap(),
+        }
+    }
+
+    // lock the shards of all the keys, always in the order of the shard index,
+    // so commands locking several of them can't deadlock
+    pub fn lock_keys<K: AsRef<str>>(&self, keys: &[K]) -> ShardsGuard<'_> {
+        let mut indexes = keys
+            .iter()
+            .map(|key| self.db.shard_index(key.as_ref()))
+            .collect::<Vec<_>>();
+        indexes.sort_unstable();
+        indexes.dedup();
+        ShardsGuard {
+            shared: &self.db,
+            guards: indexes
+                .into_iter()
+                .map(|i| (i, self.db.shards[i].lock().unwrap()))
+                .collect(),
+        }
+    }
 
-        Self { db: shard }
+    // lock every shard in order, for the operations on the whole key space
+    fn lock_all(&self) -> Vec<MutexGuard<'_, Shard>> {
+        self.db
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap())
+            .collect()
     }
 
     pub fn get(&mut self, key: &str) -> Result<Bytes> {
         trace!("Get key: {}", key);
-        let mut state = self.db.state.lock().unwrap();
-        let entry = state.table.get(key);
-        match entry {
-            Some(entry) => {
-                // check expire on read
-                if let Some(expire_at) = entry.expire_at {
-                    if expire_at < Instant::now() {
-                        state.table.remove(key);
-                        state.expire_table.remove(&(key.to_string(), expire_at));
-                        return Err(RedisErr::KeyNotFound);
-                    }
-                }
-                match &entry.value {
-                    Value::KV(v) => Ok(v.clone()),
-                    _ => Err(RedisErr::WrongType),
-                }
+        self.shard(key).get_kv(key)
+    }
+
+    // the values of the keys, read under the locks of all their shards
+    pub fn mget(&self, keys: &[String]) -> Vec<Result<Bytes>> {
+        let mut shards = self.lock_keys(keys);
+        keys.iter()
+            .map(|key| shards.shard(key).get_kv(key))
+            .collect()
+    }
+
+    // set all the keys at once, no reader sees a part of them
+    pub fn mset(&self, pairs: Vec<(String, Bytes)>) {
+        let keys = pairs
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>();
+        let mut shards = self.lock_keys(&keys);
+        let mut events = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            let old = shards
+                .shard(&key)
+                .insert(key.clone(), Entry::new(Value::KV(value), None));
+            events.push((key, old.is_none()));
+        }
+        drop(shards);
+        for (key, new) in events {
+            if new {
+                self.notify(KeyspaceEvents::NEW, "new", &key);
             }
-            None => Err(RedisErr::KeyNotFound),
+            self.notify(KeyspaceEvents::STRING, "set", &key);
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn set(
         &mut self,
         key: String,
@@ -94,17 +208,17 @@ impl DB {
             expire_at
         );
 
-        let mut state = self.db.state.lock().unwrap();
+        let mut state = self.shard(&key);
         let mut entry = Entry::new(Value::KV(value), expire_at);
-        let old = state.table.get(&key);
+        let old = state.get(&key);
         if nx && old.is_some() {
             return Err(RedisErr::NoAction);
         }
         if xx && old.is_none() {
             return Err(RedisErr::NoAction);
         }
-        if keepttl && old.is_some() {
-            entry.expire_at = old.unwrap().expire_at;
+        if let (true, Some(old)) = (keepttl, old) {
+            entry.expire_at = old.expire_at;
         }
         let mut notify = false;
         if let Some(expire_at) = entry.expire_at {
@@ -114,19 +228,8 @@ impl DB {
                 .unwrap_or(true);
         }
 
-        let old = state.table.insert(key.clone(), entry);
-        if get && old.is_some() {
-            #[allow(clippy::unnecessary_unwrap)]
-            return Ok(Some(old.unwrap().value.to_kv().unwrap()));
-        }
-        if let Some(old) = old {
-            if let Some(expire_at) = old.expire_at {
-                state.expire_table.remove(&(key.clone(), expire_at));
-            }
-        }
-        if let Some(expire_at) = expire_at {
-            state.expire_table.insert((key, expire_at));
-        }
+        let has_expire = entry.expire_at.is_some();
+        let old = state.insert(key.clone(), entry);
 
         // drop the lock before notify the background task
         // avoid the background task to wait for the lock
@@ -134,16 +237,30 @@ impl DB {
         if notify {
             self.db.background_task.notify_one();
         }
-        Ok(None)
+        if old.is_none() {
+            self.notify(KeyspaceEvents::NEW, "new", &key);
+        }
+        self.notify(KeyspaceEvents::STRING, "set", &key);
+        if has_expire {
+            self.notify(KeyspaceEvents::GENERIC, "expire", &key);
+        }
+        match old {
+            Some(old) if get => Ok(old.value.to_kv()),
+            _ => Ok(None),
+        }
     }
 
     pub fn expire(&mut self, key: &str, expire_at: Instant) -> Result<()> {
-        let mut state = self.db.state.lock().unwrap();
-        let entry = state.table.get_mut(key);
+        let mut state = self.shard(key);
+        let entry = state.get_mut(key);
         match entry {
             Some(entry) => {
-                entry.expire_at = Some(expire_at);
+                if let Some(old) = entry.expire_at.replace(expire_at) {
+                    state.expire_table.remove(&(key.to_string(), old));
+                }
                 state.expire_table.insert((key.to_string(), expire_at));
+                drop(state);
+                self.notify(KeyspaceEvents::GENERIC, "expire", key);
                 Ok(())
             }
             None => Err(RedisErr::KeyNotFound),
@@ -151,121 +268,510 @@ impl DB {
     }
 
     pub fn del(&mut self, key: &str) -> Option<Value> {
-        self.remove(key)
+        let value = self.remove(key);
+        if value.is_some() {
+            self.notify(KeyspaceEvents::GENERIC, "del", key);
+        }
+        value
     }
 
     pub fn lpush(&mut self, key: &str, values: Vec<Bytes>) -> Result<usize> {
-        let mut state = self.db.state.lock().unwrap();
-        let entry = state.table.get_mut(key);
-        let value_len = values.len();
-        match entry {
-            Some(entry) => {
-                if !entry.value.is_list() {
-                    return Err(RedisErr::WrongType);
-                }
-                let mut after_len = 0;
-                if let Value::List(list) = &mut entry.value {
-                    for v in values {
-                        list.push_front(v);
-                    }
-                    after_len = list.len();
-                }
-                Ok(after_len)
-            }
+        self.push(key, values, ListSide::Left, false)
+    }
+
+    pub fn rpush(&mut self, key: &str, values: Vec<Bytes>) -> Result<usize> {
+        self.push(key, values, ListSide::Right, false)
+    }
+
+    // push the values one by one to the side, the length of the list after it,
+    // a list is only pushed to if it exists when xx is set, 0 if it doesn't
+    pub fn push(
+        &mut self,
+        key: &str,
+        values: Vec<Bytes>,
+        side: ListSide,
+        xx: bool,
+    ) -> Result<usize> {
+        let mut state = self.shard(key);
+        let created = match state.get(key) {
+            Some(entry) if !entry.value.is_list() => return Err(RedisErr::WrongType),
+            Some(_) => false,
+            None if xx => return Ok(0),
             None => {
-                let mut list = VecDeque::new();
-                list.extend(values);
-                let entry = Entry::new(Value::List(list), None);
-                state.table.insert(key.to_string(), entry);
+                state.insert(
+                    key.to_string(),
+                    Entry::new(Value::List(VecDeque::new()), None),
+                );
+                true
+            }
+        };
+        let list = state.get_mut(key).unwrap().value.as_list_mut().unwrap();
+        for value in values {
+            side.push(list, value);
+        }
+        let len = list.len();
+        drop(state);
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", key);
+        }
+        self.notify(KeyspaceEvents::LIST, side.push_event(), key);
+        self.db.signal_key_ready(key);
+        Ok(len)
+    }
 
-                Ok(value_len)
+    // pop at most count values from the side, None if the key doesn't exist,
+    // the list is deleted once it's empty
+    pub fn pop(&mut self, key: &str, side: ListSide, count: usize) -> Result<Option<Vec<Bytes>>> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get_mut(key) else {
+            return Ok(None);
+        };
+        let list = entry.value.as_list_mut().ok_or(RedisErr::WrongType)?;
+        let popped = (0..count).map_while(|_| side.pop(list)).collect::<Vec<_>>();
+        let deleted = list.is_empty() && state.remove(key).is_some();
+        drop(state);
+        if !popped.is_empty() {
+            self.notify(KeyspaceEvents::LIST, side.pop_event(), key);
+        }
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", key);
+        }
+        Ok(Some(popped))
+    }
+
+    pub fn llen(&mut self, key: &str) -> Result<usize> {
+        let mut state = self.shard(key);
+        match state.get(key) {
+            Some(entry) => Ok(entry.value.as_list_ref().ok_or(RedisErr::WrongType)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    // the value at the index, a negative one counts from the tail
+    pub fn lindex(&mut self, key: &str, index: i64) -> Result<Option<Bytes>> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get(key) else {
+            return Ok(None);
+        };
+        let list = entry.value.as_list_ref().ok_or(RedisErr::WrongType)?;
+        Ok(list_index(list.len(), index).map(|index| list[index].clone()))
+    }
+
+    // an index out of the list is an InvalidArgument
+    pub fn lset(&mut self, key: &str, index: i64, value: Bytes) -> Result<()> {
+        let mut state = self.shard(key);
+        let entry = state.get_mut(key).ok_or(RedisErr::KeyNotFound)?;
+        let list = entry.value.as_list_mut().ok_or(RedisErr::WrongType)?;
+        let index = list_index(list.len(), index).ok_or(RedisErr::InvalidArgument)?;
+        list[index] = value;
+        drop(state);
+        self.notify(KeyspaceEvents::LIST, "lset", key);
+        Ok(())
+    }
+
+    // insert the value before or after the first pivot, the length of the list after it,
+    // None if the pivot is not found, 0 if the key doesn't exist
+    pub fn linsert(
+        &mut self,
+        key: &str,
+        before: bool,
+        pivot: &Bytes,
+        value: Bytes,
+    ) -> Result<Option<usize>> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get_mut(key) else {
+            return Ok(Some(0));
+        };
+        let list = entry.value.as_list_mut().ok_or(RedisErr::WrongType)?;
+        let Some(index) = list.iter().position(|v| v == pivot) else {
+            return Ok(None);
+        };
+        list.insert(if before { index } else { index + 1 }, value);
+        let len = list.len();
+        drop(state);
+        self.notify(KeyspaceEvents::LIST, "linsert", key);
+        Ok(Some(len))
+    }
+
+    // remove count occurrences of the value from the head, from the tail if count is negative,
+    // all of them if it's 0, the list is deleted once it's empty
+    pub fn lrem(&mut self, key: &str, count: i64, value: &Bytes) -> Result<usize> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get_mut(key) else {
+            return Ok(0);
+        };
+        let list = entry.value.as_list_mut().ok_or(RedisErr::WrongType)?;
+        let limit = match count {
+            0 => usize::MAX,
+            count => count.unsigned_abs() as usize,
+        };
+        let matches = list
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| *v == value)
+            .map(|(i, _)| i);
+        let mut indexes = if count < 0 {
+            matches.rev().take(limit).collect::<Vec<_>>()
+        } else {
+            matches.take(limit).collect()
+        };
+        indexes.sort_unstable();
+        let removed = indexes.len();
+        let mut indexes = indexes.into_iter().peekable();
+        let mut i = 0;
+        list.retain(|_| {
+            let remove = indexes.next_if_eq(&i).is_some();
+            i += 1;
+            !remove
+        });
+        let deleted = list.is_empty() && state.remove(key).is_some();
+        drop(state);
+        if removed > 0 {
+            self.notify(KeyspaceEvents::LIST, "lrem", key);
+        }
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", key);
+        }
+        Ok(removed)
+    }
+
+    // keep the values between start and stop, both inclusive,
+    // the list is deleted if nothing is kept
+    pub fn ltrim(&mut self, key: &str, start: i64, stop: i64) -> Result<()> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get_mut(key) else {
+            return Ok(());
+        };
+        let list = entry.value.as_list_mut().ok_or(RedisErr::WrongType)?;
+        match list_range(list.len(), start, stop) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
             }
+            None => list.clear(),
+        }
+        let deleted = list.is_empty() && state.remove(key).is_some();
+        drop(state);
+        self.notify(KeyspaceEvents::LIST, "ltrim", key);
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", key);
         }
+        Ok(())
     }
 
     pub fn lrange(&mut self, key: &str, start: i64, stop: i64) -> Result<Vec<Bytes>> {
-        let state = self.db.state.lock().unwrap();
-        let entry = state.table.get(key);
-        match entry {
-            Some(entry) => {
-                if !entry.value.is_list() {
-                    return Err(RedisErr::WrongType);
-                }
-                let mut res = Vec::new();
-                if let Value::List(list) = &entry.value {
-                    let len = list.len() as i64;
-                    let start = if start < 0 { len + start } else { start };
-                    let stop = if stop < 0 { len + stop } else { stop };
-                    let start = if start < 0 { 0 } else { start };
-                    let stop = if stop < 0 { 0 } else { stop };
-                    let start = start as usize;
-                    let stop = stop as usize;
-                    if start > stop {
-                        return Ok(res);
-                    }
-                    if start >= list.len() {
-                        return Ok(res);
-                    }
-                    let stop = if stop >= list.len() {
-                        list.len() - 1
-                    } else {
-                        stop
-                    };
-
-                    list.iter().skip(start).take(stop - start).for_each(|v| {
-                        res.push(v.clone());
-                    });
-                }
-                Ok(res)
+        let mut state = self.shard(key);
+        let entry = state.get(key).ok_or(RedisErr::KeyNotFound)?;
+        let list = entry.value.as_list_ref().ok_or(RedisErr::WrongType)?;
+        Ok(match list_range(list.len(), start, stop) {
+            Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+            None => vec![],
+        })
+    }
+
+    // the indexes of the matches of the value, the rank-th match first,
+    // a negative rank searches from the tail, at most count of them and 0 for all,
+    // comparing at most maxlen values and 0 for all
+    pub fn lpos(
+        &mut self,
+        key: &str,
+        value: &Bytes,
+        rank: i64,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get(key) else {
+            return Ok(vec![]);
+        };
+        let list = entry.value.as_list_ref().ok_or(RedisErr::WrongType)?;
+        let indexes: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+            Box::new(0..list.len())
+        } else {
+            Box::new((0..list.len()).rev())
+        };
+        let count = if count == 0 { usize::MAX } else { count };
+        let maxlen = if maxlen == 0 { usize::MAX } else { maxlen };
+        Ok(indexes
+            .take(maxlen)
+            .filter(|i| list[*i] == value)
+            .skip(rank.unsigned_abs() as usize - 1)
+            .take(count)
+            .collect())
+    }
+
+    // pop a value from one side of the source and push it to a side of the destination,
+    // both locked at once, None if the source doesn't exist
+    pub fn lmove(
+        &mut self,
+        source: &str,
+        destination: &str,
+        from: ListSide,
+        to: ListSide,
+    ) -> Result<Option<Bytes>> {
+        let mut shards = self.lock_keys(&[source, destination]);
+        let Some(entry) = shards.shard(source).get(source) else {
+            return Ok(None);
+        };
+        if !entry.value.is_list() {
+            return Err(RedisErr::WrongType);
+        }
+        let created = match shards.shard(destination).get(destination) {
+            Some(entry) if !entry.value.is_list() => return Err(RedisErr::WrongType),
+            Some(_) => false,
+            None => true,
+        };
+
+        let shard = shards.shard(source);
+        let list = shard.get_mut(source).unwrap().value.as_list_mut().unwrap();
+        let value = from.pop(list).unwrap();
+        // the value is pushed back to the source if it's the destination
+        let deleted = list.is_empty() && source != destination && shard.remove(source).is_some();
+        let shard = shards.shard(destination);
+        if created {
+            shard.insert(
+                destination.to_string(),
+                Entry::new(Value::List(VecDeque::new()), None),
+            );
+        }
+        let list = shard
+            .get_mut(destination)
+            .unwrap()
+            .value
+            .as_list_mut()
+            .unwrap();
+        to.push(list, value.clone());
+        drop(shards);
+
+        self.notify(KeyspaceEvents::LIST, from.pop_event(), source);
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", source);
+        }
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", destination);
+        }
+        self.notify(KeyspaceEvents::LIST, to.push_event(), destination);
+        self.db.signal_key_ready(destination);
+        Ok(Some(value))
+    }
+
+    // pop at most count values from the first non-empty list of the keys,
+    // the keys are checked in order under the locks of all their shards
+    pub fn lmpop(
+        &mut self,
+        keys: &[String],
+        side: ListSide,
+        count: usize,
+    ) -> Result<Option<(String, Vec<Bytes>)>> {
+        let mut shards = self.lock_keys(keys);
+        for key in keys {
+            let shard = shards.shard(key);
+            let Some(entry) = shard.get_mut(key) else {
+                continue;
+            };
+            let list = entry.value.as_list_mut().ok_or(RedisErr::WrongType)?;
+            let popped = (0..count).map_while(|_| side.pop(list)).collect::<Vec<_>>();
+            let deleted = list.is_empty() && shard.remove(key).is_some();
+            drop(shards);
+            self.notify(KeyspaceEvents::LIST, side.pop_event(), key);
+            if deleted {
+                self.notify(KeyspaceEvents::GENERIC, "del", key);
             }
-            None => Err(RedisErr::KeyNotFound),
+            return Ok(Some((key.clone(), popped)));
         }
+        Ok(None)
     }
 
+    // the number of fields added, the ones updated are not counted
     pub fn hset(&mut self, key: String, field_values: Vec<(String, Bytes)>) -> Result<usize> {
-        let mut state = self.db.state.lock().unwrap();
-        let entry = state.table.get_mut(&key);
-        match entry {
+        let mut state = self.shard(&key);
+        let created = match state.get(&key) {
+            Some(entry) if !entry.value.is_hash() => return Err(RedisErr::WrongType),
+            Some(_) => false,
+            None => {
+                state.insert(key.clone(), Entry::new(Value::Hash(HashMap::new()), None));
+                true
+            }
+        };
+        let map = state.get_mut(&key).unwrap().value.as_hash_mut().unwrap();
+        let added = field_values
+            .into_iter()
+            .filter(|(field, value)| map.insert(field.clone(), value.clone()).is_none())
+            .count();
+        drop(state);
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", &key);
+        }
+        self.notify(KeyspaceEvents::HASH, "hset", &key);
+        Ok(added)
+    }
+
+    // set the field only if it doesn't exist, false if it does
+    pub fn hsetnx(&mut self, key: &str, field: String, value: Bytes) -> Result<bool> {
+        let mut state = self.shard(key);
+        let created = match state.get(key) {
             Some(entry) => {
-                if !entry.value.is_hash() {
-                    return Err(RedisErr::WrongType);
+                let map = entry.value.as_hash_ref().ok_or(RedisErr::WrongType)?;
+                if map.contains_key(&field) {
+                    return Ok(false);
                 }
-                let mut value_len = 0;
-                let map = entry.value.as_hash_mut().unwrap();
-                for (field, value) in field_values {
-                    map.insert(field, value);
-                    value_len += 1;
-                }
-                Ok(value_len)
+                false
             }
             None => {
-                let mut map = HashMap::new();
-                let res = field_values.len();
-                for (field, value) in field_values {
-                    map.insert(field, value);
-                }
-                let entry = Entry::new(Value::Hash(map), None);
-                state.table.insert(key, entry);
-                Ok(res)
+                state.insert(
+                    key.to_string(),
+                    Entry::new(Value::Hash(HashMap::new()), None),
+                );
+                true
             }
+        };
+        let map = state.get_mut(key).unwrap().value.as_hash_mut().unwrap();
+        map.insert(field, value);
+        drop(state);
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", key);
         }
+        self.notify(KeyspaceEvents::HASH, "hset", key);
+        Ok(true)
     }
 
     pub fn hget(&mut self, key: &str, field: &str) -> Result<Option<Bytes>> {
-        let state = self.db.state.lock().unwrap();
-        let entry = state.table.get(key);
-        match entry {
-            Some(entry) => {
-                if !entry.value.is_hash() {
-                    return Err(RedisErr::WrongType);
-                }
-                let map = entry.value.as_hash_ref().unwrap();
-                Ok(map.get(field).cloned())
+        self.hash(key, |map| map.get(field).cloned())?
+            .ok_or(RedisErr::KeyNotFound)
+    }
+
+    // the hash of the key for reading, None if the key doesn't exist
+    pub fn hash<R>(
+        &mut self,
+        key: &str,
+        f: impl FnOnce(&HashMap<String, Bytes>) -> R,
+    ) -> Result<Option<R>> {
+        let mut state = self.shard(key);
+        match state.get(key) {
+            Some(entry) => Ok(Some(f(entry
+                .value
+                .as_hash_ref()
+                .ok_or(RedisErr::WrongType)?))),
+            None => Ok(None),
+        }
+    }
+
+    // the hash is deleted once it's empty
+    pub fn hdel(&mut self, key: &str, fields: &[String]) -> Result<usize> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get_mut(key) else {
+            return Ok(0);
+        };
+        let map = entry.value.as_hash_mut().ok_or(RedisErr::WrongType)?;
+        let removed = fields
+            .iter()
+            .filter(|field| map.remove(*field).is_some())
+            .count();
+        let deleted = map.is_empty() && state.remove(key).is_some();
+        drop(state);
+        if removed > 0 {
+            self.notify(KeyspaceEvents::HASH, "hdel", key);
+        }
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", key);
+        }
+        Ok(removed)
+    }
+
+    // add the increment to the integer of the field, a missing field counts as 0,
+    // InvalidArgument if it's not an integer and Overflow if the sum overflows
+    pub fn hincrby(&mut self, key: &str, field: &str, increment: i64) -> Result<i64> {
+        self.hincr(key, field, "hincrby", |value| {
+            let value = match value {
+                Some(value) => std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .ok_or(RedisErr::InvalidArgument)?,
+                None => 0,
+            };
+            value.checked_add(increment).ok_or(RedisErr::Overflow)
+        })
+    }
+
+    // add the increment to the float of the field, a missing field counts as 0,
+    // InvalidArgument if it's not a float and Overflow if the sum is not finite
+    pub fn hincrbyfloat(&mut self, key: &str, field: &str, increment: f64) -> Result<f64> {
+        self.hincr(key, field, "hincrbyfloat", |value| {
+            let value = match value {
+                Some(value) => std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|value| value.is_finite())
+                    .ok_or(RedisErr::InvalidArgument)?,
+                None => 0.0,
+            };
+            let sum = value + increment;
+            if !sum.is_finite() {
+                return Err(RedisErr::Overflow);
             }
-            None => Err(RedisErr::KeyNotFound),
+            Ok(sum)
+        })
+    }
+
+    // replace the value of the field with the one computed from it,
+    // the hash is created if it doesn't exist
+    fn hincr<T: ToString>(
+        &mut self,
+        key: &str,
+        field: &str,
+        event: &'static str,
+        f: impl FnOnce(Option<&Bytes>) -> Result<T>,
+    ) -> Result<T> {
+        let mut state = self.shard(key);
+        let created = match state.get(key) {
+            Some(entry) if !entry.value.is_hash() => return Err(RedisErr::WrongType),
+            Some(_) => false,
+            None => true,
+        };
+        let value = match state.get_mut(key) {
+            Some(entry) => f(entry.value.as_hash_ref().unwrap().get(field))?,
+            None => f(None)?,
+        };
+        if created {
+            state.insert(
+                key.to_string(),
+                Entry::new(Value::Hash(HashMap::new()), None),
+            );
+        }
+        let map = state.get_mut(key).unwrap().value.as_hash_mut().unwrap();
+        map.insert(field.to_string(), Bytes::from(value.to_string()));
+        drop(state);
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", key);
         }
+        self.notify(KeyspaceEvents::HASH, event, key);
+        Ok(value)
+    }
+
+    // distinct random fields for a positive count, at most all of them,
+    // a negative count allows the same field multiple times and returns exactly -count of them
+    pub fn hrandfield(&mut self, key: &str, count: i64) -> Result<Vec<(String, Bytes)>> {
+        let fields = self.hash(key, |map| {
+            let fields = if count >= 0 {
+                random_members(map.keys(), count as usize)
+            } else {
+                let fields = map.keys().collect::<Vec<_>>();
+                (0..count.unsigned_abs())
+                    .map(|_| fields[(random_u64() % fields.len() as u64) as usize].clone())
+                    .collect()
+            };
+            fields
+                .into_iter()
+                .map(|field| {
+                    let value = map[&field].clone();
+                    (field, value)
+                })
+                .collect()
+        })?;
+        Ok(fields.unwrap_or_default())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn zadd(
         &mut self,
         key: &str,
@@ -277,8 +783,8 @@ impl DB {
         incr: bool,
         zset: Vec<(f64, Bytes)>,
     ) -> Result<usize> {
-        let mut state = self.db.state.lock().unwrap();
-        let entry = state.table.get_mut(key);
+        let mut state = self.shard(key);
+        let entry = state.get_mut(key);
         match entry {
             Some(entry) => {
                 if !entry.value.is_zset() {
@@ -289,6 +795,8 @@ impl DB {
                 for (score, member) in zset {
                     value_len += value.zadd(nx, xx, lt, gt, ch, incr, score, member);
                 }
+                drop(state);
+                self.notify(KeyspaceEvents::ZSET, zadd_event(incr), key);
                 Ok(value_len)
             }
             None => {
@@ -298,15 +806,18 @@ impl DB {
                     value.zadd(nx, xx, lt, gt, ch, incr, score, member);
                 }
                 let entry = Entry::new(Value::ZSet(value), None);
-                state.table.insert(key.to_string(), entry);
+                state.insert(key.to_string(), entry);
+                drop(state);
+                self.notify(KeyspaceEvents::NEW, "new", key);
+                self.notify(KeyspaceEvents::ZSET, zadd_event(incr), key);
                 Ok(value_len)
             }
         }
     }
 
     pub fn zcard(&mut self, key: &str) -> Result<usize> {
-        let mut state = self.db.state.lock().unwrap();
-        let entry = state.table.get_mut(key);
+        let mut state = self.shard(key);
+        let entry = state.get(key);
         match entry {
             Some(entry) => {
                 if !entry.value.is_zset() {
@@ -319,8 +830,8 @@ impl DB {
     }
 
     pub fn zrem(&mut self, key: &str, members: Vec<Bytes>) -> Result<usize> {
-        let mut state = self.db.state.lock().unwrap();
-        let entry = state.table.get_mut(key);
+        let mut state = self.shard(key);
+        let entry = state.get_mut(key);
         match entry {
             Some(entry) => {
                 if !entry.value.is_zset() {
@@ -333,165 +844,1733 @@ impl DB {
                         value_len += 1;
                     }
                 }
+                drop(state);
+                if value_len > 0 {
+                    self.notify(KeyspaceEvents::ZSET, "zrem", key);
+                }
                 Ok(value_len)
             }
             None => Err(RedisErr::KeyNotFound),
         }
     }
 
-    pub fn remove(&mut self, key: &str) -> Option<Value> {
-        self.db
-            .state
-            .lock()
-            .unwrap()
-            .table
-            .remove(key)
-            .map(|entry| entry.value)
+    // the number of the members added, not counting the ones already in the set
+    pub fn sadd(&mut self, key: &str, members: Vec<Bytes>) -> Result<usize> {
+        let mut state = self.shard(key);
+        let created = match state.get(key) {
+            Some(entry) if !entry.value.is_set() => return Err(RedisErr::WrongType),
+            Some(_) => false,
+            None => {
+                state.insert(
+                    key.to_string(),
+                    Entry::new(Value::Set(HashSet::new()), None),
+                );
+                true
+            }
+        };
+        let set = state.get_mut(key).unwrap().value.as_set_mut().unwrap();
+        let added = members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count();
+        drop(state);
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", key);
+        }
+        if added > 0 {
+            self.notify(KeyspaceEvents::SET, "sadd", key);
+        }
+        Ok(added)
     }
 
-    pub fn get_type(&self, key: &str) -> Option<&'static str> {
-        let state = self.db.state.lock().unwrap();
-        let entry = state.table.get(key);
-        match entry {
-            Some(entry) => Some(entry.value.get_type().to_str()),
-            None => None,
+    // the set is deleted once it's empty
+    pub fn srem(&mut self, key: &str, members: &[Bytes]) -> Result<usize> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get_mut(key) else {
+            return Ok(0);
+        };
+        let set = entry.value.as_set_mut().ok_or(RedisErr::WrongType)?;
+        let removed = members.iter().filter(|member| set.remove(*member)).count();
+        let deleted = set.is_empty() && state.remove(key).is_some();
+        drop(state);
+        if removed > 0 {
+            self.notify(KeyspaceEvents::SET, "srem", key);
+        }
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", key);
         }
+        Ok(removed)
     }
 
-    pub fn flush(&mut self) {
-        let mut state = self.db.state.lock().unwrap();
-        state.table.clear();
-        state.expire_table.clear();
+    pub fn smembers(&mut self, key: &str) -> Result<Vec<Bytes>> {
+        let mut state = self.shard(key);
+        match state.get(key) {
+            Some(entry) => Ok(entry
+                .value
+                .as_set_ref()
+                .ok_or(RedisErr::WrongType)?
+                .iter()
+                .cloned()
+                .collect()),
+            None => Ok(vec![]),
+        }
     }
 
-    pub fn subscribe(&self, channel: String) -> broadcast::Receiver<Bytes> {
-        use std::collections::hash_map::Entry;
-        let mut state = self.db.state.lock().unwrap();
-        match state.publisher.entry(channel.clone()) {
-            Entry::Occupied(e) => e.get().subscribe(),
-            Entry::Vacant(entry) => {
-                trace!("subscribe to channel: {}", channel);
-                let (tx, rx) = broadcast::channel(1024);
-                entry.insert(tx);
-                rx
+    // whether each member is in the set
+    pub fn smismember(&mut self, key: &str, members: &[Bytes]) -> Result<Vec<bool>> {
+        let mut state = self.shard(key);
+        match state.get(key) {
+            Some(entry) => {
+                let set = entry.value.as_set_ref().ok_or(RedisErr::WrongType)?;
+                Ok(members.iter().map(|member| set.contains(member)).collect())
             }
+            None => Ok(vec![false; members.len()]),
+        }
+    }
+
+    pub fn scard(&mut self, key: &str) -> Result<usize> {
+        let mut state = self.shard(key);
+        match state.get(key) {
+            Some(entry) => Ok(entry.value.as_set_ref().ok_or(RedisErr::WrongType)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    // remove count random members, the set is deleted once it's empty
+    pub fn spop(&mut self, key: &str, count: usize) -> Result<Vec<Bytes>> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get_mut(key) else {
+            return Ok(vec![]);
+        };
+        let set = entry.value.as_set_mut().ok_or(RedisErr::WrongType)?;
+        let popped = random_members(set.iter(), count);
+        for member in &popped {
+            set.remove(member);
+        }
+        let deleted = set.is_empty() && state.remove(key).is_some();
+        drop(state);
+        if !popped.is_empty() {
+            self.notify(KeyspaceEvents::SET, "spop", key);
+        }
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", key);
+        }
+        Ok(popped)
+    }
+
+    // distinct random members for a positive count, at most all of them,
+    // a negative count allows the same member multiple times and returns exactly -count of them
+    pub fn srandmember(&mut self, key: &str, count: i64) -> Result<Vec<Bytes>> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get(key) else {
+            return Ok(vec![]);
+        };
+        let set = entry.value.as_set_ref().ok_or(RedisErr::WrongType)?;
+        if count >= 0 {
+            return Ok(random_members(set, count as usize));
+        }
+        let members = set.iter().collect::<Vec<_>>();
+        Ok((0..count.unsigned_abs())
+            .map(|_| members[(random_u64() % members.len() as u64) as usize].clone())
+            .collect())
+    }
+
+    // move the member from the source set to the destination one, both locked at once,
+    // false if it's not in the source
+    pub fn smove(&mut self, source: &str, destination: &str, member: Bytes) -> Result<bool> {
+        let mut shards = self.lock_keys(&[source, destination]);
+        let Some(entry) = shards.shard(source).get(source) else {
+            return Ok(false);
+        };
+        if !entry.value.is_set() {
+            return Err(RedisErr::WrongType);
+        }
+        let created = match shards.shard(destination).get(destination) {
+            Some(entry) if !entry.value.is_set() => return Err(RedisErr::WrongType),
+            Some(_) => false,
+            None => true,
+        };
+
+        let shard = shards.shard(source);
+        let set = shard.get_mut(source).unwrap().value.as_set_mut().unwrap();
+        if !set.remove(&member) {
+            return Ok(false);
+        }
+        if source == destination {
+            set.insert(member);
+            return Ok(true);
+        }
+        let deleted = set.is_empty() && shard.remove(source).is_some();
+        let shard = shards.shard(destination);
+        if created {
+            shard.insert(
+                destination.to_string(),
+                Entry::new(Value::Set(HashSet::new()), None),
+            );
+        }
+        let set = shard
+            .get_mut(destination)
+            .unwrap()
+            .value
+            .as_set_mut()
+            .unwrap();
+        set.insert(member);
+        drop(shards);
+
+        self.notify(KeyspaceEvents::SET, "srem", source);
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", source);
+        }
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", destination);
+        }
+        self.notify(KeyspaceEvents::SET, "sadd", destination);
+        Ok(true)
+    }
+
+    // the result of the operation on the sets, the keys not existing are empty sets
+    pub fn sop(&mut self, op: SetOp, keys: &[String]) -> Result<HashSet<Bytes>> {
+        let mut shards = self.lock_keys(keys);
+        set_operation(&mut shards, op, keys, None)
+    }
+
+    // store the result of the operation to the destination, replacing any value it holds,
+    // the destination is deleted if the result is empty
+    pub fn sopstore(&mut self, op: SetOp, destination: &str, keys: &[String]) -> Result<usize> {
+        let mut locked = keys.iter().map(String::as_str).collect::<Vec<_>>();
+        locked.push(destination);
+        let mut shards = self.lock_keys(&locked);
+        let result = set_operation(&mut shards, op, keys, None)?;
+        let len = result.len();
+        let shard = shards.shard(destination);
+        let deleted = if result.is_empty() {
+            shard.remove(destination).is_some()
+        } else {
+            shard.insert(
+                destination.to_string(),
+                Entry::new(Value::Set(result), None),
+            );
+            false
+        };
+        drop(shards);
+        if len > 0 {
+            self.notify(KeyspaceEvents::SET, op.store_event(), destination);
+        } else if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", destination);
+        }
+        Ok(len)
+    }
+
+    // the size of the intersection, counting stops at the limit if it's given
+    pub fn sintercard(&mut self, keys: &[String], limit: Option<usize>) -> Result<usize> {
+        let mut shards = self.lock_keys(keys);
+        Ok(set_operation(&mut shards, SetOp::Inter, keys, limit)?.len())
+    }
+
+    // the id of the added entry, None if the key doesn't exist and nomkstream is set,
+    // an id not greater than the last one of the stream is an InvalidArgument
+    pub fn xadd(
+        &mut self,
+        key: &str,
+        id: NewStreamId,
+        fields: StreamFields,
+        nomkstream: bool,
+        trim: Option<(StreamTrim, Option<usize>)>,
+    ) -> Result<Option<StreamId>> {
+        let mut state = self.shard(key);
+        let created = match state.get(key) {
+            Some(entry) if !entry.value.is_stream() => return Err(RedisErr::WrongType),
+            Some(_) => false,
+            None if nomkstream => return Ok(None),
+            None => true,
+        };
+        let mut stream = Stream::new();
+        let stream = match state.get_mut(key) {
+            Some(entry) => entry.value.as_stream_mut().unwrap(),
+            None => &mut stream,
+        };
+        let id = stream
+            .next_id(id, unix_timestamp_ms())
+            .ok_or(RedisErr::InvalidArgument)?;
+        stream.add(id, fields);
+        let trimmed = trim.map_or(0, |(trim, limit)| stream.trim(trim, limit));
+        if created {
+            let stream = std::mem::take(stream);
+            state.insert(key.to_string(), Entry::new(Value::Stream(stream), None));
+        }
+        drop(state);
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", key);
+        }
+        self.notify(KeyspaceEvents::STREAM, "xadd", key);
+        if trimmed > 0 {
+            self.notify(KeyspaceEvents::STREAM, "xtrim", key);
+        }
+        self.db.signal_key_ready(key);
+        Ok(Some(id))
+    }
+
+    // the entries between start and end, both inclusive, from the end if rev is set
+    pub fn xrange(
+        &mut self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Result<Vec<StreamEntry>> {
+        let mut state = self.shard(key);
+        let Some(entry) = state.get(key) else {
+            return Ok(vec![]);
+        };
+        let stream = entry.value.as_stream_ref().ok_or(RedisErr::WrongType)?;
+        let range = stream.range(start, end);
+        let range: Box<dyn Iterator<Item = _>> = if rev {
+            Box::new(range.rev())
+        } else {
+            Box::new(range)
+        };
+        Ok(range
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect())
+    }
+
+    pub fn xlen(&mut self, key: &str) -> Result<usize> {
+        let mut state = self.shard(key);
+        match state.get(key) {
+            Some(entry) => Ok(entry
+                .value
+                .as_stream_ref()
+                .ok_or(RedisErr::WrongType)?
+                .len()),
+            None => Ok(0),
+        }
+    }
+
+    // the last id of the stream, the `$` of XREAD
+    pub fn xlast_id(&mut self, key: &str) -> Result<Option<StreamId>> {
+        let mut state = self.shard(key);
+        match state.get(key) {
+            Some(entry) => Ok(Some(
+                entry
+                    .value
+                    .as_stream_ref()
+                    .ok_or(RedisErr::WrongType)?
+                    .last_id(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    // the stream is kept even if it becomes empty
+    pub fn xdel(&mut self, key: &str, ids: &[StreamId]) -> Result<usize> {
+        let mut state = self.shard(key);
+        if !state.get(key).is_some_and(|entry| entry.value.is_stream()) {
+            return match state.get(key) {
+                Some(_) => Err(RedisErr::WrongType),
+                None => Ok(0),
+            };
+        }
+        let stream = state.get_mut(key).unwrap().value.as_stream_mut().unwrap();
+        let deleted = ids.iter().filter(|id| stream.remove(id)).count();
+        drop(state);
+        if deleted > 0 {
+            self.notify(KeyspaceEvents::STREAM, "xdel", key);
+        }
+        Ok(deleted)
+    }
+
+    // the last id can't be smaller than the id of the last entry, an InvalidArgument
+    pub fn xsetid(&mut self, key: &str, id: StreamId) -> Result<()> {
+        if !self.stream_mut(key, |stream| stream.reset_last_id(id))? {
+            return Err(RedisErr::InvalidArgument);
+        }
+        self.notify(KeyspaceEvents::STREAM, "xsetid", key);
+        Ok(())
+    }
+
+    pub fn xtrim(&mut self, key: &str, trim: StreamTrim, limit: Option<usize>) -> Result<usize> {
+        let mut state = self.shard(key);
+        if !state.get(key).is_some_and(|entry| entry.value.is_stream()) {
+            return match state.get(key) {
+                Some(_) => Err(RedisErr::WrongType),
+                None => Ok(0),
+            };
+        }
+        let stream = state.get_mut(key).unwrap().value.as_stream_mut().unwrap();
+        let trimmed = stream.trim(trim, limit);
+        drop(state);
+        if trimmed > 0 {
+            self.notify(KeyspaceEvents::STREAM, "xtrim", key);
+        }
+        Ok(trimmed)
+    }
+
+    // the entries after the id of each stream, the streams with none are left out,
+    // all of them are read under the locks of their shards
+    pub fn xread(
+        &mut self,
+        streams: &[(String, StreamId)],
+        count: Option<usize>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>> {
+        let keys = streams.iter().map(|(key, _)| key).collect::<Vec<_>>();
+        let mut shards = self.lock_keys(&keys);
+        let mut result = Vec::new();
+        for (key, after) in streams {
+            let Some(entry) = shards.shard(key).get(key) else {
+                continue;
+            };
+            let stream = entry.value.as_stream_ref().ok_or(RedisErr::WrongType)?;
+            let Some(start) = after.next() else {
+                continue;
+            };
+            let entries = stream
+                .range(start, StreamId::MAX)
+                .take(count.unwrap_or(usize::MAX))
+                .map(|(id, fields)| (*id, fields.clone()))
+                .collect::<Vec<_>>();
+            if !entries.is_empty() {
+                result.push((key.clone(), entries));
+            }
+        }
+        Ok(result)
+    }
+
+    // the stream of the key for reading, None if the key doesn't exist
+    pub fn stream<R>(&mut self, key: &str, f: impl FnOnce(&Stream) -> R) -> Result<Option<R>> {
+        let mut state = self.shard(key);
+        match state.get(key) {
+            Some(entry) => Ok(Some(f(entry
+                .value
+                .as_stream_ref()
+                .ok_or(RedisErr::WrongType)?))),
+            None => Ok(None),
+        }
+    }
+
+    // the stream of the key for writing, the key must exist
+    fn stream_mut<R>(&mut self, key: &str, f: impl FnOnce(&mut Stream) -> R) -> Result<R> {
+        let mut state = self.shard(key);
+        match state.get_mut(key) {
+            Some(entry) => Ok(f(entry.value.as_stream_mut().ok_or(RedisErr::WrongType)?)),
+            None => Err(RedisErr::KeyNotFound),
+        }
+    }
+
+    // the group starts after the id, the last id of the stream if it's None,
+    // an existing group is a BusyGroup
+    pub fn xgroup_create(
+        &mut self,
+        key: &str,
+        group: &str,
+        id: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<()> {
+        let mut state = self.shard(key);
+        let created = match state.get(key) {
+            Some(entry) if !entry.value.is_stream() => return Err(RedisErr::WrongType),
+            Some(_) => false,
+            None if mkstream => {
+                state.insert(
+                    key.to_string(),
+                    Entry::new(Value::Stream(Stream::new()), None),
+                );
+                true
+            }
+            None => return Err(RedisErr::KeyNotFound),
+        };
+        let stream = state.get_mut(key).unwrap().value.as_stream_mut().unwrap();
+        let id = id.unwrap_or(stream.last_id());
+        let busy = !stream.create_group(group, id);
+        drop(state);
+        if created {
+            self.notify(KeyspaceEvents::NEW, "new", key);
+        }
+        if busy {
+            return Err(RedisErr::BusyGroup);
+        }
+        self.notify(KeyspaceEvents::STREAM, "xgroup-create", key);
+        Ok(())
+    }
+
+    // false if the group doesn't exist
+    pub fn xgroup_destroy(&mut self, key: &str, group: &str) -> Result<bool> {
+        let destroyed = self.stream_mut(key, |stream| stream.destroy_group(group))?;
+        if destroyed {
+            self.notify(KeyspaceEvents::STREAM, "xgroup-destroy", key);
+        }
+        Ok(destroyed)
+    }
+
+    // the id is the last id of the stream if it's None
+    pub fn xgroup_setid(&mut self, key: &str, group: &str, id: Option<StreamId>) -> Result<()> {
+        self.stream_mut(key, |stream| {
+            let id = id.unwrap_or(stream.last_id());
+            stream.group_mut(group).map(|group| group.set_last_id(id))
+        })?
+        .ok_or(RedisErr::NoGroup)?;
+        self.notify(KeyspaceEvents::STREAM, "xgroup-setid", key);
+        Ok(())
+    }
+
+    // false if the consumer exists
+    pub fn xgroup_create_consumer(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+    ) -> Result<bool> {
+        let created = self
+            .stream_mut(key, |stream| {
+                stream
+                    .group_mut(group)
+                    .map(|group| group.create_consumer(consumer, unix_timestamp_ms()))
+            })?
+            .ok_or(RedisErr::NoGroup)?;
+        if created {
+            self.notify(KeyspaceEvents::STREAM, "xgroup-createconsumer", key);
+        }
+        Ok(created)
+    }
+
+    // the number of the pending entries the consumer had, 0 if it doesn't exist
+    pub fn xgroup_del_consumer(&mut self, key: &str, group: &str, consumer: &str) -> Result<usize> {
+        let deleted = self
+            .stream_mut(key, |stream| {
+                stream
+                    .group_mut(group)
+                    .map(|group| group.delete_consumer(consumer))
+            })?
+            .ok_or(RedisErr::NoGroup)?;
+        if deleted.is_some() {
+            self.notify(KeyspaceEvents::STREAM, "xgroup-delconsumer", key);
+        }
+        Ok(deleted.unwrap_or(0))
+    }
+
+    // the entries read by the consumer of the group from each stream, under the locks of their shards,
+    // a None id is `>`, the entries never delivered to the group, the streams with none are left out,
+    // an id reads the history of the consumer after it, the fields are None for deleted entries,
+    // every stream must have the group or it's a NoGroup
+    #[allow(clippy::type_complexity)]
+    pub fn xreadgroup(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        streams: &[(String, Option<StreamId>)],
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<(String, Vec<(StreamId, Option<StreamFields>)>)>> {
+        let keys = streams.iter().map(|(key, _)| key).collect::<Vec<_>>();
+        let mut shards = self.lock_keys(&keys);
+        for key in &keys {
+            let entry = shards.shard(key).get(key).ok_or(RedisErr::NoGroup)?;
+            let stream = entry.value.as_stream_ref().ok_or(RedisErr::WrongType)?;
+            stream.group(group).ok_or(RedisErr::NoGroup)?;
+        }
+
+        let now = unix_timestamp_ms();
+        let mut result = Vec::new();
+        let mut created = Vec::new();
+        for (key, after) in streams {
+            let stream = shards
+                .shard(key)
+                .get_mut(key)
+                .unwrap()
+                .value
+                .as_stream_mut()
+                .unwrap();
+            if stream.group(group).unwrap().consumer(consumer).is_none() {
+                created.push(key);
+            }
+            match after {
+                None => {
+                    let entries = stream
+                        .read_group(group, consumer, count, noack, now)
+                        .unwrap();
+                    if !entries.is_empty() {
+                        let entries = entries
+                            .into_iter()
+                            .map(|(id, fields)| (id, Some(fields)))
+                            .collect();
+                        result.push((key.clone(), entries));
+                    }
+                }
+                Some(after) => {
+                    let entries = stream
+                        .read_pending(group, consumer, *after, count, now)
+                        .unwrap();
+                    result.push((key.clone(), entries));
+                }
+            }
+        }
+        drop(shards);
+        for key in created {
+            self.notify(KeyspaceEvents::STREAM, "xgroup-createconsumer", key);
+        }
+        Ok(result)
+    }
+
+    // the number of the ids acknowledged, 0 if the key or the group doesn't exist
+    pub fn xack(&mut self, key: &str, group: &str, ids: &[StreamId]) -> Result<usize> {
+        match self.stream_mut(key, |stream| {
+            stream
+                .group_mut(group)
+                .map_or(0, |group| ids.iter().filter(|id| group.ack(id)).count())
+        }) {
+            Err(RedisErr::KeyNotFound) => Ok(0),
+            result => result,
+        }
+    }
+
+    // claim the pending entries for the consumer, the last delivered id of the group
+    // is moved forward to last_id if it's given
+    #[allow(clippy::too_many_arguments)]
+    pub fn xclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        ids: &[StreamId],
+        min_idle: u64,
+        options: ClaimOptions,
+        last_id: Option<StreamId>,
+    ) -> Result<Vec<StreamEntry>> {
+        let result = self.stream_mut(key, |stream| {
+            let claimed = stream
+                .claim(group, consumer, ids, min_idle, options, unix_timestamp_ms())
+                .ok_or(RedisErr::NoGroup)?;
+            let group = stream.group_mut(group).unwrap();
+            if let Some(last_id) = last_id.filter(|id| *id > group.last_id()) {
+                group.set_last_id(last_id);
+            }
+            Ok(claimed)
+        });
+        match result {
+            Err(RedisErr::KeyNotFound) => Err(RedisErr::NoGroup),
+            result => result?,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn xautoclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        just_id: bool,
+    ) -> Result<AutoClaimed> {
+        let result = self.stream_mut(key, |stream| {
+            stream
+                .auto_claim(
+                    group,
+                    consumer,
+                    min_idle,
+                    start,
+                    count,
+                    just_id,
+                    unix_timestamp_ms(),
+                )
+                .ok_or(RedisErr::NoGroup)
+        });
+        match result {
+            Err(RedisErr::KeyNotFound) => Err(RedisErr::NoGroup),
+            result => result?,
+        }
+    }
+
+    // wake up once any of the keys is written
+    pub fn block_on_keys(&self, keys: &[String]) -> KeyWaiter {
+        self.block(keys, false)
+    }
+
+    // wake up once a value is pushed to any of the lists,
+    // only the client blocked the longest on a list is woken up by a push,
+    // the next one is woken up once it's served or gone
+    pub fn block_on_lists(&self, keys: &[String]) -> KeyWaiter {
+        self.block(keys, true)
+    }
+
+    // number of clients blocked on the key
+    #[cfg(test)]
+    pub fn blocked_clients(&self, key: &str) -> usize {
+        let blocked = self.db.blocked.lock().unwrap();
+        blocked.get(key).map_or(0, VecDeque::len)
+    }
+
+    fn block(&self, keys: &[String], pop: bool) -> KeyWaiter {
+        let notify = Arc::new(Notify::new());
+        let mut blocked = self.db.blocked.lock().unwrap();
+        for key in keys {
+            blocked.entry(key.clone()).or_default().push_back(Blocked {
+                notify: notify.clone(),
+                pop,
+            });
+        }
+        KeyWaiter {
+            shared: self.db.clone(),
+            keys: keys.to_vec(),
+            notify,
+            pop,
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.shard(key).remove(key).map(|entry| entry.value)
+    }
+
+    pub fn get_type(&self, key: &str) -> Option<&'static str> {
+        let state = self.shard(key);
+        let entry = state.table.get(key);
+        match entry {
+            Some(entry) => Some(entry.value.get_type().to_str()),
+            None => None,
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for mut shard in self.lock_all() {
+            shard.clear();
+        }
+    }
+
+    pub fn subscribe(&self, channel: String) -> Subscriber<Bytes> {
+        use std::collections::hash_map::Entry;
+        let mut publisher = self.db.publisher.lock().unwrap();
+        let rx = match publisher.entry(channel.clone()) {
+            Entry::Occupied(e) => e.get().subscribe(),
+            Entry::Vacant(entry) => {
+                trace!("subscribe to channel: {}", channel);
+                let (tx, rx) = broadcast::channel(self.db.pubsub_buffer_size());
+                entry.insert(tx);
+                rx
+            }
+        };
+        Subscriber::new(rx, self.db.clone(), Topic::Channel(channel))
+    }
+
+    // the messages published to the channels matching the pattern, with their channel
+    pub fn psubscribe(&self, pattern: String) -> Subscriber<(String, Bytes)> {
+        let mut patterns = self.db.pattern_publisher.lock().unwrap();
+        let rx = match patterns.get(&pattern) {
+            Some(tx) => tx.subscribe(),
+            None => {
+                trace!("subscribe to pattern: {}", pattern);
+                let (tx, rx) = broadcast::channel(self.db.pubsub_buffer_size());
+                patterns.insert(pattern.clone(), tx);
+                rx
+            }
+        };
+        Subscriber::new(rx, self.db.clone(), Topic::Pattern(pattern))
+    }
+
+    // PUBSUB CHANNELS, the channels having at least one subscriber
+    pub fn pubsub_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        let publisher = self.db.publisher.lock().unwrap();
+        publisher
+            .iter()
+            .filter(|(channel, tx)| {
+                tx.receiver_count() > 0
+                    && pattern
+                        .is_none_or(|pattern| glob_match(pattern.as_bytes(), channel.as_bytes()))
+            })
+            .map(|(channel, _)| channel.clone())
+            .collect()
+    }
+
+    // PUBSUB NUMSUB, the number of subscribers of the channel, not counting the patterns
+    pub fn pubsub_numsub(&self, channel: &str) -> usize {
+        let publisher = self.db.publisher.lock().unwrap();
+        publisher.get(channel).map_or(0, |tx| tx.receiver_count())
+    }
+
+    // PUBSUB NUMPAT, the number of patterns subscribed to
+    pub fn pubsub_numpat(&self) -> usize {
+        let patterns = self.db.pattern_publisher.lock().unwrap();
+        patterns
+            .values()
+            .filter(|tx| tx.receiver_count() > 0)
+            .count()
+    }
+
+    pub fn publish(&self, channel: String, msg: Bytes) -> usize {
+        self.db.publish(channel, msg)
+    }
+
+    // shard channels are routed by the hash slot of the channel like keys,
+    // the patterns don't receive their messages
+    pub fn ssubscribe(&self, channel: String) -> Subscriber<Bytes> {
+        let slot = key_slot(channel.as_bytes());
+        let mut publisher = self.db.shard_publisher.lock().unwrap();
+        let channels = publisher.entry(slot).or_default();
+        let rx = match channels.get(&channel) {
+            Some(tx) => tx.subscribe(),
+            None => {
+                trace!("subscribe to shard channel: {}, slot: {}", channel, slot);
+                let (tx, rx) = broadcast::channel(self.db.pubsub_buffer_size());
+                channels.insert(channel.clone(), tx);
+                rx
+            }
+        };
+        Subscriber::new(rx, self.db.clone(), Topic::ShardChannel(channel))
+    }
+
+    pub fn spublish(&self, channel: String, msg: Bytes) -> usize {
+        let slot = key_slot(channel.as_bytes());
+        let publisher = self.db.shard_publisher.lock().unwrap();
+        publisher
+            .get(&slot)
+            .and_then(|channels| channels.get(&channel))
+            .map_or(0, |tx| tx.send(msg).unwrap_or(0))
+    }
+
+    // PUBSUB SHARDCHANNELS, the shard channels having at least one subscriber
+    pub fn pubsub_shardchannels(&self, pattern: Option<&str>) -> Vec<String> {
+        let publisher = self.db.shard_publisher.lock().unwrap();
+        publisher
+            .values()
+            .flat_map(|channels| channels.iter())
+            .filter(|(channel, tx)| {
+                tx.receiver_count() > 0
+                    && pattern
+                        .is_none_or(|pattern| glob_match(pattern.as_bytes(), channel.as_bytes()))
+            })
+            .map(|(channel, _)| channel.clone())
+            .collect()
+    }
+
+    // PUBSUB SHARDNUMSUB, the number of subscribers of the shard channel
+    pub fn pubsub_shardnumsub(&self, channel: &str) -> usize {
+        let slot = key_slot(channel.as_bytes());
+        let publisher = self.db.shard_publisher.lock().unwrap();
+        publisher
+            .get(&slot)
+            .and_then(|channels| channels.get(channel))
+            .map_or(0, |tx| tx.receiver_count())
+    }
+
+    // publish a keyspace event of the key if its class is enabled
+    pub fn notify(&self, class: KeyspaceEvents, event: &str, key: &str) {
+        self.db.notify(class, event, key)
+    }
+
+    // copy the key space for persistence, expired keys are skipped
+    pub fn snapshot(&self) -> Vec<Record> {
+        let shards = self.lock_all();
+        let now = Instant::now();
+        shards
+            .iter()
+            .flat_map(|shard| shard.table.iter())
+            .filter(|(_, entry)| entry.expire_at.map(|at| at > now).unwrap_or(true))
+            .map(|(key, entry)| {
+                (
+                    key.clone(),
+                    entry.value.clone(),
+                    entry.expire_at.map(instant_to_unix_ms),
+                )
+            })
+            .collect()
+    }
+
+    // the key space and the function libraries
+    pub fn rdb(&self) -> RDB {
+        RDB::new(vec![self.snapshot()]).with_functions(self.scripting().function_codes())
+    }
+
+    // save the snapshot to the rdb file, block until it's done
+    pub fn save(&self) -> Result<()> {
+        if self.db.bgsave_in_progress.load(Ordering::SeqCst) {
+            return Err(RedisErr::SaveInProgress);
+        }
+        self.rdb().write(&self.config().rdb_path())?;
+        self.db.lastsave.store(unix_timestamp(), Ordering::SeqCst);
+        Ok(())
+    }
+
+    // take the snapshot now and write it to the rdb file in a blocking thread
+    pub fn bgsave(&self) -> Result<()> {
+        if self.db.bgsave_in_progress.swap(true, Ordering::SeqCst) {
+            return Err(RedisErr::SaveInProgress);
+        }
+        let rdb = self.rdb();
+        let path = self.config().rdb_path();
+        let shared = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            match rdb.write(&path) {
+                Ok(()) => {
+                    info!("Background saving terminated with success");
+                    shared.lastsave.store(unix_timestamp(), Ordering::SeqCst);
+                }
+                Err(e) => error!("Background saving error: {}", e),
+            }
+            shared.bgsave_in_progress.store(false, Ordering::SeqCst);
+        });
+        Ok(())
+    }
+
+    // fill the key space from the aof if it's enabled, otherwise the rdb file
+    // only database 0 is served, keys of other databases are dropped
+    pub fn load(&self) -> Result<usize> {
+        let aof_path = self.config().aof_path();
+        if self.config().appendonly && aof_path.exists() {
+            let applied = aof::load(&aof_path, &mut self.clone())?;
+            debug!("{} commands replayed from the aof", applied);
+            return Ok(self.dbsize());
+        }
+        let path = self.config().rdb_path();
+        if !path.exists() {
+            return Ok(0);
+        }
+        let rdb = RDB::load(&path)?;
+        self.restore_functions(rdb.functions().to_vec());
+        let mut dbs = rdb.into_dbs().into_iter();
+        let records = dbs.next().unwrap_or_default();
+        let dropped: usize = dbs.map(|db| db.len()).sum();
+        if dropped > 0 {
+            warn!("skip loading {} keys of databases other than 0", dropped);
+        }
+        Ok(self.restore(records))
+    }
+
+    // load the libraries of a snapshot, replacing the ones of the same name,
+    // a library failing to load is skipped with the others still loaded
+    pub fn restore_functions(&self, functions: Vec<Bytes>) {
+        for code in functions {
+            if let Err(e) = self
+                .scripting()
+                .function_restore(vec![code], RestorePolicy::Replace)
+            {
+                warn!("skip loading function library: {}", e);
+            }
+        }
+    }
+
+    // insert the records, keys already expired are skipped
+    pub fn restore(&self, records: Vec<Record>) -> usize {
+        let mut shards = self.lock_all();
+        let mut loaded = 0;
+        for (key, value, expire_at) in records {
+            let expire_at = match expire_at.map(unix_ms_to_instant) {
+                Some(None) => continue,
+                Some(Some(at)) => Some(at),
+                None => None,
+            };
+            shards[self.db.shard_index(&key)].insert(key, Entry::new(value, expire_at));
+            loaded += 1;
+        }
+        drop(shards);
+        self.db.background_task.notify_one();
+        loaded
+    }
+
+    // start logging write commands, the file is synced by a background task for everysec
+    pub fn open_aof(&self) -> Result<()> {
+        if !self.config().appendonly {
+            return Ok(());
+        }
+        // the key space may be loaded from the rdb file, keep it in the new aof
+        let path = self.config().aof_path();
+        if !path.exists() {
+            aof::write_base(&path, &self.snapshot(), &self.scripting().function_codes())?;
+        }
+        let aof = AOF::open(&path, self.config().appendfsync)?;
+        if aof.fsync() == AppendFsync::EverySec {
+            tokio::spawn(fsync_aof_task(self.db.clone()));
+        }
+        *self.db.aof.lock().unwrap() = Some(aof);
+        Ok(())
+    }
+
+    // held by a write command from applying to propagating
+    pub fn propagate_guard(&self) -> RwLockReadGuard<'_, ()> {
+        self.db.propagate_lock.read().unwrap()
+    }
+
+    // no write command can be applied while it's held
+    pub fn propagate_barrier(&self) -> RwLockWriteGuard<'_, ()> {
+        self.db.propagate_lock.write().unwrap()
+    }
+
+    // held by a command while it's applied, before the propagate guard
+    pub async fn command_guard(&self) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        self.db.transaction_lock.read().await
+    }
+
+    // held by EXEC and scripts to apply their commands at once,
+    // the waiting commands yield their worker instead of blocking it
+    pub async fn transaction_guard(&self) -> tokio::sync::RwLockWriteGuard<'_, ()> {
+        self.db.transaction_lock.write().await
+    }
+
+    // version of the key to WATCH, the version of the last removal in its bucket if it doesn't exist
+    pub fn version(&self, key: &str) -> u64 {
+        let mut shard = self.shard(key);
+        let version = shard.get(key).map(|entry| entry.version);
+        version.unwrap_or_else(|| shard.removed[removed_bucket(key)])
+    }
+
+    pub fn scripting(&self) -> &Scripting {
+        &self.db.scripting
+    }
+
+    pub fn replication(&self) -> &Replication {
+        &self.db.replication
+    }
+
+    pub fn cluster(&self) -> Option<&Cluster> {
+        self.db.cluster.as_ref()
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.db.replication.is_replica()
+    }
+
+    // log a write command that has been applied and stream it to the replicas
+    pub fn propagate(&self, frame: Frame) {
+        self.propagate_data(&aof::normalize(frame).serialize());
+    }
+
+    // the writes of a transaction or a script are wrapped in MULTI and EXEC,
+    // so a replica or a replay of the aof applies all of them or none
+    pub fn propagate_transaction(&self, frames: Vec<Frame>) {
+        if frames.len() <= 1 {
+            frames.into_iter().for_each(|frame| self.propagate(frame));
+            return;
+        }
+        let command = |name: &'static [u8]| {
+            Frame::Array(vec![Frame::BulkString(Bytes::from_static(name))]).serialize()
+        };
+        let mut data = command(b"MULTI").to_vec();
+        for frame in frames {
+            data.extend_from_slice(&aof::normalize(frame).serialize());
+        }
+        data.extend_from_slice(&command(b"EXEC"));
+        self.propagate_data(&data);
+    }
+
+    fn propagate_data(&self, data: &[u8]) {
+        self.db.replication.feed(data);
+
+        let mut aof = self.db.aof.lock().unwrap();
+        let Some(aof) = aof.as_mut() else {
+            return;
+        };
+        if let Err(e) = aof.append(data) {
+            error!("Error writing the aof: {}", e);
+        }
+        let config = self.config();
+        if aof.need_rewrite(
+            config.auto_aof_rewrite_percentage,
+            config.auto_aof_rewrite_min_size,
+        ) {
+            info!("Starting automatic rewriting of AOF");
+            // the caller holds the propagate guard, start it in another task
+            let db = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = db.bgrewriteaof() {
+                    debug!("automatic aof rewrite not started: {}", e);
+                }
+            });
+        }
+    }
+
+    // copy the key space and write it to a new aof in a blocking thread,
+    // writes in the meantime are buffered and appended before the new file is swapped in
+    pub fn bgrewriteaof(&self) -> Result<()> {
+        let (records, functions) = {
+            let _guard = self.propagate_barrier();
+            let mut aof = self.db.aof.lock().unwrap();
+            let aof = aof.as_mut().ok_or(RedisErr::AOFDisabled)?;
+            if aof.is_rewriting() {
+                return Err(RedisErr::RewriteInProgress);
+            }
+            aof.start_rewrite();
+            (self.snapshot(), self.scripting().function_codes())
+        };
+
+        let shared = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let temp = match shared.aof.lock().unwrap().as_ref() {
+                Some(aof) => aof.rewrite_path(),
+                None => return,
+            };
+            let res = aof::write_base(&temp, &records, &functions).and_then(|_| {
+                match shared.aof.lock().unwrap().as_mut() {
+                    Some(aof) => aof.finish_rewrite(),
+                    None => Err(RedisErr::AOFDisabled),
+                }
+            });
+            match res {
+                Ok(()) => info!("Background AOF rewrite finished successfully"),
+                Err(e) => {
+                    error!("Background AOF rewrite error: {}", e);
+                    if let Some(aof) = shared.aof.lock().unwrap().as_mut() {
+                        aof.abort_rewrite();
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub fn sync_aof(&self) {
+        if let Some(aof) = self.db.aof.lock().unwrap().as_ref() {
+            if let Err(e) = aof.file().and_then(|file| Ok(file.sync_data()?)) {
+                error!("Error syncing the aof: {}", e);
+            }
+        }
+    }
+
+    pub fn dbsize(&self) -> usize {
+        self.db
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().table.len())
+            .sum()
+    }
+
+    // unix time of the last successful save
+    pub fn lastsave(&self) -> u64 {
+        self.db.lastsave.load(Ordering::SeqCst)
+    }
+
+    // messages lost by the subscribers fallen behind their channels
+    pub fn pubsub_lagged_messages(&self) -> u64 {
+        self.db.pubsub_lagged_messages.load(Ordering::Relaxed)
+    }
+
+    // subscribers disconnected by pubsub-output-limit
+    pub fn pubsub_disconnections(&self) -> u64 {
+        self.db.pubsub_disconnections.load(Ordering::Relaxed)
+    }
+
+    pub fn shutdown_purge_task(&self) {
+        self.db.shutdown.store(true, Ordering::SeqCst);
+
+        // notify the background task to exit
+        self.db.background_task.notify_one();
+    }
+
+    pub fn bf_add(&self, key: String, value: String) -> Result<()> {
+        let mut state = self.shard(&key);
+        let entry = state.get_mut(&key);
+        match entry {
+            Some(entry) => {
+                if !entry.value.is_bloomfilter() {
+                    return Err(RedisErr::WrongType);
+                }
+                let bloom = entry.value.as_bloomfilter_mut().unwrap();
+                bloom.add(&value);
+                Ok(())
+            }
+            None => {
+                let mut bloom = crate::value::BloomFilter::new();
+                bloom.add(&value);
+                let entry = Entry::new(Value::BloomFilter(bloom), None);
+                state.insert(key, entry);
+                Ok(())
+            }
+        }
+    }
+
+    // the filter replaces the value of the key
+    pub fn bf_load_chunk(&self, key: String, bloom: crate::value::BloomFilter) {
+        let mut state = self.shard(&key);
+        state.insert(key, Entry::new(Value::BloomFilter(bloom), None));
+    }
+
+    pub fn bf_exists(&self, key: &str, value: &str) -> Result<bool> {
+        let state = self.shard(key);
+        let entry = state.table.get(key);
+        match entry {
+            Some(entry) => {
+                if !entry.value.is_bloomfilter() {
+                    return Err(RedisErr::WrongType);
+                }
+                let bloom = entry.value.as_bloomfilter_ref().unwrap();
+                Ok(bloom.contains(value))
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn get_object_last_touch(&self, key: &str) -> Option<Instant> {
+        let state = self.shard(key);
+
+        state.table.get(key).map(|entry| entry.touch_at)
+    }
+} // impl DB
+
+// what a subscriber receives the messages of
+enum Topic {
+    Channel(String),
+    Pattern(String),
+    ShardChannel(String),
+}
+
+// the receiver of a subscription, once the last one of a channel or a pattern is dropped
+// its sender is removed, so idle channels don't pile up
+pub struct Subscriber<T> {
+    rx: Option<broadcast::Receiver<T>>,
+    shared: Arc<Shared>,
+    topic: Topic,
+    // since when the pending messages have been over the soft limit
+    soft_limit_reached: Option<Instant>,
+}
+
+impl<T> Subscriber<T> {
+    fn new(rx: broadcast::Receiver<T>, shared: Arc<Shared>, topic: Topic) -> Self {
+        Self {
+            rx: Some(rx),
+            shared,
+            topic,
+            soft_limit_reached: None,
+        }
+    }
+
+    // the subscriber lost messages or has too many pending,
+    // the client is disconnected if pubsub-output-limit is enabled
+    fn check_pubsub_output_limit(&mut self, lagged: u64) -> Result<()> {
+        let limit = self.shared.config.pubsub_output_limit;
+        if lagged > 0 {
+            warn!("subscriber lagged behind, {} messages lost", lagged);
+            self.shared
+                .pubsub_lagged_messages
+                .fetch_add(lagged, Ordering::Relaxed);
+        }
+        if !limit.is_enabled() {
+            return Ok(());
+        }
+
+        let pending = self.len();
+        let soft_limit_exceeded = if limit.soft > 0 && pending >= limit.soft {
+            let since = *self.soft_limit_reached.get_or_insert_with(Instant::now);
+            since.elapsed() >= Duration::from_secs(limit.soft_seconds)
+        } else {
+            self.soft_limit_reached = None;
+            false
+        };
+        if lagged > 0 || (limit.hard > 0 && pending >= limit.hard) || soft_limit_exceeded {
+            warn!(
+                "subscriber reached pubsub-output-limit with {} pending messages",
+                pending
+            );
+            self.shared
+                .pubsub_disconnections
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(RedisErr::OutputBufferLimit);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone> Subscriber<T> {
+    // the next message, None once the channel is closed,
+    // an error if the subscriber has fallen too far behind
+    pub async fn next_message(&mut self) -> Result<Option<T>> {
+        loop {
+            match self.recv().await {
+                Ok(msg) => {
+                    self.check_pubsub_output_limit(0)?;
+                    return Ok(Some(msg));
+                }
+                Err(broadcast::error::RecvError::Lagged(lagged)) => {
+                    self.check_pubsub_output_limit(lagged)?;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<T> Deref for Subscriber<T> {
+    type Target = broadcast::Receiver<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.rx
+            .as_ref()
+            .expect("the receiver is only taken on drop")
+    }
+}
+
+impl<T> DerefMut for Subscriber<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.rx
+            .as_mut()
+            .expect("the receiver is only taken on drop")
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        // the receiver is no longer counted when the sender is checked
+        drop(self.rx.take());
+        self.shared.reclaim(&self.topic);
+    }
+}
+
+// the head or the tail of a list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListSide {
+    Left,
+    Right,
+}
+
+impl ListSide {
+    fn push(self, list: &mut VecDeque<Bytes>, value: Bytes) {
+        match self {
+            ListSide::Left => list.push_front(value),
+            ListSide::Right => list.push_back(value),
         }
     }
 
-    pub fn publish(&self, channel: String, msg: Bytes) -> usize {
-        let state = self.db.state.lock().unwrap();
+    fn pop(self, list: &mut VecDeque<Bytes>) -> Option<Bytes> {
+        match self {
+            ListSide::Left => list.pop_front(),
+            ListSide::Right => list.pop_back(),
+        }
+    }
 
-        if let Some(tx) = state.publisher.get(&channel) {
-            trace!(
-                "publish message to channel: {}, msg: {}",
-                channel,
-                String::from_utf8_lossy(msg.to_vec().as_slice())
-            );
-            tx.send(msg).unwrap()
-        } else {
-            0
+    fn push_event(self) -> &'static str {
+        match self {
+            ListSide::Left => "lpush",
+            ListSide::Right => "rpush",
         }
     }
 
-    pub fn shutdown_purge_task(&self) {
-        let mut state = self.db.state.lock().unwrap();
+    fn pop_event(self) -> &'static str {
+        match self {
+            ListSide::Left => "lpop",
+            ListSide::Right => "rpop",
+        }
+    }
+}
 
-        state.shutdown = true;
+// the index in a list of the length, a negative one counts from the tail
+fn list_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
 
-        // drop the lock before notify the background task
-        drop(state);
+// the inclusive range between start and stop in a list of the length,
+// negative ones count from the tail, None if it's empty
+fn list_range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
 
-        // notify the background task to exit
-        self.db.background_task.notify_one();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl SetOp {
+    // the event notified on the destination of the STORE variant
+    fn store_event(self) -> &'static str {
+        match self {
+            SetOp::Inter => "sinterstore",
+            SetOp::Union => "sunionstore",
+            SetOp::Diff => "sdiffstore",
+        }
     }
+}
 
-    pub fn bf_add(&self, key: String, value: String) -> Result<()> {
-        let mut state = self.db.state.lock().unwrap();
-        let entry = state.table.get_mut(&key);
-        match entry {
-            Some(entry) => {
-                if !entry.value.is_bloomfilter() {
-                    return Err(RedisErr::WrongType);
-                }
-                let bloom = entry.value.as_bloomfilter_mut().unwrap();
-                bloom.add(&value);
-                Ok(())
+// the operation on the sets of the locked keys, at most limit members of the result
+fn set_operation(
+    shards: &mut ShardsGuard,
+    op: SetOp,
+    keys: &[String],
+    limit: Option<usize>,
+) -> Result<HashSet<Bytes>> {
+    // expired keys are removed before the sets are borrowed together
+    for key in keys {
+        if let Some(entry) = shards.shard(key).get(key) {
+            if !entry.value.is_set() {
+                return Err(RedisErr::WrongType);
             }
+        }
+    }
+    let empty = HashSet::new();
+    let mut sets = keys
+        .iter()
+        .map(|key| {
+            shards
+                .peek(key)
+                .and_then(|entry| entry.value.as_set_ref())
+                .unwrap_or(&empty)
+        })
+        .collect::<Vec<_>>();
+    let limit = limit.unwrap_or(usize::MAX);
+
+    let members: Box<dyn Iterator<Item = &Bytes>> = match op {
+        SetOp::Inter => {
+            // members of the smallest set found in all the others
+            sets.sort_by_key(|set| set.len());
+            let (first, others) = sets.split_first().unwrap();
+            Box::new(
+                first
+                    .iter()
+                    .filter(move |member| others.iter().all(|set| set.contains(*member))),
+            )
+        }
+        SetOp::Union => Box::new(sets.iter().flat_map(|set| set.iter())),
+        SetOp::Diff => {
+            let (first, others) = sets.split_first().unwrap();
+            Box::new(
+                first
+                    .iter()
+                    .filter(move |member| !others.iter().any(|set| set.contains(*member))),
+            )
+        }
+    };
+    let mut result = HashSet::new();
+    for member in members {
+        if result.len() >= limit {
+            break;
+        }
+        result.insert(member.clone());
+    }
+    Ok(result)
+}
+
+// distinct random members, all of them if count is not less than the number of them
+fn random_members<'a, T: Clone + 'a>(
+    members: impl IntoIterator<Item = &'a T>,
+    count: usize,
+) -> Vec<T> {
+    let mut members = members.into_iter().collect::<Vec<_>>();
+    let count = count.min(members.len());
+    // a partial fisher-yates shuffle
+    for i in 0..count {
+        let j = i + (random_u64() % (members.len() - i) as u64) as usize;
+        members.swap(i, j);
+    }
+    members.into_iter().take(count).cloned().collect()
+}
+
+// a client blocked on a key
+#[derive(Debug)]
+struct Blocked {
+    notify: Arc<Notify>,
+    // the client pops from the key, so it's woken up only when it's first in line
+    pop: bool,
+}
+
+// a client blocked on keys, it's unregistered on drop
+pub struct KeyWaiter {
+    shared: Arc<Shared>,
+    keys: Vec<String>,
+    notify: Arc<Notify>,
+    pop: bool,
+}
+
+impl KeyWaiter {
+    // false if none of the keys is written before the deadline
+    pub async fn wait(&self, deadline: Option<Instant>) -> bool {
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), self.notify.notified())
+                .await
+                .is_ok(),
             None => {
-                let mut bloom = crate::value::BloomFilter::new();
-                bloom.add(&value);
-                let entry = Entry::new(Value::BloomFilter(bloom), None);
-                state.table.insert(key, entry);
-                Ok(())
+                self.notify.notified().await;
+                true
             }
         }
     }
+}
 
-    pub fn bf_exists(&self, key: &str, value: &str) -> Result<bool> {
-        let state = self.db.state.lock().unwrap();
-        let entry = state.table.get(key);
-        match entry {
-            Some(entry) => {
-                if !entry.value.is_bloomfilter() {
-                    return Err(RedisErr::WrongType);
+impl Drop for KeyWaiter {
+    fn drop(&mut self) {
+        let mut blocked = self.shared.blocked.lock().unwrap();
+        for key in &self.keys {
+            if let Some(waiters) = blocked.get_mut(key) {
+                waiters.retain(|waiter| !Arc::ptr_eq(&waiter.notify, &self.notify));
+                // the next client in line checks the list in case the values left
+                // or a wakeup meant for this one is not taken
+                if self.pop {
+                    if let Some(next) = waiters.iter().find(|waiter| waiter.pop) {
+                        next.notify.notify_one();
+                    }
+                }
+                if waiters.is_empty() {
+                    blocked.remove(key);
                 }
-                let bloom = entry.value.as_bloomfilter_ref().unwrap();
-                Ok(bloom.contains(value))
             }
-            None => Ok(false),
         }
     }
+}
 
-    pub fn object_info(&self, key: &str) -> Result<String> {
-        let state = self.db.state.lock().unwrap();
-        let entry = state.table.get(key);
-        match entry {
-            Some(entry) => Ok(format!("{:?}", entry.value)),
-            None => Err(RedisErr::KeyNotFound),
+// the locked shard of a key, the keys found expired while it's held are notified on drop
+struct ShardGuard<'a> {
+    shared: &'a Shared,
+    guard: MutexGuard<'a, Shard>,
+}
+
+impl Deref for ShardGuard<'_> {
+    type Target = Shard;
+
+    fn deref(&self) -> &Shard {
+        &self.guard
+    }
+}
+
+impl DerefMut for ShardGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Shard {
+        &mut self.guard
+    }
+}
+
+impl Drop for ShardGuard<'_> {
+    fn drop(&mut self) {
+        self.shared.notify_expired(&mut self.guard);
+    }
+}
+
+// the locked shards of a multi-key command
+pub struct ShardsGuard<'a> {
+    shared: &'a Shared,
+    guards: BTreeMap<usize, MutexGuard<'a, Shard>>,
+}
+
+impl Drop for ShardsGuard<'_> {
+    fn drop(&mut self) {
+        for shard in self.guards.values_mut() {
+            self.shared.notify_expired(shard);
         }
     }
+}
 
-    pub fn get_object_last_touch(&self, key: &str) -> Option<Instant> {
-        let state = self.db.state.lock().unwrap();
+impl ShardsGuard<'_> {
+    // the shard of the key, which must be one of the locked keys
+    fn shard(&mut self, key: &str) -> &mut Shard {
+        let index = self.shared.shard_index(key);
+        self.guards
+            .get_mut(&index)
+            .expect("the shard of the key is not locked")
+    }
 
-        state.table.get(key).map(|entry| entry.touch_at)
+    // the entry of the key without checking its ttl, to read several keys at once
+    // after they're checked by get
+    fn peek(&self, key: &str) -> Option<&Entry> {
+        let index = self.shared.shard_index(key);
+        self.guards
+            .get(&index)
+            .expect("the shard of the key is not locked")
+            .table
+            .get(key)
     }
-} // impl DB
+}
 
 #[derive(Debug)]
 struct Shared {
-    // guard the state by mutex
-    state: Mutex<State>,
+    // the key space is split into shards by the hash of the key,
+    // each is guarded by its own mutex so commands on different shards run in parallel
+    shards: Vec<Mutex<Shard>>,
+
+    // seperate key space for pub-sub
+    publisher: Mutex<HashMap<String, broadcast::Sender<Bytes>>>,
+    // subscribed patterns, a message is sent with the channel it's published to
+    pattern_publisher: Mutex<HashMap<String, broadcast::Sender<(String, Bytes)>>>,
+    // shard channels grouped by their hash slot, apart from the global channels
+    shard_publisher: Mutex<BTreeMap<u16, HashMap<String, broadcast::Sender<Bytes>>>>,
+
+    // clients blocked on the keys by XREAD BLOCK or the blocking list pops,
+    // in the order they are blocked
+    blocked: Mutex<HashMap<String, VecDeque<Blocked>>>,
+
+    shutdown: AtomicBool,
 
     background_task: Notify,
+
+    config: Config,
+
+    // rdb persistence states
+    bgsave_in_progress: AtomicBool,
+    lastsave: AtomicU64,
+
+    // pub/sub lag counters
+    pubsub_lagged_messages: AtomicU64,
+    pubsub_disconnections: AtomicU64,
+
+    // append only file, None if it's disabled
+    aof: Mutex<Option<AOF>>,
+
+    // write commands hold it shared from applying to logging,
+    // so the key space copied under the exclusive lock has every write logged
+    propagate_lock: RwLock<()>,
+
+    // commands hold it shared while applied, EXEC holds it exclusively
+    // so no command runs in the middle of a transaction
+    transaction_lock: tokio::sync::RwLock<()>,
+
+    // replication states, the backlog is fed by propagate
+    replication: Replication,
+
+    // hash slots and nodes, None if cluster mode is disabled
+    cluster: Option<Cluster>,
+
+    // lua interpreter and the loaded scripts
+    scripting: Scripting,
 }
 
 impl Shared {
+    // a broadcast channel holds at least one message
+    fn pubsub_buffer_size(&self) -> usize {
+        self.config.pubsub_buffer_size.max(1)
+    }
+
+    // the hasher is not seeded, a key always goes to the same shard
+    fn shard_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
     // purge all the expired keys and return the next expire time
     fn purge_expired_keys(&self) -> Option<Instant> {
-        let mut state = self.state.lock().unwrap();
-
-        if state.shutdown {
+        if self.is_shutdown() {
             return None;
         }
 
         let now = Instant::now();
+        self.shards
+            .iter()
+            .filter_map(|shard| {
+                let mut shard = shard.lock().unwrap();
+                let next = shard.purge_expired(now);
+                self.notify_expired(&mut shard);
+                next
+            })
+            .min()
+    }
 
-        while let Some((key, instant)) = state.expire_table.iter().next().cloned() {
-            if instant > now {
-                return Some(instant);
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    // the number of receivers is counted once for the channel and once for each matching pattern
+    fn publish(&self, channel: String, msg: Bytes) -> usize {
+        let mut receivers = {
+            let publisher = self.publisher.lock().unwrap();
+            if let Some(tx) = publisher.get(&channel) {
+                trace!(
+                    "publish message to channel: {}, msg: {}",
+                    channel,
+                    String::from_utf8_lossy(msg.to_vec().as_slice())
+                );
+                tx.send(msg.clone()).unwrap_or(0)
+            } else {
+                0
             }
+        };
 
-            state.expire_table.remove(&(key.clone(), instant));
+        let patterns = self.pattern_publisher.lock().unwrap();
+        for (pattern, tx) in patterns.iter() {
+            if glob_match(pattern.as_bytes(), channel.as_bytes()) {
+                receivers += tx.send((channel.clone(), msg.clone())).unwrap_or(0);
+            }
+        }
+        receivers
+    }
 
-            state.table.remove(&key);
+    // wake up the clients blocked on the key, they check the key again,
+    // the clients popping from it are served one by one in the order they are blocked
+    fn signal_key_ready(&self, key: &str) {
+        let blocked = self.blocked.lock().unwrap();
+        let mut popping = false;
+        for waiter in blocked.get(key).into_iter().flatten() {
+            if waiter.pop && std::mem::replace(&mut popping, true) {
+                continue;
+            }
+            waiter.notify.notify_one();
         }
+    }
 
-        None
+    // remove the sender of the topic if it has no receiver left,
+    // it's checked under the lock a new subscriber takes
+    fn reclaim(&self, topic: &Topic) {
+        match topic {
+            Topic::Channel(channel) => {
+                let mut publisher = self.publisher.lock().unwrap();
+                if publisher
+                    .get(channel)
+                    .is_some_and(|tx| tx.receiver_count() == 0)
+                {
+                    trace!("remove idle channel: {}", channel);
+                    publisher.remove(channel);
+                }
+            }
+            Topic::Pattern(pattern) => {
+                let mut patterns = self.pattern_publisher.lock().unwrap();
+                if patterns
+                    .get(pattern)
+                    .is_some_and(|tx| tx.receiver_count() == 0)
+                {
+                    trace!("remove idle pattern: {}", pattern);
+                    patterns.remove(pattern);
+                }
+            }
+            Topic::ShardChannel(channel) => {
+                let slot = key_slot(channel.as_bytes());
+                let mut publisher = self.shard_publisher.lock().unwrap();
+                let Some(channels) = publisher.get_mut(&slot) else {
+                    return;
+                };
+                if channels
+                    .get(channel)
+                    .is_some_and(|tx| tx.receiver_count() == 0)
+                {
+                    trace!("remove idle shard channel: {}", channel);
+                    channels.remove(channel);
+                }
+                if channels.is_empty() {
+                    publisher.remove(&slot);
+                }
+            }
+        }
     }
 
-    fn is_shutdown(&self) -> bool {
-        self.state.lock().unwrap().shutdown
+    // __keyspace@0__:<key> receives the event, __keyevent@0__:<event> receives the key
+    fn notify(&self, class: KeyspaceEvents, event: &str, key: &str) {
+        let events = self.config.notify_keyspace_events;
+        if !events.is_enabled(class) {
+            return;
+        }
+        if events.contains(KeyspaceEvents::KEYSPACE) {
+            self.publish(
+                format!("__keyspace@0__:{}", key),
+                Bytes::from(event.to_string()),
+            );
+        }
+        if events.contains(KeyspaceEvents::KEYEVENT) {
+            self.publish(
+                format!("__keyevent@0__:{}", event),
+                Bytes::from(key.to_string()),
+            );
+        }
+    }
+
+    // the keys the shard has found expired since it's locked
+    fn notify_expired(&self, shard: &mut Shard) {
+        for key in std::mem::take(&mut shard.expired) {
+            self.notify(KeyspaceEvents::EXPIRED, "expired", &key);
+        }
     }
 } // impl Shared
 
@@ -500,6 +2579,8 @@ pub struct Entry {
     value: Value,
     expire_at: Option<Instant>,
     touch_at: Instant,
+    // bumped on every write to the key, WATCH compares it at EXEC
+    version: u64,
 }
 
 impl Entry {
@@ -509,6 +2590,7 @@ impl Entry {
             value,
             expire_at,
             touch_at: Instant::now(),
+            version: 0,
         }
     }
 
@@ -539,30 +2621,136 @@ There are duplicate code in the command layer and the database could only provid
 */
 
 #[derive(Debug)]
-struct State {
+struct Shard {
     table: HashMap<String, Entry>,
 
-    // seperate key space for pub-sub
-    publisher: HashMap<String, broadcast::Sender<Bytes>>,
-
     expire_table: BTreeSet<(String, Instant)>,
 
-    shutdown: bool,
+    // last version given to an entry or a removal of the shard
+    version: u64,
+
+    // the version of the last removal of a key in each bucket, so a watched key
+    // created and removed again is seen as changed, like any key of its bucket removed
+    removed: Vec<u64>,
+
+    // keys removed on expiry, published as expired events before the shard is unlocked
+    expired: Vec<String>,
 }
 
-impl State {
+impl Shard {
     pub fn new() -> Self {
         Self {
             table: HashMap::new(),
-            publisher: HashMap::new(),
             expire_table: BTreeSet::new(),
-            shutdown: false,
+            version: 0,
+            removed: vec![0; REMOVED_BUCKETS],
+            expired: Vec::new(),
         }
     }
 
     pub fn next_expire(&self) -> Option<Instant> {
         self.expire_table.iter().next().map(|(_, instant)| *instant)
     }
+
+    // the entry of the key, an expired one is removed on read
+    fn get(&mut self, key: &str) -> Option<&mut Entry> {
+        let expired = self.table.get(key)?.expire_at.map(|at| at < Instant::now());
+        if expired == Some(true) {
+            self.remove(key);
+            self.expired.push(key.to_string());
+            return None;
+        }
+        self.table.get_mut(key)
+    }
+
+    // the entry of the key to modify, with a new version
+    fn get_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        self.version += 1;
+        let version = self.version;
+        let entry = self.get(key)?;
+        entry.version = version;
+        Some(entry)
+    }
+
+    fn get_kv(&mut self, key: &str) -> Result<Bytes> {
+        match self.get(key).map(|entry| &entry.value) {
+            Some(Value::KV(v)) => Ok(v.clone()),
+            Some(_) => Err(RedisErr::WrongType),
+            None => Err(RedisErr::KeyNotFound),
+        }
+    }
+
+    // insert the entry with a new version and keep the expire index in step
+    fn insert(&mut self, key: String, mut entry: Entry) -> Option<Entry> {
+        self.version += 1;
+        entry.version = self.version;
+        let expire_at = entry.expire_at;
+        let old = self.table.insert(key.clone(), entry);
+        if let Some(at) = old.as_ref().and_then(|old| old.expire_at) {
+            self.expire_table.remove(&(key.clone(), at));
+        }
+        if let Some(at) = expire_at {
+            self.expire_table.insert((key, at));
+        }
+        old
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let old = self.table.remove(key)?;
+        if let Some(at) = old.expire_at {
+            self.expire_table.remove(&(key.to_string(), at));
+        }
+        self.record_removal(key);
+        Some(old)
+    }
+
+    fn record_removal(&mut self, key: &str) {
+        self.version += 1;
+        self.removed[removed_bucket(key)] = self.version;
+    }
+
+    // FLUSHALL, every key is removed
+    fn clear(&mut self) {
+        self.table.clear();
+        self.expire_table.clear();
+        self.version += 1;
+        self.removed.fill(self.version);
+    }
+
+    // remove the keys expired by now and return the next expire time
+    fn purge_expired(&mut self, now: Instant) -> Option<Instant> {
+        while let Some((key, instant)) = self.expire_table.iter().next().cloned() {
+            if instant > now {
+                return Some(instant);
+            }
+
+            self.expire_table.remove(&(key.clone(), instant));
+
+            self.table.remove(&key);
+            self.record_removal(&key);
+            self.expired.push(key);
+        }
+
+        None
+    }
+}
+
+// removals are recorded in this many buckets of keys in each shard
+const REMOVED_BUCKETS: usize = 256;
+
+fn removed_bucket(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    // the low bits pick the shard
+    (hasher.finish() >> 32) as usize % REMOVED_BUCKETS
+}
+
+fn zadd_event(incr: bool) -> &'static str {
+    if incr {
+        "zincr"
+    } else {
+        "zadd"
+    }
 }
 
 async fn purge_expired_tasks(sharad: Arc<Shared>) {
@@ -584,14 +2772,38 @@ async fn purge_expired_tasks(sharad: Arc<Shared>) {
     debug!("purge expired task exit")
 }
 
+// appendfsync everysec, sync the aof once a second
+async fn fsync_aof_task(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    while !shared.is_shutdown() {
+        interval.tick().await;
+        let file = match shared.aof.lock().unwrap().as_ref().map(|aof| aof.file()) {
+            Some(Ok(file)) => file,
+            Some(Err(e)) => {
+                error!("Error syncing the aof: {}", e);
+                continue;
+            }
+            None => break,
+        };
+        match tokio::task::spawn_blocking(move || file.sync_data()).await {
+            Ok(Err(e)) => error!("Error syncing the aof: {}", e),
+            Err(e) => error!("Error syncing the aof: {}", e),
+            Ok(Ok(())) => {}
+        }
+    }
+
+    debug!("aof fsync task exit")
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
     use super::*;
+    use crate::config::PubSubOutputLimit;
 
-    #[test]
-    fn test_get_set() {
+    #[tokio::test]
+    async fn test_get_set() {
         let key = "key".to_string();
         let val = Bytes::from_static(b"value");
         let mut db = DB::new();
@@ -603,10 +2815,7 @@ mod tests {
         let res = db.set(key.clone(), val.clone(), true, false, false, false, None);
         assert_eq!(res, Err(RedisErr::NoAction));
         assert_eq!(
-            db.db
-                .state
-                .lock()
-                .unwrap()
+            db.shard(&key)
                 .table
                 .get(&key)
                 .unwrap()
@@ -628,10 +2837,7 @@ mod tests {
         );
         assert_eq!(res, Ok(Some(val.clone())));
         assert_eq!(
-            db.db
-                .state
-                .lock()
-                .unwrap()
+            db.shard(&key)
                 .table
                 .get(&key)
                 .unwrap()
@@ -652,58 +2858,19 @@ mod tests {
             Some(Instant::now() + Duration::from_secs(60)),
         );
         assert_eq!(res, Ok(None));
-        assert_eq!(
-            db.db
-                .state
-                .lock()
-                .unwrap()
-                .table
-                .get(&key)
-                .unwrap()
-                .expire_at
-                .is_some(),
-            true
-        );
+        assert!(db.shard(&key).table.get(&key).unwrap().expire_at.is_some());
 
         let _res = db.set(key.clone(), val.clone(), false, false, false, false, None);
-        assert_eq!(
-            db.db
-                .state
-                .lock()
-                .unwrap()
-                .table
-                .get(&key)
-                .unwrap()
-                .expire_at
-                .is_some(),
-            false
-        );
-        db.db
-            .state
-            .lock()
-            .unwrap()
-            .table
-            .get_mut(&key)
-            .unwrap()
-            .expire_at = Some(Instant::now() + Duration::from_secs(60));
+        assert!(db.shard(&key).table.get(&key).unwrap().expire_at.is_none());
+        db.shard(&key).table.get_mut(&key).unwrap().expire_at =
+            Some(Instant::now() + Duration::from_secs(60));
         let res = db.set(key.clone(), val.clone(), false, false, false, true, None);
         assert_eq!(res, Ok(None));
-        assert_eq!(
-            db.db
-                .state
-                .lock()
-                .unwrap()
-                .table
-                .get(&key)
-                .unwrap()
-                .expire_at
-                .is_some(),
-            true
-        );
+        assert!(db.shard(&key).table.get(&key).unwrap().expire_at.is_some());
     }
 
-    #[test]
-    fn test_del() {
+    #[tokio::test]
+    async fn test_del() {
         let key = "key".to_string();
         let val = Bytes::from_static(b"value");
         let mut db = DB::new();
@@ -714,8 +2881,8 @@ mod tests {
         assert_eq!(db.get(&key), Err(RedisErr::KeyNotFound));
     }
 
-    #[test]
-    fn test_expire() {
+    #[tokio::test]
+    async fn test_expire() {
         let key = "key".to_string();
         let val = Bytes::from_static(b"value");
         let expire_from_now = Duration::from_secs(10);
@@ -735,8 +2902,236 @@ mod tests {
         assert_eq!(db.get(&key), Err(RedisErr::KeyNotFound));
     }
 
-    #[test]
-    fn test_zadd() {
+    #[tokio::test]
+    async fn test_restore() {
+        let db = DB::new();
+        let now = instant_to_unix_ms(Instant::now());
+        let loaded = db.restore(vec![
+            ("a".to_string(), Value::KV(Bytes::from("1")), None),
+            (
+                "b".to_string(),
+                Value::KV(Bytes::from("2")),
+                Some(now + 60_000),
+            ),
+            (
+                "c".to_string(),
+                Value::KV(Bytes::from("3")),
+                Some(now - 1_000),
+            ),
+        ]);
+        assert_eq!(loaded, 2);
+        assert_eq!(db.get_type("a"), Some("string"));
+        assert!(db.get_object_last_touch("b").is_some());
+        assert_eq!(db.get_type("c"), None);
+        assert_eq!(db.snapshot().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shards() {
+        let mut db = DB::new_with_config(Config {
+            keyspace_shards: 4,
+            ..Default::default()
+        });
+        let keys = (0..16).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+        db.mset(
+            keys.iter()
+                .map(|key| (key.clone(), Bytes::from(key.clone())))
+                .collect(),
+        );
+        assert_eq!(db.dbsize(), 16);
+        assert_eq!(db.db.shard_index("key0"), db.db.shard_index("key0"));
+
+        // the shards are locked once each, in ascending order
+        let shards = db.lock_keys(&keys);
+        assert!(shards.guards.len() <= 4);
+        assert!(shards.guards.keys().is_sorted());
+        drop(shards);
+
+        db.hset(
+            "hash".to_string(),
+            vec![("f".to_string(), Bytes::from("v"))],
+        )
+        .unwrap();
+        let values = db.mget(&["key1".to_string(), "hash".to_string(), "nokey".to_string()]);
+        assert_eq!(
+            values,
+            vec![
+                Ok(Bytes::from("key1")),
+                Err(RedisErr::WrongType),
+                Err(RedisErr::KeyNotFound)
+            ]
+        );
+        db.flush();
+        assert_eq!(db.dbsize(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_notify_keyspace_events() {
+        let mut db = DB::new_with_config(Config {
+            notify_keyspace_events: "KEg$x".parse().unwrap(),
+            ..Default::default()
+        });
+        let mut keyspace = db.subscribe("__keyspace@0__:key".to_string());
+        let mut del = db.subscribe("__keyevent@0__:del".to_string());
+        let mut expired = db.subscribe("__keyevent@0__:expired".to_string());
+        let mut lpush = db.subscribe("__keyevent@0__:lpush".to_string());
+
+        db.set(
+            "key".to_string(),
+            Bytes::from("1"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        db.lpush("list", vec![Bytes::from("a")]).unwrap();
+        db.del("key");
+        db.del("key");
+        assert_eq!(keyspace.try_recv(), Ok(Bytes::from("set")));
+        assert_eq!(keyspace.try_recv(), Ok(Bytes::from("del")));
+        assert!(keyspace.try_recv().is_err());
+        assert_eq!(del.try_recv(), Ok(Bytes::from("key")));
+        assert!(del.try_recv().is_err());
+        // the list class is not enabled
+        assert!(lpush.try_recv().is_err());
+
+        // a key expired on read and one purged in the background
+        for key in ["lazy", "purged"] {
+            db.set(
+                key.to_string(),
+                Bytes::from("1"),
+                false,
+                false,
+                false,
+                false,
+                Some(Instant::now() + Duration::from_millis(10)),
+            )
+            .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(db.get("lazy"), Err(RedisErr::KeyNotFound));
+        assert_eq!(expired.try_recv(), Ok(Bytes::from("lazy")));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(expired.try_recv(), Ok(Bytes::from("purged")));
+
+        assert_eq!(
+            "KEA"
+                .parse::<KeyspaceEvents>()
+                .map(|e| e.contains(KeyspaceEvents::LIST)),
+            Ok(true)
+        );
+        assert!("KEq".parse::<KeyspaceEvents>().is_err());
+        assert!(!"g$"
+            .parse::<KeyspaceEvents>()
+            .unwrap()
+            .is_enabled(KeyspaceEvents::GENERIC));
+    }
+
+    #[tokio::test]
+    async fn test_psubscribe() {
+        let db = DB::new();
+        let mut channel = db.subscribe("news.tech".to_string());
+        let mut pattern = db.psubscribe("news.*".to_string());
+        let mut other = db.psubscribe("sport.*".to_string());
+
+        assert_eq!(db.publish("news.tech".to_string(), Bytes::from("a")), 2);
+        assert_eq!(db.publish("news.art".to_string(), Bytes::from("b")), 1);
+        assert_eq!(db.publish("weather".to_string(), Bytes::from("c")), 0);
+        assert_eq!(channel.try_recv(), Ok(Bytes::from("a")));
+        assert_eq!(
+            pattern.try_recv(),
+            Ok(("news.tech".to_string(), Bytes::from("a")))
+        );
+        assert_eq!(
+            pattern.try_recv(),
+            Ok(("news.art".to_string(), Bytes::from("b")))
+        );
+        assert!(other.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pubsub_output_limit() {
+        // the limits count messages, byte units are refused
+        assert!("32mb 8mb 60".parse::<PubSubOutputLimit>().is_err());
+        assert!("1 2".parse::<PubSubOutputLimit>().is_err());
+
+        // without a limit the lost messages are only counted
+        let db = DB::new_with_config(Config {
+            pubsub_buffer_size: 4,
+            pubsub_output_limit: "0 0 0".parse().unwrap(),
+            ..Default::default()
+        });
+        let mut rx = db.subscribe("channel".to_string());
+        for i in 0..6 {
+            db.publish("channel".to_string(), Bytes::from(i.to_string()));
+        }
+        assert_eq!(rx.next_message().await, Ok(Some(Bytes::from("2"))));
+        assert_eq!(db.pubsub_lagged_messages(), 2);
+        assert_eq!(db.pubsub_disconnections(), 0);
+
+        let db = DB::new_with_config(Config {
+            pubsub_buffer_size: 4,
+            pubsub_output_limit: "3 0 0".parse().unwrap(),
+            ..Default::default()
+        });
+        let mut rx = db.subscribe("channel".to_string());
+        for i in 0..3 {
+            db.publish("channel".to_string(), Bytes::from(i.to_string()));
+        }
+        assert_eq!(rx.next_message().await, Ok(Some(Bytes::from("0"))));
+        for i in 3..5 {
+            db.publish("channel".to_string(), Bytes::from(i.to_string()));
+        }
+        assert_eq!(rx.next_message().await, Err(RedisErr::OutputBufferLimit));
+
+        // losing a message is over any limit
+        let mut rx = db.psubscribe("*".to_string());
+        for i in 0..5 {
+            db.publish("channel".to_string(), Bytes::from(i.to_string()));
+        }
+        assert_eq!(rx.next_message().await, Err(RedisErr::OutputBufferLimit));
+        assert_eq!(db.pubsub_lagged_messages(), 1);
+        assert_eq!(db.pubsub_disconnections(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_ssubscribe() {
+        let db = DB::new();
+        let mut shard = db.ssubscribe("{user}.news".to_string());
+        let _same_slot = db.ssubscribe("{user}.sport".to_string());
+        let mut global = db.subscribe("{user}.news".to_string());
+        let _pattern = db.psubscribe("*".to_string());
+
+        // the shard channels and the global channels don't share messages
+        assert_eq!(db.spublish("{user}.news".to_string(), Bytes::from("a")), 1);
+        assert_eq!(shard.try_recv(), Ok(Bytes::from("a")));
+        assert!(global.try_recv().is_err());
+        assert_eq!(db.publish("{user}.news".to_string(), Bytes::from("b")), 2);
+        assert!(shard.try_recv().is_err());
+        assert_eq!(db.spublish("nochannel".to_string(), Bytes::from("c")), 0);
+
+        let mut channels = db.pubsub_shardchannels(None);
+        channels.sort();
+        assert_eq!(channels, vec!["{user}.news", "{user}.sport"]);
+        assert_eq!(
+            db.pubsub_shardchannels(Some("*news")),
+            vec!["{user}.news".to_string()]
+        );
+        assert_eq!(db.pubsub_shardnumsub("{user}.news"), 1);
+        assert_eq!(db.pubsub_numsub("{user}.news"), 1);
+
+        drop(shard);
+        assert_eq!(db.pubsub_shardnumsub("{user}.news"), 0);
+        assert_eq!(
+            db.pubsub_shardchannels(None),
+            vec!["{user}.sport".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_zadd() {
         let key = "key".to_string();
         let mut db = DB::new();
         let res = db.zadd(
@@ -749,10 +3144,7 @@ mod tests {
             false,
             vec![(1.0, Bytes::from_static(b"one"))],
         );
-        println!(
-            "{}",
-            db.db.state.lock().unwrap().table.get(&key).unwrap().value
-        );
+        println!("{}", db.shard(&key).table.get(&key).unwrap().value);
         assert_eq!(res, Ok(1));
 
         let res = db.zadd(
@@ -768,10 +3160,7 @@ mod tests {
                 (2.0, Bytes::from_static(b"two")),
             ],
         );
-        println!(
-            "{}",
-            db.db.state.lock().unwrap().table.get(&key).unwrap().value
-        );
+        println!("{}", db.shard(&key).table.get(&key).unwrap().value);
         assert_eq!(res, Ok(1));
 
         let res = db.zadd(
@@ -788,10 +3177,7 @@ mod tests {
             ],
         );
 
-        println!(
-            "{}",
-            db.db.state.lock().unwrap().table.get(&key).unwrap().value
-        );
+        println!("{}", db.shard(&key).table.get(&key).unwrap().value);
         assert_eq!(res, Ok(1));
 
         let res = db.zadd(
@@ -807,10 +3193,7 @@ mod tests {
                 (3.0, Bytes::from_static(b"three")),
             ],
         );
-        println!(
-            "{}",
-            db.db.state.lock().unwrap().table.get(&key).unwrap().value
-        );
+        println!("{}", db.shard(&key).table.get(&key).unwrap().value);
         assert_eq!(res, Ok(2));
 
         let res = db.zadd(
@@ -826,10 +3209,7 @@ mod tests {
                 (4.0, Bytes::from_static(b"four")),
             ],
         );
-        println!(
-            "{}",
-            db.db.state.lock().unwrap().table.get(&key).unwrap().value
-        );
+        println!("{}", db.shard(&key).table.get(&key).unwrap().value);
         assert_eq!(res, Ok(2));
 
         let res = db.zadd(
@@ -845,10 +3225,7 @@ mod tests {
                 (5.0, Bytes::from_static(b"five")),
             ],
         );
-        println!(
-            "{}",
-            db.db.state.lock().unwrap().table.get(&key).unwrap().value
-        );
+        println!("{}", db.shard(&key).table.get(&key).unwrap().value);
         assert_eq!(res, Ok(2));
     }
 }
@@ -18,12 +18,24 @@ pub enum RedisErr {
     WrongType,
     KeyNotFound,
     OutOfMemory,
+    NoGroup,
+    BusyGroup,
+    Overflow,
+
+    // Persistence Error
+    SaveInProgress,
+    RDBMalformed,
+    RDBUnsupported,
+    AOFMalformed,
+    AOFDisabled,
+    RewriteInProgress,
 
     // Server Error
     WrongAddressFormat,
     IOError,
     PollError,
     ConnectionAborted,
+    OutputBufferLimit,
 }
 
 impl std::error::Error for RedisErr {}
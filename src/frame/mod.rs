@@ -1,6 +1,10 @@
 //! this module is used to parse the RESP protocol
 //! RESP is defined as a protocol in the Redis documentation:
 //! https://redis.io/docs/reference/protocol-spec/
+//!
+//! RESP2 types are always available, the RESP3 types are only sent to
+//! the connections which switched to protocol 3 by `HELLO 3`,
+//! for the others they are converted to the closest RESP2 type.
 
 use crate::{RedisErr, Result};
 
@@ -17,10 +21,52 @@ pub enum Frame {
     Integer(i64),         // Integers: format `:1000\r\n`
     BulkString(Bytes),    // Binary safe Strings `$6\r\nfoobar\r\n`
     Array(Vec<Frame>),    // array of RESP elements `*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n`
+
+    // RESP3 types
+    Null,                           // `_\r\n`
+    Boolean(bool),                  // `#t\r\n` or `#f\r\n`
+    Double(f64),                    // `,1.23\r\n`, `,inf\r\n`, `,-inf\r\n`, `,nan\r\n`
+    BigNumber(String),              // `(3492890328409238509324850943850943825024385\r\n`
+    Verbatim(String, Bytes),        // format and text `=15\r\ntxt:Some string\r\n`
+    Map(Vec<(Frame, Frame)>),       // `%1\r\n+key\r\n:1\r\n`
+    Set(Vec<Frame>),                // `~2\r\n+a\r\n+b\r\n`
+    Attribute(Vec<(Frame, Frame)>), // `|1\r\n+key\r\n:1\r\n`, out of band data before a reply
+    Push(Vec<Frame>),               // `>2\r\n+message\r\n+hello\r\n`
+}
+
+// RESP protocol version of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn from_version(version: i64) -> Option<Self> {
+        match version {
+            2 => Some(Protocol::Resp2),
+            3 => Some(Protocol::Resp3),
+            _ => None,
+        }
+    }
+
+    pub fn version(&self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
 }
 
 const CRLF: &[u8] = b"\r\n";
 
+// the most elements an aggregate may announce, as the proto max multibulk length of redis
+const MAX_MULTIBULK_LEN: i64 = 1024 * 1024;
+// how deep aggregates may be nested in each other, the parser recurses once per level
+const MAX_NESTING_DEPTH: usize = 128;
+// the smallest element is 3 bytes, such as `_\r\n`
+const MIN_ELEMENT_LEN: usize = 3;
+
 impl Display for Frame {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -39,12 +85,66 @@ impl Display for Frame {
                 }
                 write!(f, "{}", s)
             }
+            Frame::Null => write!(f, "_\\r\\n"),
+            Frame::Boolean(b) => write!(f, "#{}\\r\\n", if *b { 't' } else { 'f' }),
+            Frame::Double(d) => write!(f, ",{}\\r\\n", format_double(*d)),
+            Frame::BigNumber(n) => write!(f, "({}\\r\\n", n),
+            Frame::Verbatim(format, text) => write!(
+                f,
+                "={}\\r\\n{}:{}\\r\\n",
+                text.len() + 4,
+                format,
+                String::from_utf8_lossy(text)
+            ),
+            Frame::Map(m) => display_pairs(f, '%', m),
+            Frame::Attribute(m) => display_pairs(f, '|', m),
+            Frame::Set(a) => display_aggregate(f, '~', a),
+            Frame::Push(a) => display_aggregate(f, '>', a),
         }
     }
 }
 
+fn display_pairs(
+    f: &mut std::fmt::Formatter<'_>,
+    prefix: char,
+    pairs: &[(Frame, Frame)],
+) -> std::fmt::Result {
+    write!(f, "{}{}\\r\\n", prefix, pairs.len())?;
+    for (k, v) in pairs {
+        write!(f, "{}{}", k, v)?;
+    }
+    Ok(())
+}
+
+fn display_aggregate(
+    f: &mut std::fmt::Formatter<'_>,
+    prefix: char,
+    frames: &[Frame],
+) -> std::fmt::Result {
+    write!(f, "{}{}\\r\\n", prefix, frames.len())?;
+    for frame in frames {
+        write!(f, "{}", frame)?;
+    }
+    Ok(())
+}
+
 impl Frame {
     pub fn from_bytes(data: &[u8]) -> Result<Frame> {
+        Self::parse(data).map(|(frame, _)| frame)
+    }
+
+    // parse one frame from the head of the buffer
+    // return the frame and the number of bytes it occupied,
+    // the bytes behind it belong to the next (pipelined) frame
+    pub fn parse(data: &[u8]) -> Result<(Frame, usize)> {
+        Self::parse_nested(data, 0)
+    }
+
+    // depth is the number of aggregates the frame is nested in
+    fn parse_nested(data: &[u8], depth: usize) -> Result<(Frame, usize)> {
+        if depth > MAX_NESTING_DEPTH {
+            return Err(RedisErr::FrameMalformed);
+        }
         if data.is_empty() {
             return Err(RedisErr::FrameIncomplete);
         }
@@ -53,95 +153,161 @@ impl Frame {
         match frist_byte {
             // SimpleString +OK\r\n
             b'+' => {
-                let mut data = &data[1..];
-                if !data.ends_with(CRLF) {
-                    return Err(RedisErr::FrameIncomplete);
-                }
-                // remove \r\n
-                data = &data[..data.len() - 2];
-                let simple_string = String::from_utf8(data.to_vec()).unwrap();
-                Ok(Frame::SimpleString(simple_string))
+                let (line, len) = read_line(&data[1..])?;
+                let simple_string = String::from_utf8(line.to_vec())?;
+                Ok((Frame::SimpleString(simple_string), len + 1))
             }
             // Error -Error message\r\n
             b'-' => {
-                let mut data = &data[1..];
-                if !data.ends_with(CRLF) {
-                    return Err(RedisErr::FrameIncomplete);
-                }
-                // remove \r\n
-                data = &data[..data.len() - 2];
-                let error_string = String::from_utf8(data.to_vec()).unwrap();
-                Ok(Frame::Error(error_string))
+                let (line, len) = read_line(&data[1..])?;
+                let error_string = String::from_utf8(line.to_vec())?;
+                Ok((Frame::Error(error_string), len + 1))
             }
             // Number :1000\r\n
             b':' => {
-                let mut data = &data[1..];
-                if !data.ends_with(CRLF) {
-                    return Err(RedisErr::FrameIncomplete);
-                }
-                // remove \r\n
-                data = &data[..data.len() - 2];
-                let num = String::from_utf8(data.to_vec())?.parse()?;
-
-                Ok(Frame::Integer(num))
+                let (line, len) = read_line(&data[1..])?;
+                let num = String::from_utf8(line.to_vec())?.parse()?;
+                Ok((Frame::Integer(num), len + 1))
             }
             // BulkString, binary safe, $6\r\nfoobar\r\n
             b'$' => {
-                let mut data = &data[1..];
-                // find \r\n
-                let index = index_of(data, CRLF);
-                if index.is_none() {
-                    return Err(RedisErr::FrameIncomplete);
+                let (line, len) = read_line(&data[1..])?;
+                let num: i64 = String::from_utf8(line.to_vec())?.parse()?;
+                // nil bulk string $-1\r\n
+                if num < 0 {
+                    return Ok((Frame::Nil, len + 1));
                 }
-                let index = index.unwrap();
-                let num = String::from_utf8(data[..index].to_vec())?.parse()?;
-                data = &data[index + 2..];
-                // check if end with \r\n
-                // find next \r\n
-                let index = index_of(data, CRLF);
-                if index.is_none() {
+                let num = num as usize;
+                let data = &data[len + 1..];
+                // the payload is followed by \r\n
+                if data.len() < num + 2 {
                     return Err(RedisErr::FrameIncomplete);
                 }
-                let index = index.unwrap();
-                if index != num {
+                if &data[num..num + 2] != CRLF {
                     return Err(RedisErr::FrameMalformed);
                 }
 
-                // remove \r\n
                 let bulk_string = Bytes::copy_from_slice(&data[..num]);
-                Ok(Frame::BulkString(bulk_string))
+                Ok((Frame::BulkString(bulk_string), len + 1 + num + 2))
             }
             // Arrays *2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n
             b'*' => {
-                let mut data = &data[1..];
-                // find first \r\n and parse the number
-                let index = index_of(data, CRLF);
-                if index.is_none() {
+                let (line, len) = read_line(&data[1..])?;
+                let num: i64 = String::from_utf8(line.to_vec())?.parse()?;
+                // nil array *-1\r\n
+                if num < 0 {
+                    return Ok((Frame::Nil, len + 1));
+                }
+                if num > MAX_MULTIBULK_LEN {
+                    return Err(RedisErr::FrameMalformed);
+                }
+                let mut consumed = len + 1;
+                // the count is sent by the client, only trust it as far as the buffer goes
+                let capacity = (num as usize).min((data.len() - consumed) / MIN_ELEMENT_LEN);
+                let mut result = Vec::with_capacity(capacity);
+                for _ in 0..num {
+                    let (frame, len) = Frame::parse_nested(&data[consumed..], depth + 1)?;
+                    consumed += len;
+                    result.push(frame)
+                }
+                Ok((Frame::Array(result), consumed))
+            }
+            // Null _\r\n
+            b'_' => {
+                let (_, len) = read_line(&data[1..])?;
+                Ok((Frame::Null, len + 1))
+            }
+            // Boolean #t\r\n
+            b'#' => {
+                let (line, len) = read_line(&data[1..])?;
+                let b = match line {
+                    b"t" => true,
+                    b"f" => false,
+                    _ => return Err(RedisErr::FrameMalformed),
+                };
+                Ok((Frame::Boolean(b), len + 1))
+            }
+            // Double ,1.23\r\n
+            b',' => {
+                let (line, len) = read_line(&data[1..])?;
+                let d = String::from_utf8(line.to_vec())?
+                    .parse()
+                    .map_err(|_| RedisErr::FrameMalformed)?;
+                Ok((Frame::Double(d), len + 1))
+            }
+            // BigNumber (3492890328409238509324850943850943825024385\r\n
+            b'(' => {
+                let (line, len) = read_line(&data[1..])?;
+                let n = String::from_utf8(line.to_vec())?;
+                Ok((Frame::BigNumber(n), len + 1))
+            }
+            // Verbatim string =15\r\ntxt:Some string\r\n
+            b'=' => {
+                let (line, len) = read_line(&data[1..])?;
+                let num: usize = String::from_utf8(line.to_vec())?.parse()?;
+                let data = &data[len + 1..];
+                if data.len() < num + 2 {
                     return Err(RedisErr::FrameIncomplete);
                 }
-                let index = index.unwrap();
-
-                let num = String::from_utf8(data[..index].to_vec())?.parse()?;
-                data = &data[index + 2..];
-                let mut result = Vec::new();
+                // 3 bytes format, a colon and the text
+                if num < 4 || data[3] != b':' || &data[num..num + 2] != CRLF {
+                    return Err(RedisErr::FrameMalformed);
+                }
+                let format = String::from_utf8(data[..3].to_vec())?;
+                let text = Bytes::copy_from_slice(&data[4..num]);
+                Ok((Frame::Verbatim(format, text), len + 1 + num + 2))
+            }
+            // Map %2\r\n... and Attribute |2\r\n... followed by key value pairs
+            b'%' | b'|' => {
+                let (line, len) = read_line(&data[1..])?;
+                let num: usize = String::from_utf8(line.to_vec())?.parse()?;
+                if num > MAX_MULTIBULK_LEN as usize {
+                    return Err(RedisErr::FrameMalformed);
+                }
+                let mut consumed = len + 1;
+                // each pair is two elements
+                let capacity = num.min((data.len() - consumed) / (2 * MIN_ELEMENT_LEN));
+                let mut result = Vec::with_capacity(capacity);
+                for _ in 0..num {
+                    let (key, len) = Frame::parse_nested(&data[consumed..], depth + 1)?;
+                    consumed += len;
+                    let (value, len) = Frame::parse_nested(&data[consumed..], depth + 1)?;
+                    consumed += len;
+                    result.push((key, value));
+                }
+                if frist_byte == b'%' {
+                    Ok((Frame::Map(result), consumed))
+                } else {
+                    Ok((Frame::Attribute(result), consumed))
+                }
+            }
+            // Set ~2\r\n... and Push >2\r\n...
+            b'~' | b'>' => {
+                let (line, len) = read_line(&data[1..])?;
+                let num: usize = String::from_utf8(line.to_vec())?.parse()?;
+                if num > MAX_MULTIBULK_LEN as usize {
+                    return Err(RedisErr::FrameMalformed);
+                }
+                let mut consumed = len + 1;
+                let capacity = num.min((data.len() - consumed) / MIN_ELEMENT_LEN);
+                let mut result = Vec::with_capacity(capacity);
                 for _ in 0..num {
-                    // remove \r\n
-                    let frame = Frame::from_bytes(data)?;
-                    data = &data[frame.len()..];
+                    let (frame, len) = Frame::parse_nested(&data[consumed..], depth + 1)?;
+                    consumed += len;
                     result.push(frame)
                 }
-                Ok(Frame::Array(result))
+                if frist_byte == b'~' {
+                    Ok((Frame::Set(result), consumed))
+                } else {
+                    Ok((Frame::Push(result), consumed))
+                }
             }
             // inline command, such as `set key value`
             // separated by space
             b if b.is_ascii_alphanumeric() => {
-                let mut data = data;
-                let index = index_of(data, CRLF);
-                if let Some(idx) = index {
-                    data = &data[..idx];
-                }
+                let (line, len) = read_line(data)?;
 
-                let s = String::from_utf8(data.to_vec())?;
+                let s = String::from_utf8(line.to_vec())?;
                 let mut result = Vec::new();
                 for item in s.split(' ') {
                     // check simple string or integer
@@ -162,35 +328,12 @@ impl Frame {
                     }
                 }
 
-                Ok(Frame::Array(result))
+                Ok((Frame::Array(result), len))
             }
             _ => Err(RedisErr::FrameMalformed),
         }
     }
 
-    // return the length after serialize
-    pub fn len(&self) -> usize {
-        match self {
-            Frame::Nil => 5,
-            // string len + '+' + '\r' + '\n'
-            Frame::SimpleString(s) => s.len() + 3,
-            // string len + '-' + '\r' + '\n'
-            Frame::Error(s) => s.len() + 3,
-            // string len + ':' + '\r' + '\n'
-            Frame::Integer(i) => i.to_string().len() + 3,
-            // string len + '$' + '\r' + '\n' + string + '\r' + '\n'
-            Frame::BulkString(s) => s.len() + 5 + s.len().to_string().len(),
-            // frame len + '*' + '\r' + '\n' + frame + '\r' + '\n'
-            Frame::Array(v) => {
-                let mut result = 0;
-                for protocol in v {
-                    result += protocol.len();
-                }
-                result + 3 + v.len().to_string().len()
-            }
-        }
-    }
-
     // RESP protocol
     // server response is Simple Strings, the first byte of the reply is "+" followed by the string itself
     // `+OK\r\n`
@@ -244,7 +387,137 @@ impl Frame {
                 }
                 Bytes::from(result)
             }
+            Frame::Null => Bytes::from_static(b"_\r\n"),
+            Frame::Boolean(true) => Bytes::from_static(b"#t\r\n"),
+            Frame::Boolean(false) => Bytes::from_static(b"#f\r\n"),
+            Frame::Double(d) => {
+                let mut result = Vec::<u8>::new();
+                result.push(b',');
+                result.extend_from_slice(format_double(d).as_bytes());
+                result.extend_from_slice(b"\r\n");
+                Bytes::from(result)
+            }
+            Frame::BigNumber(n) => {
+                let mut result = Vec::<u8>::new();
+                result.push(b'(');
+                result.extend_from_slice(n.as_bytes());
+                result.extend_from_slice(b"\r\n");
+                Bytes::from(result)
+            }
+            Frame::Verbatim(format, text) => {
+                let mut result = Vec::<u8>::new();
+                result.push(b'=');
+                result.extend((text.len() + 4).to_string().as_bytes());
+                result.extend_from_slice(b"\r\n");
+                result.extend_from_slice(format.as_bytes());
+                result.push(b':');
+                result.extend_from_slice(&text);
+                result.extend_from_slice(b"\r\n");
+                Bytes::from(result)
+            }
+            Frame::Map(m) => serialize_pairs(b'%', m),
+            Frame::Attribute(m) => serialize_pairs(b'|', m),
+            Frame::Set(v) => serialize_aggregate(b'~', v),
+            Frame::Push(v) => serialize_aggregate(b'>', v),
+        }
+    }
+
+    // convert the frame to the closest RESP2 type,
+    // used when the client does not switch to RESP3 by HELLO
+    pub fn into_resp2(self) -> Frame {
+        match self {
+            Frame::Null => Frame::Nil,
+            Frame::Boolean(b) => Frame::Integer(b as i64),
+            Frame::Double(d) => Frame::BulkString(Bytes::from(format_double(d))),
+            Frame::BigNumber(n) => Frame::BulkString(Bytes::from(n)),
+            Frame::Verbatim(_, text) => Frame::BulkString(text),
+            // maps are flatten into key value key value ...
+            Frame::Map(m) => {
+                let mut result = Vec::with_capacity(m.len() * 2);
+                for (k, v) in m {
+                    result.push(k.into_resp2());
+                    result.push(v.into_resp2());
+                }
+                Frame::Array(result)
+            }
+            Frame::Array(v) | Frame::Set(v) | Frame::Push(v) => Frame::Array(
+                v.into_iter()
+                    .filter(|f| !f.is_attribute())
+                    .map(Frame::into_resp2)
+                    .collect(),
+            ),
+            // RESP2 can't carry out of band data, the caller should drop it
+            Frame::Attribute(_) => Frame::Nil,
+            frame => frame,
+        }
+    }
+
+    // convert the RESP2 only types to their RESP3 equivalent
+    pub fn into_resp3(self) -> Frame {
+        match self {
+            Frame::Nil => Frame::Null,
+            Frame::Array(v) => Frame::Array(v.into_iter().map(Frame::into_resp3).collect()),
+            Frame::Set(v) => Frame::Set(v.into_iter().map(Frame::into_resp3).collect()),
+            Frame::Push(v) => Frame::Push(v.into_iter().map(Frame::into_resp3).collect()),
+            Frame::Map(m) => Frame::Map(
+                m.into_iter()
+                    .map(|(k, v)| (k.into_resp3(), v.into_resp3()))
+                    .collect(),
+            ),
+            frame => frame,
+        }
+    }
+
+    pub fn is_attribute(&self) -> bool {
+        matches!(self, Frame::Attribute(_))
+    }
+}
+
+fn serialize_pairs(prefix: u8, pairs: Vec<(Frame, Frame)>) -> Bytes {
+    let mut result = Vec::<u8>::new();
+    result.push(prefix);
+    result.extend_from_slice(&pairs.len().to_string().into_bytes());
+    result.extend_from_slice(b"\r\n");
+    for (k, v) in pairs {
+        result.extend(k.serialize());
+        result.extend(v.serialize());
+    }
+    Bytes::from(result)
+}
+
+fn serialize_aggregate(prefix: u8, frames: Vec<Frame>) -> Bytes {
+    let mut result = Vec::<u8>::new();
+    result.push(prefix);
+    result.extend_from_slice(&frames.len().to_string().into_bytes());
+    result.extend_from_slice(b"\r\n");
+    for frame in frames {
+        result.extend(frame.serialize());
+    }
+    Bytes::from(result)
+}
+
+// RESP3 double, infinity and nan are spelled in lower case
+fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        "nan".to_string()
+    } else if d.is_infinite() {
+        if d > 0.0 {
+            "inf".to_string()
+        } else {
+            "-inf".to_string()
         }
+    } else {
+        d.to_string()
+    }
+}
+
+// split the line terminated by \r\n from the head of the buffer
+// return the line without \r\n and the number of bytes it occupied
+#[inline]
+fn read_line(data: &[u8]) -> Result<(&[u8], usize)> {
+    match index_of(data, CRLF) {
+        Some(index) => Ok((&data[..index], index + 2)),
+        None => Err(RedisErr::FrameIncomplete),
     }
 }
 
@@ -265,34 +538,34 @@ mod tests {
     #[test]
     fn test_parse_request() {
         let data = "$7\r\nSET a b\r\n".as_bytes();
-        let command = Frame::from_bytes(&data.to_vec());
-        assert_eq!(command.is_ok(), true);
+        let command = Frame::from_bytes(data);
+        assert!(command.is_ok());
         assert_eq!(
             command.unwrap(),
             Frame::BulkString(Bytes::from_static(b"SET a b"))
         );
 
         let data = "+OK\r\n".as_bytes();
-        let command = Frame::from_bytes(&data.to_vec());
-        assert_eq!(command.is_ok(), true);
+        let command = Frame::from_bytes(data);
+        assert!(command.is_ok());
         assert_eq!(command.unwrap(), Frame::SimpleString("OK".to_string()));
 
         let data = "-ERR unknown command 'foobar'\r\n".as_bytes();
-        let command = Frame::from_bytes(&data.to_vec());
-        assert_eq!(command.is_ok(), true);
+        let command = Frame::from_bytes(data);
+        assert!(command.is_ok());
         assert_eq!(
             command.unwrap(),
             Frame::Error("ERR unknown command 'foobar'".to_string())
         );
 
         let data = ":1000\r\n".as_bytes();
-        let command = Frame::from_bytes(&data.to_vec());
-        assert_eq!(command.is_ok(), true);
+        let command = Frame::from_bytes(data);
+        assert!(command.is_ok());
         assert_eq!(command.unwrap(), Frame::Integer(1000));
 
         let data = "*2\r\n$5\r\nhello\r\n$5\r\nworld\r\n".as_bytes();
-        let command = Frame::from_bytes(&data.to_vec());
-        assert_eq!(command.is_ok(), true);
+        let command = Frame::from_bytes(data);
+        assert!(command.is_ok());
         assert_eq!(
             command.unwrap(),
             Frame::Array(vec![
@@ -302,9 +575,9 @@ mod tests {
         );
 
         // inline command
-        let data = "SET a b 1".as_bytes();
-        let command = Frame::from_bytes(&data.to_vec());
-        assert_eq!(command.is_ok(), true);
+        let data = "SET a b 1\r\n".as_bytes();
+        let command = Frame::from_bytes(data);
+        assert!(command.is_ok());
         assert_eq!(
             command.unwrap(),
             Frame::Array(vec![
@@ -317,8 +590,182 @@ mod tests {
 
         // bad case
         let data = "$7\r\nSET a ba\r\n".as_bytes();
-        let command = Frame::from_bytes(&data.to_vec());
-        assert_eq!(command.is_ok(), false);
+        let command = Frame::from_bytes(data);
+        assert!(command.is_err());
         assert_eq!(command.unwrap_err(), RedisErr::FrameMalformed);
     }
+
+    #[test]
+    fn test_parse_incomplete() {
+        let cases = [
+            "+OK",
+            ":10",
+            "$5\r\nhel",
+            "$5\r\nhello",
+            "*2\r\n$3\r\nfoo\r\n",
+            "SET a b",
+        ];
+        for data in cases {
+            assert_eq!(
+                Frame::parse(data.as_bytes()),
+                Err(RedisErr::FrameIncomplete),
+                "{}",
+                data
+            );
+        }
+
+        let data = "$-1\r\n".as_bytes();
+        assert_eq!(Frame::parse(data), Ok((Frame::Nil, 5)));
+
+        // bulk string is binary safe
+        let data = "$4\r\na\r\nb\r\n".as_bytes();
+        assert_eq!(
+            Frame::parse(data),
+            Ok((Frame::BulkString(Bytes::from_static(b"a\r\nb")), 10))
+        );
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        // the announced length is not allocated up front
+        let data = "*100000000000000000\r\n".as_bytes();
+        assert_eq!(Frame::parse(data), Err(RedisErr::FrameMalformed));
+        let data = format!("*{}\r\n", i64::MAX);
+        assert_eq!(Frame::parse(data.as_bytes()), Err(RedisErr::FrameMalformed));
+        let data = format!("*{}\r\n", MAX_MULTIBULK_LEN);
+        assert_eq!(
+            Frame::parse(data.as_bytes()),
+            Err(RedisErr::FrameIncomplete)
+        );
+
+        let nested = |depth: usize| "*1\r\n".repeat(depth) + ":1\r\n";
+        let data = nested(MAX_NESTING_DEPTH);
+        assert!(Frame::parse(data.as_bytes()).is_ok());
+        let data = nested(MAX_NESTING_DEPTH + 1);
+        assert_eq!(Frame::parse(data.as_bytes()), Err(RedisErr::FrameMalformed));
+        // deeper than the stack would allow without the limit
+        let data = nested(1_000_000);
+        assert_eq!(Frame::parse(data.as_bytes()), Err(RedisErr::FrameMalformed));
+
+        // the same limits hold for the RESP3 aggregates
+        for prefix in ["%", "|", "~", ">"] {
+            let data = format!("{}100000000000000000\r\n", prefix);
+            assert_eq!(Frame::parse(data.as_bytes()), Err(RedisErr::FrameMalformed));
+            let data = format!("{}{}\r\n", prefix, MAX_MULTIBULK_LEN);
+            assert_eq!(
+                Frame::parse(data.as_bytes()),
+                Err(RedisErr::FrameIncomplete)
+            );
+
+            let data = format!("{}1\r\n", prefix).repeat(1_000_000);
+            assert_eq!(Frame::parse(data.as_bytes()), Err(RedisErr::FrameMalformed));
+        }
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let data = "*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\nPING\r\n*1\r\n$3".as_bytes();
+
+        let (frame, len) = Frame::parse(data).unwrap();
+        assert_eq!(
+            frame,
+            Frame::Array(vec![Frame::BulkString(Bytes::from_static(b"PING"))])
+        );
+        assert_eq!(len, 14);
+        let data = &data[len..];
+
+        let (frame, len) = Frame::parse(data).unwrap();
+        assert_eq!(
+            frame,
+            Frame::Array(vec![
+                Frame::BulkString(Bytes::from_static(b"GET")),
+                Frame::BulkString(Bytes::from_static(b"a")),
+            ])
+        );
+        assert_eq!(len, 20);
+        let data = &data[len..];
+
+        let (frame, len) = Frame::parse(data).unwrap();
+        assert_eq!(
+            frame,
+            Frame::Array(vec![Frame::SimpleString("PING".to_string())])
+        );
+        assert_eq!(len, 6);
+        let data = &data[len..];
+
+        // the tail is the beginning of the next command
+        assert_eq!(Frame::parse(data), Err(RedisErr::FrameIncomplete));
+    }
+
+    #[test]
+    fn test_resp3() {
+        let frames = vec![
+            Frame::Null,
+            Frame::Boolean(true),
+            Frame::Boolean(false),
+            Frame::Double(1.5),
+            Frame::Double(f64::INFINITY),
+            Frame::BigNumber("3492890328409238509324850943850943825024385".to_string()),
+            Frame::Verbatim("txt".to_string(), Bytes::from_static(b"Some string")),
+            Frame::Map(vec![(
+                Frame::SimpleString("first".to_string()),
+                Frame::Integer(1),
+            )]),
+            Frame::Set(vec![
+                Frame::SimpleString("a".to_string()),
+                Frame::Integer(2),
+            ]),
+            Frame::Attribute(vec![(
+                Frame::SimpleString("ttl".to_string()),
+                Frame::Integer(3600),
+            )]),
+            Frame::Push(vec![
+                Frame::BulkString(Bytes::from_static(b"message")),
+                Frame::BulkString(Bytes::from_static(b"channel")),
+            ]),
+        ];
+        for frame in frames {
+            let data = frame.clone().serialize();
+            assert_eq!(Frame::parse(&data), Ok((frame, data.len())));
+        }
+
+        assert_eq!(
+            Frame::Verbatim("txt".to_string(), Bytes::from_static(b"Some string")).serialize(),
+            Bytes::from_static(b"=15\r\ntxt:Some string\r\n")
+        );
+        assert_eq!(
+            Frame::Double(f64::NEG_INFINITY).serialize(),
+            Bytes::from_static(b",-inf\r\n")
+        );
+    }
+
+    #[test]
+    fn test_into_resp2() {
+        let frame = Frame::Map(vec![
+            (
+                Frame::BulkString(Bytes::from_static(b"a")),
+                Frame::Double(1.5),
+            ),
+            (Frame::BulkString(Bytes::from_static(b"b")), Frame::Null),
+        ]);
+        assert_eq!(
+            frame.into_resp2(),
+            Frame::Array(vec![
+                Frame::BulkString(Bytes::from_static(b"a")),
+                Frame::BulkString(Bytes::from_static(b"1.5")),
+                Frame::BulkString(Bytes::from_static(b"b")),
+                Frame::Nil,
+            ])
+        );
+
+        assert_eq!(Frame::Boolean(true).into_resp2(), Frame::Integer(1));
+        assert_eq!(
+            Frame::Push(vec![Frame::Integer(1)]).into_resp2(),
+            Frame::Array(vec![Frame::Integer(1)])
+        );
+        assert_eq!(
+            Frame::Array(vec![Frame::Nil]).into_resp3(),
+            Frame::Array(vec![Frame::Null])
+        );
+    }
 }
@@ -9,9 +9,9 @@
 //! - one of the worker threads will serialize the response into bytes and send it back to the client
 //!
 //!
-use crate::{cmd, connection::AsyncConnection, db::DB};
+use crate::{cmd, connection::AsyncConnection, db::DB, frame::Frame};
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use log::trace;
 use tokio::{net::TcpStream, sync::Notify};
@@ -24,11 +24,9 @@ pub struct Handler {
 
 impl Handler {
     pub fn new(stream: TcpStream, db: DB, shutdown: Arc<Notify>) -> Handler {
-        Handler {
-            db,
-            conn: AsyncConnection::new(stream),
-            shutdown,
-        }
+        let mut conn = AsyncConnection::new(stream);
+        conn.set_authenticated(db.config().requirepass.is_none());
+        Handler { db, conn, shutdown }
     }
 
     pub async fn run(&mut self) -> crate::Result<()> {
@@ -38,19 +36,80 @@ impl Handler {
                 frame = self.conn.read_frame() => {
                     let frame = frame?;
 
-                    let cmd = parser.parse(frame);
-                    if cmd.is_err() {
-                        self.conn.write_frame(crate::frame::Frame::Error(cmd.unwrap_err().to_string())).await?;
+                    // keep the request to log it once it's applied
+                    let request = frame.clone();
+                    // a command rejected in a transaction makes EXEC discard it
+                    let cmd = match parser.parse(frame) {
+                        Ok(cmd) => cmd,
+                        Err(e) => {
+                            self.conn.fail_multi();
+                            self.conn.write_frame(Frame::Error(e.to_string())).await?;
+                            continue;
+                        }
+                    };
+                    trace!("parsed command {:?}", cmd);
+                    if cmd.need_auth() && !self.conn.is_authenticated() {
+                        self.conn.fail_multi();
+                        self.conn.write_frame(Frame::Error("NOAUTH Authentication required.".to_string())).await?;
                         continue;
                     }
-                    let cmd = cmd.unwrap();
-                    trace!("parsed command {:?}", cmd);
+                    // only SCRIPT KILL can stop a script running for too long
+                    if !cmd.is_script_kill() && self.db.scripting().busy(Duration::from_millis(self.db.config().busy_reply_threshold)) {
+                        self.conn.fail_multi();
+                        self.conn.write_frame(Frame::Error("BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.".to_string())).await?;
+                        continue;
+                    }
+                    // keys in a slot served by another node are redirected
+                    let asking = self.conn.take_asking();
+                    if let Some(cluster) = self.db.cluster() {
+                        if let Some(redirect) = cluster.redirect(&self.db, &cmd.keys(&request), asking) {
+                            self.conn.fail_multi();
+                            self.conn.write_frame(redirect).await?;
+                            continue;
+                        }
+                    }
+                    if cmd.is_write() && self.db.is_replica() {
+                        self.conn.fail_multi();
+                        self.conn.write_frame(Frame::Error("READONLY You can't write against a read only replica.".to_string())).await?;
+                        continue;
+                    }
+                    // commands are queued from MULTI until EXEC or DISCARD
+                    if self.conn.in_multi() && (!cmd.is_connection_bound() || cmd.is_blocking()) {
+                        self.conn.queue(cmd, request);
+                        self.conn.write_frame(Frame::SimpleString("QUEUED".to_string())).await?;
+                        continue;
+                    }
+                    if self.conn.in_multi() && !matches!(cmd, cmd::Command::Multi(_) | cmd::Command::Exec(_) | cmd::Command::Discard(_) | cmd::Command::Watch(_) | cmd::Command::Unwatch(_)) {
+                        self.conn.fail_multi();
+                        self.conn.write_frame(Frame::Error("ERR Command not allowed inside a transaction".to_string())).await?;
+                        continue;
+                    }
+                    // the connection becomes the replication link until the replica is gone
+                    if let cmd::Command::Psync(cmd) = cmd {
+                        return cmd.apply(&mut self.db, &mut self.conn, self.shutdown.clone()).await;
+                    }
                     // normally, the apply function would return a frame
                     // and we should write that frame to the client
                     // but subscribe would block the thread and never return
                     // until the connection is unsubscribed
-                    let resp = cmd.apply(&mut self.db, &mut self.conn, self.shutdown.clone()).await;
+                    // blocking writes propagate by themselves once they're served
+                    // replies of the commands pipelined before are due before the wait
+                    if cmd.is_blocking() || matches!(cmd, cmd::Command::Wait(_)) {
+                        self.conn.flush().await?;
+                    }
+                    let resp = if cmd.is_write() && !cmd.is_connection_bound() {
+                        self.apply_write(cmd, request).await
+                    } else if cmd.is_connection_bound() || cmd.is_script_kill() {
+                        cmd.apply(&mut self.db, &mut self.conn, self.shutdown.clone()).await
+                    } else {
+                        let db = self.db.clone();
+                        let _guard = db.command_guard().await;
+                        cmd.apply_to_db(&mut self.db).unwrap_or_else(|e| Frame::Error(e.to_string()))
+                    };
                     trace!("command response {:?}", resp);
+                    if self.conn.is_closed() {
+                        return Ok(());
+                    }
                     self.conn.write_frame(resp).await?;
                 }
                 _ = self.shutdown.notified() => {
@@ -59,4 +118,17 @@ impl Handler {
             }
         }
     }
+
+    // write commands are logged once applied,
+    // the guard keeps an aof rewrite from starting in between
+    async fn apply_write(&mut self, cmd: cmd::Command, request: Frame) -> Frame {
+        let db = self.db.clone();
+        let _command = db.command_guard().await;
+        let _guard = db.propagate_guard();
+        let resp = cmd.apply_and_propagate(&mut self.db, request);
+        if !matches!(resp, Frame::Error(_)) {
+            self.conn.set_write_offset(self.db.replication().offset());
+        }
+        resp
+    }
 }
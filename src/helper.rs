@@ -1,7 +1,11 @@
 //! helper functions in this crate
 
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
 use bytes::Bytes;
 
+use crate::frame::Frame;
+
 #[inline]
 #[allow(dead_code)]
 pub fn bytes_to_printable_string(bytes: &Bytes) -> String {
@@ -10,6 +14,133 @@ pub fn bytes_to_printable_string(bytes: &Bytes) -> String {
     str.replace('\r', "\\r").replace('\n', "\\n")
 }
 
+// expire time is kept as monotonic instant in memory,
+// but persisted as unix time in milliseconds
+pub fn instant_to_unix_ms(instant: Instant) -> u64 {
+    let now = SystemTime::now();
+    let unix = match instant.checked_duration_since(Instant::now()) {
+        Some(after) => now + after,
+        None => now - Instant::now().duration_since(instant),
+    };
+    unix.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// the instant of a persisted expire time, None if it has already passed
+pub fn unix_ms_to_instant(ms: u64) -> Option<Instant> {
+    let at = UNIX_EPOCH + Duration::from_millis(ms);
+    at.duration_since(SystemTime::now())
+        .ok()
+        .map(|after| Instant::now() + after)
+}
+
+// random hex string, such as the replication id
+pub fn random_hex(len: usize) -> String {
+    let mut res = String::with_capacity(len);
+    while res.len() < len {
+        res.push_str(&format!("{:016x}", random_u64()));
+    }
+    res.truncate(len);
+    res
+}
+
+// random number, not for cryptography
+pub fn random_u64() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    // every RandomState is seeded differently
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+// current unix time in milliseconds
+pub fn unix_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// current unix time in seconds
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// glob style matching as redis does: * and ? wildcards, [abc], [^a] and [a-z] classes,
+// and a backslash escaping the next character
+pub fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| glob_match(rest, &s[i..])),
+        Some((b'?', rest)) => !s.is_empty() && glob_match(rest, &s[1..]),
+        Some((b'[', rest)) => {
+            let Some((&c, s)) = s.split_first() else {
+                return false;
+            };
+            let (negate, mut class) = match rest.split_first() {
+                Some((b'^', class)) => (true, class),
+                _ => (false, rest),
+            };
+            let mut matched = false;
+            loop {
+                match class {
+                    // an unclosed class ends with the pattern
+                    [] => break,
+                    [b']', tail @ ..] => {
+                        class = tail;
+                        break;
+                    }
+                    [b'\\', escaped, tail @ ..] => {
+                        matched |= *escaped == c;
+                        class = tail;
+                    }
+                    [start, b'-', end, tail @ ..] if *end != b']' => {
+                        let (low, high) = if start <= end {
+                            (*start, *end)
+                        } else {
+                            (*end, *start)
+                        };
+                        matched |= low <= c && c <= high;
+                        class = tail;
+                    }
+                    [first, tail @ ..] => {
+                        matched |= *first == c;
+                        class = tail;
+                    }
+                }
+            }
+            matched != negate && glob_match(class, s)
+        }
+        Some((b'\\', [escaped, rest @ ..])) => {
+            s.first() == Some(escaped) && glob_match(rest, &s[1..])
+        }
+        Some((first, rest)) => s.first() == Some(first) && glob_match(rest, &s[1..]),
+    }
+}
+
+// bulk string of an argument built by the server, such as a rewritten command
+#[inline]
+pub fn bulk(s: &str) -> Frame {
+    Frame::BulkString(Bytes::from(s.to_string()))
+}
+
+// the frames of a request, for the tests to parse commands from
+#[cfg(test)]
+pub fn command(args: &[&str]) -> Vec<Frame> {
+    args.iter().map(|arg| bulk(arg)).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -21,4 +152,36 @@ mod tests {
         println!("{}", str);
         assert_eq!(str, "*2\\r\\n$3\\r\\nGET\\r\\n$5\\r\\nHello\\r\\n");
     }
+
+    #[test]
+    fn test_glob_match() {
+        let cases: Vec<(&str, &str, bool)> = vec![
+            ("*", "", true),
+            ("*", "anything", true),
+            ("news.*", "news.tech", true),
+            ("news.*", "news", false),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[b-a]llo", "hallo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("lib_*", "lib_one", true),
+        ];
+        for (pattern, s, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), s.as_bytes()),
+                expected,
+                "{} {}",
+                pattern,
+                s
+            );
+        }
+    }
 }
@@ -1,12 +1,13 @@
+mod aof;
 mod cmd;
+mod config;
 mod connection;
 mod db;
 mod err;
 mod frame;
-mod helper;
-mod shutdown;
-// mod rdb;
 mod handler;
+mod helper;
+mod rdb;
 mod value;
 
 pub mod client;
@@ -15,6 +16,7 @@ pub mod arg;
 pub mod server;
 
 pub use arg::Arg;
+pub use config::{AppendFsync, KeyspaceEvents, PubSubOutputLimit};
 pub use err::RedisErr;
 
 type Result<T> = std::result::Result<T, RedisErr>;
@@ -0,0 +1,86 @@
+//! crc64 used by redis to checksum the rdb file
+//! Jones polynomial 0xad93d23594c935a9, reflected input and output,
+//! initial value 0 and no final xor, check value of "123456789" is 0xe9c6d914c4b8d9ca
+
+use std::io::{self, Write};
+
+// 0xad93d23594c935a9 reflected
+const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const TABLE: [u64; 256] = make_table();
+
+const fn make_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub fn crc64(crc: u64, data: &[u8]) -> u64 {
+    let mut crc = crc;
+    for b in data {
+        crc = TABLE[((crc ^ *b as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+// writer checksums every byte passing through it
+pub struct Crc64Writer<W: Write> {
+    inner: W,
+    crc: u64,
+}
+
+impl<W: Write> Crc64Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, crc: 0 }
+    }
+
+    pub fn crc(&self) -> u64 {
+        self.crc
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Crc64Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.crc = crc64(self.crc, &buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+
+        let mut writer = Crc64Writer::new(Vec::new());
+        writer.write_all(b"12345").unwrap();
+        writer.write_all(b"6789").unwrap();
+        assert_eq!(writer.crc(), 0xe9c6d914c4b8d9ca);
+        assert_eq!(writer.into_inner(), b"123456789".to_vec());
+    }
+}
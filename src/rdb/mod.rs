@@ -1,36 +1,342 @@
 //! https://rdb.fnordig.de/file_format.html#high-level-algorithm-to-parse-rdb
 //! follow official document to implement rdb parser and writer
 
-use std::{collections::HashMap, io::Write};
+mod crc64;
+mod reader;
 
-use crate::value::Value;
+use crate::helper::unix_timestamp;
+use crate::value::{
+    BloomFilter, ConsumerGroup, PendingEntry, Stream, StreamFields, StreamId, Value, ZSet,
+};
+use crate::{RedisErr, Result};
+use crc64::Crc64Writer;
+use reader::{intset_entries, listpack_entries, ziplist_entries, Reader};
 
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use bytes::Bytes;
+
+// opcodes
+const RDB_OPCODE_SLOT_INFO: u8 = 0xF4;
+const RDB_OPCODE_FUNCTION2: u8 = 0xF5;
+const RDB_OPCODE_MODULE_AUX: u8 = 0xF7;
+const RDB_OPCODE_FREQ: u8 = 0xF8;
+const RDB_OPCODE_IDLE: u8 = 0xF9;
+const RDB_OPCODE_AUX: u8 = 0xFA;
+const RDB_OPCODE_RESIZEDB: u8 = 0xFB;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const RDB_OPCODE_EXPIRETIME: u8 = 0xFD;
+const RDB_OPCODE_SELECTDB: u8 = 0xFE;
+const RDB_OPCODE_EOF: u8 = 0xFF;
+
+// value types
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_MODULE_2: u8 = 7;
+const RDB_TYPE_LIST_ZIPLIST: u8 = 10;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
+const RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// a module value is a sequence of opcodes followed by their values, ended by EOF
+const RDB_MODULE_OPCODE_EOF: u64 = 0;
+const RDB_MODULE_OPCODE_STRING: u64 = 5;
+// bloom filters are saved as the value of a module type of this name,
+// redis without the type refuses to load it instead of dropping the keys
+const BLOOM_FILTER_MODULE_ID: u64 = module_id(b"bloomfltr", 1);
+
+// quicklist node containers
+const QUICKLIST_NODE_CONTAINER_PLAIN: u64 = 1;
+
+// flags of an entry in a stream listpack
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+// entries in a listpack, the default stream-node-max-entries
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+// length encoding, the two most significant bits of the first byte
+const RDB_6BITLEN: u8 = 0;
+const RDB_14BITLEN: u8 = 1;
+const RDB_32BITLEN: u8 = 0x80;
+const RDB_64BITLEN: u8 = 0x81;
+
+// version 9 can be loaded by redis 5.0 and later
+const RDB_VERSION: u32 = 9;
+// written by redis 7.4
+const RDB_MAX_VERSION: u32 = 12;
+
+// key, value and the expire time in unix milliseconds
+pub type Record = (String, Value, Option<u64>);
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct RDB {
-    dbs: Vec<HashMap<String, Value>>,
-    expire_table: HashMap<String, u64>,
+    dbs: Vec<Vec<Record>>,
     version: u32,
     aux_fields: Vec<(String, String)>,
-    file: std::fs::File,
+    // code of the function libraries
+    functions: Vec<Bytes>,
 }
 
 impl RDB {
-    pub fn new() -> Self {
+    pub fn new(dbs: Vec<Vec<Record>>) -> Self {
+        let ctime = unix_timestamp();
         Self {
-            version: 0,
-            aux_fields: Vec::new(),
-            dbs: Vec::new(),
-            expire_table: HashMap::new(),
-            file: std::fs::File::create("dump.rdb").unwrap(),
+            version: RDB_VERSION,
+            aux_fields: vec![
+                (
+                    "redis-ver".to_string(),
+                    env!("CARGO_PKG_VERSION").to_string(),
+                ),
+                ("redis-bits".to_string(), "64".to_string()),
+                ("ctime".to_string(), ctime.to_string()),
+            ],
+            dbs,
+            functions: vec![],
         }
     }
-    pub fn load(&mut self, path: &str) {}
 
-    pub fn write(&mut self, path: &str) {
-        let file = std::fs::File::create(path).unwrap();
-        let mut writer: Box<dyn Write> = Box::new(file);
-        self.write_header(&mut writer);
-        self.write_content(&mut writer);
-        self.write_footer(&mut writer);
+    pub fn with_functions(mut self, functions: Vec<Bytes>) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    pub fn functions(&self) -> &[Bytes] {
+        &self.functions
+    }
+
+    pub fn into_dbs(self) -> Vec<Vec<Record>> {
+        self.dbs
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        Self::load_from(&data)
+    }
+
+    pub fn load_from(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let version = Self::read_header(&mut reader)?;
+        let mut rdb = Self {
+            dbs: vec![],
+            version,
+            aux_fields: vec![],
+            functions: vec![],
+        };
+
+        let mut index = 0;
+        let mut expire_at = None;
+        loop {
+            match reader.read_u8()? {
+                RDB_OPCODE_EOF => break,
+                RDB_OPCODE_SELECTDB => index = reader.read_length()? as usize,
+                RDB_OPCODE_RESIZEDB => {
+                    reader.read_length()?;
+                    reader.read_length()?;
+                }
+                RDB_OPCODE_AUX => {
+                    let key = reader.read_string()?;
+                    let value = reader.read_string()?;
+                    rdb.aux_fields.push((
+                        String::from_utf8_lossy(&key).to_string(),
+                        String::from_utf8_lossy(&value).to_string(),
+                    ));
+                }
+                RDB_OPCODE_EXPIRETIME_MS => expire_at = Some(reader.read_u64_le()?),
+                RDB_OPCODE_EXPIRETIME => expire_at = Some(reader.read_u32_le()? as u64 * 1000),
+                // eviction hints, not used
+                RDB_OPCODE_IDLE => {
+                    reader.read_length()?;
+                }
+                RDB_OPCODE_FREQ => {
+                    reader.read_u8()?;
+                }
+                RDB_OPCODE_SLOT_INFO => {
+                    for _ in 0..3 {
+                        reader.read_length()?;
+                    }
+                }
+                RDB_OPCODE_FUNCTION2 => rdb.functions.push(reader.read_string()?),
+                RDB_OPCODE_MODULE_AUX => return Err(RedisErr::RDBUnsupported),
+                value_type => {
+                    let key = String::from_utf8(reader.read_string()?.to_vec())
+                        .map_err(|_| RedisErr::RDBUnsupported)?;
+                    let value = Self::read_value(&mut reader, value_type)?;
+                    if rdb.dbs.len() <= index {
+                        rdb.dbs.resize_with(index + 1, Vec::new);
+                    }
+                    rdb.dbs[index].push((key, value, expire_at.take()));
+                }
+            }
+        }
+
+        // checksum is zero when it's disabled
+        if version >= 5 {
+            let body = reader.pos();
+            let crc = reader.read_u64_le()?;
+            if crc != 0 && crc != crc64::crc64(0, &data[..body]) {
+                return Err(RedisErr::RDBMalformed);
+            }
+        }
+        Ok(rdb)
+    }
+
+    fn read_header(reader: &mut Reader) -> Result<u32> {
+        if reader.read_bytes(5)? != b"REDIS" {
+            return Err(RedisErr::RDBMalformed);
+        }
+        let version = std::str::from_utf8(reader.read_bytes(4)?)
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .ok_or(RedisErr::RDBMalformed)?;
+        if version > RDB_MAX_VERSION {
+            return Err(RedisErr::RDBUnsupported);
+        }
+        Ok(version)
+    }
+
+    // small values are saved in the compact encodings, which are expanded here
+    fn read_value(reader: &mut Reader, value_type: u8) -> Result<Value> {
+        let value = match value_type {
+            RDB_TYPE_STRING => Value::KV(reader.read_string()?),
+            RDB_TYPE_LIST => {
+                let len = reader.read_length()?;
+                let mut list = VecDeque::new();
+                for _ in 0..len {
+                    list.push_back(reader.read_string()?);
+                }
+                Value::List(list)
+            }
+            RDB_TYPE_SET => {
+                let len = reader.read_length()?;
+                let mut set = HashSet::new();
+                for _ in 0..len {
+                    set.insert(reader.read_string()?);
+                }
+                Value::Set(set)
+            }
+            RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+                let len = reader.read_length()?;
+                let mut zset = ZSet::new();
+                for _ in 0..len {
+                    let member = reader.read_string()?;
+                    let score = if value_type == RDB_TYPE_ZSET {
+                        reader.read_string_double()?
+                    } else {
+                        reader.read_f64_le()?
+                    };
+                    zset.zadd(false, false, false, false, false, false, score, member);
+                }
+                Value::ZSet(zset)
+            }
+            RDB_TYPE_HASH => {
+                let len = reader.read_length()?;
+                let mut pairs = Vec::new();
+                for _ in 0..len {
+                    pairs.push(reader.read_string()?);
+                    pairs.push(reader.read_string()?);
+                }
+                hash_from_entries(pairs)?
+            }
+            RDB_TYPE_LIST_ZIPLIST => Value::List(ziplist_entries(&reader.read_string()?)?.into()),
+            RDB_TYPE_SET_INTSET => Value::Set(
+                intset_entries(&reader.read_string()?)?
+                    .into_iter()
+                    .collect(),
+            ),
+            RDB_TYPE_SET_LISTPACK => Value::Set(
+                listpack_entries(&reader.read_string()?)?
+                    .into_iter()
+                    .collect(),
+            ),
+            RDB_TYPE_ZSET_ZIPLIST => zset_from_entries(ziplist_entries(&reader.read_string()?)?)?,
+            RDB_TYPE_ZSET_LISTPACK => zset_from_entries(listpack_entries(&reader.read_string()?)?)?,
+            RDB_TYPE_HASH_ZIPLIST => hash_from_entries(ziplist_entries(&reader.read_string()?)?)?,
+            RDB_TYPE_HASH_LISTPACK => hash_from_entries(listpack_entries(&reader.read_string()?)?)?,
+            // a linked list of ziplists
+            RDB_TYPE_LIST_QUICKLIST => {
+                let len = reader.read_length()?;
+                let mut list = VecDeque::new();
+                for _ in 0..len {
+                    list.extend(ziplist_entries(&reader.read_string()?)?);
+                }
+                Value::List(list)
+            }
+            // a linked list of listpacks, large elements are stored as plain nodes
+            RDB_TYPE_LIST_QUICKLIST_2 => {
+                let len = reader.read_length()?;
+                let mut list = VecDeque::new();
+                for _ in 0..len {
+                    let container = reader.read_length()?;
+                    let node = reader.read_string()?;
+                    if container == QUICKLIST_NODE_CONTAINER_PLAIN {
+                        list.push_back(node);
+                    } else {
+                        list.extend(listpack_entries(&node)?);
+                    }
+                }
+                Value::List(list)
+            }
+            RDB_TYPE_STREAM_LISTPACKS
+            | RDB_TYPE_STREAM_LISTPACKS_2
+            | RDB_TYPE_STREAM_LISTPACKS_3 => read_stream(reader, value_type)?,
+            RDB_TYPE_MODULE_2 => {
+                // the types of the other modules are unknown
+                if reader.read_length()? != BLOOM_FILTER_MODULE_ID {
+                    return Err(RedisErr::RDBUnsupported);
+                }
+                if reader.read_length()? != RDB_MODULE_OPCODE_STRING {
+                    return Err(RedisErr::RDBMalformed);
+                }
+                let bloom = BloomFilter::from_bytes(&reader.read_string()?)
+                    .ok_or(RedisErr::RDBMalformed)?;
+                if reader.read_length()? != RDB_MODULE_OPCODE_EOF {
+                    return Err(RedisErr::RDBMalformed);
+                }
+                Value::BloomFilter(bloom)
+            }
+            _ => return Err(RedisErr::RDBUnsupported),
+        };
+        Ok(value)
+    }
+
+    // write to a temporary file and rename it to the target,
+    // so a crash in the middle won't corrupt the previous snapshot
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let temp = dir.join(format!("temp-{}.rdb", std::process::id()));
+
+        let file = BufWriter::new(File::create(&temp)?);
+        let file = self.write_to(file)?;
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<W> {
+        let mut writer = Crc64Writer::new(writer);
+        self.write_header(&mut writer)?;
+        self.write_functions(&mut writer)?;
+        self.write_content(&mut writer)?;
+        self.write_footer(writer)
     }
 
     /*
@@ -48,59 +354,845 @@ impl RDB {
     $length-encoded-int         # Size of the corresponding expire hash table
     */
 
-    fn write_header(&mut self, writer: &mut Box<dyn Write>) {
-        writer.write(b"REDIS").unwrap();
-        writer.write(b"0003").unwrap();
-        writer.write(b"FA").unwrap();
+    fn write_header(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(b"REDIS")?;
+        writer.write_all(format!("{:04}", self.version).as_bytes())?;
         for (key, value) in self.aux_fields.iter() {
-            writer.write(key.as_bytes()).unwrap();
-            writer.write(value.as_bytes()).unwrap();
+            writer.write_all(&[RDB_OPCODE_AUX])?;
+            write_string(writer, key.as_bytes())?;
+            write_string(writer, value.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /*
+    ----------------------------
+    F5                          # Function library
+    $string-encoded-code        # The code of the library, with its #! metadata line
+    ----------------------------
+    */
+
+    fn write_functions(&self, writer: &mut impl Write) -> Result<()> {
+        for code in self.functions.iter() {
+            writer.write_all(&[RDB_OPCODE_FUNCTION2])?;
+            write_string(writer, code)?;
         }
+        Ok(())
     }
 
     /*
     ----------------------------# Key-Value pair starts
-    FD $unsigned-int            # "expiry time in seconds", followed by 4 byte unsigned int
+    FC $unsigned long           # "expiry time in ms", followed by 8 byte unsigned long
     $value-type                 # 1 byte flag indicating the type of value
     $string-encoded-key         # The key, encoded as a redis string
     $encoded-value              # The value, encoding depends on $value-type
     ----------------------------
     */
 
-    fn write_content(&mut self, writer: &mut Box<dyn Write>) {
-        for (idx, value) in self.dbs.iter().enumerate() {
-            Self::write_db(idx, value, writer);
+    fn write_content(&self, writer: &mut impl Write) -> Result<()> {
+        for (idx, db) in self.dbs.iter().enumerate() {
+            if db.is_empty() {
+                continue;
+            }
+            Self::write_db(idx, db, writer)?;
         }
+        Ok(())
     }
 
-    fn write_db(index: usize, db: &HashMap<String, Value>, writer: &mut Box<dyn Write>) {
-        writer.write(b"FE").unwrap();
-        writer.write(b"00").unwrap();
-        writer.write(b"FB").unwrap();
-        for (key, value) in db.iter() {
-            Self::write_key_value_pair(key, value, writer)
+    fn write_db(index: usize, db: &[Record], writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&[RDB_OPCODE_SELECTDB])?;
+        write_length(writer, index as u64)?;
+        writer.write_all(&[RDB_OPCODE_RESIZEDB])?;
+        write_length(writer, db.len() as u64)?;
+        let expires = db.iter().filter(|(_, _, e)| e.is_some()).count();
+        write_length(writer, expires as u64)?;
+
+        for (key, value, expire_at) in db.iter() {
+            Self::write_key_value_pair(key, value, *expire_at, writer)?;
         }
+        Ok(())
     }
 
-    fn write_key_value_pair(key: &str, value: &Value, writer: &mut Box<dyn Write>) {
-        Self::write_key(key, writer);
-        Self::write_value(value, writer);
+    fn write_key_value_pair(
+        key: &str,
+        value: &Value,
+        expire_at: Option<u64>,
+        writer: &mut impl Write,
+    ) -> Result<()> {
+        let value_type = match value {
+            Value::KV(_) => RDB_TYPE_STRING,
+            Value::List(_) => RDB_TYPE_LIST,
+            Value::Set(_) => RDB_TYPE_SET,
+            Value::Hash(_) => RDB_TYPE_HASH,
+            Value::ZSet(_) => RDB_TYPE_ZSET_2,
+            Value::BloomFilter(_) => RDB_TYPE_MODULE_2,
+            Value::Stream(_) => RDB_TYPE_STREAM_LISTPACKS,
+        };
+
+        if let Some(expire_at) = expire_at {
+            writer.write_all(&[RDB_OPCODE_EXPIRETIME_MS])?;
+            writer.write_all(&expire_at.to_le_bytes())?;
+        }
+        writer.write_all(&[value_type])?;
+        write_string(writer, key.as_bytes())?;
+        Self::write_value(value, writer)
     }
 
-    fn write_key(key: &str, writer: &mut Box<dyn Write>) {
-        writer.write(key.as_bytes()).unwrap();
+    fn write_value(value: &Value, writer: &mut impl Write) -> Result<()> {
+        match value {
+            Value::KV(v) => write_string(writer, v)?,
+            Value::List(list) => {
+                write_length(writer, list.len() as u64)?;
+                for v in list {
+                    write_string(writer, v)?;
+                }
+            }
+            Value::Set(set) => {
+                write_length(writer, set.len() as u64)?;
+                for v in set {
+                    write_string(writer, v)?;
+                }
+            }
+            Value::Hash(map) => {
+                write_length(writer, map.len() as u64)?;
+                for (field, v) in map {
+                    write_string(writer, field.as_bytes())?;
+                    write_string(writer, v)?;
+                }
+            }
+            // score is saved as 8 bytes little endian double
+            Value::ZSet(zset) => {
+                write_length(writer, zset.len() as u64)?;
+                for (member, score) in zset.iter() {
+                    write_string(writer, member)?;
+                    writer.write_all(&score.to_le_bytes())?;
+                }
+            }
+            Value::Stream(stream) => write_stream(writer, stream)?,
+            Value::BloomFilter(bloom) => {
+                write_length(writer, BLOOM_FILTER_MODULE_ID)?;
+                write_length(writer, RDB_MODULE_OPCODE_STRING)?;
+                write_string(writer, &bloom.to_bytes())?;
+                write_length(writer, RDB_MODULE_OPCODE_EOF)?;
+            }
+        }
+        Ok(())
     }
 
-    fn write_value(value: &Value, writer: &mut Box<dyn Write>) {
-        todo!("write value")
+    // EOF opcode followed by 8 bytes little endian crc64 of the whole file
+    fn write_footer<W: Write>(&self, mut writer: Crc64Writer<W>) -> Result<W> {
+        writer.write_all(&[RDB_OPCODE_EOF])?;
+        let crc = writer.crc();
+        let mut writer = writer.into_inner();
+        writer.write_all(&crc.to_le_bytes())?;
+        writer.flush()?;
+        Ok(writer)
     }
+}
 
-    fn write_footer(&mut self, writer: &mut Box<dyn Write>) {
-        writer.write(b"FF").unwrap();
-        todo!("crc32 checksum");
+// the payload of FUNCTION DUMP: the libraries as in the rdb,
+// followed by 2 bytes little endian rdb version and 8 bytes little endian crc64
+pub fn dump_functions(functions: &[Bytes]) -> Result<Vec<u8>> {
+    let mut payload = vec![];
+    for code in functions {
+        payload.push(RDB_OPCODE_FUNCTION2);
+        write_string(&mut payload, code)?;
     }
+    payload.extend_from_slice(&(RDB_VERSION as u16).to_le_bytes());
+    let crc = crc64::crc64(0, &payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    Ok(payload)
 }
 
+// the libraries in the payload of FUNCTION RESTORE
+pub fn restore_functions(payload: &[u8]) -> Result<Vec<Bytes>> {
+    if payload.len() < 10 {
+        return Err(RedisErr::RDBMalformed);
+    }
+    let (body, footer) = payload.split_at(payload.len() - 10);
+    let version = u16::from_le_bytes([footer[0], footer[1]]) as u32;
+    let crc = u64::from_le_bytes(footer[2..].try_into().unwrap());
+    if version > RDB_MAX_VERSION || crc64::crc64(0, &payload[..payload.len() - 8]) != crc {
+        return Err(RedisErr::RDBMalformed);
+    }
+
+    let mut reader = Reader::new(body);
+    let mut functions = vec![];
+    while reader.pos() < body.len() {
+        if reader.read_u8()? != RDB_OPCODE_FUNCTION2 {
+            return Err(RedisErr::RDBMalformed);
+        }
+        functions.push(reader.read_string()?);
+    }
+    Ok(functions)
+}
+
+/*
+the 9 characters of the name, 6 bits each, followed by 10 bits of the encoding version
+*/
+const fn module_id(name: &[u8; 9], encver: u64) -> u64 {
+    const CHARSET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut id = 0;
+    let mut i = 0;
+    while i < name.len() {
+        let mut index = 0;
+        while CHARSET[index] != name[i] {
+            index += 1;
+        }
+        id = (id << 6) | index as u64;
+        i += 1;
+    }
+    (id << 10) | encver
+}
+
+/*
+00|XXXXXX                     # 6 bits length
+01|XXXXXX XXXXXXXX            # 14 bits length, big endian
+10000000 XXXXXXXX * 4         # 32 bits length, big endian
+10000001 XXXXXXXX * 8         # 64 bits length, big endian
+*/
+fn write_length(writer: &mut impl Write, len: u64) -> Result<()> {
+    if len < 1 << 6 {
+        writer.write_all(&[(RDB_6BITLEN << 6) | len as u8])?;
+    } else if len < 1 << 14 {
+        writer.write_all(&[(RDB_14BITLEN << 6) | (len >> 8) as u8, len as u8])?;
+    } else if len <= u32::MAX as u64 {
+        writer.write_all(&[RDB_32BITLEN])?;
+        writer.write_all(&(len as u32).to_be_bytes())?;
+    } else {
+        writer.write_all(&[RDB_64BITLEN])?;
+        writer.write_all(&len.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+// length prefixed string
+fn write_string(writer: &mut impl Write, s: &[u8]) -> Result<()> {
+    write_length(writer, s.len() as u64)?;
+    writer.write_all(s)?;
+    Ok(())
+}
+
+/*
+<total_bytes u32><num_elements u16><entry>...<entry><0xFF>
+entry: <encoding><data><backlen>, strings are written as they are,
+integers in 7 bits if they fit or else in 8 bytes little endian
+*/
+#[derive(Default)]
+struct Listpack {
+    entries: Vec<u8>,
+    len: usize,
+}
+
+impl Listpack {
+    fn push_string(&mut self, s: &[u8]) {
+        let start = self.entries.len();
+        match s.len() {
+            len @ 0..=63 => self.entries.push(0x80 | len as u8),
+            len @ 64..=4095 => self
+                .entries
+                .extend_from_slice(&[0xe0 | (len >> 8) as u8, len as u8]),
+            len => {
+                self.entries.push(0xf0);
+                self.entries.extend_from_slice(&(len as u32).to_le_bytes());
+            }
+        }
+        self.entries.extend_from_slice(s);
+        self.push_backlen(start);
+    }
+
+    fn push_integer(&mut self, i: i64) {
+        let start = self.entries.len();
+        match i {
+            0..=127 => self.entries.push(i as u8),
+            _ => {
+                self.entries.push(0xf4);
+                self.entries.extend_from_slice(&i.to_le_bytes());
+            }
+        }
+        self.push_backlen(start);
+    }
+
+    // the length of the entry in 7 bits groups, the most significant first,
+    // every group but the first has the high bit set
+    fn push_backlen(&mut self, start: usize) {
+        let len = self.entries.len() - start;
+        let size = match len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        for i in (0..size).rev() {
+            let group = ((len >> (7 * i)) & 0x7f) as u8;
+            self.entries
+                .push(if i == size - 1 { group } else { group | 0x80 });
+        }
+        self.len += 1;
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let total = 4 + 2 + self.entries.len() + 1;
+        let mut data = Vec::with_capacity(total);
+        data.extend_from_slice(&(total as u32).to_le_bytes());
+        // the number is unknown beyond u16
+        data.extend_from_slice(&(self.len.min(u16::MAX as usize) as u16).to_le_bytes());
+        data.extend_from_slice(&self.entries);
+        data.push(0xff);
+        data
+    }
+}
+
+/*
+$length-encoded-int         # Number of listpacks
+$string-encoded-id          # The id of the first entry in the listpack, 8 bytes big endian ms and seq
+$string-encoded-listpack    # The entries, see write_stream_node
+... repeated for each listpack
+$length-encoded-int         # Number of entries
+$length-encoded-int * 2     # Last id, ms and seq
+$length-encoded-int         # Number of consumer groups
+$group                      # Each of the groups, see write_stream_group
+*/
+fn write_stream(writer: &mut impl Write, stream: &Stream) -> Result<()> {
+    let entries = stream.iter().collect::<Vec<_>>();
+    let nodes = entries.chunks(STREAM_NODE_MAX_ENTRIES);
+    write_length(writer, nodes.len() as u64)?;
+    for node in nodes {
+        write_string(writer, &stream_id_bytes(node[0].0))?;
+        write_string(writer, &stream_node(node))?;
+    }
+    write_length(writer, stream.len() as u64)?;
+    write_length(writer, stream.last_id().ms)?;
+    write_length(writer, stream.last_id().seq)?;
+    write_length(writer, stream.groups().count() as u64)?;
+    for (name, group) in stream.groups() {
+        write_stream_group(writer, name, group)?;
+    }
+    Ok(())
+}
+
+/*
+$string-encoded-name        # Name of the group
+$length-encoded-int * 2     # Last delivered id, ms and seq
+$length-encoded-int         # Number of pending entries
+$id $time $length-encoded-int
+                            # Each pending entry: 16 bytes id, 8 bytes little endian
+                            # delivery time and the delivery count
+$length-encoded-int         # Number of consumers
+$string-encoded-name $time $length-encoded-int $id...
+                            # Each consumer: name, seen time, its pending entries
+*/
+fn write_stream_group(writer: &mut impl Write, name: &str, group: &ConsumerGroup) -> Result<()> {
+    write_string(writer, name.as_bytes())?;
+    write_length(writer, group.last_id().ms)?;
+    write_length(writer, group.last_id().seq)?;
+    write_length(writer, group.pending_len() as u64)?;
+    for (id, entry) in group.pending() {
+        writer.write_all(&stream_id_bytes(id))?;
+        writer.write_all(&entry.delivery_time.to_le_bytes())?;
+        write_length(writer, entry.delivery_count)?;
+    }
+    write_length(writer, group.consumers().count() as u64)?;
+    for (name, consumer) in group.consumers() {
+        write_string(writer, name.as_bytes())?;
+        writer.write_all(&consumer.seen_time.to_le_bytes())?;
+        write_length(writer, consumer.pending_len() as u64)?;
+        for id in consumer.pending() {
+            writer.write_all(&stream_id_bytes(id))?;
+        }
+    }
+    Ok(())
+}
+
+/*
+the master entry, the fields of the first entry, shared by the entries with the same fields:
+<count><deleted><num-fields><field>...<field><0>
+then the entries, ids relative to the id of the first entry:
+<flags><ms-diff><seq-diff>[<num-fields>]<field><value>...<field><value><lp-count>
+with SAMEFIELDS in flags, the field names and their number are left out
+*/
+fn stream_node(entries: &[(&StreamId, &StreamFields)]) -> Vec<u8> {
+    let (master_id, master_fields) = entries[0];
+    let mut lp = Listpack::default();
+    lp.push_integer(entries.len() as i64);
+    lp.push_integer(0);
+    lp.push_integer(master_fields.len() as i64);
+    for (field, _) in master_fields {
+        lp.push_string(field);
+    }
+    lp.push_integer(0);
+
+    for &(id, fields) in entries {
+        let same_fields = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(master_fields)
+                .all(|((field, _), (master, _))| field == master);
+        let flags = if same_fields {
+            STREAM_ITEM_FLAG_SAMEFIELDS
+        } else {
+            0
+        };
+        lp.push_integer(flags);
+        lp.push_integer(id.ms.wrapping_sub(master_id.ms) as i64);
+        lp.push_integer(id.seq.wrapping_sub(master_id.seq) as i64);
+        if same_fields {
+            for (_, value) in fields {
+                lp.push_string(value);
+            }
+            lp.push_integer(fields.len() as i64 + 3);
+        } else {
+            lp.push_integer(fields.len() as i64);
+            for (field, value) in fields {
+                lp.push_string(field);
+                lp.push_string(value);
+            }
+            lp.push_integer(fields.len() as i64 * 2 + 4);
+        }
+    }
+    lp.into_bytes()
+}
+
+fn read_stream(reader: &mut Reader, value_type: u8) -> Result<Value> {
+    let mut stream = Stream::new();
+    let nodes = reader.read_length()?;
+    for _ in 0..nodes {
+        let master_id = stream_id_from_bytes(&reader.read_string()?)?;
+        let entries = listpack_entries(&reader.read_string()?)?;
+        read_stream_node(&mut stream, master_id, entries)?;
+    }
+    reader.read_length()?; // number of entries
+    let last_id = StreamId::new(reader.read_length()?, reader.read_length()?);
+    stream.set_last_id(last_id);
+    if value_type >= RDB_TYPE_STREAM_LISTPACKS_2 {
+        // first id, max deleted id and the number of entries ever added
+        for _ in 0..5 {
+            reader.read_length()?;
+        }
+    }
+    for _ in 0..reader.read_length()? {
+        read_stream_group(reader, value_type, &mut stream)?;
+    }
+    Ok(Value::Stream(stream))
+}
+
+// the pending entries are listed by the group, then by the consumers they are pending for
+fn read_stream_group(reader: &mut Reader, value_type: u8, stream: &mut Stream) -> Result<()> {
+    let name =
+        String::from_utf8(reader.read_string()?.to_vec()).map_err(|_| RedisErr::RDBUnsupported)?;
+    let last_id = StreamId::new(reader.read_length()?, reader.read_length()?);
+    if value_type >= RDB_TYPE_STREAM_LISTPACKS_2 {
+        reader.read_length()?; // entries read
+    }
+    if !stream.create_group(&name, last_id) {
+        return Err(RedisErr::RDBMalformed);
+    }
+    let group = stream.group_mut(&name).unwrap();
+
+    let mut pending = HashMap::new();
+    for _ in 0..reader.read_length()? {
+        let id = stream_id_from_bytes(reader.read_bytes(16)?)?;
+        let delivery_time = reader.read_u64_le()?;
+        pending.insert(id, (delivery_time, reader.read_length()?));
+    }
+    for _ in 0..reader.read_length()? {
+        let consumer = String::from_utf8(reader.read_string()?.to_vec())
+            .map_err(|_| RedisErr::RDBUnsupported)?;
+        let seen_time = reader.read_u64_le()?;
+        // saved since redis 7.2, it's -1 if the consumer never got an entry
+        let active_time = if value_type >= RDB_TYPE_STREAM_LISTPACKS_3 {
+            Some(reader.read_u64_le()?).filter(|time| (*time as i64) >= 0)
+        } else {
+            Some(seen_time)
+        };
+        if !group.create_consumer(&consumer, seen_time) {
+            return Err(RedisErr::RDBMalformed);
+        }
+        group.consumer_mut(&consumer).unwrap().active_time = active_time;
+        for _ in 0..reader.read_length()? {
+            let id = stream_id_from_bytes(reader.read_bytes(16)?)?;
+            let (delivery_time, delivery_count) =
+                pending.remove(&id).ok_or(RedisErr::RDBMalformed)?;
+            let entry = PendingEntry {
+                consumer: consumer.clone(),
+                delivery_time,
+                delivery_count,
+            };
+            group.restore_pending(id, entry);
+        }
+    }
+    // every pending entry belongs to a consumer
+    if !pending.is_empty() {
+        return Err(RedisErr::RDBMalformed);
+    }
+    Ok(())
+}
+
+// the entries of a listpack, deleted ones are skipped
+fn read_stream_node(stream: &mut Stream, master_id: StreamId, entries: Vec<Bytes>) -> Result<()> {
+    let mut iter = entries.into_iter();
+    let count = lp_integer(&mut iter)?;
+    let deleted = lp_integer(&mut iter)?;
+    let master_fields = (0..lp_integer(&mut iter)?)
+        .map(|_| lp_entry(&mut iter))
+        .collect::<Result<Vec<_>>>()?;
+    lp_integer(&mut iter)?; // end of the master entry
+
+    for _ in 0..count + deleted {
+        let flags = lp_integer(&mut iter)?;
+        let id = StreamId::new(
+            master_id.ms.wrapping_add(lp_integer(&mut iter)? as u64),
+            master_id.seq.wrapping_add(lp_integer(&mut iter)? as u64),
+        );
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), lp_entry(&mut iter)?)))
+                .collect::<Result<StreamFields>>()?
+        } else {
+            (0..lp_integer(&mut iter)?)
+                .map(|_| Ok((lp_entry(&mut iter)?, lp_entry(&mut iter)?)))
+                .collect::<Result<StreamFields>>()?
+        };
+        lp_integer(&mut iter)?; // lp-count
+        if flags & STREAM_ITEM_FLAG_DELETED != 0 {
+            continue;
+        }
+        if id <= stream.last_id() {
+            return Err(RedisErr::RDBMalformed);
+        }
+        stream.add(id, fields);
+    }
+    Ok(())
+}
+
+fn lp_entry(iter: &mut impl Iterator<Item = Bytes>) -> Result<Bytes> {
+    iter.next().ok_or(RedisErr::RDBMalformed)
+}
+
+fn lp_integer(iter: &mut impl Iterator<Item = Bytes>) -> Result<i64> {
+    std::str::from_utf8(&lp_entry(iter)?)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(RedisErr::RDBMalformed)
+}
+
+// 8 bytes big endian ms followed by 8 bytes big endian seq
+fn stream_id_bytes(id: &StreamId) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&id.ms.to_be_bytes());
+    bytes[8..].copy_from_slice(&id.seq.to_be_bytes());
+    bytes
+}
+
+fn stream_id_from_bytes(bytes: &[u8]) -> Result<StreamId> {
+    let bytes: [u8; 16] = bytes.try_into().map_err(|_| RedisErr::RDBMalformed)?;
+    Ok(StreamId::new(
+        u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+        u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+    ))
+}
+
+// field and value are stored one after another
+fn hash_from_entries(entries: Vec<Bytes>) -> Result<Value> {
+    if !entries.len().is_multiple_of(2) {
+        return Err(RedisErr::RDBMalformed);
+    }
+    let mut map = HashMap::new();
+    let mut iter = entries.into_iter();
+    while let (Some(field), Some(value)) = (iter.next(), iter.next()) {
+        let field = String::from_utf8(field.to_vec()).map_err(|_| RedisErr::RDBUnsupported)?;
+        map.insert(field, value);
+    }
+    Ok(Value::Hash(map))
+}
+
+// member and score are stored one after another, score as a string
+fn zset_from_entries(entries: Vec<Bytes>) -> Result<Value> {
+    if !entries.len().is_multiple_of(2) {
+        return Err(RedisErr::RDBMalformed);
+    }
+    let mut zset = ZSet::new();
+    let mut iter = entries.into_iter();
+    while let (Some(member), Some(score)) = (iter.next(), iter.next()) {
+        let score = std::str::from_utf8(&score)
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .ok_or(RedisErr::RDBMalformed)?;
+        zset.zadd(false, false, false, false, false, false, score, member);
+    }
+    Ok(Value::ZSet(zset))
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+
+    use bytes::Bytes;
+
+    #[test]
+    fn test_write_length() {
+        let cases: Vec<(u64, Vec<u8>)> = vec![
+            (10, vec![0x0a]),
+            (700, vec![0x42, 0xbc]),
+            (17000, vec![0x80, 0x00, 0x00, 0x42, 0x68]),
+            (
+                1 << 33,
+                vec![0x81, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00],
+            ),
+        ];
+        for (len, expected) in cases {
+            let mut buf = vec![];
+            write_length(&mut buf, len).unwrap();
+            assert_eq!(buf, expected);
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let rdb = RDB::new(vec![vec![(
+            "key".to_string(),
+            Value::KV(Bytes::from_static(b"value")),
+            Some(1700000000000),
+        )]]);
+        let data = rdb.write_to(Vec::new()).unwrap();
+
+        assert!(data.starts_with(b"REDIS0009"));
+        let mut expected = vec![RDB_OPCODE_SELECTDB, 0, RDB_OPCODE_RESIZEDB, 1, 1];
+        expected.push(RDB_OPCODE_EXPIRETIME_MS);
+        expected.extend_from_slice(&1700000000000u64.to_le_bytes());
+        expected.extend_from_slice(b"\x00\x03key\x05value\xff");
+        let body = &data[..data.len() - 8];
+        assert!(body.ends_with(&expected));
+
+        // checksum covers everything before it
+        let crc = u64::from_le_bytes(data[data.len() - 8..].try_into().unwrap());
+        assert_eq!(crc, crc64::crc64(0, body));
+    }
+
+    #[test]
+    fn test_functions() {
+        let functions = vec![
+            Bytes::from("#!lua name=lib1\nredis.register_function('f1', function() end)"),
+            Bytes::from("#!lua name=lib2\nredis.register_function('f2', function() end)"),
+        ];
+        let data = RDB::new(vec![])
+            .with_functions(functions.clone())
+            .write_to(Vec::new())
+            .unwrap();
+        assert_eq!(RDB::load_from(&data).unwrap().functions(), functions);
+
+        let payload = dump_functions(&functions).unwrap();
+        assert_eq!(restore_functions(&payload).unwrap(), functions);
+        let mut corrupted = payload.clone();
+        corrupted[3] ^= 1;
+        assert!(restore_functions(&corrupted).is_err());
+        assert!(restore_functions(b"short").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let mut zset = ZSet::new();
+        zset.zadd(
+            false,
+            false,
+            false,
+            false,
+            false,
+            false,
+            1.5,
+            Bytes::from("a"),
+        );
+        let rdb = RDB::new(vec![vec![
+            (
+                "key".to_string(),
+                Value::KV(Bytes::from_static(b"value")),
+                Some(1700000000000),
+            ),
+            (
+                "list".to_string(),
+                Value::List(VecDeque::from(vec![Bytes::from("a"), Bytes::from("b")])),
+                None,
+            ),
+            ("zset".to_string(), Value::ZSet(zset), None),
+        ]]);
+        let data = rdb.write_to(Vec::new()).unwrap();
+
+        let mut dbs = RDB::load_from(&data).unwrap().into_dbs();
+        assert_eq!(dbs.len(), 1);
+        let db = dbs.remove(0);
+        assert_eq!(db.len(), 3);
+        assert_eq!(db[0].0, "key");
+        assert_eq!(db[0].1.as_kv_ref().unwrap(), &Bytes::from("value"));
+        assert_eq!(db[0].2, Some(1700000000000));
+        assert_eq!(db[1].1.as_list_ref().unwrap().len(), 2);
+        let (member, score) = db[2].1.as_zset_ref().unwrap().iter().next().unwrap();
+        assert_eq!((member.clone(), score), (Bytes::from("a"), 1.5));
+
+        // corrupted checksum
+        let mut data = data;
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        assert_eq!(RDB::load_from(&data).err(), Some(RedisErr::RDBMalformed));
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut bloom = BloomFilter::new();
+        bloom.add("a");
+        let rdb = RDB::new(vec![vec![(
+            "bf".to_string(),
+            Value::BloomFilter(bloom),
+            None,
+        )]]);
+        let data = rdb.write_to(Vec::new()).unwrap();
+
+        let mut dbs = RDB::load_from(&data).unwrap().into_dbs();
+        let (key, value, _) = dbs[0].remove(0);
+        assert_eq!(key, "bf");
+        let bloom = value.as_bloomfilter_ref().unwrap();
+        assert!(bloom.contains("a"));
+        assert!(!bloom.contains("b"));
+
+        // the value of another module
+        let mut data = vec![];
+        write_length(&mut data, module_id(b"MBbloom--", 4)).unwrap();
+        assert_eq!(
+            RDB::read_value(&mut Reader::new(&data), RDB_TYPE_MODULE_2).err(),
+            Some(RedisErr::RDBUnsupported)
+        );
+    }
+
+    #[test]
+    fn test_stream() {
+        let fields = |pairs: &[(&str, &str)]| -> StreamFields {
+            pairs
+                .iter()
+                .map(|(f, v)| (Bytes::from(f.to_string()), Bytes::from(v.to_string())))
+                .collect()
+        };
+        let mut stream = Stream::new();
+        for seq in 1..=150 {
+            stream.add(StreamId::new(1, seq), fields(&[("a", &seq.to_string())]));
+        }
+        let long = "x".repeat(5000);
+        stream.add(StreamId::new(2, 0), fields(&[("b", &long), ("c", "")]));
+        stream.remove(&StreamId::new(1, 7));
+        stream.set_last_id(StreamId::new(3, 0));
+        // two entries pending for alice, bob never got any
+        stream.create_group("g", StreamId::MIN);
+        stream.read_group("g", "alice", Some(2), false, 1000);
+        stream.read_group("g", "bob", Some(0), false, 2000);
+
+        // the first entry sets the master fields, the others share them
+        let entries = stream.iter().take(2).collect::<Vec<_>>();
+        let node = listpack_entries(&stream_node(&entries)).unwrap();
+        let expected = [
+            "2", "0", "1", "a", "0", "2", "0", "0", "1", "4", "2", "0", "1", "2", "4",
+        ];
+        assert_eq!(node, expected.map(Bytes::from));
+
+        let data = RDB::new(vec![vec![("s".to_string(), Value::Stream(stream), None)]])
+            .write_to(Vec::new())
+            .unwrap();
+        let db = RDB::load_from(&data).unwrap().into_dbs().remove(0);
+        let Value::Stream(loaded) = &db[0].1 else {
+            panic!("unexpected value {:?}", db[0].1);
+        };
+        assert_eq!(loaded.len(), 150);
+        assert_eq!(loaded.last_id(), StreamId::new(3, 0));
+        assert!(loaded
+            .range(StreamId::new(1, 7), StreamId::new(1, 7))
+            .next()
+            .is_none());
+        let (id, last) = loaded.last_entry().unwrap();
+        assert_eq!(*id, StreamId::new(2, 0));
+        assert_eq!(last, &fields(&[("b", &long), ("c", "")]));
+
+        let group = loaded.group("g").unwrap();
+        assert_eq!(group.last_id(), StreamId::new(1, 2));
+        let pending = group.pending().map(|(id, entry)| (*id, entry.clone()));
+        let entry = |delivery_time| PendingEntry {
+            consumer: "alice".to_string(),
+            delivery_time,
+            delivery_count: 1,
+        };
+        assert_eq!(
+            pending.collect::<Vec<_>>(),
+            vec![
+                (StreamId::new(1, 1), entry(1000)),
+                (StreamId::new(1, 2), entry(1000))
+            ]
+        );
+        let alice = group.consumer("alice").unwrap();
+        assert_eq!((alice.seen_time, alice.pending_len()), (1000, 2));
+        assert_eq!(group.consumer("bob").unwrap().seen_time, 2000);
+    }
+
+    // compact encodings written by redis 7
+    #[test]
+    fn test_load_compact_encodings() {
+        let mut data = b"REDIS0011".to_vec();
+        data.extend_from_slice(b"\xfa\x09redis-ver\x057.2.0");
+        data.extend_from_slice(b"\xfe\x00\xfb\x04\x01");
+        // hash in a listpack: f1 => v1, f2 => 7
+        data.extend_from_slice(b"\x10\x01h");
+        let lp = b"\x00\x00\x00\x00\x04\x00\x82f1\x03\x82v1\x03\x82f2\x03\x07\x01\xff";
+        data.push(lp.len() as u8);
+        data.extend_from_slice(lp);
+        // set of integers
+        data.extend_from_slice(b"\x0b\x01s");
+        let intset = b"\x02\x00\x00\x00\x02\x00\x00\x00\x01\x00\x02\x00";
+        data.push(intset.len() as u8);
+        data.extend_from_slice(intset);
+        // quicklist with a packed node and a plain node, expires in seconds
+        data.extend_from_slice(b"\xfd\xff\xff\xff\x7f\x12\x01l\x02");
+        let lp = b"\x00\x00\x00\x00\x02\x00\x81a\x02\x0c\x01\xff";
+        data.push(0x02);
+        data.push(lp.len() as u8);
+        data.extend_from_slice(lp);
+        data.extend_from_slice(b"\x01\x03big");
+        // zset in a ziplist: m => 2.5
+        data.extend_from_slice(b"\x0c\x01z");
+        let zl = b"\x00\x00\x00\x00\x00\x00\x00\x00\x02\x00\x00\x01m\x03\x032.5\xff";
+        data.push(zl.len() as u8);
+        data.extend_from_slice(zl);
+        // lzf compressed string
+        data.extend_from_slice(b"\x00\x01k\xc3\x07\x0c\x02abc\xe0\x00\x02");
+        // checksum disabled
+        data.push(RDB_OPCODE_EOF);
+        data.extend_from_slice(&[0; 8]);
+
+        let rdb = RDB::load_from(&data).unwrap();
+        assert_eq!(rdb.version, 11);
+        assert_eq!(
+            rdb.aux_fields,
+            vec![("redis-ver".to_string(), "7.2.0".to_string())]
+        );
+        let db = rdb.into_dbs().remove(0);
+        assert_eq!(db.len(), 5);
+
+        let hash = db[0].1.as_hash_ref().unwrap();
+        assert_eq!(hash.get("f1"), Some(&Bytes::from("v1")));
+        assert_eq!(hash.get("f2"), Some(&Bytes::from("7")));
+
+        let set = db[1].1.as_set_ref().unwrap();
+        assert!(set.contains(&Bytes::from("1")) && set.contains(&Bytes::from("2")));
+
+        assert_eq!(db[2].2, Some(0x7fffffff * 1000));
+        assert_eq!(
+            db[2].1.as_list_ref().unwrap(),
+            &VecDeque::from(vec![
+                Bytes::from("a"),
+                Bytes::from("12"),
+                Bytes::from("big")
+            ])
+        );
+
+        let (member, score) = db[3].1.as_zset_ref().unwrap().iter().next().unwrap();
+        assert_eq!((member.clone(), score), (Bytes::from("m"), 2.5));
+
+        assert_eq!(db[4].1.as_kv_ref().unwrap(), &Bytes::from("abcabcabcabc"));
+    }
 }
@@ -0,0 +1,422 @@
+//! decoding the building blocks of the rdb file
+//! length and string encodings, lzf compression,
+//! and the compact encodings redis uses for small values: ziplist, listpack and intset
+
+use crate::{RedisErr, Result};
+
+use bytes::Bytes;
+
+// special string encodings, the lower 6 bits of a length byte starting with 11
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+// length or a special encoding
+pub enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(RedisErr::RDBMalformed);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16_le(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32_le(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64_le(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_f64_le(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    /*
+    00|XXXXXX                     # 6 bits length
+    01|XXXXXX XXXXXXXX            # 14 bits length, big endian
+    10000000 XXXXXXXX * 4         # 32 bits length, big endian
+    10000001 XXXXXXXX * 8         # 64 bits length, big endian
+    11|XXXXXX                     # special encoding in the lower 6 bits
+    */
+    pub fn read_length_or_encoding(&mut self) -> Result<Length> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0 => Ok(Length::Len((first & 0x3f) as u64)),
+            1 => {
+                let next = self.read_u8()?;
+                Ok(Length::Len((((first & 0x3f) as u64) << 8) | next as u64))
+            }
+            2 => match first {
+                0x80 => Ok(Length::Len(
+                    u32::from_be_bytes(self.read_bytes(4)?.try_into().unwrap()) as u64,
+                )),
+                0x81 => Ok(Length::Len(u64::from_be_bytes(
+                    self.read_bytes(8)?.try_into().unwrap(),
+                ))),
+                _ => Err(RedisErr::RDBMalformed),
+            },
+            _ => Ok(Length::Encoded(first & 0x3f)),
+        }
+    }
+
+    pub fn read_length(&mut self) -> Result<u64> {
+        match self.read_length_or_encoding()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err(RedisErr::RDBMalformed),
+        }
+    }
+
+    // strings may be raw, integers or lzf compressed
+    pub fn read_string(&mut self) -> Result<Bytes> {
+        match self.read_length_or_encoding()? {
+            Length::Len(len) => Ok(Bytes::copy_from_slice(self.read_bytes(len as usize)?)),
+            Length::Encoded(RDB_ENC_INT8) => Ok(int_to_bytes(self.read_u8()? as i8 as i64)),
+            Length::Encoded(RDB_ENC_INT16) => Ok(int_to_bytes(self.read_u16_le()? as i16 as i64)),
+            Length::Encoded(RDB_ENC_INT32) => Ok(int_to_bytes(self.read_u32_le()? as i32 as i64)),
+            Length::Encoded(RDB_ENC_LZF) => {
+                let compressed_len = self.read_length()? as usize;
+                let len = self.read_length()? as usize;
+                let compressed = self.read_bytes(compressed_len)?;
+                Ok(Bytes::from(lzf_decompress(compressed, len)?))
+            }
+            Length::Encoded(_) => Err(RedisErr::RDBMalformed),
+        }
+    }
+
+    // double of the old zset type, a length byte followed by the ascii number
+    // 253, 254 and 255 are nan, +inf and -inf
+    pub fn read_string_double(&mut self) -> Result<f64> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let s = std::str::from_utf8(self.read_bytes(len as usize)?)
+                    .map_err(|_| RedisErr::RDBMalformed)?;
+                s.parse().map_err(|_| RedisErr::RDBMalformed)
+            }
+        }
+    }
+}
+
+#[inline]
+fn int_to_bytes(i: i64) -> Bytes {
+    Bytes::from(i.to_string())
+}
+
+/*
+lzf compressed data is a sequence of
+000LLLLL <L+1 literal bytes>               # literal run
+LLLooooo oooooooo                          # back reference of L+2 bytes, offset o+1
+111ooooo LLLLLLLL oooooooo                 # back reference of L+9 bytes, offset o+1
+*/
+pub fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    // the length is read from the file, trust it only as far as the input goes
+    let mut output = Vec::with_capacity(len.min(input.len()));
+    let mut ip = 0;
+    while ip < input.len() {
+        let ctrl = input[ip] as usize;
+        ip += 1;
+        if ctrl < 32 {
+            let run = ctrl + 1;
+            if ip + run > input.len() || output.len() + run > len {
+                return Err(RedisErr::RDBMalformed);
+            }
+            output.extend_from_slice(&input[ip..ip + run]);
+            ip += run;
+        } else {
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(ip).ok_or(RedisErr::RDBMalformed)? as usize;
+                ip += 1;
+            }
+            let offset =
+                ((ctrl & 0x1f) << 8) + *input.get(ip).ok_or(RedisErr::RDBMalformed)? as usize + 1;
+            ip += 1;
+            if offset > output.len() || output.len() + run + 2 > len {
+                return Err(RedisErr::RDBMalformed);
+            }
+            // the reference may overlap with the bytes being copied
+            let start = output.len() - offset;
+            for i in 0..run + 2 {
+                output.push(output[start + i]);
+            }
+        }
+    }
+    if output.len() != len {
+        return Err(RedisErr::RDBMalformed);
+    }
+    Ok(output)
+}
+
+/*
+<zlbytes u32><zltail u32><zllen u16><entry>...<entry><0xFF>
+entry: <prevlen 1 or 5 bytes><encoding><data>
+*/
+pub fn ziplist_entries(data: &[u8]) -> Result<Vec<Bytes>> {
+    let mut reader = Reader::new(data);
+    reader.read_u32_le()?; // zlbytes
+    reader.read_u32_le()?; // zltail
+    reader.read_u16_le()?; // zllen, not reliable for more than 65535 entries
+    let mut entries = Vec::new();
+    loop {
+        let prevlen = reader.read_u8()?;
+        if prevlen == 0xff {
+            break;
+        }
+        if prevlen == 0xfe {
+            reader.read_u32_le()?;
+        }
+
+        let encoding = reader.read_u8()?;
+        let entry = match encoding >> 6 {
+            0 => Bytes::copy_from_slice(reader.read_bytes((encoding & 0x3f) as usize)?),
+            1 => {
+                let len = (((encoding & 0x3f) as usize) << 8) | reader.read_u8()? as usize;
+                Bytes::copy_from_slice(reader.read_bytes(len)?)
+            }
+            2 => {
+                let len = u32::from_be_bytes(reader.read_bytes(4)?.try_into().unwrap()) as usize;
+                Bytes::copy_from_slice(reader.read_bytes(len)?)
+            }
+            _ => {
+                let value = match encoding {
+                    0xc0 => reader.read_u16_le()? as i16 as i64,
+                    0xd0 => reader.read_u32_le()? as i32 as i64,
+                    0xe0 => reader.read_u64_le()? as i64,
+                    0xf0 => read_i24_le(&mut reader)?,
+                    0xfe => reader.read_u8()? as i8 as i64,
+                    // 1111xxxx, immediate value between 0 and 12
+                    0xf1..=0xfd => (encoding & 0x0f) as i64 - 1,
+                    _ => return Err(RedisErr::RDBMalformed),
+                };
+                int_to_bytes(value)
+            }
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[inline]
+fn read_i24_le(reader: &mut Reader) -> Result<i64> {
+    let b = reader.read_bytes(3)?;
+    // shift into the high bytes to keep the sign
+    Ok((i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as i64)
+}
+
+/*
+<total_bytes u32><num_elements u16><entry>...<entry><0xFF>
+entry: <encoding><data><backlen>, backlen is the length of encoding and data
+*/
+pub fn listpack_entries(data: &[u8]) -> Result<Vec<Bytes>> {
+    let mut reader = Reader::new(data);
+    reader.read_u32_le()?; // total bytes
+    reader.read_u16_le()?; // num elements, not reliable for more than 65535 entries
+    let mut entries = Vec::new();
+    loop {
+        let start = reader.pos();
+        let encoding = reader.read_u8()?;
+        let entry = match encoding {
+            0xff => break,
+            // 0xxxxxxx, 7 bits unsigned integer
+            0x00..=0x7f => int_to_bytes(encoding as i64),
+            // 10xxxxxx, string up to 63 bytes
+            0x80..=0xbf => Bytes::copy_from_slice(reader.read_bytes((encoding & 0x3f) as usize)?),
+            // 110xxxxx yyyyyyyy, 13 bits signed integer
+            0xc0..=0xdf => {
+                let value = (((encoding & 0x1f) as i64) << 8) | reader.read_u8()? as i64;
+                int_to_bytes(if value >= 1 << 12 {
+                    value - (1 << 13)
+                } else {
+                    value
+                })
+            }
+            // 1110xxxx yyyyyyyy, string up to 4095 bytes
+            0xe0..=0xef => {
+                let len = (((encoding & 0x0f) as usize) << 8) | reader.read_u8()? as usize;
+                Bytes::copy_from_slice(reader.read_bytes(len)?)
+            }
+            0xf0 => {
+                let len = reader.read_u32_le()? as usize;
+                Bytes::copy_from_slice(reader.read_bytes(len)?)
+            }
+            0xf1 => int_to_bytes(reader.read_u16_le()? as i16 as i64),
+            0xf2 => int_to_bytes(read_i24_le(&mut reader)?),
+            0xf3 => int_to_bytes(reader.read_u32_le()? as i32 as i64),
+            0xf4 => int_to_bytes(reader.read_u64_le()? as i64),
+            _ => return Err(RedisErr::RDBMalformed),
+        };
+        entries.push(entry);
+
+        // skip the backlen
+        let entry_len = reader.pos() - start;
+        let backlen_size = match entry_len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        reader.read_bytes(backlen_size)?;
+    }
+    Ok(entries)
+}
+
+/*
+<encoding u32><length u32><contents>
+contents are integers of `encoding` bytes, little endian
+*/
+pub fn intset_entries(data: &[u8]) -> Result<Vec<Bytes>> {
+    let mut reader = Reader::new(data);
+    let encoding = reader.read_u32_le()?;
+    let len = reader.read_u32_le()?;
+    // each entry takes at least 2 bytes
+    let mut entries = Vec::with_capacity((len as usize).min(data.len() / 2));
+    for _ in 0..len {
+        let value = match encoding {
+            2 => reader.read_u16_le()? as i16 as i64,
+            4 => reader.read_u32_le()? as i32 as i64,
+            8 => reader.read_u64_le()? as i64,
+            _ => return Err(RedisErr::RDBMalformed),
+        };
+        entries.push(int_to_bytes(value));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_string() {
+        // raw, int8, int16, int32
+        let data = b"\x03foo\xc0\xfb\xc1\x39\x30\xc2\x87\xd6\x12\x00";
+        let mut reader = Reader::new(data);
+        assert_eq!(reader.read_string().unwrap(), Bytes::from_static(b"foo"));
+        assert_eq!(reader.read_string().unwrap(), Bytes::from_static(b"-5"));
+        assert_eq!(reader.read_string().unwrap(), Bytes::from_static(b"12345"));
+        assert_eq!(
+            reader.read_string().unwrap(),
+            Bytes::from_static(b"1234567")
+        );
+        assert_eq!(reader.pos(), data.len());
+    }
+
+    #[test]
+    fn test_lzf() {
+        // literal "abc" and a back reference of 9 bytes at offset 3
+        let data = b"\xc3\x07\x0c\x02abc\xe0\x00\x02";
+        let mut reader = Reader::new(data);
+        assert_eq!(
+            reader.read_string().unwrap(),
+            Bytes::from_static(b"abcabcabcabc")
+        );
+
+        // a short back reference
+        assert_eq!(
+            lzf_decompress(b"\x01ab\x20\x01", 5).unwrap(),
+            b"ababa".to_vec()
+        );
+        assert!(lzf_decompress(b"\x01ab\x20\x05", 5).is_err());
+        // the length in the file is not allocated up front
+        assert!(lzf_decompress(b"\x01ab\x20\x01", usize::MAX).is_err());
+        // nor exceeded
+        assert!(lzf_decompress(b"\x01ab\x20\x01", 4).is_err());
+    }
+
+    #[test]
+    fn test_ziplist() {
+        // "a", 5, -2, 300, "hello"
+        let data = b"\x00\x00\x00\x00\x00\x00\x00\x00\x05\x00\
+            \x00\x01a\
+            \x03\xf6\
+            \x02\xfe\xfe\
+            \x03\xc0\x2c\x01\
+            \x04\x05hello\
+            \xff";
+        let entries = ziplist_entries(data).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Bytes::from_static(b"a"),
+                Bytes::from_static(b"5"),
+                Bytes::from_static(b"-2"),
+                Bytes::from_static(b"300"),
+                Bytes::from_static(b"hello"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listpack() {
+        // "a", 5, -2, 300, "hello"
+        let data = b"\x00\x00\x00\x00\x05\x00\
+            \x81a\x02\
+            \x05\x01\
+            \xdf\xfe\x02\
+            \xc1\x2c\x02\
+            \x85hello\x06\
+            \xff";
+        let entries = listpack_entries(data).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Bytes::from_static(b"a"),
+                Bytes::from_static(b"5"),
+                Bytes::from_static(b"-2"),
+                Bytes::from_static(b"300"),
+                Bytes::from_static(b"hello"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_intset() {
+        let data = b"\x02\x00\x00\x00\x03\x00\x00\x00\x01\x00\xff\xff\x10\x27";
+        let entries = intset_entries(data).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Bytes::from_static(b"1"),
+                Bytes::from_static(b"-1"),
+                Bytes::from_static(b"10000"),
+            ]
+        );
+
+        // a length far beyond the contents
+        let data = b"\x02\x00\x00\x00\xff\xff\xff\xff\x01\x00";
+        assert!(intset_entries(data).is_err());
+    }
+}
@@ -0,0 +1,989 @@
+//! https://redis.io/docs/reference/cluster-spec/
+//! the key space is split into 16384 hash slots, each served by one node.
+//! nodes talk to each other on the cluster bus, the client port + 10000,
+//! pinging the known nodes every second with the slots they serve and
+//! gossip about the other nodes they know, so a node met once is known to everyone.
+//! a command for a key in a slot served by another node is redirected by -MOVED,
+//! or by -ASK while the slot is migrating
+
+use crate::connection::AsyncConnection;
+use crate::db::DB;
+use crate::frame::Frame;
+use crate::helper::{bulk, random_hex, unix_timestamp_ms};
+use crate::{RedisErr, Result};
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const CLUSTER_SLOTS: usize = 16384;
+
+// the bus port is the client port + 10000
+const BUS_PORT_OFFSET: u16 = 10000;
+
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+// CRC16-CCITT (XMODEM), the checksum of the key slot
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// only the part between the first { and the next } is hashed if it's not empty,
+// so keys like {user1}.name and {user1}.age are in the same slot
+pub fn key_slot(key: &[u8]) -> u16 {
+    let key = match key.iter().position(|&b| b == b'{') {
+        Some(start) => match key[start + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &key[start + 1..start + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+    crc16(key) % CLUSTER_SLOTS as u16
+}
+
+#[derive(Debug)]
+pub struct Cluster {
+    myself: String,
+    node_timeout: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    // all the known nodes, myself included
+    nodes: HashMap<String, Node>,
+
+    // the node serving each slot
+    slots: Vec<Option<String>>,
+
+    // slots moving out of this node to another one, and into this node from another one
+    migrating: HashMap<u16, String>,
+    importing: HashMap<u16, String>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    id: String,
+    ip: String,
+    port: u16,
+    cport: u16,
+
+    // the claim with the greater epoch wins when two nodes serve the same slot
+    config_epoch: u64,
+
+    // met by CLUSTER MEET, the id is a random one until the node replies
+    handshake: bool,
+    created: Instant,
+
+    // unix time in milliseconds of the last ping sent and pong received, 0 if none
+    ping_sent: u64,
+    pong_recv: u64,
+
+    // a task is keeping a link to the node
+    linked: bool,
+    link_up: bool,
+}
+
+impl Node {
+    fn new(id: String, ip: String, port: u16, cport: u16) -> Self {
+        Self {
+            id,
+            ip,
+            port,
+            cport,
+            config_epoch: 0,
+            handshake: false,
+            created: Instant::now(),
+            ping_sent: 0,
+            pong_recv: 0,
+            linked: false,
+            link_up: false,
+        }
+    }
+
+    fn addr(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    // the node has not replied to a ping for node timeout
+    fn is_pfail(&self, timeout: Duration) -> bool {
+        self.ping_sent > self.pong_recv
+            && unix_timestamp_ms().saturating_sub(self.ping_sent) > timeout.as_millis() as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MessageType {
+    Ping,
+    Pong,
+    Meet,
+}
+
+impl MessageType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MessageType::Ping => "PING",
+            MessageType::Pong => "PONG",
+            MessageType::Meet => "MEET",
+        }
+    }
+}
+
+// a message on the cluster bus, sent as a RESP array
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    kind: MessageType,
+    sender: String,
+    // empty if the sender doesn't know its address, the peer address is used then
+    ip: String,
+    port: u16,
+    cport: u16,
+    config_epoch: u64,
+    // bitmap of the slots the sender serves
+    slots: Vec<u8>,
+    // id, ip, port and bus port of the other nodes the sender knows
+    gossip: Vec<(String, String, u16, u16)>,
+}
+
+impl Message {
+    fn into_frame(self) -> Frame {
+        let gossip = self
+            .gossip
+            .into_iter()
+            .map(|(id, ip, port, cport)| {
+                Frame::Array(vec![
+                    Frame::BulkString(Bytes::from(id)),
+                    Frame::BulkString(Bytes::from(ip)),
+                    Frame::Integer(port as i64),
+                    Frame::Integer(cport as i64),
+                ])
+            })
+            .collect();
+        Frame::Array(vec![
+            Frame::BulkString(Bytes::from_static(self.kind.as_str().as_bytes())),
+            Frame::BulkString(Bytes::from(self.sender)),
+            Frame::BulkString(Bytes::from(self.ip)),
+            Frame::Integer(self.port as i64),
+            Frame::Integer(self.cport as i64),
+            Frame::Integer(self.config_epoch as i64),
+            Frame::BulkString(Bytes::from(self.slots)),
+            Frame::Array(gossip),
+        ])
+    }
+
+    fn from_frame(frame: Frame) -> Result<Self> {
+        let Frame::Array(args) = frame else {
+            return Err(RedisErr::InvalidProtocol);
+        };
+        let mut iter = args.into_iter();
+        let kind = match next_string(&mut iter)?.as_str() {
+            "PING" => MessageType::Ping,
+            "PONG" => MessageType::Pong,
+            "MEET" => MessageType::Meet,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let sender = next_string(&mut iter)?;
+        let ip = next_string(&mut iter)?;
+        let port = next_integer(&mut iter)? as u16;
+        let cport = next_integer(&mut iter)? as u16;
+        let config_epoch = next_integer(&mut iter)? as u64;
+        let slots = match iter.next() {
+            Some(Frame::BulkString(slots)) if slots.len() == CLUSTER_SLOTS / 8 => slots.to_vec(),
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        let gossip = match iter.next() {
+            Some(Frame::Array(nodes)) => nodes
+                .into_iter()
+                .map(|node| {
+                    let Frame::Array(node) = node else {
+                        return Err(RedisErr::InvalidProtocol);
+                    };
+                    let mut iter = node.into_iter();
+                    Ok((
+                        next_string(&mut iter)?,
+                        next_string(&mut iter)?,
+                        next_integer(&mut iter)? as u16,
+                        next_integer(&mut iter)? as u16,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => return Err(RedisErr::InvalidProtocol),
+        };
+        Ok(Self {
+            kind,
+            sender,
+            ip,
+            port,
+            cport,
+            config_epoch,
+            slots,
+            gossip,
+        })
+    }
+
+    fn serves(&self, slot: usize) -> bool {
+        self.slots[slot / 8] & (1 << (slot % 8)) != 0
+    }
+}
+
+fn next_string(iter: &mut std::vec::IntoIter<Frame>) -> Result<String> {
+    match iter.next() {
+        Some(Frame::BulkString(b)) => Ok(String::from_utf8(b.to_vec())?),
+        _ => Err(RedisErr::InvalidProtocol),
+    }
+}
+
+fn next_integer(iter: &mut std::vec::IntoIter<Frame>) -> Result<i64> {
+    match iter.next() {
+        Some(Frame::Integer(i)) => Ok(i),
+        _ => Err(RedisErr::InvalidProtocol),
+    }
+}
+
+// a slot being moved by CLUSTER SETSLOT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetSlot {
+    Migrating(String),
+    Importing(String),
+    Stable,
+    Node(String),
+}
+
+impl Cluster {
+    pub fn new(port: u16, node_timeout: Duration) -> Self {
+        let myself = random_hex(40);
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            myself.clone(),
+            Node::new(
+                myself.clone(),
+                "127.0.0.1".to_string(),
+                port,
+                port.wrapping_add(BUS_PORT_OFFSET),
+            ),
+        );
+        Self {
+            myself,
+            node_timeout,
+            state: Mutex::new(State {
+                nodes,
+                slots: vec![None; CLUSTER_SLOTS],
+                migrating: HashMap::new(),
+                importing: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn myself(&self) -> &str {
+        &self.myself
+    }
+
+    fn bus_port(&self) -> u16 {
+        self.state.lock().unwrap().nodes[&self.myself].cport
+    }
+
+    // the address of this node as seen by the others
+    fn learn_ip(&self, ip: IpAddr) {
+        if let Some(node) = self.state.lock().unwrap().nodes.get_mut(&self.myself) {
+            node.ip = ip.to_string();
+        }
+    }
+
+    // a message carrying the view of this node
+    fn message(&self, kind: MessageType) -> Message {
+        let state = self.state.lock().unwrap();
+        let myself = &state.nodes[&self.myself];
+        let mut slots = vec![0u8; CLUSTER_SLOTS / 8];
+        for (slot, owner) in state.slots.iter().enumerate() {
+            if owner.as_deref() == Some(&self.myself) {
+                slots[slot / 8] |= 1 << (slot % 8);
+            }
+        }
+        let gossip = state
+            .nodes
+            .values()
+            .filter(|node| node.id != self.myself && !node.handshake)
+            .map(|node| (node.id.clone(), node.ip.clone(), node.port, node.cport))
+            .collect();
+        Message {
+            kind,
+            sender: self.myself.clone(),
+            ip: myself.ip.clone(),
+            port: myself.port,
+            cport: myself.cport,
+            config_epoch: myself.config_epoch,
+            slots,
+            gossip,
+        }
+    }
+
+    // update the view of the cluster from a message of another node
+    fn process(&self, msg: &Message, peer: IpAddr) {
+        if msg.sender == self.myself {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let ip = if msg.ip.is_empty() {
+            peer.to_string()
+        } else {
+            msg.ip.clone()
+        };
+        if !state.nodes.contains_key(&msg.sender) {
+            // only MEET introduces a node, others learn about it by gossip
+            if msg.kind != MessageType::Meet {
+                return;
+            }
+            info!("Node {} ({}:{}) met", msg.sender, ip, msg.port);
+            state.nodes.insert(
+                msg.sender.clone(),
+                Node::new(msg.sender.clone(), ip, msg.port, msg.cport),
+            );
+        }
+        let node = state.nodes.get_mut(&msg.sender).unwrap();
+        node.config_epoch = msg.config_epoch;
+        if msg.kind == MessageType::Pong {
+            node.pong_recv = unix_timestamp_ms();
+        }
+
+        for slot in 0..CLUSTER_SLOTS {
+            let owner = state.slots[slot].clone();
+            if owner.as_deref() == Some(&msg.sender) {
+                if !msg.serves(slot) {
+                    state.slots[slot] = None;
+                }
+                continue;
+            }
+            if !msg.serves(slot) {
+                continue;
+            }
+            let owner_epoch = owner
+                .as_ref()
+                .and_then(|owner| state.nodes.get(owner))
+                .map(|owner| owner.config_epoch);
+            match owner_epoch {
+                Some(epoch) if epoch >= msg.config_epoch => {}
+                _ => {
+                    if owner.as_deref() == Some(&self.myself) {
+                        warn!("Slot {} is taken over by node {}", slot, msg.sender);
+                        state.migrating.remove(&(slot as u16));
+                    }
+                    state.importing.remove(&(slot as u16));
+                    state.slots[slot] = Some(msg.sender.clone());
+                }
+            }
+        }
+
+        for (id, ip, port, cport) in &msg.gossip {
+            if *id != self.myself && !state.nodes.contains_key(id) {
+                debug!("Node {} ({}:{}) learned by gossip", id, ip, port);
+                state
+                    .nodes
+                    .insert(id.clone(), Node::new(id.clone(), ip.clone(), *port, *cport));
+            }
+        }
+    }
+
+    // the first pong of a node met by CLUSTER MEET gives its real id,
+    // returns None if the node is already known or it's myself
+    fn finish_handshake(&self, temp: &str, msg: &Message) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let mut node = state.nodes.remove(temp)?;
+        if msg.sender == self.myself || state.nodes.contains_key(&msg.sender) {
+            return None;
+        }
+        node.id = msg.sender.clone();
+        node.handshake = false;
+        state.nodes.insert(node.id.clone(), node);
+        Some(msg.sender.clone())
+    }
+
+    fn is_handshake(&self, id: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .nodes
+            .get(id)
+            .map(|node| node.handshake)
+            .unwrap_or(false)
+    }
+
+    fn node_bus_addr(&self, id: &str) -> Option<(String, u16)> {
+        let state = self.state.lock().unwrap();
+        state
+            .nodes
+            .get(id)
+            .map(|node| (node.ip.clone(), node.cport))
+    }
+
+    fn set_ping_sent(&self, id: &str) {
+        if let Some(node) = self.state.lock().unwrap().nodes.get_mut(id) {
+            // the time of the oldest ping not answered yet
+            if node.ping_sent <= node.pong_recv {
+                node.ping_sent = unix_timestamp_ms();
+            }
+        }
+    }
+
+    fn set_link(&self, id: &str, linked: bool, up: bool) {
+        if let Some(node) = self.state.lock().unwrap().nodes.get_mut(id) {
+            node.linked = linked;
+            node.link_up = up;
+        }
+    }
+
+    // drop the handshakes not finished in time,
+    // and returns the nodes to start a link with
+    fn nodes_to_link(&self) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        let timeout = self.node_timeout;
+        state.nodes.retain(|_, node| {
+            let expired = node.handshake && node.created.elapsed() > timeout;
+            if expired {
+                warn!("Handshake with node {} timed out", node.addr());
+            }
+            !expired
+        });
+        state
+            .nodes
+            .values_mut()
+            .filter(|node| node.id != self.myself && !node.linked)
+            .map(|node| {
+                node.linked = true;
+                node.id.clone()
+            })
+            .collect()
+    }
+
+    // CLUSTER MEET ip port [cport], the node is linked by the cron
+    pub fn meet(&self, ip: IpAddr, port: u16, cport: Option<u16>) {
+        let id = random_hex(40);
+        let mut node = Node::new(
+            id.clone(),
+            ip.to_string(),
+            port,
+            cport.unwrap_or(port.wrapping_add(BUS_PORT_OFFSET)),
+        );
+        node.handshake = true;
+        self.state.lock().unwrap().nodes.insert(id, node);
+    }
+
+    // CLUSTER ADDSLOTS slot [slot ...]
+    pub fn add_slots(&self, slots: &[u16]) -> std::result::Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        for &slot in slots {
+            if state.slots[slot as usize].is_some() {
+                return Err(format!("ERR Slot {} is already busy", slot));
+            }
+        }
+        for &slot in slots {
+            state.slots[slot as usize] = Some(self.myself.clone());
+        }
+        Ok(())
+    }
+
+    // CLUSTER SETSLOT slot MIGRATING|IMPORTING|NODE node-id or STABLE
+    pub fn set_slot(&self, slot: u16, option: SetSlot) -> std::result::Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let known = |state: &State, id: &str| {
+            if state.nodes.contains_key(id) {
+                Ok(())
+            } else {
+                Err(format!("ERR I don't know about node {}", id))
+            }
+        };
+        let owner = state.slots[slot as usize].clone();
+        match option {
+            SetSlot::Migrating(id) => {
+                known(&state, &id)?;
+                if owner.as_deref() != Some(&self.myself) {
+                    return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+                }
+                state.migrating.insert(slot, id);
+            }
+            SetSlot::Importing(id) => {
+                known(&state, &id)?;
+                if owner.as_deref() == Some(&self.myself) {
+                    return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+                }
+                state.importing.insert(slot, id);
+            }
+            SetSlot::Stable => {
+                state.migrating.remove(&slot);
+                state.importing.remove(&slot);
+            }
+            SetSlot::Node(id) => {
+                known(&state, &id)?;
+                state.migrating.remove(&slot);
+                state.importing.remove(&slot);
+                state.slots[slot as usize] = Some(id.clone());
+                // claim the slot with a greater epoch than any other node,
+                // so the previous owner gives it up
+                if id == self.myself {
+                    let epoch = state.nodes.values().map(|node| node.config_epoch).max();
+                    let myself = state.nodes.get_mut(&self.myself).unwrap();
+                    myself.config_epoch = epoch.unwrap_or_default() + 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the error redirecting a command on the keys, None if it can be served here
+    // asking is set by ASKING, for the slots being imported
+    pub fn redirect(&self, db: &DB, keys: &[String], asking: bool) -> Option<Frame> {
+        let slot = key_slot(keys.first()?.as_bytes());
+        if keys.iter().any(|key| key_slot(key.as_bytes()) != slot) {
+            return Some(Frame::Error(
+                "CROSSSLOT Keys in request don't hash to the same slot".to_string(),
+            ));
+        }
+
+        let migrating = {
+            let state = self.state.lock().unwrap();
+            let importing = asking && state.importing.contains_key(&slot);
+            match state.slots[slot as usize].as_deref() {
+                _ if importing => return None,
+                None => return Some(Frame::Error("CLUSTERDOWN Hash slot not served".to_string())),
+                Some(owner) if owner != self.myself => {
+                    let owner = &state.nodes[owner];
+                    return Some(Frame::Error(format!("MOVED {} {}", slot, owner.addr())));
+                }
+                Some(_) => match state.migrating.get(&slot) {
+                    Some(target) => state.nodes.get(target)?.addr(),
+                    None => return None,
+                },
+            }
+        };
+
+        // the keys still here are served, the missing ones may have been moved
+        let missing = keys.iter().filter(|key| db.get_type(key).is_none()).count();
+        if missing == 0 {
+            None
+        } else if missing == keys.len() {
+            Some(Frame::Error(format!("ASK {} {}", slot, migrating)))
+        } else {
+            Some(Frame::Error(
+                "TRYAGAIN Multiple keys request during rehashing of slot".to_string(),
+            ))
+        }
+    }
+
+    // CLUSTER SLOTS, ranges of slots served by the same node
+    pub fn slots(&self) -> Frame {
+        let state = self.state.lock().unwrap();
+        let ranges = slot_ranges(&state.slots);
+        Frame::Array(
+            ranges
+                .into_iter()
+                .filter_map(|(start, end, id)| {
+                    let node = state.nodes.get(&id)?;
+                    Some(Frame::Array(vec![
+                        Frame::Integer(start as i64),
+                        Frame::Integer(end as i64),
+                        Frame::Array(vec![
+                            Frame::BulkString(Bytes::from(node.ip.clone())),
+                            Frame::Integer(node.port as i64),
+                            Frame::BulkString(Bytes::from(node.id.clone())),
+                        ]),
+                    ]))
+                })
+                .collect(),
+        )
+    }
+
+    // CLUSTER SHARDS, every node is a shard of its own as replicas are not part of the cluster
+    pub fn shards(&self) -> Frame {
+        let state = self.state.lock().unwrap();
+        let ranges = slot_ranges(&state.slots);
+        let mut nodes = state
+            .nodes
+            .values()
+            .filter(|node| !node.handshake)
+            .collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        Frame::Array(
+            nodes
+                .into_iter()
+                .map(|node| {
+                    let slots = ranges
+                        .iter()
+                        .filter(|(_, _, id)| *id == node.id)
+                        .flat_map(|(start, end, _)| {
+                            [Frame::Integer(*start as i64), Frame::Integer(*end as i64)]
+                        })
+                        .collect();
+                    let health = if node.is_pfail(self.node_timeout) {
+                        "fail"
+                    } else {
+                        "online"
+                    };
+                    Frame::Map(vec![
+                        (bulk("slots"), Frame::Array(slots)),
+                        (
+                            bulk("nodes"),
+                            Frame::Array(vec![Frame::Map(vec![
+                                (bulk("id"), bulk(&node.id)),
+                                (bulk("port"), Frame::Integer(node.port as i64)),
+                                (bulk("ip"), bulk(&node.ip)),
+                                (bulk("endpoint"), bulk(&node.ip)),
+                                (bulk("role"), bulk("master")),
+                                (bulk("replication-offset"), Frame::Integer(0)),
+                                (bulk("health"), bulk(health)),
+                            ])]),
+                        ),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    // CLUSTER NODES, a line for each node:
+    // id ip:port@cport flags master ping-sent pong-recv config-epoch link-state slot...
+    pub fn nodes(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let ranges = slot_ranges(&state.slots);
+        let mut nodes = state.nodes.values().collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut res = String::new();
+        for node in nodes {
+            let myself = node.id == self.myself;
+            let mut flags = Vec::new();
+            if myself {
+                flags.push("myself");
+            }
+            flags.push("master");
+            if node.handshake {
+                flags.push("handshake");
+            }
+            if !myself && node.is_pfail(self.node_timeout) {
+                flags.push("fail?");
+            }
+            let link = if myself || node.link_up {
+                "connected"
+            } else {
+                "disconnected"
+            };
+            res.push_str(&format!(
+                "{} {}@{} {} - {} {} {} {}",
+                node.id,
+                node.addr(),
+                node.cport,
+                flags.join(","),
+                node.ping_sent,
+                node.pong_recv,
+                node.config_epoch,
+                link
+            ));
+            for (start, end, _) in ranges.iter().filter(|(_, _, id)| *id == node.id) {
+                if start == end {
+                    res.push_str(&format!(" {}", start));
+                } else {
+                    res.push_str(&format!(" {}-{}", start, end));
+                }
+            }
+            if myself {
+                for (slot, target) in &state.migrating {
+                    res.push_str(&format!(" [{}->-{}]", slot, target));
+                }
+                for (slot, source) in &state.importing {
+                    res.push_str(&format!(" [{}-<-{}]", slot, source));
+                }
+            }
+            res.push('\n');
+        }
+        res
+    }
+
+    // CLUSTER INFO
+    pub fn info(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let assigned = state.slots.iter().filter(|owner| owner.is_some()).count();
+        let size = state
+            .nodes
+            .keys()
+            .filter(|id| state.slots.iter().any(|owner| owner.as_ref() == Some(*id)))
+            .count();
+        let current_epoch = state
+            .nodes
+            .values()
+            .map(|node| node.config_epoch)
+            .max()
+            .unwrap_or_default();
+        let cluster_state = if assigned == CLUSTER_SLOTS {
+            "ok"
+        } else {
+            "fail"
+        };
+        format!(
+            "cluster_enabled:1\r\ncluster_state:{}\r\ncluster_slots_assigned:{}\r\ncluster_slots_ok:{}\r\ncluster_known_nodes:{}\r\ncluster_size:{}\r\ncluster_current_epoch:{}\r\ncluster_my_epoch:{}\r\n",
+            cluster_state,
+            assigned,
+            assigned,
+            state.nodes.len(),
+            size,
+            current_epoch,
+            state.nodes[&self.myself].config_epoch,
+        )
+    }
+}
+
+// contiguous slots served by the same node, start and end inclusive
+fn slot_ranges(slots: &[Option<String>]) -> Vec<(usize, usize, String)> {
+    let mut ranges: Vec<(usize, usize, String)> = Vec::new();
+    for (slot, owner) in slots.iter().enumerate() {
+        let Some(owner) = owner else {
+            continue;
+        };
+        match ranges.last_mut() {
+            Some((_, end, id)) if *end + 1 == slot && id == owner => *end = slot,
+            _ => ranges.push((slot, slot, owner.clone())),
+        }
+    }
+    ranges
+}
+
+// listen on the cluster bus and keep links to the known nodes
+pub async fn start(db: DB, bind: IpAddr) -> Result<()> {
+    let cluster = db.cluster().ok_or(RedisErr::InvalidProtocol)?;
+    if !bind.is_unspecified() {
+        cluster.learn_ip(bind);
+    }
+    let listener = TcpListener::bind((bind, cluster.bus_port())).await?;
+    info!("Cluster bus listening on port {}", cluster.bus_port());
+
+    let bus = db.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let db = bus.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve_bus(&db, stream).await {
+                            debug!("cluster bus connection closed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Error accepting cluster bus connection: {}", e),
+            }
+        }
+    });
+    tokio::spawn(cron(db));
+    Ok(())
+}
+
+// start a link with every node without one
+async fn cron(db: DB) {
+    let mut interval = tokio::time::interval(PING_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(cluster) = db.cluster() else {
+            return;
+        };
+        for id in cluster.nodes_to_link() {
+            tokio::spawn(link(db.clone(), id));
+        }
+    }
+}
+
+// answer the pings from another node
+async fn serve_bus(db: &DB, stream: TcpStream) -> Result<()> {
+    let cluster = db.cluster().ok_or(RedisErr::InvalidProtocol)?;
+    let peer = stream.peer_addr()?.ip();
+    if let Ok(local) = stream.local_addr() {
+        cluster.learn_ip(local.ip());
+    }
+    let mut conn = AsyncConnection::new(stream);
+    loop {
+        let msg = Message::from_frame(conn.read_frame().await?)?;
+        cluster.process(&msg, peer);
+        if msg.kind != MessageType::Pong {
+            let pong = cluster.message(MessageType::Pong);
+            conn.write_frame(pong.into_frame()).await?;
+        }
+    }
+}
+
+// ping the node every second until the link is broken, the cron starts a new one then
+async fn link(db: DB, mut id: String) {
+    let Some(cluster) = db.cluster() else {
+        return;
+    };
+    if let Err(e) = ping_node(cluster, &mut id).await {
+        debug!("cluster link to node {} closed: {}", id, e);
+    }
+    cluster.set_link(&id, false, false);
+}
+
+async fn ping_node(cluster: &Cluster, id: &mut String) -> Result<()> {
+    let (ip, cport) = cluster
+        .node_bus_addr(id)
+        .ok_or(RedisErr::ConnectionAborted)?;
+    let stream = tokio::time::timeout(cluster.node_timeout, TcpStream::connect((ip, cport)))
+        .await
+        .map_err(|_| RedisErr::ConnectionAborted)??;
+    let peer = stream.peer_addr()?.ip();
+    if let Ok(local) = stream.local_addr() {
+        cluster.learn_ip(local.ip());
+    }
+    let mut conn = AsyncConnection::new(stream);
+    cluster.set_link(id, true, true);
+
+    loop {
+        let handshake = cluster.is_handshake(id);
+        let kind = if handshake {
+            MessageType::Meet
+        } else {
+            MessageType::Ping
+        };
+        cluster.set_ping_sent(id);
+        conn.write_frame(cluster.message(kind).into_frame()).await?;
+        let frame = tokio::time::timeout(cluster.node_timeout, conn.read_frame())
+            .await
+            .map_err(|_| RedisErr::ConnectionAborted)??;
+        let msg = Message::from_frame(frame)?;
+        if handshake {
+            *id = cluster
+                .finish_handshake(id, &msg)
+                .ok_or(RedisErr::ConnectionAborted)?;
+            cluster.set_link(id, true, true);
+        }
+        cluster.process(&msg, peer);
+        tokio::time::sleep(PING_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(
+            key_slot(b"{user1000}.following"),
+            key_slot(b"{user1000}.followers")
+        );
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        // an empty hashtag hashes the whole key
+        assert_eq!(key_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % 16384);
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+
+    #[test]
+    fn test_message() {
+        let cluster = Cluster::new(7000, Duration::from_secs(15));
+        cluster.add_slots(&[0, 1, 100]).unwrap();
+        let msg = cluster.message(MessageType::Ping);
+        assert!(msg.serves(0) && msg.serves(100) && !msg.serves(2));
+        assert_eq!(Message::from_frame(msg.clone().into_frame()).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_process() {
+        let a = Cluster::new(7000, Duration::from_secs(15));
+        let b = Cluster::new(7001, Duration::from_secs(15));
+        a.add_slots(&[0, 1]).unwrap();
+        b.add_slots(&[2]).unwrap();
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+
+        // a ping from an unknown node is ignored
+        a.process(&b.message(MessageType::Ping), localhost);
+        assert_eq!(a.state.lock().unwrap().slots[2], None);
+
+        a.process(&b.message(MessageType::Meet), localhost);
+        b.process(&a.message(MessageType::Meet), localhost);
+        assert_eq!(
+            a.state.lock().unwrap().slots[2].as_deref(),
+            Some(b.myself())
+        );
+        assert_eq!(
+            b.state.lock().unwrap().slots[0].as_deref(),
+            Some(a.myself())
+        );
+        assert!(a
+            .nodes()
+            .contains(&format!("{} 127.0.0.1:7001@17001 master", b.myself())));
+
+        // moving slot 1 to b, the greater epoch takes it from a
+        b.set_slot(1, SetSlot::Node(b.myself().to_string()))
+            .unwrap();
+        a.process(&b.message(MessageType::Ping), localhost);
+        assert_eq!(
+            a.state.lock().unwrap().slots[1].as_deref(),
+            Some(b.myself())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        let mut db = DB::new();
+        let a = Cluster::new(7000, Duration::from_secs(15));
+        let b = Cluster::new(7001, Duration::from_secs(15));
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let foo = key_slot(b"foo");
+        a.add_slots(&[foo]).unwrap();
+        b.add_slots(&[key_slot(b"bar")]).unwrap();
+        a.process(&b.message(MessageType::Meet), localhost);
+
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        assert_eq!(a.redirect(&db, &keys(&["foo"]), false), None);
+        assert_eq!(a.redirect(&db, &keys(&[]), false), None);
+        assert_eq!(
+            a.redirect(&db, &keys(&["bar"]), false),
+            Some(Frame::Error("MOVED 5061 127.0.0.1:7001".to_string()))
+        );
+        assert_eq!(
+            a.redirect(&db, &keys(&["foo", "bar"]), false),
+            Some(Frame::Error(
+                "CROSSSLOT Keys in request don't hash to the same slot".to_string()
+            ))
+        );
+        assert_eq!(
+            a.redirect(&db, &keys(&["baz"]), false),
+            Some(Frame::Error("CLUSTERDOWN Hash slot not served".to_string()))
+        );
+
+        // keys not here are asked to the target while the slot is migrating
+        a.set_slot(foo, SetSlot::Migrating(b.myself().to_string()))
+            .unwrap();
+        assert_eq!(
+            a.redirect(&db, &keys(&["foo"]), false),
+            Some(Frame::Error(format!("ASK {} 127.0.0.1:7001", foo)))
+        );
+        db.set(
+            "foo".to_string(),
+            Bytes::from_static(b"1"),
+            false,
+            false,
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(a.redirect(&db, &keys(&["foo"]), false), None);
+
+        b.process(&a.message(MessageType::Meet), localhost);
+        b.set_slot(foo, SetSlot::Importing(a.myself().to_string()))
+            .unwrap();
+        assert!(b.redirect(&db, &keys(&["foo"]), false).is_some());
+        assert_eq!(b.redirect(&db, &keys(&["foo"]), true), None);
+    }
+}
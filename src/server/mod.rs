@@ -0,0 +1,297 @@
+//! Redis Server implementation
+//! use mio to achieve non-blocking IO, multiplexing and event driven
+//! an event loop is used to handle all the IO events
+
+pub(crate) mod cluster;
+pub(crate) mod replication;
+pub(crate) mod scripting;
+
+use crate::config::{AppendFsync, Config, KeyspaceEvents, PubSubOutputLimit};
+use crate::db::DBDropGuard;
+use crate::handler::Handler;
+use crate::Arg;
+use crate::Result;
+
+use std::sync::Arc;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use tokio::net::TcpListener;
+use tokio::sync::{Notify, Semaphore};
+
+pub struct ServerBuilder {
+    addr: String,
+    port: u16,
+    max_client: usize,
+    config: Config,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self {
+            addr: "127.0.0.1".to_string(),
+            port: 6379,
+            max_client: 1024,
+            config: Config::default(),
+        }
+    }
+
+    pub fn new_with_arg(args: Arg) -> Self {
+        Self {
+            addr: args.get_host(),
+            port: args.get_port(),
+            max_client: args.get_max_clients(),
+            config: Config {
+                requirepass: args.get_requirepass(),
+                dir: args.get_dir(),
+                dbfilename: args.get_dbfilename(),
+                appendonly: args.get_appendonly(),
+                appendfilename: args.get_appendfilename(),
+                appendfsync: args.get_appendfsync(),
+                auto_aof_rewrite_percentage: args.get_auto_aof_rewrite_percentage(),
+                auto_aof_rewrite_min_size: args.get_auto_aof_rewrite_min_size(),
+                port: args.get_port(),
+                replicaof: args.get_replicaof().and_then(|s| {
+                    let (host, port) = s.split_once(' ')?;
+                    Some((host.to_string(), port.parse().ok()?))
+                }),
+                repl_backlog_size: args.get_repl_backlog_size(),
+                cluster_enabled: args.get_cluster_enabled(),
+                cluster_node_timeout: args.get_cluster_node_timeout(),
+                keyspace_shards: args.get_keyspace_shards(),
+                busy_reply_threshold: args.get_busy_reply_threshold(),
+                notify_keyspace_events: args.get_notify_keyspace_events(),
+                pubsub_buffer_size: args.get_pubsub_buffer_size(),
+                pubsub_output_limit: args.get_pubsub_output_limit(),
+            },
+        }
+    }
+
+    pub fn addr(mut self, addr: &str) -> Self {
+        self.addr = addr.to_string();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn max_client(mut self, max_client: usize) -> Self {
+        self.max_client = max_client;
+        self
+    }
+
+    pub fn requirepass(mut self, password: &str) -> Self {
+        self.config.requirepass = Some(password.to_string());
+        self
+    }
+
+    pub fn dir(mut self, dir: &str) -> Self {
+        self.config.dir = dir.to_string();
+        self
+    }
+
+    pub fn dbfilename(mut self, dbfilename: &str) -> Self {
+        self.config.dbfilename = dbfilename.to_string();
+        self
+    }
+
+    pub fn appendonly(mut self, appendonly: bool) -> Self {
+        self.config.appendonly = appendonly;
+        self
+    }
+
+    pub fn appendfilename(mut self, appendfilename: &str) -> Self {
+        self.config.appendfilename = appendfilename.to_string();
+        self
+    }
+
+    pub fn appendfsync(mut self, appendfsync: AppendFsync) -> Self {
+        self.config.appendfsync = appendfsync;
+        self
+    }
+
+    pub fn auto_aof_rewrite(mut self, percentage: u64, min_size: u64) -> Self {
+        self.config.auto_aof_rewrite_percentage = percentage;
+        self.config.auto_aof_rewrite_min_size = min_size;
+        self
+    }
+
+    pub fn replicaof(mut self, host: &str, port: u16) -> Self {
+        self.config.replicaof = Some((host.to_string(), port));
+        self
+    }
+
+    pub fn repl_backlog_size(mut self, size: usize) -> Self {
+        self.config.repl_backlog_size = size;
+        self
+    }
+
+    pub fn cluster_enabled(mut self, enabled: bool) -> Self {
+        self.config.cluster_enabled = enabled;
+        self
+    }
+
+    pub fn cluster_node_timeout(mut self, timeout: u64) -> Self {
+        self.config.cluster_node_timeout = timeout;
+        self
+    }
+
+    pub fn keyspace_shards(mut self, shards: usize) -> Self {
+        self.config.keyspace_shards = shards;
+        self
+    }
+
+    pub fn busy_reply_threshold(mut self, threshold: u64) -> Self {
+        self.config.busy_reply_threshold = threshold;
+        self
+    }
+
+    pub fn notify_keyspace_events(mut self, events: KeyspaceEvents) -> Self {
+        self.config.notify_keyspace_events = events;
+        self
+    }
+
+    pub fn pubsub_buffer_size(mut self, size: usize) -> Self {
+        self.config.pubsub_buffer_size = size;
+        self
+    }
+
+    pub fn pubsub_output_limit(mut self, limit: PubSubOutputLimit) -> Self {
+        self.config.pubsub_output_limit = limit;
+        self
+    }
+
+    pub async fn build(mut self) -> Result<Server> {
+        self.config.port = self.port;
+        Server::new_with_config(&self.addr, self.port, self.max_client, self.config).await
+    }
+} // impl ServerBuilder
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Server is the main struct of the server
+// data retrieval and storage are done through the server
+// this process can divide into different layers
+// 1. transport layer: handle the connection
+// 2. protocol layer: parse the command from the stream
+// 3. command layer: execute the command
+// 4. storage layer: store the data
+// 5. response layer: represent the response
+// 6. protocol layer: serialize the response
+// 7. transport layer: write the response to the stream
+// the data flow is like this:
+// client -> transport -> protocol -> request  -> storage
+//                                                 |
+//                                                 v
+// client <- transport <- protocol <- response <- storage
+pub struct Server {
+    db: DBDropGuard,
+    listener: TcpListener,
+    limit_connections: Arc<Semaphore>, // limit the max connections
+    shutdown: Arc<Notify>,
+}
+
+impl Server {
+    pub async fn new(addr: &str, port: u16, max_client: usize) -> Result<Self> {
+        let config = Config {
+            port,
+            ..Default::default()
+        };
+        Self::new_with_config(addr, port, max_client, config).await
+    }
+
+    async fn new_with_config(
+        addr: &str,
+        port: u16,
+        max_client: usize,
+        config: Config,
+    ) -> Result<Self> {
+        let addr: std::net::SocketAddr = format!("{}:{}", addr, port).parse()?;
+        let db = DBDropGuard::new_with_config(config);
+        let start = std::time::Instant::now();
+        let loaded = db.db().load().map_err(|e| {
+            error!("Error loading the rdb snapshot: {}", e);
+            e
+        })?;
+        info!(
+            "DB loaded from disk: {} keys in {:.3} seconds",
+            loaded,
+            start.elapsed().as_secs_f64()
+        );
+        db.db().open_aof()?;
+        if let Some(primary) = db.db().config().replicaof.clone() {
+            replication::replicaof(&db.db(), Some(primary));
+        }
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        if db.db().cluster().is_some() {
+            cluster::start(db.db(), addr.ip()).await?;
+        }
+
+        Ok(Self {
+            db,
+            listener,
+            limit_connections: Arc::new(Semaphore::new(max_client)),
+            shutdown: Arc::new(Notify::new()),
+        })
+    }
+
+    // run the server
+    pub async fn run(self) -> Result<()> {
+        // waitting for new connections
+        loop {
+            let premit = match self.limit_connections.clone().acquire_owned().await {
+                Ok(premit) => premit,
+                Err(e) => {
+                    error!("Error acquiring premit: {}", e);
+                    continue;
+                }
+            };
+            tokio::select! {
+                // a single accept, a connection taken by a branch whose pattern
+                // doesn't match would be dropped
+                accepted = self.listener.accept() => {
+                    let (stream, addr) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            error!("Error accepting connection: {}", e);
+                            continue;
+                        }
+                    };
+                    trace!("Accepting connection from: {}", addr);
+
+                    let mut handler = Handler::new(stream, self.db.db(), self.shutdown.clone());
+                    tokio::spawn(async move {
+                        if let Err(err) = handler.run().await {
+                            error!("Error handling connection: {}", err);
+                        }
+                        drop(premit)
+                    });
+                }
+                // Ctrl-C to shutdown
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Ctrl-C received, shutting down");
+                    // keep the data for the next start
+                    self.db.db().sync_aof();
+                    if let Err(e) = self.db.db().save() {
+                        error!("Error saving the rdb snapshot: {}", e);
+                    }
+                    self.shutdown.notify_waiters();
+                    self.limit_connections.acquire_owned().await.expect("already closed").forget();
+                    return Ok(())
+                }
+                // may notified by a shutdown command from worker threads
+                _ = self.shutdown.notified() => {
+                    info!("Shutdown complete");
+                    return Ok(())
+                }
+            }
+        }
+    }
+} // impl Server
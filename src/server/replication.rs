@@ -0,0 +1,758 @@
+//! https://redis.io/docs/management/replication/
+//! a replica sends PSYNC replid offset to the primary, and gets either
+//! +FULLRESYNC replid offset followed by an rdb snapshot, or +CONTINUE replid.
+//! then every write command the primary applies is streamed to the replica,
+//! and kept in a fixed size backlog so a replica reconnecting soon can continue from its offset
+
+use crate::cmd::{Command, Parser};
+use crate::connection::AsyncConnection;
+use crate::db::DB;
+use crate::frame::Frame;
+use crate::helper::random_hex;
+use crate::rdb::RDB;
+use crate::{RedisErr, Result};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use log::{debug, error, info, warn};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{watch, Notify},
+    task::AbortHandle,
+};
+
+#[derive(Debug)]
+pub struct Replication {
+    state: Mutex<State>,
+
+    // offset of the primary, replica links wait on it for new commands
+    offset_tx: watch::Sender<u64>,
+
+    // notified on every REPLCONF ACK, for WAIT
+    acked: Notify,
+}
+
+#[derive(Debug)]
+struct State {
+    // history of the data set, a replica takes the ones of its primary
+    replid: String,
+    offset: u64,
+
+    backlog: Option<Backlog>,
+    role: Role,
+
+    // replica links of the primary by the connection id
+    replicas: HashMap<u64, ReplicaInfo>,
+}
+
+#[derive(Debug)]
+enum Role {
+    Primary,
+    Replica(PrimaryLink),
+}
+
+#[derive(Debug)]
+struct PrimaryLink {
+    host: String,
+    port: u16,
+    up: bool,
+    task: AbortHandle,
+}
+
+#[derive(Debug)]
+struct ReplicaInfo {
+    ip: String,
+    port: u16,
+    ack_offset: u64,
+    ack_at: Instant,
+}
+
+// the latest bytes of the replication stream
+#[derive(Debug)]
+struct Backlog {
+    buf: VecDeque<u8>,
+    size: usize,
+}
+
+impl Backlog {
+    fn new(size: usize) -> Self {
+        Self {
+            buf: VecDeque::new(),
+            size,
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        self.buf.extend(data);
+        if self.buf.len() > self.size {
+            let overflow = self.buf.len() - self.size;
+            self.buf.drain(..overflow);
+        }
+    }
+
+    // bytes from offset to the end, None if they are no longer kept
+    fn since(&self, end: u64, offset: u64) -> Option<Vec<u8>> {
+        let start = end - self.buf.len() as u64;
+        if offset < start || offset > end {
+            return None;
+        }
+        Some(
+            self.buf
+                .range((offset - start) as usize..)
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+impl Replication {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                replid: random_hex(40),
+                offset: 0,
+                backlog: None,
+                role: Role::Primary,
+                replicas: HashMap::new(),
+            }),
+            offset_tx: watch::channel(0).0,
+            acked: Notify::new(),
+        }
+    }
+
+    pub fn is_replica(&self) -> bool {
+        matches!(self.state.lock().unwrap().role, Role::Replica(_))
+    }
+
+    // append a write command to the backlog, nothing to do before a replica has connected
+    pub fn feed(&self, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(state.role, Role::Replica(_)) {
+            return;
+        }
+        if let Some(backlog) = state.backlog.as_mut() {
+            backlog.feed(data);
+            state.offset += data.len() as u64;
+            self.offset_tx.send_replace(state.offset);
+        }
+    }
+
+    fn backlog_since(&self, offset: u64) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.backlog.as_ref()?.since(state.offset, offset)
+    }
+
+    // the offset to continue from if the replica asks for the data set the backlog still has
+    // psync offset is the next byte the replica wants, counting from 1
+    fn continue_from(&self, replid: &str, psync_offset: i64) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        if replid != state.replid || psync_offset < 1 {
+            return None;
+        }
+        let offset = psync_offset as u64 - 1;
+        state.backlog.as_ref()?.since(state.offset, offset)?;
+        Some(offset)
+    }
+
+    // the backlog is created when the first replica connects
+    fn start_backlog(&self, size: usize) -> (String, u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.backlog.is_none() {
+            state.backlog = Some(Backlog::new(size));
+        }
+        (state.replid.clone(), state.offset)
+    }
+
+    fn add_replica(&self, id: u64, ip: String, port: u16, offset: u64) {
+        self.state.lock().unwrap().replicas.insert(
+            id,
+            ReplicaInfo {
+                ip,
+                port,
+                ack_offset: offset,
+                ack_at: Instant::now(),
+            },
+        );
+    }
+
+    fn remove_replica(&self, id: u64) {
+        self.state.lock().unwrap().replicas.remove(&id);
+    }
+
+    fn ack(&self, id: u64, offset: u64) {
+        if let Some(replica) = self.state.lock().unwrap().replicas.get_mut(&id) {
+            replica.ack_offset = offset;
+            replica.ack_at = Instant::now();
+        }
+        self.acked.notify_waiters();
+    }
+
+    // number of replicas which have acknowledged the offset
+    fn num_acked(&self, offset: u64) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .replicas
+            .values()
+            .filter(|replica| replica.ack_offset >= offset)
+            .count()
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.state.lock().unwrap().offset
+    }
+
+    // WAIT numreplicas timeout, block until enough replicas have acknowledged the offset
+    // or the timeout is reached, returns the number of replicas which have acknowledged it
+    pub async fn wait(&self, numreplicas: usize, offset: u64, timeout: Option<Duration>) -> usize {
+        let acked = self.num_acked(offset);
+        if acked >= numreplicas {
+            return acked;
+        }
+        // ask the replicas for their offsets rather than waiting for the next periodic ack
+        self.feed(
+            &Frame::Array(vec![
+                Frame::BulkString(Bytes::from_static(b"REPLCONF")),
+                Frame::BulkString(Bytes::from_static(b"GETACK")),
+                Frame::BulkString(Bytes::from_static(b"*")),
+            ])
+            .serialize(),
+        );
+
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        loop {
+            // registered before counting, so an ack in between is not missed
+            let notified = self.acked.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let acked = self.num_acked(offset);
+            if acked >= numreplicas {
+                return acked;
+            }
+            match deadline {
+                Some(deadline) => {
+                    if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                        return self.num_acked(offset);
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    // arguments of PSYNC, ? -1 asks for a full resync
+    fn psync_args(&self) -> (String, String) {
+        let state = self.state.lock().unwrap();
+        if state.offset == 0 {
+            return ("?".to_string(), "-1".to_string());
+        }
+        (state.replid.clone(), (state.offset + 1).to_string())
+    }
+
+    // the replica takes the history of the primary
+    fn set_primary_history(&self, replid: String, offset: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.replid = replid;
+        state.offset = offset;
+    }
+
+    // bytes of the stream processed by the replica
+    fn advance(&self, len: u64) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.offset += len;
+        state.offset
+    }
+
+    fn set_link_up(&self, up: bool) {
+        if let Role::Replica(link) = &mut self.state.lock().unwrap().role {
+            link.up = up;
+        }
+    }
+
+    pub fn info(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut info = String::from("# Replication\r\n");
+        match &state.role {
+            Role::Primary => {
+                info.push_str("role:master\r\n");
+                info.push_str(&format!("connected_slaves:{}\r\n", state.replicas.len()));
+                for (i, replica) in state.replicas.values().enumerate() {
+                    info.push_str(&format!(
+                        "slave{}:ip={},port={},state=online,offset={},lag={}\r\n",
+                        i,
+                        replica.ip,
+                        replica.port,
+                        replica.ack_offset,
+                        replica.ack_at.elapsed().as_secs()
+                    ));
+                }
+            }
+            Role::Replica(link) => {
+                info.push_str("role:slave\r\n");
+                info.push_str(&format!("master_host:{}\r\n", link.host));
+                info.push_str(&format!("master_port:{}\r\n", link.port));
+                let status = if link.up { "up" } else { "down" };
+                info.push_str(&format!("master_link_status:{}\r\n", status));
+                info.push_str(&format!("slave_repl_offset:{}\r\n", state.offset));
+            }
+        }
+        info.push_str(&format!("master_replid:{}\r\n", state.replid));
+        info.push_str(&format!("master_repl_offset:{}\r\n", state.offset));
+        let (active, size) = match &state.backlog {
+            Some(backlog) => (1, backlog.size),
+            None => (0, 0),
+        };
+        info.push_str(&format!("repl_backlog_active:{}\r\n", active));
+        info.push_str(&format!("repl_backlog_size:{}\r\n", size));
+        info
+    }
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// REPLICAOF host port starts replicating from the primary,
+// REPLICAOF NO ONE turns the replica into a primary
+pub fn replicaof(db: &DB, primary: Option<(String, u16)>) {
+    let replication = db.replication();
+    let mut state = replication.state.lock().unwrap();
+    if let Role::Replica(link) = &state.role {
+        if primary
+            .as_ref()
+            .map(|(host, port)| *host == link.host && *port == link.port)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        link.task.abort();
+    }
+
+    match primary {
+        Some((host, port)) => {
+            info!("Connecting to MASTER {}:{}", host, port);
+            let task = tokio::spawn(replicate(db.clone(), host.clone(), port));
+            state.role = Role::Replica(PrimaryLink {
+                host,
+                port,
+                up: false,
+                task: task.abort_handle(),
+            });
+            // the replicas of this server can't follow the new history
+            state.backlog = None;
+        }
+        None => {
+            info!("MASTER MODE enabled");
+            state.role = Role::Primary;
+            state.replid = random_hex(40);
+        }
+    }
+}
+
+// the primary side of PSYNC, the connection becomes the link to the replica
+pub async fn serve_replica(
+    db: &DB,
+    conn: &mut AsyncConnection,
+    replid: String,
+    psync_offset: i64,
+    shutdown: Arc<Notify>,
+) -> Result<()> {
+    let replication = db.replication();
+    if replication.is_replica() {
+        conn.write_frame(Frame::Error(
+            "ERR replica of a replica is not supported".to_string(),
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let offset = match replication.continue_from(&replid, psync_offset) {
+        Some(offset) => {
+            info!("Partial resynchronization request accepted");
+            conn.write_bytes(format!("+CONTINUE {}\r\n", replid).as_bytes())
+                .await?;
+            offset
+        }
+        None => {
+            info!("Starting full resynchronization with replica");
+            // no write can slip in between the snapshot and the offset
+            let (rdb, (replid, offset)) = {
+                let _barrier = db.propagate_barrier();
+                (
+                    db.rdb(),
+                    replication.start_backlog(db.config().repl_backlog_size),
+                )
+            };
+            let rdb = rdb.write_to(Vec::new())?;
+            conn.write_bytes(format!("+FULLRESYNC {} {}\r\n", replid, offset).as_bytes())
+                .await?;
+            // a bulk string without the trailing CRLF
+            conn.write_bytes(format!("${}\r\n", rdb.len()).as_bytes())
+                .await?;
+            conn.write_bytes(&rdb).await?;
+            offset
+        }
+    };
+
+    let ip = conn
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+    let port = conn.listening_port().unwrap_or_default();
+    replication.add_replica(conn.id(), ip, port, offset);
+    let res = stream_to_replica(db, conn, offset, shutdown).await;
+    replication.remove_replica(conn.id());
+    info!("Connection with replica lost");
+    res
+}
+
+async fn stream_to_replica(
+    db: &DB,
+    conn: &mut AsyncConnection,
+    mut offset: u64,
+    shutdown: Arc<Notify>,
+) -> Result<()> {
+    let replication = db.replication();
+    let mut rx = replication.offset_tx.subscribe();
+    loop {
+        // mark the current offset seen before reading, so no feed is missed
+        rx.borrow_and_update();
+        let data = replication.backlog_since(offset).ok_or_else(|| {
+            warn!("replica is too far behind the backlog");
+            RedisErr::ConnectionAborted
+        })?;
+        if !data.is_empty() {
+            conn.write_bytes(&data).await?;
+            offset += data.len() as u64;
+        }
+
+        tokio::select! {
+            changed = rx.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+            }
+            frame = conn.read_frame() => {
+                if let Some((option, offset)) = parse_replconf(&frame?) {
+                    match (option.as_str(), offset.parse()) {
+                        ("ACK", Ok(offset)) => replication.ack(conn.id(), offset),
+                        _ => debug!("unexpected REPLCONF {} from replica", option),
+                    }
+                }
+            }
+            _ = shutdown.notified() => return Ok(()),
+        }
+    }
+}
+
+// REPLCONF ACK offset from a replica, or REPLCONF GETACK * from the primary
+// returns the option in upper case and its argument
+fn parse_replconf(frame: &Frame) -> Option<(String, String)> {
+    let Frame::Array(args) = frame else {
+        return None;
+    };
+    let arg = |i: usize| match args.get(i) {
+        Some(Frame::BulkString(b)) => Some(String::from_utf8_lossy(b).to_string()),
+        Some(Frame::SimpleString(s)) => Some(s.clone()),
+        Some(Frame::Integer(i)) => Some(i.to_string()),
+        _ => None,
+    };
+    if args.len() != 3 || !arg(0)?.eq_ignore_ascii_case("REPLCONF") {
+        return None;
+    }
+    Some((arg(1)?.to_ascii_uppercase(), arg(2)?))
+}
+
+// the replica side, keep syncing with the primary and reconnect when the link is lost
+async fn replicate(db: DB, host: String, port: u16) {
+    loop {
+        if let Err(e) = sync_with_primary(&db, &host, port).await {
+            warn!("Replication with MASTER {}:{} stopped: {}", host, port, e);
+        }
+        db.replication().set_link_up(false);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn sync_with_primary(db: &DB, host: &str, port: u16) -> Result<()> {
+    let replication = db.replication();
+    let stream = TcpStream::connect((host, port)).await?;
+    let mut link = Link::new(stream);
+
+    link.command(&["PING"]).await?;
+    let listening_port = db.config().port.to_string();
+    link.command(&["REPLCONF", "listening-port", &listening_port])
+        .await?;
+    link.command(&["REPLCONF", "capa", "psync2"]).await?;
+
+    let (replid, offset) = replication.psync_args();
+    link.send(&["PSYNC", &replid, &offset]).await?;
+    let reply = link.read_line().await?;
+    let mut parts = reply.split(' ');
+    match parts.next() {
+        Some("+FULLRESYNC") => {
+            let replid = parts.next().ok_or(RedisErr::InvalidProtocol)?;
+            let offset = parts
+                .next()
+                .and_then(|offset| offset.parse().ok())
+                .ok_or(RedisErr::InvalidProtocol)?;
+            let payload = link.read_payload().await?;
+            load_snapshot(db, &payload)?;
+            replication.set_primary_history(replid.to_string(), offset);
+            info!("MASTER <-> REPLICA sync: Finished with success");
+        }
+        Some("+CONTINUE") => {
+            if let Some(replid) = parts.next() {
+                let offset = replication.advance(0);
+                replication.set_primary_history(replid.to_string(), offset);
+            }
+            info!("Successful partial resynchronization with master");
+        }
+        _ => {
+            error!("Unexpected reply to PSYNC from master: {}", reply);
+            return Err(RedisErr::InvalidProtocol);
+        }
+    }
+    replication.set_link_up(true);
+
+    let parser = Parser::new();
+    let mut transaction = None;
+    let mut ack = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            frame = link.read_frame() => {
+                let (frame, len) = frame?;
+                let getack = matches!(parse_replconf(&frame), Some((option, _)) if option == "GETACK");
+                apply_from_primary(db, &parser, frame, &mut transaction).await;
+                let offset = replication.advance(len as u64);
+                // the primary is waiting for the offset of the replicas
+                if getack {
+                    link.send(&["REPLCONF", "ACK", &offset.to_string()]).await?;
+                }
+            }
+            _ = ack.tick() => {
+                let offset = replication.advance(0).to_string();
+                link.send(&["REPLCONF", "ACK", &offset]).await?;
+            }
+        }
+    }
+}
+
+// replace the data set with the snapshot of the primary
+fn load_snapshot(db: &DB, payload: &[u8]) -> Result<()> {
+    let rdb = RDB::load_from(payload)?;
+    let functions = rdb.functions().to_vec();
+    let records = rdb.into_dbs().into_iter().next().unwrap_or_default();
+    let _barrier = db.propagate_barrier();
+    db.clone().flush();
+    db.scripting().function_flush();
+    db.restore_functions(functions);
+    let loaded = db.restore(records);
+    info!("MASTER <-> REPLICA sync: Loaded {} keys", loaded);
+    drop(_barrier);
+
+    // the aof has to follow the new data set
+    match db.bgrewriteaof() {
+        Ok(()) | Err(RedisErr::AOFDisabled) => {}
+        Err(e) => warn!("Error rewriting the aof after sync: {}", e),
+    }
+    Ok(())
+}
+
+// the commands between MULTI and EXEC are queued and applied at once on EXEC
+async fn apply_from_primary(
+    db: &DB,
+    parser: &Parser,
+    frame: Frame,
+    transaction: &mut Option<Vec<(Command, Frame)>>,
+) {
+    let cmd = match parser.parse(frame.clone()) {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            debug!("skip command from master: {}", e);
+            return;
+        }
+    };
+    let mut target = db.clone();
+    match (cmd, transaction.as_mut()) {
+        (Command::Multi(_), None) => *transaction = Some(Vec::new()),
+        (Command::Exec(_), Some(_)) => {
+            let queued = transaction.take().unwrap_or_default();
+            let _transaction = db.transaction_guard().await;
+            let _guard = db.propagate_guard();
+            Command::apply_transaction(&mut target, queued);
+        }
+        (cmd, Some(queued)) => queued.push((cmd, frame)),
+        (cmd, None) => {
+            let _command = db.command_guard().await;
+            let _guard = db.propagate_guard();
+            // the reply is dropped, reads such as PING are not propagated
+            cmd.apply_and_propagate(&mut target, frame);
+        }
+    }
+}
+
+// connection of the replica to the primary
+struct Link {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+
+impl Link {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buffer: BytesMut::with_capacity(4096),
+        }
+    }
+
+    async fn send(&mut self, args: &[&str]) -> Result<()> {
+        let frame = Frame::Array(
+            args.iter()
+                .map(|arg| Frame::BulkString(Bytes::from(arg.to_string())))
+                .collect(),
+        );
+        self.stream.write_all(&frame.serialize()).await?;
+        Ok(())
+    }
+
+    // send a command of the handshake, an error reply fails the sync
+    async fn command(&mut self, args: &[&str]) -> Result<()> {
+        self.send(args).await?;
+        match self.read_frame().await?.0 {
+            Frame::Error(e) => {
+                error!("Error reply to {} from master: {}", args[0], e);
+                Err(RedisErr::InvalidProtocol)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            return Err(RedisErr::ConnectionAborted);
+        }
+        Ok(())
+    }
+
+    async fn read_frame(&mut self) -> Result<(Frame, usize)> {
+        loop {
+            match Frame::parse(&self.buffer) {
+                Ok((frame, len)) => {
+                    self.buffer.advance(len);
+                    return Ok((frame, len));
+                }
+                Err(RedisErr::FrameIncomplete) => self.fill().await?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buffer[..end]).to_string();
+                self.buffer.advance(end + 2);
+                return Ok(line);
+            }
+            self.fill().await?;
+        }
+    }
+
+    // $len\r\n followed by len bytes without CRLF
+    async fn read_payload(&mut self) -> Result<Vec<u8>> {
+        let mut line = self.read_line().await?;
+        // the primary may send newlines to keep the link alive while preparing the snapshot
+        while line.is_empty() {
+            line = self.read_line().await?;
+        }
+        let len: usize = line
+            .strip_prefix('$')
+            .and_then(|len| len.parse().ok())
+            .ok_or(RedisErr::InvalidProtocol)?;
+        while self.buffer.len() < len {
+            self.fill().await?;
+        }
+        Ok(self.buffer.split_to(len).to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlog() {
+        let mut backlog = Backlog::new(8);
+        backlog.feed(b"abcdef");
+        assert_eq!(backlog.since(6, 2), Some(b"cdef".to_vec()));
+        assert_eq!(backlog.since(6, 6), Some(vec![]));
+        backlog.feed(b"ghij");
+        // ab are dropped
+        assert_eq!(backlog.since(10, 1), None);
+        assert_eq!(backlog.since(10, 2), Some(b"cdefghij".to_vec()));
+        assert_eq!(backlog.since(10, 11), None);
+    }
+
+    #[test]
+    fn test_continue_from() {
+        let replication = Replication::new();
+        let (replid, offset) = replication.start_backlog(1024);
+        assert_eq!(offset, 0);
+        replication.feed(b"*1\r\n$4\r\nPING\r\n");
+        assert_eq!(replication.continue_from(&replid, 1), Some(0));
+        assert_eq!(replication.continue_from(&replid, 15), Some(14));
+        assert_eq!(replication.continue_from(&replid, 16), None);
+        assert_eq!(replication.continue_from("?", -1), None);
+        assert_eq!(replication.continue_from(&random_hex(40), 1), None);
+    }
+
+    #[test]
+    fn test_parse_replconf() {
+        let frame = Frame::Array(vec![
+            Frame::BulkString(Bytes::from_static(b"REPLCONF")),
+            Frame::BulkString(Bytes::from_static(b"ack")),
+            Frame::BulkString(Bytes::from_static(b"42")),
+        ]);
+        assert_eq!(
+            parse_replconf(&frame),
+            Some(("ACK".to_string(), "42".to_string()))
+        );
+        assert_eq!(parse_replconf(&Frame::Array(vec![])), None);
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let replication = Arc::new(Replication::new());
+        replication.start_backlog(1024);
+        replication.add_replica(1, "127.0.0.1".to_string(), 7001, 0);
+        replication.add_replica(2, "127.0.0.1".to_string(), 7002, 0);
+        replication.feed(b"*1\r\n$4\r\nPING\r\n");
+        let offset = replication.offset();
+
+        // nothing acknowledged before the timeout
+        let acked = replication
+            .wait(1, offset, Some(Duration::from_millis(10)))
+            .await;
+        assert_eq!(acked, 0);
+
+        let waiter = replication.clone();
+        let wait = tokio::spawn(async move { waiter.wait(2, offset, None).await });
+        replication.ack(1, offset);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!wait.is_finished());
+        replication.ack(2, offset + 100);
+        assert_eq!(wait.await.unwrap(), 2);
+
+        // enough replicas have acknowledged the offset already
+        assert_eq!(replication.wait(1, offset, None).await, 2);
+    }
+}
@@ -0,0 +1,1091 @@
+//! Lua scripting
+//! scripts run one at a time in an embedded lua 5.1 interpreter, with no other command
+//! applied in between. the write commands called by a script are propagated once it returns,
+//! wrapped in MULTI and EXEC, so the aof and the replicas never run the script itself
+//!
+//! function libraries are loaded into the same interpreter, each registers named functions
+//! with redis.register_function. unlike the cached scripts, their code is saved in the rdb
+//! next to the key space and propagated by FUNCTION LOAD
+
+use crate::{cmd::Parser, db::DB, frame::Frame, helper::glob_match, RedisErr};
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use log::{debug, info, warn};
+use mlua::{
+    Function, HookTriggers, Lua, LuaOptions, MultiValue, RegistryKey, StdLib, Table, Value,
+};
+
+// instructions between two checks of SCRIPT KILL
+const KILL_CHECK_INTERVAL: u32 = 10_000;
+
+// flags a function can be registered with, only no-writes changes how it runs
+const FUNCTION_FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+// the script being run
+struct Running {
+    started: Instant,
+    // a script which has written to the key space can't be killed
+    wrote: bool,
+}
+
+// reachable from redis.call while a script runs
+struct Context {
+    db: DB,
+    read_only: bool,
+    // the writes called so far, propagated at once when the script returns
+    propagated: RefCell<Vec<Frame>>,
+}
+
+// a function registered by a library
+struct LibraryFunction {
+    callback: RegistryKey,
+    description: Option<String>,
+    flags: Vec<String>,
+}
+
+impl LibraryFunction {
+    fn no_writes(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "no-writes")
+    }
+}
+
+struct Library {
+    code: Bytes,
+    functions: BTreeMap<String, LibraryFunction>,
+}
+
+// what FUNCTION RESTORE does with the existing libraries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestorePolicy {
+    Flush,
+    Append,
+    Replace,
+}
+
+pub struct Scripting {
+    lua: Mutex<Lua>,
+    // bodies of the loaded scripts by their sha1
+    scripts: Mutex<HashMap<String, Bytes>>,
+    // function libraries by their name, locked after lua
+    libraries: Mutex<BTreeMap<String, Library>>,
+    running: Arc<Mutex<Option<Running>>>,
+    killed: Arc<AtomicBool>,
+}
+
+impl core::fmt::Debug for Scripting {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Scripting")
+    }
+}
+
+impl Scripting {
+    pub fn new() -> Self {
+        let running = Arc::new(Mutex::new(None));
+        let killed = Arc::new(AtomicBool::new(false));
+        let lua = new_lua(running.clone(), killed.clone()).expect("failed to create lua");
+        Self {
+            lua: Mutex::new(lua),
+            scripts: Mutex::new(HashMap::new()),
+            libraries: Mutex::new(BTreeMap::new()),
+            running,
+            killed,
+        }
+    }
+
+    // cache the script and return its sha1
+    pub fn load(&self, body: Bytes) -> String {
+        let sha = sha1hex(&body);
+        self.scripts.lock().unwrap().insert(sha.clone(), body);
+        sha
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.scripts
+            .lock()
+            .unwrap()
+            .contains_key(&sha.to_ascii_lowercase())
+    }
+
+    pub fn flush(&self) {
+        self.scripts.lock().unwrap().clear();
+    }
+
+    // stop the running script at its next check
+    pub fn kill(&self) -> std::result::Result<(), String> {
+        match self.running.lock().unwrap().as_ref() {
+            None => Err("NOTBUSY No scripts in execution right now.".to_string()),
+            Some(running) if running.wrote => Err("UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.".to_string()),
+            Some(_) => {
+                self.killed.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+    }
+
+    // a script has been running for longer than the threshold,
+    // other clients are replied BUSY instead of waiting for it
+    pub fn busy(&self, threshold: Duration) -> bool {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|running| running.started.elapsed() >= threshold)
+    }
+
+    pub fn eval(
+        &self,
+        db: &DB,
+        body: Bytes,
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+        read_only: bool,
+    ) -> Frame {
+        self.load(body.clone());
+        self.run(db, read_only, |lua| {
+            set_keys_args(lua, &keys, &args)?;
+            lua.load(body.as_ref()).set_name("=user_script").eval()
+        })
+    }
+
+    pub fn eval_sha(
+        &self,
+        db: &DB,
+        sha: &str,
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+        read_only: bool,
+    ) -> Frame {
+        let body = self
+            .scripts
+            .lock()
+            .unwrap()
+            .get(&sha.to_ascii_lowercase())
+            .cloned();
+        match body {
+            Some(body) => self.eval(db, body, keys, args, read_only),
+            None => Frame::Error("NOSCRIPT No matching script. Please use EVAL.".to_string()),
+        }
+    }
+
+    // load a library, its code starts with the #!lua name=<library> line,
+    // return the name of the library
+    pub fn function_load(&self, code: Bytes, replace: bool) -> std::result::Result<String, String> {
+        let name = library_name(&code)?;
+        let lua = self.lua.lock().unwrap();
+        let mut libraries = self.libraries.lock().unwrap();
+        if libraries.contains_key(&name) && !replace {
+            return Err(format!("ERR Library '{}' already exists", name));
+        }
+        let functions = register_functions(&lua, &code)?;
+        for function in functions.keys() {
+            if libraries
+                .iter()
+                .any(|(library, other)| *library != name && other.functions.contains_key(function))
+            {
+                return Err(format!("ERR Function {} already exists", function));
+            }
+        }
+        libraries.insert(name.clone(), Library { code, functions });
+        // the callbacks of a replaced library are released
+        lua.expire_registry_values();
+        Ok(name)
+    }
+
+    pub fn function_delete(&self, name: &str) -> std::result::Result<(), String> {
+        let lua = self.lua.lock().unwrap();
+        if self.libraries.lock().unwrap().remove(name).is_none() {
+            return Err("ERR Library not found".to_string());
+        }
+        lua.expire_registry_values();
+        Ok(())
+    }
+
+    pub fn function_flush(&self) {
+        let lua = self.lua.lock().unwrap();
+        self.libraries.lock().unwrap().clear();
+        lua.expire_registry_values();
+    }
+
+    // the libraries matching the pattern, as replied by FUNCTION LIST
+    pub fn function_list(&self, pattern: Option<&str>, with_code: bool) -> Frame {
+        let bulk = |s: &str| Frame::BulkString(Bytes::from(s.to_string()));
+        let libraries = self.libraries.lock().unwrap();
+        Frame::Array(
+            libraries
+                .iter()
+                .filter(|(name, _)| {
+                    pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), name.as_bytes()))
+                })
+                .map(|(name, library)| {
+                    let functions = library
+                        .functions
+                        .iter()
+                        .map(|(name, function)| {
+                            Frame::Map(vec![
+                                (bulk("name"), bulk(name)),
+                                (
+                                    bulk("description"),
+                                    function.description.as_deref().map_or(Frame::Nil, bulk),
+                                ),
+                                (
+                                    bulk("flags"),
+                                    Frame::Set(function.flags.iter().map(|f| bulk(f)).collect()),
+                                ),
+                            ])
+                        })
+                        .collect();
+                    let mut fields = vec![
+                        (bulk("library_name"), bulk(name)),
+                        (bulk("engine"), bulk("LUA")),
+                        (bulk("functions"), Frame::Array(functions)),
+                    ];
+                    if with_code {
+                        fields.push((
+                            bulk("library_code"),
+                            Frame::BulkString(library.code.clone()),
+                        ));
+                    }
+                    Frame::Map(fields)
+                })
+                .collect(),
+        )
+    }
+
+    // the code of all the libraries, to be saved with the key space
+    pub fn function_codes(&self) -> Vec<Bytes> {
+        self.libraries
+            .lock()
+            .unwrap()
+            .values()
+            .map(|library| library.code.clone())
+            .collect()
+    }
+
+    // load the libraries of a snapshot or a FUNCTION RESTORE payload,
+    // all of them are compiled and checked before any existing library is touched
+    pub fn function_restore(
+        &self,
+        codes: Vec<Bytes>,
+        policy: RestorePolicy,
+    ) -> std::result::Result<(), String> {
+        let lua = self.lua.lock().unwrap();
+        let mut libraries = self.libraries.lock().unwrap();
+        let mut restored = BTreeMap::new();
+        for code in codes {
+            let name = library_name(&code)?;
+            if restored.contains_key(&name) {
+                return Err(format!("ERR Library {} already exists", name));
+            }
+            let functions = register_functions(&lua, &code)?;
+            restored.insert(name, Library { code, functions });
+        }
+        // the existing libraries left next to the restored ones
+        let kept = |name: &String| match policy {
+            RestorePolicy::Flush => false,
+            RestorePolicy::Append | RestorePolicy::Replace => !restored.contains_key(name),
+        };
+        if policy == RestorePolicy::Append {
+            if let Some(name) = restored.keys().find(|name| libraries.contains_key(*name)) {
+                return Err(format!("ERR Library {} already exists", name));
+            }
+        }
+        let mut functions = HashSet::new();
+        for library in restored.values().chain(
+            libraries
+                .iter()
+                .filter(|(name, _)| kept(name))
+                .map(|(_, library)| library),
+        ) {
+            if let Some(function) = library.functions.keys().find(|f| !functions.insert(*f)) {
+                return Err(format!("ERR Function {} already exists", function));
+            }
+        }
+
+        libraries.retain(|name, _| kept(name));
+        libraries.extend(restored);
+        // the callbacks of the replaced libraries are released
+        lua.expire_registry_values();
+        Ok(())
+    }
+
+    // FCALL and FCALL_RO, a function registered with the no-writes flag can't write,
+    // and only such a function can be called read only
+    pub fn fcall(
+        &self,
+        db: &DB,
+        name: &str,
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+        read_only: bool,
+    ) -> Frame {
+        let no_writes = match self
+            .libraries
+            .lock()
+            .unwrap()
+            .values()
+            .find_map(|library| library.functions.get(name))
+        {
+            Some(function) => function.no_writes(),
+            None => return Frame::Error("ERR Function not found".to_string()),
+        };
+        if read_only && !no_writes {
+            return Frame::Error(
+                "ERR Can not execute a script with write flag using *_ro command.".to_string(),
+            );
+        }
+        self.run(db, no_writes, |lua| {
+            let callback: Function = {
+                let libraries = self.libraries.lock().unwrap();
+                match libraries
+                    .values()
+                    .find_map(|library| library.functions.get(name))
+                {
+                    Some(function) => lua.registry_value(&function.callback)?,
+                    None => {
+                        return Err(mlua::Error::RuntimeError(
+                            "ERR Function not found".to_string(),
+                        ))
+                    }
+                }
+            };
+            callback.call((values_table(lua, &keys)?, values_table(lua, &args)?))
+        })
+    }
+
+    // run the lua code, the caller holds the transaction guard so no other command
+    // is applied until it returns, the write commands it calls are propagated under the held guard
+    fn run<F>(&self, db: &DB, read_only: bool, f: F) -> Frame
+    where
+        F: for<'lua> FnOnce(&'lua Lua) -> mlua::Result<Value<'lua>>,
+    {
+        let _propagate = db.propagate_guard();
+        let lua = self.lua.lock().unwrap();
+
+        *self.running.lock().unwrap() = Some(Running {
+            started: Instant::now(),
+            wrote: false,
+        });
+        self.killed.store(false, Ordering::SeqCst);
+        lua.set_app_data(Context {
+            db: db.clone(),
+            read_only,
+            propagated: RefCell::new(Vec::new()),
+        });
+
+        let resp = match f(&lua) {
+            Ok(value) => from_lua(value),
+            Err(e) => error_reply(e),
+        };
+
+        if let Some(ctx) = lua.remove_app_data::<Context>() {
+            db.propagate_transaction(ctx.propagated.into_inner());
+        }
+        self.running.lock().unwrap().take();
+        resp
+    }
+}
+
+impl Default for Scripting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn sha1hex(body: &[u8]) -> String {
+    sha1_smol::Sha1::from(body).digest().to_string()
+}
+
+fn new_lua(running: Arc<Mutex<Option<Running>>>, killed: Arc<AtomicBool>) -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+    let globals = lua.globals();
+    // scripts have no access to the file system
+    for name in ["dofile", "loadfile"] {
+        globals.set(name, Value::Nil)?;
+    }
+
+    let parser = Arc::new(Parser::new());
+    let redis = lua.create_table()?;
+    let (call_parser, call_running) = (parser.clone(), running.clone());
+    redis.set(
+        "call",
+        lua.create_function(move |lua, args: MultiValue| {
+            match call(lua, &call_parser, &call_running, args) {
+                Ok(Frame::Error(e)) | Err(e) => Err(mlua::Error::RuntimeError(e)),
+                Ok(frame) => to_lua(lua, frame),
+            }
+        })?,
+    )?;
+    redis.set(
+        "pcall",
+        lua.create_function(move |lua, args: MultiValue| {
+            match call(lua, &parser, &running, args) {
+                Ok(Frame::Error(e)) | Err(e) => to_lua(lua, Frame::Error(e)),
+                Ok(frame) => to_lua(lua, frame),
+            }
+        })?,
+    )?;
+    redis.set(
+        "status_reply",
+        lua.create_function(|lua, status: mlua::String| {
+            let reply = lua.create_table()?;
+            reply.set("ok", status)?;
+            Ok(reply)
+        })?,
+    )?;
+    redis.set(
+        "error_reply",
+        lua.create_function(|lua, error: mlua::String| {
+            let reply = lua.create_table()?;
+            reply.set("err", error)?;
+            Ok(reply)
+        })?,
+    )?;
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, body: mlua::String| Ok(sha1hex(body.as_bytes())))?,
+    )?;
+    redis.set(
+        "log",
+        lua.create_function(|_, (level, message): (i64, mlua::String)| {
+            let message = message.to_string_lossy();
+            match level {
+                0 => debug!("script: {}", message),
+                1 | 2 => info!("script: {}", message),
+                _ => warn!("script: {}", message),
+            }
+            Ok(())
+        })?,
+    )?;
+    for (i, level) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"]
+        .into_iter()
+        .enumerate()
+    {
+        redis.set(level, i)?;
+    }
+    globals.set("redis", redis)?;
+    // scripts share the interpreter, so they can't create globals that leak into the next one
+    let guard = lua.create_table()?;
+    guard.set(
+        "__newindex",
+        lua.create_function(|_, (_, name): (Value, Value)| -> mlua::Result<()> {
+            Err(mlua::Error::RuntimeError(format!(
+                "Script attempted to create global variable '{}'",
+                global_name(&name)
+            )))
+        })?,
+    )?;
+    guard.set(
+        "__index",
+        lua.create_function(|_, (_, name): (Value, Value)| -> mlua::Result<()> {
+            Err(mlua::Error::RuntimeError(format!(
+                "Script attempted to access nonexistent global variable '{}'",
+                global_name(&name)
+            )))
+        })?,
+    )?;
+    globals.set_metatable(Some(guard));
+    drop(globals);
+
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(KILL_CHECK_INTERVAL),
+        move |_, _| {
+            if killed.swap(false, Ordering::SeqCst) {
+                return Err(mlua::Error::RuntimeError(
+                    "ERR Script killed by user with SCRIPT KILL...".to_string(),
+                ));
+            }
+            Ok(())
+        },
+    );
+    Ok(lua)
+}
+
+fn set_keys_args(lua: &Lua, keys: &[Bytes], args: &[Bytes]) -> mlua::Result<()> {
+    let globals = lua.globals();
+    for (name, values) in [("KEYS", keys), ("ARGV", args)] {
+        globals.raw_set(name, values_table(lua, values)?)?;
+    }
+    Ok(())
+}
+
+fn global_name(name: &Value) -> String {
+    match name {
+        Value::String(name) => name.to_string_lossy().to_string(),
+        name => format!("{:?}", name),
+    }
+}
+
+fn values_table<'lua>(lua: &'lua Lua, values: &[Bytes]) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table_with_capacity(values.len(), 0)?;
+    for value in values {
+        table.raw_push(lua.create_string(value)?)?;
+    }
+    Ok(table)
+}
+
+// the name in the metadata line of a library, such as #!lua name=mylib
+fn library_name(code: &[u8]) -> std::result::Result<String, String> {
+    let line = code.split(|&c| c == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let Some(metadata) = line.trim_end().strip_prefix("#!") else {
+        return Err("ERR Missing library metadata".to_string());
+    };
+    let mut parts = metadata.split(' ').filter(|part| !part.is_empty());
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("ERR Engine '{}' not found", engine));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(format!("ERR Invalid metadata value given: {}", part)),
+        }
+    }
+    let Some(name) = name else {
+        return Err("ERR Library name was not given".to_string());
+    };
+    if !is_valid_name(&name) {
+        return Err("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string());
+    }
+    Ok(name)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// run the code of a library with redis.register_function available,
+// the functions it registers keep the environment they are defined in
+fn register_functions(
+    lua: &Lua,
+    code: &[u8],
+) -> std::result::Result<BTreeMap<String, LibraryFunction>, String> {
+    let registered = RefCell::new(BTreeMap::new());
+    lua.scope(|scope| {
+        let redis = lua.create_table()?;
+        redis.set(
+            "register_function",
+            scope.create_function(|lua, args: MultiValue| {
+                let (name, function) = register_args(lua, args)?;
+                let mut registered = registered.borrow_mut();
+                if registered.contains_key(&name) {
+                    return Err(mlua::Error::RuntimeError(
+                        "Function already exists in the library".to_string(),
+                    ));
+                }
+                registered.insert(name, function);
+                Ok(())
+            })?,
+        )?;
+        let globals = lua.globals();
+        redis.set_metatable(Some(index_table(lua, globals.get::<_, Table>("redis")?)?));
+        let env = lua.create_table()?;
+        env.set("redis", redis)?;
+        env.set_metatable(Some(index_table(lua, globals)?));
+        // the metadata line isn't lua, the newline after it is kept for the line numbers
+        let body = &code[code.iter().position(|&c| c == b'\n').unwrap_or(code.len())..];
+        lua.load(body)
+            .set_name("=user_function")
+            .set_environment(env)
+            .exec()
+    })
+    .map_err(|e| match e {
+        mlua::Error::SyntaxError { message, .. } => {
+            format!("ERR Error compiling function: {}", message)
+        }
+        e => format!("ERR Error registering functions: {}", error_message(&e)),
+    })?;
+
+    let registered = registered.into_inner();
+    if registered.is_empty() {
+        return Err("ERR No functions registered".to_string());
+    }
+    Ok(registered)
+}
+
+// a table looking up the missing fields in another one
+fn index_table<'lua>(lua: &'lua Lua, table: Table<'lua>) -> mlua::Result<Table<'lua>> {
+    let metatable = lua.create_table()?;
+    metatable.set("__index", table)?;
+    Ok(metatable)
+}
+
+// redis.register_function(name, callback) or
+// redis.register_function{function_name=name, callback=callback, flags={...}, description=text}
+fn register_args(lua: &Lua, args: MultiValue) -> mlua::Result<(String, LibraryFunction)> {
+    fn error<T>(message: &str) -> mlua::Result<T> {
+        Err(mlua::Error::RuntimeError(message.to_string()))
+    }
+    let mut args = args.into_vec();
+    let (name, callback, flags, description) = match args.len() {
+        1 => {
+            let Value::Table(named) = args.remove(0) else {
+                return error("calling redis.register_function with a single argument is only applicable to Lua table (representing named arguments).");
+            };
+            let (mut name, mut callback, mut flags, mut description) =
+                (Value::Nil, Value::Nil, Value::Nil, Value::Nil);
+            for pair in named.pairs::<String, Value>() {
+                let (key, value) = pair?;
+                match key.as_str() {
+                    "function_name" => name = value,
+                    "callback" => callback = value,
+                    "flags" => flags = value,
+                    "description" => description = value,
+                    _ => return error("unknown argument given to redis.register_function"),
+                }
+            }
+            (name, callback, flags, description)
+        }
+        2 => {
+            let callback = args.pop().unwrap_or(Value::Nil);
+            let name = args.pop().unwrap_or(Value::Nil);
+            (name, callback, Value::Nil, Value::Nil)
+        }
+        _ => return error("wrong number of arguments to redis.register_function"),
+    };
+
+    let Value::String(name) = name else {
+        return error("function_name argument given to redis.register_function must be a string");
+    };
+    let name = name.to_str()?.to_string();
+    if !is_valid_name(&name) {
+        return error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long");
+    }
+    let Value::Function(callback) = callback else {
+        return error("callback argument given to redis.register_function must be a function");
+    };
+    let flags = match flags {
+        Value::Nil => vec![],
+        Value::Table(flags) => flags
+            .sequence_values::<String>()
+            .map(|flag| match flag {
+                Ok(flag) if FUNCTION_FLAGS.contains(&flag.as_str()) => Ok(flag),
+                _ => error("unknown flag given"),
+            })
+            .collect::<mlua::Result<_>>()?,
+        _ => return error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        ),
+    };
+    let description = match description {
+        Value::Nil => None,
+        Value::String(description) => Some(description.to_string_lossy().to_string()),
+        _ => {
+            return error("description argument given to redis.register_function must be a string")
+        }
+    };
+    Ok((
+        name,
+        LibraryFunction {
+            callback: lua.create_registry_value(callback)?,
+            description,
+            flags,
+        },
+    ))
+}
+
+// the first line of the message, without the callback wrapping it
+fn error_message(e: &mlua::Error) -> String {
+    match e {
+        mlua::Error::CallbackError { cause, .. } => error_message(cause),
+        mlua::Error::RuntimeError(message) => {
+            message.lines().next().unwrap_or_default().to_string()
+        }
+        e => e.to_string().lines().next().unwrap_or_default().to_string(),
+    }
+}
+
+// apply a command called by redis.call or redis.pcall,
+// an error is the reply the script sees
+fn call(
+    lua: &Lua,
+    parser: &Parser,
+    running: &Mutex<Option<Running>>,
+    args: MultiValue,
+) -> std::result::Result<Frame, String> {
+    let Some((mut db, read_only)) = lua
+        .app_data_ref::<Context>()
+        .map(|ctx| (ctx.db.clone(), ctx.read_only))
+    else {
+        return Err("ERR redis.call is only available while a script runs".to_string());
+    };
+    if args.is_empty() {
+        return Err("ERR Please specify at least one argument for this redis lib call".to_string());
+    }
+    let request = Frame::Array(
+        args.into_iter()
+            .map(|arg| match arg {
+                Value::String(s) => Ok(Frame::BulkString(Bytes::copy_from_slice(s.as_bytes()))),
+                Value::Integer(i) => Ok(Frame::BulkString(Bytes::from(i.to_string()))),
+                Value::Number(n) => Ok(Frame::BulkString(Bytes::from(n.to_string()))),
+                _ => Err("ERR Lua redis lib command arguments must be strings or integers"),
+            })
+            .collect::<std::result::Result<_, _>>()?,
+    );
+
+    let cmd = parser.parse(request.clone()).map_err(|e| match e {
+        RedisErr::UnknownCommand => "ERR Unknown Redis command called from script".to_string(),
+        e => e.to_string(),
+    })?;
+    if cmd.is_noscript() {
+        return Err("ERR This Redis command is not allowed from script".to_string());
+    }
+    let is_write = cmd.is_write();
+    if is_write && read_only {
+        return Err("ERR Write commands are not allowed from read-only scripts.".to_string());
+    }
+    if is_write && db.is_replica() {
+        return Err("READONLY You can't write against a read only replica.".to_string());
+    }
+    if let Some(cluster) = db.cluster() {
+        if cluster.redirect(&db, &cmd.keys(&request), false).is_some() {
+            return Err(
+                "ERR Script attempted to access a non local key in a cluster node".to_string(),
+            );
+        }
+    }
+
+    let (resp, request) = cmd.apply_and_collect(&mut db, request);
+    if let Some(ctx) = lua.app_data_ref::<Context>() {
+        ctx.propagated.borrow_mut().extend(request);
+    }
+    if is_write && !matches!(resp, Frame::Error(_)) {
+        if let Some(running) = running.lock().unwrap().as_mut() {
+            running.wrote = true;
+        }
+    }
+    Ok(resp)
+}
+
+// the reply of a command as seen by the script
+fn to_lua(lua: &Lua, frame: Frame) -> mlua::Result<Value<'_>> {
+    Ok(match frame.into_resp2() {
+        Frame::Integer(i) => Value::Integer(i),
+        Frame::BulkString(b) => Value::String(lua.create_string(&b)?),
+        Frame::SimpleString(s) => Value::Table(single_field(lua, "ok", s)?),
+        Frame::Error(e) => Value::Table(single_field(lua, "err", e)?),
+        Frame::Array(frames) => {
+            let table = lua.create_table_with_capacity(frames.len(), 0)?;
+            for frame in frames {
+                table.raw_push(to_lua(lua, frame)?)?;
+            }
+            Value::Table(table)
+        }
+        // nil replies are false, a nil can't be kept in a lua array
+        _ => Value::Boolean(false),
+    })
+}
+
+fn single_field<'lua>(lua: &'lua Lua, field: &str, value: String) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set(field, value)?;
+    Ok(table)
+}
+
+// the value returned by the script as a reply
+fn from_lua(value: Value) -> Frame {
+    match value {
+        Value::Boolean(true) => Frame::Integer(1),
+        Value::Integer(i) => Frame::Integer(i),
+        Value::Number(n) => Frame::Integer(n as i64),
+        Value::String(s) => Frame::BulkString(Bytes::copy_from_slice(s.as_bytes())),
+        Value::Table(table) => {
+            if let Ok(Value::String(e)) = table.raw_get("err") {
+                return Frame::Error(e.to_string_lossy().to_string());
+            }
+            if let Ok(Value::String(s)) = table.raw_get("ok") {
+                return Frame::SimpleString(s.to_string_lossy().to_string());
+            }
+            // an array ends at the first nil
+            let mut frames = Vec::new();
+            for i in 1.. {
+                match table.raw_get(i) {
+                    Ok(Value::Nil) | Err(_) => break,
+                    Ok(value) => frames.push(from_lua(value)),
+                }
+            }
+            Frame::Array(frames)
+        }
+        _ => Frame::Nil,
+    }
+}
+
+// errors raised by redis.call are replied as they are
+fn error_reply(e: mlua::Error) -> Frame {
+    match e {
+        mlua::Error::CallbackError { cause, .. } => error_reply(cause.as_ref().clone()),
+        mlua::Error::SyntaxError { message, .. } => Frame::Error(format!(
+            "ERR Error compiling script (new function): {}",
+            message
+        )),
+        mlua::Error::RuntimeError(message) if is_error_code(&message) => Frame::Error(message),
+        // the traceback after the first line can't be carried by an error reply
+        mlua::Error::RuntimeError(message) => Frame::Error(format!(
+            "ERR Error running script: {}",
+            message.lines().next().unwrap_or_default()
+        )),
+        e => Frame::Error(format!(
+            "ERR Error running script: {}",
+            e.to_string().lines().next().unwrap_or_default()
+        )),
+    }
+}
+
+// errors of the commands start with an upper case code, such as ERR or WRONGTYPE
+fn is_error_code(message: &str) -> bool {
+    message
+        .split(' ')
+        .next()
+        .is_some_and(|code| !code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(db: &DB, body: &str, keys: &[&str], args: &[&str]) -> Frame {
+        let bytes = |v: &[&str]| v.iter().map(|s| Bytes::from(s.to_string())).collect();
+        db.scripting().eval(
+            db,
+            Bytes::from(body.to_string()),
+            bytes(keys),
+            bytes(args),
+            false,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_eval() {
+        let db = DB::new();
+        assert_eq!(
+            eval(&db, "return {1, 'two', {ok='OK'}, nil, 5}", &[], &[]),
+            Frame::Array(vec![
+                Frame::Integer(1),
+                Frame::BulkString(Bytes::from("two")),
+                Frame::SimpleString("OK".to_string()),
+            ])
+        );
+        assert_eq!(
+            eval(
+                &db,
+                "redis.call('set', KEYS[1], ARGV[1]) return redis.call('get', KEYS[1])",
+                &["key"],
+                &["value"],
+            ),
+            Frame::BulkString(Bytes::from("value"))
+        );
+        assert_eq!(
+            eval(&db, "return redis.call('get', 'nokey')", &[], &[]),
+            Frame::Nil
+        );
+        assert_eq!(
+            eval(&db, "return redis.call('lpush', 'key', 'x')", &[], &[]),
+            Frame::Error(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            )
+        );
+        assert_eq!(
+            eval(
+                &db,
+                "return redis.pcall('lpush', 'key', 'x')['err'] ~= nil",
+                &[],
+                &[]
+            ),
+            Frame::Integer(1)
+        );
+        assert!(matches!(
+            eval(&db, "return redis.call('multi')", &[], &[]),
+            Frame::Error(e) if e.contains("not allowed from script")
+        ));
+        assert!(matches!(eval(&db, "return +", &[], &[]), Frame::Error(_)));
+        // globals don't leak between scripts
+        assert!(matches!(
+            eval(&db, "leak = 42 return 1", &[], &[]),
+            Frame::Error(e) if e.contains("Script attempted to create global variable 'leak'")
+        ));
+        assert!(matches!(
+            eval(&db, "return leak", &[], &[]),
+            Frame::Error(e) if e.contains("nonexistent global variable 'leak'")
+        ));
+        assert_eq!(
+            eval(&db, "local n = 42 return n", &[], &[]),
+            Frame::Integer(42)
+        );
+        assert!(matches!(
+            eval(&db, "error('boom')", &[], &[]),
+            Frame::Error(e) if e.starts_with("ERR") && e.contains("boom")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_eval_sha() {
+        let db = DB::new();
+        let sha = db.scripting().load(Bytes::from("return ARGV[1]"));
+        assert_eq!(sha, sha1hex(b"return ARGV[1]"));
+        assert!(db.scripting().exists(&sha.to_uppercase()));
+        assert_eq!(
+            db.scripting()
+                .eval_sha(&db, &sha, vec![], vec![Bytes::from("a")], false),
+            Frame::BulkString(Bytes::from("a"))
+        );
+        assert_eq!(
+            db.scripting().eval(
+                &db,
+                Bytes::from("return redis.call('set', 'k', 'v')"),
+                vec![],
+                vec![],
+                true
+            ),
+            Frame::Error("ERR Write commands are not allowed from read-only scripts.".to_string())
+        );
+        db.scripting().flush();
+        assert!(!db.scripting().exists(&sha));
+        assert!(matches!(
+            db.scripting().eval_sha(&db, &sha, vec![], vec![], false),
+            Frame::Error(e) if e.starts_with("NOSCRIPT")
+        ));
+        assert!(db.scripting().kill().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_functions() {
+        let db = DB::new();
+        let scripting = db.scripting();
+        let code = "#!lua name=mylib\n\
+            redis.register_function('set_get', function(keys, args)\n\
+                redis.call('set', keys[1], args[1])\n\
+                return redis.call('get', keys[1])\n\
+            end)\n\
+            redis.register_function{function_name='echo', callback=function(keys, args) return args[1] end, flags={'no-writes'}}";
+        assert_eq!(
+            scripting.function_load(Bytes::from(code), false),
+            Ok("mylib".to_string())
+        );
+        assert!(scripting
+            .function_load(Bytes::from(code), false)
+            .is_err_and(|e| e.contains("already exists")));
+        assert_eq!(
+            scripting.fcall(
+                &db,
+                "set_get",
+                vec![Bytes::from("key")],
+                vec![Bytes::from("value")],
+                false
+            ),
+            Frame::BulkString(Bytes::from("value"))
+        );
+        assert_eq!(
+            scripting.fcall(&db, "echo", vec![], vec![Bytes::from("hi")], true),
+            Frame::BulkString(Bytes::from("hi"))
+        );
+        assert!(matches!(
+            scripting.fcall(&db, "set_get", vec![Bytes::from("key")], vec![Bytes::from("v")], true),
+            Frame::Error(e) if e.contains("*_ro")
+        ));
+        assert_eq!(
+            scripting.fcall(&db, "nofunc", vec![], vec![], false),
+            Frame::Error("ERR Function not found".to_string())
+        );
+
+        // a function name is unique across the libraries
+        assert!(scripting
+            .function_load(
+                Bytes::from("#!lua name=other\nredis.register_function('echo', function() end)"),
+                false
+            )
+            .is_err_and(|e| e.contains("Function echo already exists")));
+        for (code, error) in [
+            ("return 1", "Missing library metadata"),
+            ("#!js name=lib\n", "Engine 'js' not found"),
+            ("#!lua\n", "Library name was not given"),
+            ("#!lua name=lib\nreturn 1", "No functions registered"),
+            (
+                "#!lua name=lib\nredis.register_function('f', 1)",
+                "must be a function",
+            ),
+            ("#!lua name=lib\nreturn +", "Error compiling function"),
+        ] {
+            assert!(
+                scripting
+                    .function_load(Bytes::from(code), false)
+                    .is_err_and(|e| e.contains(error)),
+                "{}",
+                code
+            );
+        }
+
+        assert_eq!(scripting.function_codes(), vec![Bytes::from(code)]);
+        assert!(matches!(
+            scripting.function_list(Some("my*"), true),
+            Frame::Array(libraries) if libraries.len() == 1
+        ));
+        assert_eq!(
+            scripting.function_list(Some("other*"), false),
+            Frame::Array(vec![])
+        );
+        assert!(scripting.function_delete("nolib").is_err());
+        assert_eq!(scripting.function_delete("mylib"), Ok(()));
+        assert_eq!(
+            scripting.fcall(&db, "echo", vec![], vec![], false),
+            Frame::Error("ERR Function not found".to_string())
+        );
+
+        // restoring without a policy keeps the existing libraries
+        scripting
+            .function_restore(vec![Bytes::from(code)], RestorePolicy::Append)
+            .unwrap();
+        assert!(scripting
+            .function_restore(vec![Bytes::from(code)], RestorePolicy::Append)
+            .is_err());
+        assert_eq!(
+            scripting.function_restore(vec![Bytes::from(code)], RestorePolicy::Flush),
+            Ok(())
+        );
+
+        // nothing is flushed or loaded unless all the libraries load
+        let other = "#!lua name=other\nredis.register_function('other', function() return 1 end)";
+        let broken = "#!lua name=broken\nredis.register_function(";
+        for policy in [
+            RestorePolicy::Flush,
+            RestorePolicy::Append,
+            RestorePolicy::Replace,
+        ] {
+            assert!(scripting
+                .function_restore(vec![Bytes::from(other), Bytes::from(broken)], policy)
+                .is_err());
+            assert_eq!(scripting.function_codes(), vec![Bytes::from(code)]);
+        }
+        // a function of another library
+        let clash = "#!lua name=clash\nredis.register_function('echo', function() return 1 end)";
+        assert!(scripting
+            .function_restore(vec![Bytes::from(clash)], RestorePolicy::Replace)
+            .is_err_and(|e| e.contains("Function echo already exists")));
+        assert_eq!(
+            scripting.function_restore(vec![Bytes::from(clash)], RestorePolicy::Flush),
+            Ok(())
+        );
+        assert_eq!(scripting.function_codes(), vec![Bytes::from(clash)]);
+        scripting.function_flush();
+        assert!(scripting.function_codes().is_empty());
+    }
+}
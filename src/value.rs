@@ -1,6 +1,13 @@
 //! Redis Data Definition
 //! All Redis data types are defined in this document: https://redis.io/topics/data-types-intro
-//! We start implementing the most common data types: String, List, Set, Hash, ZSet
+//! We start implementing the most common data types: String, List, Set, Hash, ZSet, Stream
+
+mod stream;
+
+pub use stream::{
+    AutoClaimed, ClaimOptions, ConsumerGroup, NewStreamId, PendingEntry, Stream, StreamEntry,
+    StreamFields, StreamId, StreamTrim,
+};
 
 use std::{
     collections::{HashMap, HashSet, VecDeque},
@@ -70,7 +77,13 @@ impl ZSet {
     pub fn len(&self) -> usize {
         self.hmap.len()
     }
-    
+
+    // members with their scores, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.hmap.iter().map(|(member, score)| (member, *score))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn zadd(
         &mut self,
         nx: bool,   // Only set the key if it does not already exist.
@@ -176,6 +189,44 @@ impl BloomFilter {
     pub fn contains(&self, value: &str) -> bool {
         self.bloom.check(&value.to_string())
     }
+
+    // the number of bits, the number of hash functions, the sip keys and the bitmap,
+    // the integers are little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.bloom.number_of_bits().to_le_bytes());
+        data.extend_from_slice(&self.bloom.number_of_hash_functions().to_le_bytes());
+        for (k0, k1) in self.bloom.sip_keys() {
+            data.extend_from_slice(&k0.to_le_bytes());
+            data.extend_from_slice(&k1.to_le_bytes());
+        }
+        data.extend_from_slice(&self.bloom.bitmap());
+        data
+    }
+
+    // None if the data is not written by to_bytes
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        const HEADER_LEN: usize = 8 + 4 + 4 * 8;
+        // far more than a filter of any size needs, each is a hash of every add and check
+        const MAX_HASH_FUNCTIONS: u32 = 64;
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+        let bits = u64_at(0);
+        let k_num = u32::from_le_bytes(data[8..12].try_into().unwrap());
+        let sip_keys = [(u64_at(12), u64_at(20)), (u64_at(28), u64_at(36))];
+        let bitmap = &data[HEADER_LEN..];
+        if bits == 0
+            || !(1..=MAX_HASH_FUNCTIONS).contains(&k_num)
+            || bits.div_ceil(8) != bitmap.len() as u64
+        {
+            return None;
+        }
+        Some(Self {
+            bloom: Bloom::from_existing(bitmap, bits, k_num, sip_keys),
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -186,6 +237,7 @@ pub enum ValueType {
     Set,
     Hash,
     ZSet,
+    Stream,
     BloomFilter,
 }
 
@@ -198,6 +250,7 @@ impl ValueType {
             ValueType::Set => "set",
             ValueType::Hash => "hash",
             ValueType::ZSet => "zset",
+            ValueType::Stream => "stream",
             ValueType::BloomFilter => "bloomfilter",
         }
     }
@@ -211,6 +264,7 @@ pub enum Value {
     Set(HashSet<Bytes>),
     Hash(HashMap<String, Bytes>),
     ZSet(ZSet),
+    Stream(Stream),
 
     BloomFilter(BloomFilter),
 }
@@ -259,6 +313,28 @@ impl Display for Value {
                 }
                 write!(f, "}}")
             }
+            Value::Stream(v) => {
+                write!(f, "[")?;
+                for (i, (id, fields)) in v.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}:{{", id)?;
+                    for (j, (field, value)) in fields.iter().enumerate() {
+                        if j != 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(
+                            f,
+                            "{}:{}",
+                            String::from_utf8_lossy(field),
+                            String::from_utf8_lossy(value)
+                        )?;
+                    }
+                    write!(f, "}}")?;
+                }
+                write!(f, "]")
+            }
             Value::BloomFilter(_v) => {
                 // TODO: implement this
                 write!(f, "{{")?;
@@ -0,0 +1,722 @@
+//! Stream data type
+//! entries are ordered by their ids, an id is the milliseconds it's added at and a sequence number,
+//! the ids only grow, a new entry must have an id greater than any added before,
+//! a consumer group tracks the entries delivered to its consumers until they're acknowledged
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Display, Formatter},
+};
+
+use bytes::Bytes;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    // the smallest id greater than it, None for the max
+    pub fn next(self) -> Option<Self> {
+        match (self.ms, self.seq) {
+            (u64::MAX, u64::MAX) => None,
+            (ms, u64::MAX) => Some(Self::new(ms + 1, 0)),
+            (ms, seq) => Some(Self::new(ms, seq + 1)),
+        }
+    }
+
+    // the greatest id less than it, None for the min
+    pub fn prev(self) -> Option<Self> {
+        match (self.ms, self.seq) {
+            (0, 0) => None,
+            (ms, 0) => Some(Self::new(ms - 1, u64::MAX)),
+            (ms, seq) => Some(Self::new(ms, seq - 1)),
+        }
+    }
+
+    // "ms-seq", or "ms" with the sequence given
+    pub fn parse(s: &str, default_seq: u64) -> Option<Self> {
+        match s.split_once('-') {
+            Some((ms, seq)) => Some(Self::new(ms.parse().ok()?, seq.parse().ok()?)),
+            None => Some(Self::new(s.parse().ok()?, default_seq)),
+        }
+    }
+}
+
+impl Display for StreamId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+// the id of a new entry: `*`, `ms-*` or `ms-seq`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewStreamId {
+    Auto,
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+// entries evicted from the head of the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamTrim {
+    // keep at most the number of entries
+    MaxLen(usize),
+    // evict the entries with an id less than it
+    MinId(StreamId),
+}
+
+pub type StreamFields = Vec<(Bytes, Bytes)>;
+pub type StreamEntry = (StreamId, StreamFields);
+
+// an entry delivered to a consumer but not acknowledged yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: String,
+    // unix time in milliseconds of the last delivery
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Consumer {
+    // the last time it read or claimed, in unix milliseconds
+    pub seen_time: u64,
+    // the last time it got any entry, None if it never did
+    pub active_time: Option<u64>,
+    pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    fn new(now_ms: u64) -> Self {
+        Self {
+            seen_time: now_ms,
+            ..Default::default()
+        }
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    // ids of its pending entries in order
+    pub fn pending(&self) -> impl Iterator<Item = &StreamId> {
+        self.pending.iter()
+    }
+}
+
+// how XCLAIM updates the entries it claims
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClaimOptions {
+    // the delivery time set, now if it's None
+    pub delivery_time: Option<u64>,
+    // the delivery count set instead of counting one more delivery
+    pub retry_count: Option<u64>,
+    // an id not pending yet is claimed if its entry exists
+    pub force: bool,
+    // the claim is not counted as a delivery
+    pub just_id: bool,
+}
+
+// the entries claimed by XAUTOCLAIM, the id to continue the scan from
+// and the ids of the deleted entries removed from the pending list
+pub type AutoClaimed = (StreamId, Vec<StreamEntry>, Vec<StreamId>);
+
+#[derive(Debug, Clone)]
+pub struct ConsumerGroup {
+    // the id of the last entry delivered to any consumer
+    last_id: StreamId,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_id: StreamId) -> Self {
+        Self {
+            last_id,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = id;
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    // pending entries in the id order
+    pub fn pending(&self) -> impl DoubleEndedIterator<Item = (&StreamId, &PendingEntry)> {
+        self.pending.iter()
+    }
+
+    // the pending entries between start and end, of the consumer if it's given
+    pub fn pending_range<'a>(
+        &'a self,
+        start: StreamId,
+        end: StreamId,
+        consumer: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a StreamId, &'a PendingEntry)> {
+        (start <= end)
+            .then(|| self.pending.range(start..=end))
+            .into_iter()
+            .flatten()
+            .filter(move |(_, entry)| consumer.is_none_or(|name| entry.consumer == name))
+    }
+
+    pub fn consumers(&self) -> impl Iterator<Item = (&String, &Consumer)> {
+        self.consumers.iter()
+    }
+
+    pub fn consumer(&self, name: &str) -> Option<&Consumer> {
+        self.consumers.get(name)
+    }
+
+    pub fn consumer_mut(&mut self, name: &str) -> Option<&mut Consumer> {
+        self.consumers.get_mut(name)
+    }
+
+    // false if the consumer exists
+    pub fn create_consumer(&mut self, name: &str, now_ms: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers
+            .insert(name.to_string(), Consumer::new(now_ms));
+        true
+    }
+
+    // the number of its pending entries, which are dropped with it
+    pub fn delete_consumer(&mut self, name: &str) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+
+    // false if the id is not pending
+    pub fn ack(&mut self, id: &StreamId) -> bool {
+        let Some(entry) = self.pending.remove(id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(id);
+        }
+        true
+    }
+
+    // the consumer seen now, created if it doesn't exist
+    fn seen(&mut self, name: &str, now_ms: u64) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.to_string())
+            .or_insert_with(|| Consumer::new(now_ms));
+        consumer.seen_time = now_ms;
+        consumer
+    }
+
+    // deliver the entry to the consumer, moving it from the consumer it was pending for
+    fn deliver(&mut self, id: StreamId, consumer: &str, delivery_time: u64, delivery_count: u64) {
+        let previous = self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_string(),
+                delivery_time,
+                delivery_count,
+            },
+        );
+        if let Some(previous) = previous {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        if let Some(owner) = self.consumers.get_mut(consumer) {
+            owner.pending.insert(id);
+        }
+    }
+
+    // a pending entry saved in a snapshot, its consumer is created before
+    pub fn restore_pending(&mut self, id: StreamId, entry: PendingEntry) {
+        self.deliver(
+            id,
+            &entry.consumer,
+            entry.delivery_time,
+            entry.delivery_count,
+        );
+    }
+
+    fn remove_pending(&mut self, id: &StreamId) {
+        self.ack(id);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamFields>,
+    // the id of the last entry ever added, deleted or not
+    last_id: StreamId,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    // the id of a new entry added at the time, None if it's not greater than the last one
+    pub fn next_id(&self, id: NewStreamId, now_ms: u64) -> Option<StreamId> {
+        let last = self.last_id;
+        let id = match id {
+            NewStreamId::Auto if now_ms > last.ms => StreamId::new(now_ms, 0),
+            NewStreamId::Auto => last.next()?,
+            NewStreamId::AutoSeq(ms) if ms == last.ms => {
+                StreamId::new(ms, last.seq.checked_add(1)?)
+            }
+            // 0-0 is never a valid id
+            NewStreamId::AutoSeq(ms) => StreamId::new(ms, (ms == 0) as u64),
+            NewStreamId::Explicit(id) => id,
+        };
+        (id > last).then_some(id)
+    }
+
+    // the id must be one from next_id
+    pub fn add(&mut self, id: StreamId, fields: StreamFields) {
+        debug_assert!(id > self.last_id, "stream id {} is not increasing", id);
+        self.entries.insert(id, fields);
+        self.last_id = id;
+    }
+
+    // the last id as saved in a snapshot, entries after it may have been deleted
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = self.last_id.max(id);
+    }
+
+    // the last id set by XSETID, false if an entry has a greater id
+    pub fn reset_last_id(&mut self, id: StreamId) -> bool {
+        if self
+            .entries
+            .last_key_value()
+            .is_some_and(|(last, _)| *last > id)
+        {
+            return false;
+        }
+        self.last_id = id;
+        true
+    }
+
+    // the entries with the ids between start and end, both inclusive
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &StreamFields)> {
+        // an empty range instead of a panic on start > end
+        (start <= end)
+            .then(|| self.entries.range(start..=end))
+            .into_iter()
+            .flatten()
+    }
+
+    pub fn remove(&mut self, id: &StreamId) -> bool {
+        self.entries.remove(id).is_some()
+    }
+
+    // evict the entries from the head, at most limit of them if it's given,
+    // returns the number evicted
+    pub fn trim(&mut self, trim: StreamTrim, limit: Option<usize>) -> usize {
+        let mut evicted = 0;
+        while limit.is_none_or(|limit| evicted < limit) {
+            let len = self.entries.len();
+            let Some(first) = self.entries.first_entry() else {
+                break;
+            };
+            let evict = match trim {
+                StreamTrim::MaxLen(max_len) => len > max_len,
+                StreamTrim::MinId(min_id) => *first.key() < min_id,
+            };
+            if !evict {
+                break;
+            }
+            first.remove();
+            evicted += 1;
+        }
+        evicted
+    }
+
+    // entries in the id order
+    pub fn iter(&self) -> impl Iterator<Item = (&StreamId, &StreamFields)> {
+        self.entries.iter()
+    }
+
+    pub fn first_entry(&self) -> Option<(&StreamId, &StreamFields)> {
+        self.entries.first_key_value()
+    }
+
+    pub fn last_entry(&self) -> Option<(&StreamId, &StreamFields)> {
+        self.entries.last_key_value()
+    }
+
+    // false if the group exists
+    pub fn create_group(&mut self, name: &str, last_id: StreamId) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        self.groups
+            .insert(name.to_string(), ConsumerGroup::new(last_id));
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    pub fn group(&self, name: &str) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    // groups in the name order
+    pub fn groups(&self) -> impl Iterator<Item = (&String, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    // deliver the entries never delivered to the group, `>` of XREADGROUP,
+    // they're pending for the consumer unless noack is set, None if the group doesn't exist
+    pub fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: Option<usize>,
+        noack: bool,
+        now_ms: u64,
+    ) -> Option<Vec<StreamEntry>> {
+        let group = self.groups.get_mut(group)?;
+        group.seen(consumer, now_ms);
+        let entries = group
+            .last_id
+            .next()
+            .map(|start| self.entries.range(start..))
+            .into_iter()
+            .flatten()
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect::<Vec<_>>();
+        if let Some((last, _)) = entries.last() {
+            group.last_id = *last;
+            group.seen(consumer, now_ms).active_time = Some(now_ms);
+        }
+        if !noack {
+            for (id, _) in &entries {
+                group.deliver(*id, consumer, now_ms, 1);
+            }
+        }
+        Some(entries)
+    }
+
+    // the entries pending for the consumer after the id, the history of XREADGROUP,
+    // the fields are None for the entries deleted from the stream
+    pub fn read_pending(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        after: StreamId,
+        count: Option<usize>,
+        now_ms: u64,
+    ) -> Option<Vec<(StreamId, Option<StreamFields>)>> {
+        let group = self.groups.get_mut(group)?;
+        let consumer = group.seen(consumer, now_ms);
+        let Some(start) = after.next() else {
+            return Some(vec![]);
+        };
+        Some(
+            consumer
+                .pending
+                .range(start..)
+                .take(count.unwrap_or(usize::MAX))
+                .map(|id| (*id, self.entries.get(id).cloned()))
+                .collect(),
+        )
+    }
+
+    // claim the pending entries idle for at least min_idle for the consumer,
+    // the ones deleted from the stream are removed from the pending list instead,
+    // None if the group doesn't exist
+    pub fn claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        ids: &[StreamId],
+        min_idle: u64,
+        options: ClaimOptions,
+        now_ms: u64,
+    ) -> Option<Vec<StreamEntry>> {
+        let group = self.groups.get_mut(group)?;
+        group.seen(consumer, now_ms);
+        let mut claimed = Vec::new();
+        for id in ids {
+            if !group.pending.contains_key(id) {
+                if !options.force || !self.entries.contains_key(id) {
+                    continue;
+                }
+                group.deliver(*id, consumer, now_ms, 0);
+            }
+            let entry = &group.pending[id];
+            if now_ms.saturating_sub(entry.delivery_time) < min_idle {
+                continue;
+            }
+            let Some(fields) = self.entries.get(id) else {
+                group.remove_pending(id);
+                continue;
+            };
+            let delivery_count = match options.retry_count {
+                Some(count) => count,
+                None if options.just_id => entry.delivery_count,
+                None => entry.delivery_count + 1,
+            };
+            let delivery_time = options.delivery_time.unwrap_or(now_ms);
+            group.deliver(*id, consumer, delivery_time, delivery_count);
+            claimed.push((*id, fields.clone()));
+        }
+        if !claimed.is_empty() {
+            group.seen(consumer, now_ms).active_time = Some(now_ms);
+        }
+        Some(claimed)
+    }
+
+    // claim at most count entries idle for at least min_idle, scanning the pending list from start,
+    // the scan stops after count * 10 entries like redis, None if the group doesn't exist
+    #[allow(clippy::too_many_arguments)]
+    pub fn auto_claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        just_id: bool,
+        now_ms: u64,
+    ) -> Option<AutoClaimed> {
+        let group = self.groups.get_mut(group)?;
+        group.seen(consumer, now_ms);
+        let mut attempts = count.saturating_mul(10);
+        // one more than scanned at most, where the next scan starts
+        let scanned = group
+            .pending
+            .range(start..)
+            .map(|(id, entry)| (*id, entry.delivery_time, entry.delivery_count))
+            .take(attempts.saturating_add(1))
+            .collect::<Vec<_>>();
+        let mut claimed = Vec::new();
+        let mut deleted = Vec::new();
+        let mut next = StreamId::MIN;
+        for (id, delivery_time, delivery_count) in scanned {
+            if attempts == 0 || claimed.len() == count {
+                next = id;
+                break;
+            }
+            attempts -= 1;
+            if now_ms.saturating_sub(delivery_time) < min_idle {
+                continue;
+            }
+            let Some(fields) = self.entries.get(&id) else {
+                group.remove_pending(&id);
+                deleted.push(id);
+                continue;
+            };
+            let delivery_count = if just_id {
+                delivery_count
+            } else {
+                delivery_count + 1
+            };
+            group.deliver(id, consumer, now_ms, delivery_count);
+            claimed.push((id, fields.clone()));
+        }
+        if !claimed.is_empty() {
+            group.seen(consumer, now_ms).active_time = Some(now_ms);
+        }
+        Some((next, claimed, deleted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> StreamFields {
+        vec![(Bytes::from("field"), Bytes::from("value"))]
+    }
+
+    #[test]
+    fn test_next_id() {
+        let mut stream = Stream::new();
+        assert_eq!(
+            stream.next_id(NewStreamId::Explicit(StreamId::MIN), 0),
+            None
+        );
+        assert_eq!(
+            stream.next_id(NewStreamId::AutoSeq(0), 0),
+            Some(StreamId::new(0, 1))
+        );
+        assert_eq!(
+            stream.next_id(NewStreamId::Auto, 100),
+            Some(StreamId::new(100, 0))
+        );
+
+        stream.add(StreamId::new(100, 5), fields());
+        // the clock went backwards
+        assert_eq!(
+            stream.next_id(NewStreamId::Auto, 50),
+            Some(StreamId::new(100, 6))
+        );
+        assert_eq!(
+            stream.next_id(NewStreamId::AutoSeq(100), 0),
+            Some(StreamId::new(100, 6))
+        );
+        assert_eq!(stream.next_id(NewStreamId::AutoSeq(99), 0), None);
+        assert_eq!(
+            stream.next_id(NewStreamId::Explicit(StreamId::new(100, 5)), 0),
+            None
+        );
+
+        assert_eq!(StreamId::parse("1-2", 0), Some(StreamId::new(1, 2)));
+        assert_eq!(
+            StreamId::parse("1", u64::MAX),
+            Some(StreamId::new(1, u64::MAX))
+        );
+        assert_eq!(StreamId::parse("1-x", 0), None);
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::new(2, 0).prev(), Some(StreamId::new(1, u64::MAX)));
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut stream = Stream::new();
+        for ms in 1..=10 {
+            stream.add(StreamId::new(ms, 0), fields());
+        }
+        assert_eq!(stream.trim(StreamTrim::MaxLen(8), None), 2);
+        assert_eq!(
+            stream.trim(StreamTrim::MinId(StreamId::new(6, 0)), Some(2)),
+            2
+        );
+        assert_eq!(stream.len(), 6);
+        assert_eq!(
+            stream
+                .range(StreamId::MIN, StreamId::MAX)
+                .next()
+                .map(|(id, _)| *id),
+            Some(StreamId::new(5, 0))
+        );
+
+        // the last id stays after its entry is deleted
+        assert!(stream.remove(&StreamId::new(10, 0)));
+        assert_eq!(stream.last_id(), StreamId::new(10, 0));
+        assert_eq!(
+            stream
+                .range(StreamId::new(7, 0), StreamId::new(3, 0))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_consumer_group() {
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            stream.add(StreamId::new(ms, 0), fields());
+        }
+        assert!(stream.create_group("g", StreamId::MIN));
+        assert!(!stream.create_group("g", StreamId::MIN));
+        assert_eq!(stream.read_group("nogroup", "c", None, false, 0), None);
+
+        let read = stream
+            .read_group("g", "alice", Some(2), false, 100)
+            .unwrap();
+        assert_eq!(read.len(), 2);
+        let read = stream.read_group("g", "bob", None, false, 100).unwrap();
+        assert_eq!(read.len(), 1);
+        assert!(stream
+            .read_group("g", "bob", None, false, 100)
+            .unwrap()
+            .is_empty());
+
+        let group = stream.group_mut("g").unwrap();
+        assert_eq!(group.last_id(), StreamId::new(3, 0));
+        assert_eq!(group.pending_len(), 3);
+        assert!(group.ack(&StreamId::new(1, 0)));
+        assert!(!group.ack(&StreamId::new(1, 0)));
+        assert_eq!(group.consumer("alice").unwrap().pending_len(), 1);
+
+        // the history of the consumer, a deleted entry has no fields
+        stream.remove(&StreamId::new(2, 0));
+        assert_eq!(
+            stream.read_pending("g", "alice", StreamId::MIN, None, 100),
+            Some(vec![(StreamId::new(2, 0), None)])
+        );
+
+        // not idle for long enough
+        let ids = [StreamId::new(2, 0), StreamId::new(3, 0)];
+        let claimed = stream.claim("g", "carol", &ids, 50, ClaimOptions::default(), 120);
+        assert_eq!(claimed, Some(vec![]));
+        // the deleted entry is dropped from the pending list
+        let claimed = stream
+            .claim("g", "carol", &ids, 50, ClaimOptions::default(), 200)
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        let group = stream.group("g").unwrap();
+        assert_eq!(group.pending_len(), 1);
+        assert_eq!(
+            group.pending().next(),
+            Some((
+                &StreamId::new(3, 0),
+                &PendingEntry {
+                    consumer: "carol".to_string(),
+                    delivery_time: 200,
+                    delivery_count: 2,
+                }
+            ))
+        );
+        assert_eq!(group.consumer("bob").unwrap().pending_len(), 0);
+
+        let (next, claimed, deleted) = stream
+            .auto_claim("g", "dave", 10, StreamId::MIN, 1, false, 300)
+            .unwrap();
+        assert_eq!(next, StreamId::MIN);
+        assert_eq!(claimed.len(), 1);
+        assert!(deleted.is_empty());
+        assert_eq!(
+            stream.group_mut("g").unwrap().delete_consumer("dave"),
+            Some(1)
+        );
+        assert_eq!(stream.group("g").unwrap().pending_len(), 0);
+    }
+}
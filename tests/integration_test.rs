@@ -2,7 +2,6 @@ mod test {
 
     #[allow(unused_imports)]
     use std::sync::{Arc, Mutex};
-    use std::thread;
 
     use lazy_static::lazy_static;
     use std::sync::Once;
@@ -10,10 +9,6 @@ mod test {
     #[allow(unused_imports)]
     use redis_rs::client::AsyncClient;
 
-    const REDIS_HOST: &str = "0.0.0.0";
-    const REDIS_PORT: u16 = 6379;
-    const MAX_CLIENTS: usize = 1024;
-
     lazy_static! {
         static ref INIT: Once = Once::new();
     }